@@ -0,0 +1,42 @@
+/* A C interface of ftx-history-downloder. Link with libftx_history_ffi. */
+#ifndef FTX_HISTORY_H
+#define FTX_HISTORY_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Called after each page is written with the number of records written so far and the date of the
+ * file being written as YYYY-MM-DD, or NULL if nothing is written yet. Returning non-zero stops the
+ * download after the page, and ftx_history_collect returns 130.
+ */
+typedef int32_t (*ftx_history_progress)(void *user_data, uint64_t records, const char *date);
+
+/*
+ * Downloads the fills of the exchange (e.g. "ftx") into the daily csv files of outdir, as the
+ * download command of the CLI does. credential is the content of the credential file as json.
+ * account is a subaccount of ftx as --sub-account, and symbols are the comma separated symbols of
+ * binance as --symbols. They, start, end (YYYY-MM-DD) and timezone may be NULL for the defaults of
+ * the CLI, and progress may be NULL. progress is called on the calling thread.
+ *
+ * Returns 0, or the exit code of the CLI for the failure, e.g. 2 for a rejected credential.
+ */
+int32_t ftx_history_collect(const char *exchange, const char *credential, const char *outdir,
+                            const char *account, const char *start, const char *end,
+                            const char *timezone, const char *symbols,
+                            ftx_history_progress progress, void *user_data);
+
+/*
+ * Returns the message of the last failure of ftx_history_collect on this thread, or NULL if it
+ * succeeded. The string is valid until the next call on the thread.
+ */
+const char *ftx_history_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,301 @@
+//! A C interface of the collector, to embed it into tools not written in Rust.
+//!
+//! See `include/ftx_history.h` for the functions.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::NaiveDate;
+use collector::collect::{collect_into, Output};
+use collector::error::{ExitCode, Usage};
+use collector::exchange::binance::Binance;
+use collector::exchange::bitfinex::Bitfinex;
+use collector::exchange::bybit::Bybit;
+use collector::exchange::coinbase::Coinbase;
+use collector::exchange::deribit::Deribit;
+use collector::exchange::dydx::Dydx;
+use collector::exchange::ftx::Ftx;
+use collector::exchange::gateio::GateIo;
+use collector::exchange::kraken::Kraken;
+use collector::exchange::kucoin::Kucoin;
+use collector::exchange::okx::Okx;
+use collector::exchange::{DecimalSeparator, Exchange, Fill, TimeRange};
+use collector::sink::{CsvSink, Sink, SinkOptions};
+use collector::state::FileState;
+use collector::timezone::Timezone;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::de::DeserializeOwned;
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+
+/// Called after each page is written with the number of records written so far and the date of
+/// the file being written as `YYYY-MM-DD`, or NULL if nothing is written yet. Returning non-zero
+/// stops the download after the page.
+pub type ProgressCallback =
+    Option<extern "C" fn(user_data: *mut c_void, records: u64, date: *const c_char) -> i32>;
+
+// shared by all downloads since the http client keeps connections on the runtime
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Downloads the fills of the exchange into the daily csv files of `outdir`, as the download
+/// command of the CLI does, and returns 0 or the exit code of the CLI for the failure.
+///
+/// `credential` is the content of the credential file as json. `account` is a subaccount of ftx
+/// as `--sub-account`, and `symbols` are the comma separated symbols of binance as `--symbols`.
+/// They, `start`, `end` and `timezone` may be NULL for the defaults of the CLI. `progress` is
+/// called on the calling thread.
+///
+/// # Safety
+///
+/// The strings must be NULL or valid NUL-terminated UTF-8 strings.
+#[no_mangle]
+pub unsafe extern "C" fn ftx_history_collect(
+    exchange: *const c_char,
+    credential: *const c_char,
+    outdir: *const c_char,
+    account: *const c_char,
+    start: *const c_char,
+    end: *const c_char,
+    timezone: *const c_char,
+    symbols: *const c_char,
+    progress: ProgressCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    // a panic must not unwind into the caller
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let args = Args {
+            exchange: required(exchange, "exchange")?,
+            credential: required(credential, "credential")?,
+            outdir: required(outdir, "outdir")?,
+            account: optional(account)?,
+            start: optional(start)?.map(parse_date).transpose()?,
+            end: optional(end)?.map(parse_date).transpose()?,
+            timezone: optional(timezone)?
+                .unwrap_or("local")
+                .parse()
+                .map_err(Usage)?,
+            symbols: optional(symbols)?
+                .map(|s| s.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+        };
+        let runtime = match RUNTIME.get() {
+            Some(runtime) => runtime,
+            None => {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()?;
+                RUNTIME.get_or_init(|| runtime)
+            }
+        };
+        runtime.block_on(run(&args, progress, UserData(user_data)))
+    }))
+    .unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(anyhow!("panicked: {}", message))
+    });
+    match result {
+        Ok(()) => {
+            LAST_ERROR.with(|e| *e.borrow_mut() = None);
+            0
+        }
+        Err(e) => {
+            let code = ExitCode::of(&e) as i32;
+            let message = CString::new(format!("{:#}", e).replace('\0', " ")).unwrap_or_default();
+            LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+            code
+        }
+    }
+}
+
+/// Returns the message of the last failure of `ftx_history_collect` on this thread, or NULL if
+/// it succeeded. The string is valid until the next call on the thread.
+#[no_mangle]
+pub extern "C" fn ftx_history_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+struct Args<'a> {
+    exchange: &'a str,
+    credential: &'a str,
+    outdir: &'a str,
+    // a subaccount of ftx, or None for the main account
+    account: Option<&'a str>,
+    start: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+    timezone: Timezone,
+    // of binance
+    symbols: Vec<String>,
+}
+
+// the callback is called only on the thread which passed it
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+async fn run(args: &Args<'_>, progress: ProgressCallback, user_data: UserData) -> Result<()> {
+    let tz = args.timezone;
+    let range = TimeRange {
+        start: args.start.map(|d| tz.start_of(d)),
+        end: tz.start_of(args.end.unwrap_or_else(|| tz.today())),
+    };
+    if args.account.is_some() && args.exchange != "ftx" {
+        bail!(Usage(format!(
+            "a subaccount is not supported for {}",
+            args.exchange
+        )));
+    }
+    let cancel = CancellationToken::new();
+    let output = Output {
+        dir: Path::new(args.outdir),
+        account: args.account.unwrap_or("main"),
+        raw: false,
+        resume: false,
+        dry_run: false,
+        preview: None,
+        skip_existing: false,
+        continue_on_error: false,
+        timezone: tz,
+        markets: &[],
+        side: None,
+        liquidity: None,
+        types: &[],
+        min_notional: None,
+        limit: None,
+        limit_requests: None,
+        cancel: cancel.clone(),
+        // written by ProgressSink instead
+        sink: "csv",
+        convert: None,
+        decimal_separator: DecimalSeparator::Dot,
+    };
+    let credential = args.credential;
+    macro_rules! collect {
+        ($exchange:expr) => {{
+            let exchange = $exchange;
+            let mut sink = ProgressSink {
+                inner: CsvSink::new(SinkOptions {
+                    dir: output.dir,
+                    account: output.account,
+                    exchange: exchange.name(),
+                    raw: output.raw,
+                    decimal_separator: output.decimal_separator,
+                }),
+                records: 0,
+                date: None,
+                progress,
+                user_data,
+                cancel,
+            };
+            Ok(collect_into(&exchange, &range, &output, &mut sink).await?)
+        }};
+    }
+    match args.exchange {
+        "ftx" => {
+            let mut ftx = Ftx::builder()
+                .credential(parse(credential)?)
+                .sub_account(args.account.map(str::to_string))
+                .build()?;
+            // the local clock is used if the server time is not available
+            let _ = ftx.sync_clock().await;
+            collect!(ftx)
+        }
+        "binance" => collect!(Binance::new(parse(credential)?, &args.symbols).await?),
+        "bybit" => collect!(Bybit::new(parse(credential)?)),
+        "kraken" => collect!(Kraken::new(parse(credential)?).await?),
+        "coinbase" => collect!(Coinbase::new(parse(credential)?)),
+        "okx" => collect!(Okx::new(parse(credential)?)),
+        "deribit" => collect!(Deribit::new(parse(credential)?).await?),
+        "bitfinex" => collect!(Bitfinex::new(parse(credential)?)),
+        "kucoin" => collect!(Kucoin::new(parse(credential)?)),
+        "gateio" => collect!(GateIo::new(parse(credential)?)),
+        "dydx" => collect!(Dydx::new(parse(credential)?)),
+        other => bail!(Usage(format!("unknown exchange: {}", other))),
+    }
+}
+
+/// Writes the csv files and reports the progress after each page.
+struct ProgressSink<'a> {
+    inner: CsvSink<'a>,
+    records: u64,
+    date: Option<NaiveDate>,
+    progress: ProgressCallback,
+    user_data: UserData,
+    cancel: CancellationToken,
+}
+
+impl Sink for ProgressSink<'_> {
+    fn write_record<'a>(&'a mut self, fill: &'a Fill) -> BoxFuture<'a, Result<()>> {
+        self.records += 1;
+        self.inner.write_record(fill)
+    }
+
+    fn rotate(&mut self, date: NaiveDate) -> BoxFuture<'_, Result<()>> {
+        self.date = Some(date);
+        self.inner.rotate(date)
+    }
+
+    fn reopen<'a>(&'a mut self, state: &'a FileState) -> BoxFuture<'a, Result<()>> {
+        self.date = Some(state.date);
+        self.inner.reopen(state)
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, Result<Option<FileState>>> {
+        async move {
+            let state = self.inner.flush().await?;
+            if let Some(progress) = self.progress {
+                let date = self.date.map(|d| CString::new(d.to_string()).unwrap());
+                let date = date.as_ref().map_or(std::ptr::null(), |d| d.as_ptr());
+                if progress(self.user_data.0, self.records, date) != 0 {
+                    self.cancel.cancel();
+                }
+            }
+            Ok(state)
+        }
+        .boxed()
+    }
+
+    fn finalize(&mut self) -> BoxFuture<'_, Result<()>> {
+        self.inner.finalize()
+    }
+}
+
+unsafe fn optional<'a>(s: *const c_char) -> Result<Option<&'a str>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(
+        CStr::from_ptr(s)
+            .to_str()
+            .map_err(|_| Usage("a string is not utf-8".into()))?,
+    ))
+}
+
+unsafe fn required<'a>(s: *const c_char, name: &str) -> Result<&'a str> {
+    optional(s)?.ok_or_else(|| anyhow!(Usage(format!("{} is required", name))))
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    s.parse()
+        .map_err(|_| anyhow!(Usage(format!("invalid date: {}. expected YYYY-MM-DD", s))))
+}
+
+fn parse<T: DeserializeOwned>(credential: &str) -> Result<T> {
+    serde_json::from_str(credential).with_context(|| "invalid credential")
+}
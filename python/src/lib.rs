@@ -0,0 +1,292 @@
+//! Python bindings of the collector, which yield the fills of an exchange as dicts.
+//!
+//! ```python
+//! import ftx_history
+//!
+//! for fill in ftx_history.collect_fills("ftx", {"api_key": "...", "api_secret": "..."},
+//!                                       start="2022-01-01", end="2022-02-01"):
+//!     print(fill["time"], fill["market"], fill["side"], fill["size"])
+//! ```
+
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{NaiveDate, SecondsFormat};
+use collector::error::ExitCode;
+use collector::exchange::binance::Binance;
+use collector::exchange::bitfinex::Bitfinex;
+use collector::exchange::bybit::Bybit;
+use collector::exchange::coinbase::Coinbase;
+use collector::exchange::deribit::Deribit;
+use collector::exchange::dydx::Dydx;
+use collector::exchange::ftx::Ftx;
+use collector::exchange::gateio::GateIo;
+use collector::exchange::kraken::Kraken;
+use collector::exchange::kucoin::Kucoin;
+use collector::exchange::okx::Okx;
+use collector::exchange::{self, Exchange, Fill, TimeRange};
+use collector::timezone::Timezone;
+use futures::TryStreamExt;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de::DeserializeOwned;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+// fills downloaded ahead of the iteration. the download waits while this many are not consumed
+const BUFFER: usize = 1000;
+
+// shared by all downloads since the http client keeps connections on the runtime
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+create_exception!(
+    ftx_history,
+    CollectorError,
+    PyException,
+    "Raised when the download fails, e.g. by an invalid api key."
+);
+create_exception!(
+    ftx_history,
+    AuthError,
+    CollectorError,
+    "Raised when the credential is rejected."
+);
+create_exception!(
+    ftx_history,
+    NetworkError,
+    CollectorError,
+    "Raised when the exchange can't be reached even after retries."
+);
+create_exception!(
+    ftx_history,
+    ParseError,
+    CollectorError,
+    "Raised when a response has an unexpected format."
+);
+create_exception!(
+    ftx_history,
+    IoError,
+    CollectorError,
+    "Raised when a file can't be read or written."
+);
+
+// the kind and the message of the error which stopped the download
+type Failure = (ExitCode, String);
+
+/// An iterator of the fills being downloaded in the background.
+#[pyclass]
+struct Fills {
+    receiver: Mutex<Receiver<Result<Fill, Failure>>>,
+}
+
+#[pymethods]
+impl Fills {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        // the download continues while waiting for the next fill
+        match py.allow_threads(|| self.receiver.lock().unwrap().blocking_recv()) {
+            Some(Ok(fill)) => Ok(Some(to_dict(py, &fill)?)),
+            Some(Err((code, message))) => Err(match code {
+                ExitCode::Auth => AuthError::new_err(message),
+                ExitCode::Network => NetworkError::new_err(message),
+                ExitCode::Parse => ParseError::new_err(message),
+                ExitCode::Io => IoError::new_err(message),
+                _ => CollectorError::new_err(message),
+            }),
+            // the download finished
+            None => Ok(None),
+        }
+    }
+}
+
+/// Downloads the fills of the exchange between `start` and `end` lazily, newest first.
+///
+/// `credential` is the content of the credential file of the CLI as a dict. `start` and `end`
+/// are dates (or `YYYY-MM-DD`) in `timezone`, and `end` defaults to today.
+#[pyfunction]
+#[pyo3(signature = (exchange, credential, start=None, end=None, timezone="local", sub_account=None, symbols=Vec::new()))]
+#[allow(clippy::too_many_arguments)]
+fn collect_fills(
+    py: Python<'_>,
+    exchange: &str,
+    credential: &Bound<'_, PyDict>,
+    start: Option<&Bound<'_, PyAny>>,
+    end: Option<&Bound<'_, PyAny>>,
+    timezone: &str,
+    sub_account: Option<String>,
+    symbols: Vec<String>,
+) -> PyResult<Fills> {
+    let credential: String = py
+        .import("json")?
+        .call_method1("dumps", (credential,))?
+        .extract()?;
+    let timezone: Timezone = timezone.parse().map_err(PyValueError::new_err)?;
+    let end = match end {
+        Some(end) => date(end)?,
+        None => timezone.today(),
+    };
+    let range = TimeRange {
+        start: start.map(date).transpose()?.map(|d| timezone.start_of(d)),
+        end: timezone.start_of(end),
+    };
+    let exchange = exchange.to_ascii_lowercase();
+    if sub_account.is_some() && exchange != "ftx" {
+        return Err(PyValueError::new_err(format!(
+            "sub_account is not supported for {}",
+            exchange
+        )));
+    }
+
+    let (sender, receiver) = channel(BUFFER);
+    let runtime = match RUNTIME.get() {
+        Some(runtime) => runtime,
+        None => {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+            RUNTIME.get_or_init(|| runtime)
+        }
+    };
+    runtime.spawn(async move {
+        if let Err(e) = download(
+            &exchange,
+            &credential,
+            sub_account,
+            &symbols,
+            &range,
+            &sender,
+        )
+        .await
+        {
+            let _ = sender
+                .send(Err((ExitCode::of(&e), format!("{:#}", e))))
+                .await;
+        }
+    });
+    Ok(Fills {
+        receiver: Mutex::new(receiver),
+    })
+}
+
+async fn download(
+    exchange: &str,
+    credential: &str,
+    sub_account: Option<String>,
+    symbols: &[String],
+    range: &TimeRange,
+    sender: &Sender<Result<Fill, Failure>>,
+) -> anyhow::Result<()> {
+    match exchange {
+        "ftx" => {
+            let mut ftx = Ftx::builder()
+                .credential(parse(credential)?)
+                .sub_account(sub_account)
+                .build()?;
+            // the local clock is used if the server time is not available
+            let _ = ftx.sync_clock().await;
+            send(&ftx, range, sender).await
+        }
+        "binance" => {
+            send(
+                &Binance::new(parse(credential)?, symbols).await?,
+                range,
+                sender,
+            )
+            .await
+        }
+        "bybit" => send(&Bybit::new(parse(credential)?), range, sender).await,
+        "kraken" => send(&Kraken::new(parse(credential)?).await?, range, sender).await,
+        "coinbase" => send(&Coinbase::new(parse(credential)?), range, sender).await,
+        "okx" => send(&Okx::new(parse(credential)?), range, sender).await,
+        "deribit" => send(&Deribit::new(parse(credential)?).await?, range, sender).await,
+        "bitfinex" => send(&Bitfinex::new(parse(credential)?), range, sender).await,
+        "kucoin" => send(&Kucoin::new(parse(credential)?), range, sender).await,
+        "gateio" => send(&GateIo::new(parse(credential)?), range, sender).await,
+        "dydx" => send(&Dydx::new(parse(credential)?), range, sender).await,
+        _ => anyhow::bail!("unknown exchange: {}", exchange),
+    }
+}
+
+/// Sends the fills of the range until the iterator is dropped.
+async fn send<E: Exchange>(
+    exchange: &E,
+    range: &TimeRange,
+    sender: &Sender<Result<Fill, Failure>>,
+) -> anyhow::Result<()> {
+    let fills = exchange::fills(exchange, range);
+    futures::pin_mut!(fills);
+    while let Some(fill) = fills.try_next().await? {
+        // the iterator is dropped
+        if sender.send(Ok(fill)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn parse<T: DeserializeOwned>(credential: &str) -> anyhow::Result<T> {
+    serde_json::from_str(credential).map_err(|e| anyhow::anyhow!("invalid credential: {}", e))
+}
+
+/// Reads a `datetime.date` or a `YYYY-MM-DD` string.
+fn date(value: &Bound<'_, PyAny>) -> PyResult<NaiveDate> {
+    let text: String = match value.extract() {
+        Ok(text) => text,
+        Err(_) => value.call_method0("isoformat")?.extract()?,
+    };
+    text.parse()
+        .map_err(|_| PyValueError::new_err(format!("expected a date but got {}", text)))
+}
+
+/// Converts a fill into a dict of the raw columns, with decimals as `decimal.Decimal` and the time
+/// as an aware `datetime.datetime`.
+fn to_dict<'py>(py: Python<'py>, fill: &Fill) -> PyResult<Bound<'py, PyDict>> {
+    let decimal = py.import("decimal")?.getattr("Decimal")?;
+    let datetime = py.import("datetime")?.getattr("datetime")?;
+    let to_decimal = |d: rust_decimal::Decimal| decimal.call1((d.to_string(),));
+    let dict = PyDict::new(py);
+    dict.set_item("fee", to_decimal(fill.fee)?)?;
+    dict.set_item("fee_currency", &fill.fee_currency)?;
+    dict.set_item("fee_rate", fill.fee_rate.map(to_decimal).transpose()?)?;
+    dict.set_item("future", &fill.future)?;
+    dict.set_item("id", fill.id.as_str())?;
+    dict.set_item("liquidity", fill.liquidity.map(|l| l.to_string()))?;
+    dict.set_item("market", &fill.market)?;
+    dict.set_item("base_currency", &fill.base_currency)?;
+    dict.set_item("quote_currency", &fill.quote_currency)?;
+    dict.set_item("order_id", fill.order_id.as_ref().map(|id| id.as_str()))?;
+    dict.set_item("trade_id", &fill.trade_id)?;
+    dict.set_item("price", fill.price.map(to_decimal).transpose()?)?;
+    dict.set_item("side", fill.side.map(|s| s.to_string()))?;
+    dict.set_item("size", to_decimal(fill.size)?)?;
+    dict.set_item(
+        "time",
+        datetime.call_method1(
+            "fromisoformat",
+            (fill.time.to_rfc3339_opts(SecondsFormat::Micros, false),),
+        )?,
+    )?;
+    dict.set_item("type", fill.typ.as_ref().map(|t| t.as_str()))?;
+    dict.set_item("instrument_kind", &fill.instrument_kind)?;
+    dict.set_item("strike", fill.strike.map(to_decimal).transpose()?)?;
+    dict.set_item("option_type", &fill.option_type)?;
+    dict.set_item("index_price", fill.index_price.map(to_decimal).transpose()?)?;
+    Ok(dict)
+}
+
+#[pymodule]
+fn ftx_history(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(collect_fills, m)?)?;
+    m.add_class::<Fills>()?;
+    let py = m.py();
+    m.add("CollectorError", py.get_type::<CollectorError>())?;
+    m.add("AuthError", py.get_type::<AuthError>())?;
+    m.add("NetworkError", py.get_type::<NetworkError>())?;
+    m.add("ParseError", py.get_type::<ParseError>())?;
+    m.add("IoError", py.get_type::<IoError>())?;
+    Ok(())
+}
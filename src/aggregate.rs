@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive::{self, Record};
+use crate::exchange::{ser_decimal, DecimalSeparator, FillType, Localized, Side};
+
+/// The trades of a market in a day.
+#[derive(Serialize)]
+pub struct MarketAggregate {
+    pub market: String,
+    // the settlement currency, which is the fee currency for the futures of ftx
+    pub quote_currency: Option<String>,
+    pub trades: u64,
+    // the sum of the sizes
+    #[serde(serialize_with = "ser_decimal")]
+    pub volume: Decimal,
+    // the sum of price * size in the quote currency
+    #[serde(serialize_with = "ser_decimal")]
+    pub notional: Decimal,
+    // in the settlement currency. fees in other currencies than the base and the settlement
+    // currency are excluded
+    #[serde(serialize_with = "ser_decimal")]
+    pub fees: Decimal,
+    // bought - sold
+    #[serde(serialize_with = "ser_decimal")]
+    pub net_position_change: Decimal,
+}
+
+/// Returns the path of the aggregate of a daily file.
+pub fn path(outdir: &Path, account: &str, date: NaiveDate) -> PathBuf {
+    outdir.join(format!("{}_{}.daily.csv", account, date))
+}
+
+/// Writes the aggregate of each market next to each daily file of the range, which replaces the
+/// one written before.
+pub async fn write(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<()> {
+    let files = archive::files(outdir, account);
+    let files = match start {
+        Some(start) => files.range(start..end),
+        None => files.range(..end),
+    };
+    let mut written = 0;
+    let mut excluded_fees = BTreeSet::new();
+    for (date, file) in files {
+        let aggregates = aggregate(
+            archive::read_records(file, *date).await?,
+            &mut excluded_fees,
+        );
+        let path = path(outdir, account, *date);
+        let file = File::create(&path)
+            .await
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = AsyncSerializer::from_writer(file);
+        for aggregate in aggregates.into_values() {
+            writer
+                .serialize(Localized(&aggregate, decimal_separator))
+                .await
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+        writer
+            .flush()
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        written += 1;
+    }
+    if !excluded_fees.is_empty() {
+        warn!(
+            "fees in {} are not included since they are not in the currencies of the markets",
+            excluded_fees.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    info!("{} daily aggregates are written", written);
+    Ok(())
+}
+
+// the currencies of the fees which can't be summed are added to `excluded_fees`
+fn aggregate(
+    records: Vec<Record>,
+    excluded_fees: &mut BTreeSet<String>,
+) -> BTreeMap<String, MarketAggregate> {
+    let mut markets = BTreeMap::new();
+    for record in records {
+        let (Some(market), Some(side)) = (record.market.clone(), record.side) else {
+            continue;
+        };
+        if !matches!(
+            record.typ,
+            None | Some(FillType::Order | FillType::Liquidation)
+        ) {
+            continue;
+        }
+        let aggregate = markets
+            .entry(market.clone())
+            .or_insert_with(|| MarketAggregate {
+                market,
+                quote_currency: None,
+                trades: 0,
+                volume: Decimal::ZERO,
+                notional: Decimal::ZERO,
+                fees: Decimal::ZERO,
+                net_position_change: Decimal::ZERO,
+            });
+        let size = record.size.abs();
+        aggregate.trades += 1;
+        aggregate.volume += size;
+        aggregate.net_position_change += match side {
+            Side::Buy => size,
+            Side::Sell => -size,
+        };
+        if let Some(price) = record.price {
+            aggregate.notional += price * size;
+            match record.fee_in_quote(price) {
+                Some(fee) => aggregate.fees += fee,
+                None => excluded_fees.extend(record.fee_currency.clone()),
+            }
+        }
+        aggregate.quote_currency = aggregate
+            .quote_currency
+            .take()
+            .or(record.settlement_currency().cloned());
+    }
+    for aggregate in markets.values_mut() {
+        aggregate.volume = aggregate.volume.normalize();
+        aggregate.notional = aggregate.notional.normalize();
+        aggregate.fees = aggregate.fees.normalize();
+        aggregate.net_position_change = aggregate.net_position_change.normalize();
+    }
+    markets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    #[tokio::test]
+    async fn sums_the_fees_in_the_settlement_currency() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        write_file(
+            dir.path(),
+            date,
+            &[
+                "ftx,main,BTC-PERP,,,buy,40000,0.5,7,USD,2022-01-01T00:00:00Z,1,order",
+                "ftx,main,BTC-PERP,,,sell,41000,0.25,-0.5,USD,2022-01-01T01:00:00Z,2,order",
+                "ftx,main,ETH/USD,ETH,USD,buy,3000,1,0.001,ETH,2022-01-01T02:00:00Z,3,order",
+                // excluded
+                "ftx,main,ETH/USD,ETH,USD,sell,3100,1,0.01,FTT,2022-01-01T03:00:00Z,4,order",
+                "ftx,main,BTC-PERP,,,,,0.1,0,USD,2022-01-01T04:00:00Z,5,funding",
+            ],
+        );
+        write(dir.path(), "main", None, date.succ(), DecimalSeparator::Dot)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path(dir.path(), "main", date)).unwrap(),
+            "market,quote_currency,trades,volume,notional,fees,net_position_change\n\
+             BTC-PERP,USD,2,0.75,30250,6.5,0.25\n\
+             ETH/USD,USD,2,2,6100,3,0\n"
+        );
+    }
+}
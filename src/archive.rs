@@ -0,0 +1,422 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate};
+use futures::TryStreamExt;
+use log::*;
+use rust_decimal::Decimal;
+use tokio::fs::File;
+
+use crate::exchange::{Conversion, Fill, FillId, FillType, Liquidity, Side};
+
+/// The columns of the unified and the raw schema holding numbers.
+pub const NUMBERS: [&str; 10] = [
+    "price",
+    "size",
+    "fee",
+    "fee_rate",
+    "feeRate",
+    "strike",
+    "index_price",
+    "indexPrice",
+    "converted_fee",
+    "converted_notional",
+];
+
+/// Returns the path of the daily file of the account.
+pub fn path(outdir: &Path, account: &str, date: NaiveDate) -> PathBuf {
+    outdir.join(format!("{}_{}.csv", account, date))
+}
+
+/// Returns the daily files of the account already downloaded into `outdir`, ordered by date.
+pub fn files(outdir: &Path, account: &str) -> BTreeMap<NaiveDate, PathBuf> {
+    let entries = match std::fs::read_dir(outdir) {
+        Ok(entries) => entries,
+        Err(_) => return BTreeMap::new(),
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let date = name
+                .strip_prefix(account)?
+                .strip_prefix('_')?
+                .strip_suffix(".csv")?
+                .parse()
+                .ok()?;
+            Some((date, entry.path()))
+        })
+        .collect()
+}
+
+/// Reads the ids of the records in a file, which are in the `native_id` column of the unified
+/// schema or in the `id` column of the raw one.
+pub async fn read_ids(path: &Path) -> Result<Vec<FillId>> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = csv_async::AsyncReader::from_reader(file);
+    let column = reader
+        .headers()
+        .await?
+        .iter()
+        .position(|h| h == "native_id" || h == "id")
+        .ok_or_else(|| anyhow!("no id column in {}", path.display()))?;
+    reader
+        .records()
+        .map_ok(|r| FillId::from(r.get(column).unwrap_or_default()))
+        .try_collect()
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// A record of a daily file, which is either of the unified schema or the raw one.
+pub struct Record {
+    // of the file
+    pub date: NaiveDate,
+    pub time: DateTime<FixedOffset>,
+    pub market: Option<String>,
+    pub base_currency: Option<String>,
+    pub quote_currency: Option<String>,
+    pub side: Option<Side>,
+    pub price: Option<Decimal>,
+    pub size: Decimal,
+    pub fee: Decimal,
+    pub fee_currency: Option<String>,
+    pub typ: Option<FillType>,
+    // only in the raw schema
+    pub liquidity: Option<Liquidity>,
+    // the id given by the exchange
+    pub id: Option<String>,
+}
+
+impl Record {
+    /// Returns the currency a trade is settled in, which is the quote currency or the fee
+    /// currency for the futures of ftx, which have no quote currency.
+    pub fn settlement_currency(&self) -> Option<&String> {
+        self.quote_currency.as_ref().or(self.fee_currency.as_ref())
+    }
+
+    /// Returns the fee of a trade in its settlement currency, or `None` if it's in another
+    /// currency.
+    pub fn fee_in_quote(&self, price: Decimal) -> Option<Decimal> {
+        match &self.fee_currency {
+            Some(c) if Some(c) == self.base_currency.as_ref() => Some(self.fee * price),
+            Some(c) if Some(c) != self.settlement_currency() => None,
+            _ => Some(self.fee),
+        }
+    }
+}
+
+/// Returns the markets of the records which have a funding payment. They are derivatives even
+/// if their records have both currencies.
+pub fn derivative_markets(records: &[Record]) -> BTreeSet<String> {
+    records
+        .iter()
+        .filter(|r| r.typ == Some(FillType::Funding))
+        .filter_map(|r| r.market.clone())
+        .collect()
+}
+
+/// Reads the records of a daily file of the date, with the columns used by the reports.
+pub async fn read_records(path: &Path, date: NaiveDate) -> Result<Vec<Record>> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = csv_async::AsyncReader::from_reader(file);
+    let headers = reader.headers().await?.clone();
+    let column = |names: &[&str]| {
+        headers
+            .iter()
+            .position(|h| names.contains(&h))
+            .ok_or_else(|| anyhow!("no {} column in {}", names[0], path.display()))
+    };
+    let (time, market, base, quote, side, price, size, fee, fee_currency, typ) = (
+        column(&["timestamp", "time"])?,
+        column(&["market"])?,
+        // the raw schema is in camel case
+        column(&["base_currency", "baseCurrency"])?,
+        column(&["quote_currency", "quoteCurrency"])?,
+        column(&["side"])?,
+        column(&["price"])?,
+        column(&["size"])?,
+        column(&["fee"])?,
+        column(&["fee_currency", "feeCurrency"])?,
+        column(&["type"])?,
+    );
+    let liquidity = headers.iter().position(|h| h == "liquidity");
+    let id = headers.iter().position(|h| h == "native_id" || h == "id");
+    reader
+        .records()
+        .map_err(anyhow::Error::from)
+        .and_then(|r| async move {
+            let text = |i: usize| r.get(i).filter(|s| !s.is_empty());
+            let string = |i: usize| text(i).map(str::to_string);
+            // the values are written with a comma by --decimal-separator comma
+            let decimal = |i: usize| {
+                text(i)
+                    .map(|s| s.replace(',', ".").parse::<Decimal>())
+                    .transpose()
+            };
+            Ok(Record {
+                date,
+                time: text(time)
+                    .ok_or_else(|| anyhow!("a record without the time"))?
+                    .parse()?,
+                market: string(market),
+                base_currency: string(base),
+                quote_currency: string(quote),
+                side: text(side).and_then(|s| s.parse().ok()),
+                price: decimal(price)?,
+                size: decimal(size)?.unwrap_or_default(),
+                fee: decimal(fee)?.unwrap_or_default(),
+                fee_currency: string(fee_currency),
+                typ: text(typ).map(FillType::from),
+                liquidity: liquidity.and_then(text).and_then(|s| s.parse().ok()),
+                id: id.and_then(string),
+            })
+        })
+        .try_collect()
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Reads the records of the daily files of the dates in `range`, ordered by time.
+pub async fn read_range(
+    outdir: &Path,
+    account: &str,
+    range: impl RangeBounds<NaiveDate>,
+) -> Result<Vec<Record>> {
+    let mut records = vec![];
+    for (date, path) in files(outdir, account).range(range) {
+        records.extend(read_records(path, *date).await?);
+    }
+    if records.is_empty() {
+        info!("no downloaded fill is found");
+    }
+    // stable so that the fills of the same time keep the order of the file
+    records.sort_by_key(|r| r.time);
+    Ok(records)
+}
+
+/// Reads the records of a daily file back into fills, e.g. to write them into another sink.
+///
+/// Columns which the schema of the file doesn't have, e.g. the liquidity in the unified schema,
+/// are left empty.
+pub async fn read_fills(path: &Path) -> Result<Vec<Fill>> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = csv_async::AsyncReader::from_reader(file);
+    let headers = reader.headers().await?.clone();
+    // the raw schema is in camel case
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h));
+    let required = |names: &[&str]| {
+        column(names).ok_or_else(|| anyhow!("no {} column in {}", names[0], path.display()))
+    };
+    let (time, id) = (
+        required(&["timestamp", "time"])?,
+        required(&["native_id", "id"])?,
+    );
+    let optional = |name: &str| column(&[name, &camel_case(name)]);
+    let (
+        fee,
+        fee_currency,
+        fee_rate,
+        future,
+        liquidity,
+        market,
+        base,
+        quote,
+        order_id,
+        trade_id,
+        price,
+        side,
+        size,
+        typ,
+        instrument_kind,
+        strike,
+        option_type,
+        index_price,
+        converted_currency,
+        converted_fee,
+        converted_notional,
+    ) = (
+        optional("fee"),
+        optional("fee_currency"),
+        optional("fee_rate"),
+        optional("future"),
+        optional("liquidity"),
+        optional("market"),
+        optional("base_currency"),
+        optional("quote_currency"),
+        optional("order_id"),
+        optional("trade_id"),
+        optional("price"),
+        optional("side"),
+        optional("size"),
+        optional("type"),
+        optional("instrument_kind"),
+        optional("strike"),
+        optional("option_type"),
+        optional("index_price"),
+        optional("converted_currency"),
+        optional("converted_fee"),
+        optional("converted_notional"),
+    );
+    reader
+        .records()
+        .map_err(anyhow::Error::from)
+        .and_then(|r| async move {
+            let text = |i: Option<usize>| i.and_then(|i| r.get(i)).filter(|s| !s.is_empty());
+            let string = |i: Option<usize>| text(i).map(str::to_string);
+            // the values are written with a comma by --decimal-separator comma
+            let decimal = |i: Option<usize>| {
+                text(i)
+                    .map(|s| s.replace(',', ".").parse::<Decimal>())
+                    .transpose()
+            };
+            Ok(Fill {
+                fee: decimal(fee)?.unwrap_or_default(),
+                fee_currency: string(fee_currency),
+                fee_rate: decimal(fee_rate)?,
+                future: string(future),
+                id: text(Some(id)).unwrap_or_default().into(),
+                liquidity: text(liquidity).and_then(|s| s.parse().ok()),
+                market: string(market),
+                base_currency: string(base),
+                quote_currency: string(quote),
+                order_id: text(order_id).map(Into::into),
+                trade_id: string(trade_id),
+                price: decimal(price)?,
+                side: text(side).and_then(|s| s.parse().ok()),
+                size: decimal(size)?.unwrap_or_default(),
+                time: text(Some(time))
+                    .ok_or_else(|| anyhow!("a record without the time"))?
+                    .parse::<DateTime<FixedOffset>>()?
+                    .with_timezone(&Local),
+                typ: text(typ).map(FillType::from),
+                instrument_kind: string(instrument_kind),
+                strike: decimal(strike)?,
+                option_type: string(option_type),
+                index_price: decimal(index_price)?,
+                conversion: match string(converted_currency) {
+                    Some(converted_currency) => Some(Conversion {
+                        converted_currency,
+                        converted_fee: decimal(converted_fee)?,
+                        converted_notional: decimal(converted_notional)?,
+                    }),
+                    None => None,
+                },
+            })
+        })
+        .try_collect()
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+// e.g. fee_currency to feeCurrency
+fn camel_case(name: &str) -> String {
+    let mut words = name.split('_');
+    let first = words.next().unwrap_or_default().to_string();
+    words.fold(first, |mut camel, word| {
+        let mut chars = word.chars();
+        camel.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        camel.push_str(chars.as_str());
+        camel
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // the header of the unified schema
+    const HEADER: &str = "exchange,account,market,base_currency,quote_currency,side,price,size,fee,fee_currency,timestamp,native_id,type";
+
+    /// Writes the rows of the unified schema into the daily file of `main` of the date.
+    pub(crate) fn write_file(outdir: &Path, date: NaiveDate, rows: &[&str]) {
+        let mut content = format!("{}\n", HEADER);
+        for row in rows {
+            content.push_str(row);
+            content.push('\n');
+        }
+        std::fs::write(path(outdir, "main", date), content).unwrap();
+    }
+
+    fn trade(base: Option<&str>, quote: Option<&str>, fee_currency: &str) -> Record {
+        Record {
+            date: NaiveDate::from_ymd(2022, 1, 1),
+            time: "2022-01-01T00:00:00Z".parse().unwrap(),
+            market: Some("BTC/USD".into()),
+            base_currency: base.map(Into::into),
+            quote_currency: quote.map(Into::into),
+            side: Some(Side::Buy),
+            price: Some(Decimal::from(40000)),
+            size: Decimal::ONE,
+            fee: "0.001".parse().unwrap(),
+            fee_currency: Some(fee_currency.into()),
+            typ: Some(FillType::Order),
+            liquidity: None,
+            id: Some("1".into()),
+        }
+    }
+
+    #[test]
+    fn converts_the_fee_into_the_settlement_currency() {
+        let price = Decimal::from(40000);
+        let spot = |fee_currency| trade(Some("BTC"), Some("USD"), fee_currency);
+        assert_eq!(
+            spot("USD").fee_in_quote(price),
+            Some("0.001".parse().unwrap())
+        );
+        assert_eq!(spot("BTC").fee_in_quote(price), Some(Decimal::from(40)));
+        assert_eq!(spot("FTT").fee_in_quote(price), None);
+        // the futures of ftx have no currencies but the fee currency
+        let perp = trade(None, None, "USD");
+        assert_eq!(perp.settlement_currency(), Some(&"USD".to_string()));
+        assert_eq!(perp.fee_in_quote(price), Some("0.001".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn reads_the_files_of_the_range_ordered_by_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (
+            NaiveDate::from_ymd(2022, 1, 1),
+            NaiveDate::from_ymd(2022, 1, 2),
+        );
+        write_file(
+            dir.path(),
+            first,
+            &[
+                "ftx,main,BTC/USD,BTC,USD,sell,40000,0.1,0.4,USD,2022-01-01T12:00:00Z,2,order",
+                "ftx,main,BTC/USD,BTC,USD,buy,39000,0.1,0.39,USD,2022-01-01T06:00:00Z,1,order",
+            ],
+        );
+        write_file(
+            dir.path(),
+            second,
+            &["ftx,main,ETH-PERP,,,buy,3000,1,0.3,USD,2022-01-02T00:00:00Z,3,order"],
+        );
+        let ids = |records: Vec<Record>| {
+            records
+                .into_iter()
+                .map(|r| r.id.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(read_range(dir.path(), "main", ..).await.unwrap()),
+            ["1", "2", "3"]
+        );
+        assert_eq!(
+            ids(read_range(dir.path(), "main", ..second).await.unwrap()),
+            ["1", "2"]
+        );
+        let perp = &read_range(dir.path(), "main", second..).await.unwrap()[0];
+        assert_eq!(perp.date, second);
+        assert_eq!(perp.quote_currency, None);
+        assert_eq!(perp.fee_currency.as_deref(), Some("USD"));
+    }
+}
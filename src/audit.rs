@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use chrono::{Duration, Utc};
+use log::*;
+use rust_decimal::Decimal;
+
+use crate::exchange::{for_each_fill, Exchange, OrderId, TimeRange};
+
+/// Downloads the orders and the fills of the range and reports the orders whose filled size
+/// doesn't match the total size of their fills, which means fills are missing.
+///
+/// Orders created just after the start of the range may be reported if they were partially
+/// filled before it.
+pub async fn audit<E: Exchange>(exchange: &E, range: &TimeRange) -> Result<()> {
+    let orders = exchange.fetch_orders(range).await?;
+
+    // orders created in the range may be filled a bit after its end
+    let fills_range = TimeRange {
+        start: range.start,
+        end: (range.end + Duration::days(1)).min(Utc::now().naive_utc()),
+    };
+    let mut filled: HashMap<OrderId, Decimal> = HashMap::new();
+    for_each_fill(exchange, &fills_range, |fill| {
+        if let Some(order_id) = fill.order_id {
+            *filled.entry(order_id).or_default() += fill.size;
+        }
+    })
+    .await?;
+
+    let mut mismatches = 0;
+    for order in &orders {
+        let total = filled.get(&order.id).copied().unwrap_or_default();
+        if total != order.filled_size {
+            mismatches += 1;
+            warn!(
+                "order {} of {} created at {}: {} filled but the fills sum up to {}",
+                order.id,
+                order.market.as_deref().unwrap_or("unknown market"),
+                order.created_at,
+                order.filled_size,
+                total,
+            );
+        }
+    }
+    if mismatches > 0 {
+        bail!(
+            "{} of {} orders don't match their fills",
+            mismatches,
+            orders.len()
+        );
+    }
+    info!("all {} orders match their fills", orders.len());
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, DecimalSeparator, FillType, Localized};
+use crate::timezone::Timezone;
+
+/// A length of a candle, e.g. `1m`, `15m`, `4h` and `1d`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolution(Duration);
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid resolution: {}. specify a positive number followed by s, m, h or d (e.g. 15m)",
+                s
+            )
+        };
+        let (n, unit) = s.split_at(s.len().saturating_sub(1));
+        let n: i64 = n.parse().map_err(|_| invalid())?;
+        if n <= 0 {
+            return Err(invalid());
+        }
+        match unit {
+            "s" => Ok(Resolution(Duration::seconds(n))),
+            "m" => Ok(Resolution(Duration::minutes(n))),
+            "h" => Ok(Resolution(Duration::hours(n))),
+            "d" => Ok(Resolution(Duration::days(n))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Resolution {
+    /// Returns the length of a candle.
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+
+    /// Returns the start of the candle of the time. Candles which divide a day start at
+    /// midnight.
+    fn start_of(&self, time: NaiveDateTime) -> NaiveDateTime {
+        let secs = time.timestamp();
+        let start = secs - secs.rem_euclid(self.0.num_seconds());
+        NaiveDateTime::from_timestamp(start, 0)
+    }
+}
+
+/// A candle of the fill prices of a market.
+#[derive(Serialize)]
+pub struct Candle {
+    // the start in the timezone of the report
+    pub time: NaiveDateTime,
+    pub market: String,
+    #[serde(serialize_with = "ser_decimal")]
+    pub open: Decimal,
+    #[serde(serialize_with = "ser_decimal")]
+    pub high: Decimal,
+    #[serde(serialize_with = "ser_decimal")]
+    pub low: Decimal,
+    #[serde(serialize_with = "ser_decimal")]
+    pub close: Decimal,
+    // the sum of the sizes of both sides
+    #[serde(serialize_with = "ser_decimal")]
+    pub volume: Decimal,
+    pub trades: u64,
+}
+
+/// Builds candles of the fills of the archive in the range per market and writes them into
+/// `<account>.candles.csv` of `outdir`. Only candles with fills are written.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    resolution: Resolution,
+    timezone: Timezone,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let records = match start {
+        Some(start) => archive::read_range(outdir, account, start..end).await?,
+        None => archive::read_range(outdir, account, ..end).await?,
+    };
+
+    let mut candles: BTreeMap<(String, NaiveDateTime), Candle> = BTreeMap::new();
+    for record in records {
+        let (Some(market), Some(price)) = (record.market, record.price) else {
+            continue;
+        };
+        if record.side.is_none()
+            || !matches!(
+                record.typ,
+                None | Some(FillType::Order | FillType::Liquidation)
+            )
+        {
+            continue;
+        }
+        let time = resolution.start_of(timezone.local(&record.time));
+        let candle = candles
+            .entry((market.clone(), time))
+            .or_insert_with(|| Candle {
+                time,
+                market,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume: Decimal::ZERO,
+                trades: 0,
+            });
+        candle.high = candle.high.max(price);
+        candle.low = candle.low.min(price);
+        candle.close = price;
+        candle.volume += record.size.abs();
+        candle.trades += 1;
+    }
+
+    let path = outdir.join(format!("{}.candles.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    for mut candle in candles.into_values() {
+        candle.volume = candle.volume.normalize();
+        writer
+            .serialize(Localized(&candle, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!("the candles are written into {}", path.display());
+    Ok(path)
+}
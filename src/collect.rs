@@ -0,0 +1,689 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDate, TimeZone, Utc};
+use futures::TryStreamExt;
+use glob::{MatchOptions, Pattern};
+use log::*;
+use rust_decimal::Decimal;
+use tokio_util::sync::CancellationToken;
+
+use crate::convert::Converter;
+use crate::error::{CollectorError, Interrupted, Partial, Usage};
+use crate::exchange::{
+    for_each_fill, request_count, retry_with, Cursor, DecimalSeparator, Exchange, Fill, Progress,
+    TimeRange, Unauthorized,
+};
+pub use crate::exchange::{Liquidity, Side};
+use crate::index::Index;
+use crate::manifest::{self, FailedWindow};
+use crate::progress::DownloadBar;
+use crate::sink::{self, Sink, SinkOptions};
+use crate::state::{self, State};
+use crate::timezone::Timezone;
+use crate::{preview, summary};
+
+/// Where and how downloaded fills are written.
+pub struct Output<'a> {
+    pub dir: &'a Path,
+    pub account: &'a str,
+    // writes all columns instead of the unified schema
+    pub raw: bool,
+    // continues the interrupted download
+    pub resume: bool,
+    // only counts the fills without writing files
+    pub dry_run: bool,
+    // prints this many records instead of writing files
+    pub preview: Option<usize>,
+    // doesn't download the days already complete in the manifest
+    pub skip_existing: bool,
+    // skips the days which fail to be downloaded instead of aborting
+    pub continue_on_error: bool,
+    // fills are split into files by the date in this timezone
+    pub timezone: Timezone,
+    // writes only the fills of the markets matching any of them if not empty
+    pub markets: &'a [Pattern],
+    // writes only the fills of the side if specified
+    pub side: Option<Side>,
+    // writes only the fills of the liquidity if specified
+    pub liquidity: Option<Liquidity>,
+    // writes only the records of the types if not empty
+    pub types: &'a [String],
+    // drops the fills whose notional is less than this. records without a price are kept
+    pub min_notional: Option<Decimal>,
+    // stops after the page in which this many records are written in the run
+    pub limit: Option<u64>,
+    // stops after this many requests are sent
+    pub limit_requests: Option<u64>,
+    // stops after writing the page being fetched when cancelled
+    pub cancel: CancellationToken,
+    // the name of the sink `collect` writes into, csv or one added by `sink::register`
+    pub sink: &'a str,
+    // sets the fee and the notional of each fill in another currency if specified
+    pub convert: Option<&'a Converter>,
+    // of the numbers in the files
+    pub decimal_separator: DecimalSeparator,
+}
+
+impl Output<'_> {
+    pub fn is_limit_reached(&self) -> bool {
+        self.limit.is_some_and(|limit| summary::records() >= limit)
+            || self
+                .limit_requests
+                .is_some_and(|limit| request_count() >= limit)
+    }
+
+    pub fn selects(&self, fill: &Fill) -> bool {
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        let market = self.markets.is_empty()
+            || fill
+                .market
+                .as_deref()
+                .is_some_and(|market| self.markets.iter().any(|p| p.matches_with(market, options)));
+        let typ = self.types.is_empty()
+            || fill.typ.as_ref().is_some_and(|typ| {
+                self.types
+                    .iter()
+                    .any(|t| t.eq_ignore_ascii_case(typ.as_str()))
+            });
+        let notional = match (self.min_notional, fill.price) {
+            // an overflowing notional is never dust
+            (Some(min), Some(price)) => price
+                .checked_mul(fill.size)
+                .is_none_or(|notional| notional.abs() >= min),
+            _ => true,
+        };
+        market
+            && typ
+            && notional
+            // records without the side or the liquidity never match
+            && self.side.is_none_or(|side| fill.side == Some(side))
+            && self
+                .liquidity
+                .is_none_or(|liquidity| fill.liquidity == Some(liquidity))
+    }
+}
+
+/// Downloads the fills of the range and writes them into the sink of `output`, the daily csv
+/// files by default.
+pub async fn collect<E: Exchange>(
+    exchange: &E,
+    range: &TimeRange,
+    output: &Output<'_>,
+) -> Result<(), CollectorError> {
+    let options = SinkOptions {
+        dir: output.dir,
+        account: output.account,
+        exchange: exchange.name(),
+        raw: output.raw,
+        decimal_separator: output.decimal_separator,
+    };
+    let mut sink = sink::open(output.sink, options)?;
+    collect_into(exchange, range, output, &mut sink).await
+}
+
+/// Downloads the fills of the range and writes them into `sink`. The state, the index and the
+/// manifest are still kept in the output directory.
+pub async fn collect_into<E: Exchange, S: Sink>(
+    exchange: &E,
+    range: &TimeRange,
+    output: &Output<'_>,
+    sink: &mut S,
+) -> Result<(), CollectorError> {
+    download(exchange, range, output, sink)
+        .await
+        .map_err(CollectorError::from)
+}
+
+async fn download<E: Exchange, S: Sink>(
+    exchange: &E,
+    range: &TimeRange,
+    output: &Output<'_>,
+    sink: &mut S,
+) -> Result<()> {
+    let Output {
+        dir: outdir,
+        account,
+        resume,
+        dry_run,
+        continue_on_error,
+        timezone,
+        ..
+    } = *output;
+    if dry_run {
+        return count(exchange, range, output).await;
+    }
+    if let Some(n) = output.preview {
+        return preview::preview(exchange, range, output, n).await;
+    }
+    let state_path = &state::path(outdir, account);
+    let saved = if resume {
+        State::<E::Cursor>::load(state_path)
+            .await
+            .with_context(|| "failed to read the state file")?
+    } else {
+        if state_path.exists() {
+            warn!("an interrupted download is found. specify --resume to continue it");
+        }
+        None
+    };
+    let (first_cursor, current, failed) = match saved {
+        Some(state) if state.exchange != exchange.name() => {
+            bail!(Usage(format!(
+                "the state file is of {} but {} is specified",
+                state.exchange,
+                exchange.name()
+            )));
+        }
+        Some(State {
+            cursor,
+            file,
+            failed,
+            ..
+        }) => {
+            info!("resuming the interrupted download");
+            if let Some(file) = &file {
+                sink.reopen(file).await?;
+            }
+            (cursor, file.map(|f| f.date), failed)
+        }
+        None => {
+            if resume {
+                warn!("no interrupted download is found so starting from the beginning");
+            }
+            (exchange.first_cursor(range), None, vec![])
+        }
+    };
+
+    let index = Index::load(outdir, account)
+        .await
+        .with_context(|| "failed to load the index of written fills")?;
+    let bar = &DownloadBar::new(range);
+    let limited = &AtomicBool::new(false);
+    let folded = futures::stream::try_unfold(
+        (Some(first_cursor), 0, Progress::default()),
+        |(cursor, failures, mut progress)| async move {
+            // None after the oldest day of the range is skipped
+            let cursor = match cursor {
+                Some(cursor) => cursor,
+                None => return Ok(None),
+            };
+            if output.cancel.is_cancelled() {
+                bail!(Interrupted);
+            }
+            if output.is_limit_reached() {
+                limited.store(true, Ordering::SeqCst);
+                return Ok(None);
+            }
+            let page = tokio::select! {
+                page = retry_with(exchange.max_retries(), || exchange.fetch_page(range, cursor.clone())) => page,
+                // the records fetched so far are already written with the state
+                _ = output.cancel.cancelled() => bail!(Interrupted),
+            };
+            match page {
+                Ok(Some((records, next))) => {
+                    progress.check(&cursor, &next)?;
+                    anyhow::Ok(Some((
+                        (records, next.clone(), None),
+                        (Some(next), 0, progress),
+                    )))
+                }
+                Ok(None) => Ok(None),
+                Err(e)
+                    if continue_on_error
+                        && !is_fatal(&e)
+                        && failures < MAX_CONSECUTIVE_FAILURES =>
+                {
+                    let (window, next) = skip_day(range, timezone, &cursor, &e);
+                    warn!(
+                        "skipping {} - {} since it failed to be downloaded: {:#}",
+                        window.start, window.end, e
+                    );
+                    let reached_start = range.start.is_some_and(|s| next.end_time() <= s);
+                    Ok(Some((
+                        (vec![], next.clone(), Some(window)),
+                        ((!reached_start).then_some(next), failures + 1, progress),
+                    )))
+                }
+                Err(e) => Err(e.context("failed to request")),
+            }
+        },
+    )
+    .try_fold(
+        (&mut *sink, current, index, 0, failed),
+        |(sink, mut current, mut index, mut skipped, mut failed), (records, next, window)| async move {
+            failed.extend(window);
+            let mut written = 0;
+            for record in records {
+                let mut fill = exchange.to_fill(record);
+                if !output.selects(&fill) {
+                    continue;
+                }
+                let fill_date = timezone.date(&fill.time);
+                // ids are unique only within an exchange
+                let key = format!("{}:{}", exchange.name(), fill.id);
+                if let Some(written) = index.written_elsewhere(&key, fill_date) {
+                    debug!("{} is already written to the file of {}", key, written);
+                    skipped += 1;
+                    continue;
+                }
+                if let Some(converter) = output.convert {
+                    fill.conversion = Some(converter.convert(&fill).await?);
+                }
+                // date is changed or nothing is written yet
+                if current != Some(fill_date) {
+                    sink.rotate(fill_date).await?;
+                    current = Some(fill_date);
+                }
+                sink.write_record(&fill).await?;
+                summary::add(&fill, fill_date);
+                index.insert(key, fill_date);
+                written += 1;
+            }
+            bar.update(next.end_time(), written);
+
+            // save where to continue from after all records of the page are written
+            let file = sink.flush().await?;
+            index
+                .save()
+                .await
+                .with_context(|| "failed to save the index of written fills")?;
+            let state = State {
+                exchange: exchange.name().to_string(),
+                cursor: next,
+                file,
+                failed,
+            };
+            state
+                .save(state_path)
+                .await
+                .with_context(|| "failed to save the state file")?;
+            anyhow::Ok((sink, current, index, skipped, state.failed))
+        },
+    )
+    .await
+    .map(|(_, _, _, skipped, failed)| (skipped, failed));
+    // the written records are flushed even when the download is stopped
+    let finalized = sink.finalize().await;
+    let (skipped, failed) = folded?;
+    finalized?;
+    bar.finish();
+    if skipped > 0 {
+        warn!(
+            "{} fills are skipped since they are already written to files of other dates",
+            skipped
+        );
+    }
+
+    if limited.load(Ordering::SeqCst) {
+        // the state is kept and the manifest isn't written since the range isn't complete
+        info!(
+            "stopped after {} records and {} requests by the limit. specify --resume to continue",
+            summary::records(),
+            request_count()
+        );
+        return Ok(());
+    }
+
+    // the download is completed so there is nothing to resume
+    State::<E::Cursor>::remove(state_path)
+        .await
+        .with_context(|| "failed to remove the state file")?;
+    let failed_count = failed.len();
+    manifest::write(outdir, account, range, failed)
+        .await
+        .with_context(|| "failed to write the manifest")?;
+    if failed_count > 0 {
+        bail!(Partial(format!(
+            "{} days failed to be downloaded. they are recorded in the manifest and can be downloaded again with `gaps --backfill`",
+            failed_count
+        )));
+    }
+    Ok(())
+}
+
+// the exchange is likely to be unreachable if this many days in a row fail
+const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+
+// errors which would happen again on any other days
+fn is_fatal(e: &anyhow::Error) -> bool {
+    e.chain()
+        .any(|c| c.is::<Interrupted>() || c.is::<Unauthorized>() || c.is::<Usage>())
+}
+
+/// Returns the day in `timezone` of the end of the page pointed by `cursor` as a failed window,
+/// and the cursor to continue from. The window is the whole day since downloading a part of a day
+/// again replaces the file of the day.
+fn skip_day<C: Cursor>(
+    range: &TimeRange,
+    timezone: Timezone,
+    cursor: &C,
+    e: &anyhow::Error,
+) -> (FailedWindow, C) {
+    // the end is exclusive
+    let last = Utc.from_utc_datetime(&(cursor.end_time() - chrono::Duration::nanoseconds(1)));
+    let date = timezone.date(&last);
+    let (day_start, day_end) = (timezone.start_of(date), timezone.start_of(date.succ()));
+    (
+        FailedWindow {
+            start: Utc.from_utc_datetime(&day_start),
+            end: Utc.from_utc_datetime(&day_end),
+            error: format!("{:#}", e),
+        },
+        cursor.with_end_time(range.start.map_or(day_start, |s| s.max(day_start))),
+    )
+}
+
+/// Splits the range into the ranges of the days which are not complete in the manifest.
+pub async fn pending_ranges(range: &TimeRange, output: &Output<'_>) -> Result<Vec<TimeRange>> {
+    let tz = output.timezone;
+    let complete = manifest::complete_dates(output.dir, output.account)
+        .await
+        .with_context(|| "failed to read the manifest")?;
+    let mut ranges = vec![];
+    let mut start = range.start;
+    let mut skipped = 0;
+    for date in complete {
+        let (day_start, day_end) = (
+            tz.start_of(date),
+            tz.start_of(date + chrono::Duration::days(1)),
+        );
+        if !range.is_after_start(&day_start) || range.end < day_end {
+            continue;
+        }
+        if start.map(|s| s < day_start).unwrap_or(true) {
+            ranges.push(TimeRange {
+                start,
+                end: day_start,
+            });
+        }
+        start = Some(day_end);
+        skipped += 1;
+    }
+    if start.map(|s| s < range.end).unwrap_or(true) {
+        ranges.push(TimeRange {
+            start,
+            end: range.end,
+        });
+    }
+    info!("skipping {} days already complete", skipped);
+    Ok(ranges)
+}
+
+/// Logs the number of fills of each day.
+async fn count<E: Exchange>(exchange: &E, range: &TimeRange, output: &Output<'_>) -> Result<()> {
+    let mut counts = BTreeMap::<NaiveDate, usize>::new();
+    for_each_fill(exchange, range, |fill| {
+        if output.selects(&fill) {
+            *counts.entry(output.timezone.date(&fill.time)).or_default() += 1;
+        }
+    })
+    .await?;
+    for (date, count) in &counts {
+        info!("{}: {} records", date, count);
+    }
+    info!(
+        "{} records in {} days",
+        counts.values().sum::<usize>(),
+        counts.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive;
+    use crate::exchange::ftx::{Ftx, FtxCredential};
+    use crate::exchange::mock::MockTransport;
+
+    fn ftx(transport: MockTransport) -> Ftx {
+        Ftx::builder()
+            .credential(FtxCredential::new("key".into(), "secret".into()))
+            .transport(transport)
+            .build()
+            .unwrap()
+    }
+
+    fn output(dir: &Path, timezone: Timezone) -> Output<'_> {
+        Output {
+            dir,
+            account: "main",
+            raw: false,
+            resume: false,
+            dry_run: false,
+            preview: None,
+            skip_existing: false,
+            continue_on_error: false,
+            timezone,
+            markets: &[],
+            side: None,
+            liquidity: None,
+            types: &[],
+            min_notional: None,
+            limit: None,
+            limit_requests: None,
+            cancel: CancellationToken::new(),
+            sink: "csv",
+            convert: None,
+            decimal_separator: DecimalSeparator::Dot,
+        }
+    }
+
+    // the days of the fixtures
+    fn range() -> TimeRange {
+        TimeRange {
+            start: Some(NaiveDate::from_ymd(2022, 1, 1).and_hms(0, 0, 0)),
+            end: NaiveDate::from_ymd(2022, 1, 3).and_hms(0, 0, 0),
+        }
+    }
+
+    async fn ids(dir: &Path) -> Vec<(NaiveDate, String)> {
+        let mut ids = archive::read_range(dir, "main", ..)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.date, r.id.unwrap()))
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn writes_all_pages_into_the_daily_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = MockTransport::ftx();
+        let requests = transport.requests();
+        collect(
+            &ftx(transport),
+            &range(),
+            &output(dir.path(), "UTC".parse().unwrap()),
+        )
+        .await
+        .unwrap();
+
+        // each page ends at the second after the oldest fill of the previous one
+        let end_times = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|url| url.path() == "/api/fills")
+            .filter_map(|url| url.query_pairs().find(|(k, _)| k == "end_time"))
+            .map(|(_, v)| v.into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            end_times,
+            ["1641168000", "1641081601", "1641029401", "1640995206"]
+        );
+        let ids = ids(dir.path()).await;
+        let first = NaiveDate::from_ymd(2022, 1, 1);
+        let second = NaiveDate::from_ymd(2022, 1, 2);
+        assert_eq!(
+            ids,
+            [
+                (first, "1001"),
+                (first, "1002"),
+                (first, "1003"),
+                (first, "1004"),
+                (second, "1005"),
+                (second, "1006"),
+                (second, "1007"),
+                (second, "1008"),
+            ]
+            .map(|(date, id)| (date, id.to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn backfills_an_unaligned_window_without_losing_the_rest_of_the_days() {
+        let dir = tempfile::tempdir().unwrap();
+        let utc: Timezone = "UTC".parse().unwrap();
+        collect(
+            &ftx(MockTransport::ftx()),
+            &range(),
+            &output(dir.path(), utc),
+        )
+        .await
+        .unwrap();
+        let written = ids(dir.path()).await;
+
+        // e.g. recorded by an older version
+        let window = FailedWindow {
+            start: Utc.ymd(2022, 1, 1).and_hms(12, 0, 0),
+            end: Utc.ymd(2022, 1, 2).and_hms(12, 0, 0),
+            error: "timed out".into(),
+        };
+        let backfill = window.range(utc);
+        assert_eq!(backfill.start, range().start);
+        assert_eq!(backfill.end, range().end);
+        collect(
+            &ftx(MockTransport::ftx()),
+            &backfill,
+            &output(dir.path(), utc),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(dir.path()).await, written);
+    }
+
+    #[test]
+    fn skips_the_whole_day_of_the_failed_page() {
+        let tokyo: Timezone = "+09:00".parse().unwrap();
+        let range = range();
+        // 2022-01-02T21:00 in +09:00
+        let cursor = ftx(MockTransport::new()).first_cursor(&TimeRange {
+            start: range.start,
+            end: NaiveDate::from_ymd(2022, 1, 2).and_hms(12, 0, 0),
+        });
+        let (window, next) = skip_day(&range, tokyo, &cursor, &anyhow::anyhow!("timed out"));
+        assert_eq!(window.start, Utc.ymd(2022, 1, 1).and_hms(15, 0, 0));
+        assert_eq!(window.end, Utc.ymd(2022, 1, 2).and_hms(15, 0, 0));
+        assert_eq!(
+            next.end_time(),
+            NaiveDate::from_ymd(2022, 1, 1).and_hms(15, 0, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_the_fills_already_in_the_file_of_another_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let utc: Timezone = "UTC".parse().unwrap();
+        collect(
+            &ftx(MockTransport::ftx()),
+            &range(),
+            &output(dir.path(), utc),
+        )
+        .await
+        .unwrap();
+        let written = ids(dir.path()).await;
+
+        // 1004 is on 2022-01-02 in +09:00 but already in the file of 2022-01-01
+        let tokyo: Timezone = "+09:00".parse().unwrap();
+        let second = NaiveDate::from_ymd(2022, 1, 2);
+        let range = TimeRange {
+            start: Some(tokyo.start_of(second)),
+            end: tokyo.start_of(second + chrono::Duration::days(1)),
+        };
+        collect(
+            &ftx(MockTransport::ftx()),
+            &range,
+            &output(dir.path(), tokyo),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(dir.path()).await, written);
+    }
+
+    async fn pending(
+        range: &TimeRange,
+        output: &Output<'_>,
+    ) -> Vec<(Option<chrono::NaiveDateTime>, chrono::NaiveDateTime)> {
+        pending_ranges(range, output)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.start, r.end))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn skips_the_days_complete_in_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let day = |d| NaiveDate::from_ymd(2022, 1, d);
+        let at = |d, h| day(d).and_hms(h, 0, 0);
+        let row = "ftx,main,BTC/USD,BTC,USD,buy,40000,0.01,0.0004,USD,2022-01-01T00:00:00Z,1,order";
+        for d in [2, 4, 5] {
+            archive::tests::write_file(dir.path(), day(d), &[row]);
+        }
+        let all = TimeRange {
+            start: Some(at(1, 0)),
+            end: at(8, 0),
+        };
+        manifest::write(dir.path(), "main", &all, vec![])
+            .await
+            .unwrap();
+        // modified after the manifest
+        archive::tests::write_file(dir.path(), day(5), &[row, row]);
+
+        let utc = output(dir.path(), "UTC".parse().unwrap());
+        assert_eq!(
+            pending(&all, &utc).await,
+            [
+                (Some(at(1, 0)), at(2, 0)),
+                (Some(at(3, 0)), at(4, 0)),
+                (Some(at(5, 0)), at(8, 0)),
+            ]
+        );
+        // the days partly in the range are downloaded
+        let partial = TimeRange {
+            start: Some(at(2, 12)),
+            end: at(4, 12),
+        };
+        assert_eq!(
+            pending(&partial, &utc).await,
+            [(partial.start, partial.end)]
+        );
+        // a range of complete days only is skipped
+        let complete = TimeRange {
+            start: Some(at(4, 0)),
+            end: at(5, 0),
+        };
+        assert!(pending(&complete, &utc).await.is_empty());
+
+        // the days are of the timezone
+        let tokyo: Timezone = "+09:00".parse().unwrap();
+        let range = TimeRange {
+            start: Some(tokyo.start_of(day(1))),
+            end: tokyo.start_of(day(3)),
+        };
+        assert_eq!(
+            pending(&range, &output(dir.path(), tokyo)).await,
+            [(range.start, tokyo.start_of(day(2)))]
+        );
+    }
+}
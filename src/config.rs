@@ -0,0 +1,91 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use toml::Value;
+
+use ftx_history::error::Usage;
+
+/// Returns the command line arguments with the options of the file specified by `--config`
+/// inserted before them, so that the ones in the command line override them.
+///
+/// Keys of the file are the long names of the options, e.g. `sub-account = "foo"` or
+/// `sub_account = "foo"`. `true` is given as a flag and arrays are joined with commas.
+pub fn args(command: &clap::Command) -> Result<Vec<OsString>> {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let path = match config_path(&args) {
+        Some(path) => path,
+        None => return Ok(args),
+    };
+    let table = read(Path::new(&path))?;
+
+    let mut options = vec![];
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+        if name == "config" || !command.get_arguments().any(|a| a.get_long() == Some(&name)) {
+            bail!(Usage(format!("unknown option in {}: {}", path, key)));
+        }
+        let flag = format!("--{}", name);
+        let in_command_line = args.iter().any(|a| {
+            a.to_str()
+                .map(|a| a == flag || a.starts_with(&format!("{}=", flag)))
+                .unwrap_or(false)
+        });
+        if in_command_line {
+            continue;
+        }
+        match value {
+            Value::Boolean(true) => options.push(flag.into()),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                let values = values.iter().map(to_arg).collect::<Result<Vec<_>>>()?;
+                options.push(flag.into());
+                options.push(values.join(",").into());
+            }
+            value => {
+                options.push(flag.into());
+                options.push(to_arg(&value)?.into());
+            }
+        }
+    }
+    // right after the program name since options must come before the subcommand
+    args.splice(1..1, options);
+    Ok(args)
+}
+
+fn config_path(args: &[OsString]) -> Option<String> {
+    let args = args.iter().filter_map(|a| a.to_str()).collect::<Vec<_>>();
+    args.iter()
+        .enumerate()
+        .find_map(|(i, a)| match a.strip_prefix("--config") {
+            Some("") => args.get(i + 1).map(|p| p.to_string()),
+            Some(rest) => rest.strip_prefix('=').map(|p| p.to_string()),
+            None => None,
+        })
+}
+
+fn read(path: &Path) -> Result<toml::value::Table> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read the config file {}", path.display()))?;
+    match text
+        .parse::<Value>()
+        .with_context(|| format!("failed to parse the config file {}", path.display()))?
+    {
+        Value::Table(table) => Ok(table),
+        _ => bail!("the config file {} is not a table", path.display()),
+    }
+}
+
+fn to_arg(value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Datetime(d) => d.to_string(),
+        _ => bail!(Usage(format!(
+            "unsupported value in the config file: {}",
+            value
+        ))),
+    })
+}
@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::{FutureExt, TryStreamExt};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tokio::fs::File;
+
+use crate::exchange::ftx::FtxMarkets;
+use crate::exchange::{Conversion, Fill};
+
+/// Where the rates are looked up.
+enum Source {
+    // the public hourly candles of ftx
+    Ftx(FtxMarkets),
+    // the rates of each currency by the time they are valid from
+    Table(HashMap<String, BTreeMap<DateTime<Utc>, Decimal>>),
+}
+
+/// Converts the fees and the notionals of records into a currency at the time of each record.
+pub struct Converter {
+    currency: String,
+    source: Source,
+    // by the currency and the hour, so that a candle is requested once
+    cache: Mutex<HashMap<(String, i64), Option<Decimal>>>,
+}
+
+#[derive(Deserialize)]
+struct RateRow {
+    time: DateTime<Utc>,
+    currency: String,
+    // the value of a unit of the currency
+    rate: Decimal,
+}
+
+impl Converter {
+    /// Converts with the prices of the markets of ftx, e.g. `BTC/USD`. A currency without a
+    /// market of the target currency is converted through USD.
+    pub fn ftx(currency: &str, markets: FtxMarkets) -> Self {
+        Self {
+            currency: currency.to_uppercase(),
+            source: Source::Ftx(markets),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Converts with the rates of a csv file of `time,currency,rate`, where `rate` is the value
+    /// of a unit of the currency in the target currency from the time until the next rate.
+    pub async fn from_csv(currency: &str, path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let rows: Vec<RateRow> = csv_async::AsyncDeserializer::from_reader(file)
+            .deserialize()
+            .try_collect()
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut rates: HashMap<String, BTreeMap<DateTime<Utc>, Decimal>> = HashMap::new();
+        for row in rows {
+            rates
+                .entry(row.currency.to_uppercase())
+                .or_default()
+                .insert(row.time, row.rate);
+        }
+        Ok(Self {
+            currency: currency.to_uppercase(),
+            source: Source::Table(rates),
+            cache: Mutex::default(),
+        })
+    }
+
+    /// Converts the fee and the notional of the fill at its time.
+    pub async fn convert(&self, fill: &Fill) -> Result<Conversion> {
+        let time = fill.time.with_timezone(&Utc);
+        let fee = match &fill.fee_currency {
+            Some(currency) => match self.rate(currency, time).await? {
+                Some(rate) => Some(checked_mul(&[fill.fee, rate])?),
+                None => None,
+            },
+            None => None,
+        };
+        let notional = match (fill.price, fill.settlement_currency()) {
+            (Some(price), Some(currency)) => match self.rate(currency, time).await? {
+                Some(rate) => Some(checked_mul(&[price, fill.size.abs(), rate])?),
+                None => None,
+            },
+            _ => None,
+        };
+        Ok(Conversion {
+            converted_currency: self.currency.clone(),
+            converted_fee: fee.map(|f| f.normalize()),
+            converted_notional: notional.map(|n| n.normalize()),
+        })
+    }
+
+    /// Returns the value of a unit of the currency in the target currency at the time.
+    pub(crate) async fn rate(
+        &self,
+        currency: &str,
+        time: DateTime<Utc>,
+    ) -> Result<Option<Decimal>> {
+        let currency = currency.to_uppercase();
+        if currency == self.currency {
+            return Ok(Some(Decimal::ONE));
+        }
+        match &self.source {
+            Source::Table(rates) => Ok(rates
+                .get(&currency)
+                .and_then(|r| r.range(..=time).next_back())
+                .map(|(_, rate)| *rate)),
+            Source::Ftx(markets) => {
+                let key = (currency.clone(), time.timestamp().div_euclid(3600));
+                if let Some(rate) = self.cache.lock().unwrap().get(&key) {
+                    return Ok(*rate);
+                }
+                let rate = ftx_rate(markets, &currency, &self.currency, time).await?;
+                self.cache.lock().unwrap().insert(key, rate);
+                Ok(rate)
+            }
+        }
+    }
+}
+
+// fails as a value which can't be parsed instead of panicking on an overflow
+fn checked_mul(values: &[Decimal]) -> Result<Decimal> {
+    values
+        .iter()
+        .try_fold(Decimal::ONE, |product, v| product.checked_mul(*v))
+        .ok_or(rust_decimal::Error::ExceedsMaximumPossibleValue)
+        .with_context(|| {
+            format!(
+                "failed to convert {}",
+                values
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" * ")
+            )
+        })
+}
+
+/// Looks up the rate in the market of the pair, its inverse, or through USD.
+fn ftx_rate<'a>(
+    markets: &'a FtxMarkets,
+    from: &'a str,
+    to: &'a str,
+    time: DateTime<Utc>,
+) -> BoxFuture<'a, Result<Option<Decimal>>> {
+    async move {
+        if from == to {
+            return Ok(Some(Decimal::ONE));
+        }
+        let price = |market: String| async move {
+            markets
+                .price_at(&market, time)
+                .await
+                .with_context(|| format!("failed to get the price of {} at {}", market, time))
+        };
+        if let Some(price) = price(format!("{}/{}", from, to)).await? {
+            return Ok(Some(price));
+        }
+        if let Some(price) = price(format!("{}/{}", to, from)).await? {
+            return Ok((!price.is_zero()).then(|| Decimal::ONE / price));
+        }
+        if from == "USD" || to == "USD" {
+            return Ok(None);
+        }
+        let (Some(from_usd), Some(to_usd)) = (
+            ftx_rate(markets, from, "USD", time).await?,
+            ftx_rate(markets, to, "USD", time).await?,
+        ) else {
+            return Ok(None);
+        };
+        Ok((!to_usd.is_zero()).then(|| from_usd / to_usd))
+    }
+    .boxed()
+}
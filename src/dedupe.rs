@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use csv_async::{AsyncReader, AsyncWriter, StringRecord};
+use futures::TryStreamExt;
+use log::*;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::index::Index;
+use crate::timezone::Timezone;
+
+/// A daily file read into memory.
+struct DailyFile {
+    date: NaiveDate,
+    path: PathBuf,
+    headers: StringRecord,
+    // with the keys of the index
+    rows: Vec<(String, StringRecord)>,
+}
+
+/// Removes the records of the account whose ids appear more than once in the archive and
+/// rewrites the files, e.g. of overlapping runs in different timezones.
+///
+/// The record in the file of its date in `timezone` is kept, or the first one if no file is of
+/// its date. `exchange` makes the keys for the files of the raw schema, which has no exchange
+/// column. Only the duplicates are reported when `dry_run`.
+pub async fn dedupe(
+    outdir: &Path,
+    account: &str,
+    exchange: &str,
+    timezone: Timezone,
+    dry_run: bool,
+) -> Result<()> {
+    let mut files = vec![];
+    for (date, path) in archive::files(outdir, account) {
+        files.push(read(date, path, exchange).await?);
+    }
+
+    // the file and the row of the record kept for each key, and whether it's in the file of its
+    // date
+    let mut kept: HashMap<&str, (usize, usize, bool)> = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        for (j, (key, row)) in file.rows.iter().enumerate() {
+            let in_date = date_of(file, row, timezone)? == Some(file.date);
+            match kept.get(key.as_str()) {
+                Some((_, _, true)) => {}
+                Some((_, _, false)) if !in_date => {}
+                _ => {
+                    kept.insert(key, (i, j, in_date));
+                }
+            }
+        }
+    }
+
+    let is_kept = |i: usize, j: usize, key: &str| {
+        let (file, row, _) = kept[key];
+        (file, row) == (i, j)
+    };
+    let mut removed = vec![0; files.len()];
+    for (i, file) in files.iter().enumerate() {
+        for (j, (key, _)) in file.rows.iter().enumerate() {
+            if !is_kept(i, j, key) {
+                debug!(
+                    "{} in {} is a duplicate of the one in {}",
+                    key,
+                    file.path.display(),
+                    files[kept[key.as_str()].0].path.display()
+                );
+                removed[i] += 1;
+            }
+        }
+    }
+    let total: usize = removed.iter().sum();
+    if total == 0 {
+        info!("no duplicated record is found in {} files", files.len());
+        return Ok(());
+    }
+    for (file, removed) in files.iter().zip(&removed) {
+        if *removed > 0 {
+            info!(
+                "{}: {} of {} records are duplicates",
+                file.path.display(),
+                removed,
+                file.rows.len()
+            );
+        }
+    }
+    if dry_run {
+        info!("{} duplicated records are found", total);
+        return Ok(());
+    }
+
+    let mut index = Index::load(outdir, account)
+        .await
+        .with_context(|| "failed to load the index of written fills")?;
+    for (i, file) in files.iter().enumerate() {
+        if removed[i] == 0 {
+            continue;
+        }
+        let rows: Vec<_> = file
+            .rows
+            .iter()
+            .enumerate()
+            .filter(|(j, (key, _))| is_kept(i, *j, key))
+            .map(|(_, (_, row))| row)
+            .collect();
+        if rows.is_empty() {
+            tokio::fs::remove_file(&file.path)
+                .await
+                .with_context(|| format!("failed to remove {}", file.path.display()))?;
+            info!(
+                "{} is removed since all records are duplicates",
+                file.path.display()
+            );
+            continue;
+        }
+        let mut writer = AsyncWriter::from_writer(
+            File::create(&file.path)
+                .await
+                .with_context(|| format!("failed to create {}", file.path.display()))?,
+        );
+        writer
+            .write_record(&file.headers)
+            .await
+            .with_context(|| format!("failed to write {}", file.path.display()))?;
+        for row in rows {
+            writer
+                .write_record(row)
+                .await
+                .with_context(|| format!("failed to write {}", file.path.display()))?;
+        }
+        writer
+            .flush()
+            .await
+            .with_context(|| format!("failed to write {}", file.path.display()))?;
+    }
+    // the index points to the files the kept records are in
+    for (key, (i, _, _)) in &kept {
+        index.insert(key.to_string(), files[*i].date);
+    }
+    index
+        .save()
+        .await
+        .with_context(|| "failed to save the index of written fills")?;
+    info!("{} duplicated records are removed", total);
+    Ok(())
+}
+
+async fn read(date: NaiveDate, path: PathBuf, exchange: &str) -> Result<DailyFile> {
+    let file = File::open(&path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = AsyncReader::from_reader(file);
+    let headers = reader.headers().await?.clone();
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h));
+    let id = column(&["native_id", "id"])
+        .ok_or_else(|| anyhow!("no id column in {}", path.display()))?;
+    let exchange_column = column(&["exchange"]);
+    let records: Vec<StringRecord> = reader
+        .records()
+        .try_collect()
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let rows = records
+        .into_iter()
+        .map(|row| {
+            // ids are unique only within an exchange
+            let key = format!(
+                "{}:{}",
+                exchange_column.and_then(|i| row.get(i)).unwrap_or(exchange),
+                row.get(id).unwrap_or_default()
+            );
+            (key, row)
+        })
+        .collect();
+    Ok(DailyFile {
+        date,
+        path,
+        headers,
+        rows,
+    })
+}
+
+/// Returns the date of the record in the timezone.
+fn date_of(file: &DailyFile, row: &StringRecord, timezone: Timezone) -> Result<Option<NaiveDate>> {
+    let Some(column) = file
+        .headers
+        .iter()
+        .position(|h| h == "timestamp" || h == "time")
+    else {
+        return Ok(None);
+    };
+    let time: DateTime<FixedOffset> = row
+        .get(column)
+        .unwrap_or_default()
+        .parse()
+        .with_context(|| format!("invalid time in {}", file.path.display()))?;
+    Ok(Some(timezone.date(&time)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 1, day)
+    }
+
+    fn row(time: &str, id: &str) -> String {
+        format!("ftx,main,BTC/USD,BTC,USD,buy,40000,0.01,0.0004,USD,{time},{id},order")
+    }
+
+    fn ids(dir: &Path, date: NaiveDate) -> Option<Vec<String>> {
+        let content = std::fs::read_to_string(archive::path(dir, "main", date)).ok()?;
+        Some(
+            content
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').nth(11).unwrap().to_string())
+                .collect(),
+        )
+    }
+
+    // 1 is in 2022-01-01 and 2 is in 2022-01-02 in UTC, while both are in 2022-01-02 in +09:00
+    fn write_overlapping(dir: &Path) {
+        let (first, second) = (
+            row("2022-01-01T23:00:00Z", "1"),
+            row("2022-01-02T01:00:00Z", "2"),
+        );
+        write_file(dir, date(1), &[&first, &second]);
+        write_file(dir, date(2), &[&second, &first]);
+        write_file(dir, date(3), &[&second]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_records_in_the_files_of_their_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        write_overlapping(dir.path());
+        dedupe(dir.path(), "main", "ftx", "UTC".parse().unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(ids(dir.path(), date(1)).unwrap(), ["1"]);
+        assert_eq!(ids(dir.path(), date(2)).unwrap(), ["2"]);
+        // all records are duplicates
+        assert_eq!(ids(dir.path(), date(3)), None);
+        let index = Index::load(dir.path(), "main").await.unwrap();
+        assert_eq!(index.written_elsewhere("ftx:1", date(2)), Some(date(1)));
+        assert_eq!(index.written_elsewhere("ftx:2", date(1)), Some(date(2)));
+
+        let dir = tempfile::tempdir().unwrap();
+        write_overlapping(dir.path());
+        dedupe(dir.path(), "main", "ftx", "+09:00".parse().unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(ids(dir.path(), date(1)), None);
+        assert_eq!(ids(dir.path(), date(2)).unwrap(), ["2", "1"]);
+        assert_eq!(ids(dir.path(), date(3)), None);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_first_record_if_none_is_in_the_file_of_its_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let row = row("2022-01-05T00:00:00Z", "1");
+        write_file(dir.path(), date(1), &[&row]);
+        write_file(dir.path(), date(2), &[&row]);
+        dedupe(dir.path(), "main", "ftx", "UTC".parse().unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(ids(dir.path(), date(1)).unwrap(), ["1"]);
+        assert_eq!(ids(dir.path(), date(2)), None);
+    }
+
+    #[tokio::test]
+    async fn only_reports_the_duplicates_in_a_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        write_overlapping(dir.path());
+        dedupe(dir.path(), "main", "ftx", "UTC".parse().unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(ids(dir.path(), date(1)).unwrap(), ["1", "2"]);
+        assert_eq!(ids(dir.path(), date(2)).unwrap(), ["2", "1"]);
+        assert_eq!(ids(dir.path(), date(3)).unwrap(), ["2"]);
+        assert!(!dir.path().join("main.index.csv").exists());
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, Utc};
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive::{self, Record};
+use crate::convert::Converter;
+use crate::exchange::{ser_decimal, DecimalSeparator, FillType, Localized, Side};
+use crate::pnl::{CostMethod, Position};
+
+/// The equity of the account at the end of a day.
+#[derive(Serialize)]
+pub struct DailyEquity {
+    pub date: NaiveDate,
+    pub currency: String,
+    // the balances and the unrealized pnl of the open positions
+    #[serde(serialize_with = "ser_decimal")]
+    pub equity: Decimal,
+    // the deposits and the transfers in minus the withdrawals and the transfers out so far, each
+    // valued at the end of its day
+    #[serde(serialize_with = "ser_decimal")]
+    pub net_deposits: Decimal,
+    // equity - net_deposits
+    #[serde(serialize_with = "ser_decimal")]
+    pub pnl: Decimal,
+    // the space separated currencies held without a price, which are excluded from the equity
+    pub unpriced: String,
+}
+
+/// A derivative market replayed from the fills.
+#[derive(Default)]
+struct Derivative {
+    position: Position,
+    last_price: Decimal,
+    // where the pnl is settled
+    currency: Option<String>,
+}
+
+/// Replays the fills, the funding payments, the deposits, the withdrawals and the transfers of
+/// the archive into the balances of the account, and writes its equity in `currency` at the end
+/// of each day into `<account>.equity.csv` of `outdir`, e.g. to plot the performance.
+///
+/// A currency is valued with `rates` if it has a rate, or else at the last price of its spot
+/// market against `currency` in the archive. The open derivative positions add their pnl at the
+/// last fill price. All files are read since the balances come from the whole history, and only
+/// the days in the range are written. The days are the ones of the files, from the first to the last without a gap.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    currency: &str,
+    rates: Option<&Converter>,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let currency = currency.to_uppercase();
+    let records = archive::read_range(outdir, account, ..end).await?;
+
+    let derivatives = archive::derivative_markets(&records);
+    let mut balances: HashMap<String, Decimal> = HashMap::new();
+    let mut markets: HashMap<String, Derivative> = HashMap::new();
+    // the last price of each spot pair of (base, quote)
+    let mut prices: HashMap<(String, String), Decimal> = HashMap::new();
+    let mut net_deposits = Decimal::ZERO;
+    let mut unpriced_deposits = BTreeSet::new();
+    let mut days = vec![];
+
+    let last = records.iter().map(|r| r.date).max();
+    let mut records = records.into_iter().peekable();
+    let mut date = records.peek().map(|r| r.date);
+    let mut now = records.peek().map(|r| r.time.with_timezone(&Utc));
+    while let (Some(day), Some(time)) = (date, now) {
+        if day >= end || last.is_some_and(|last| day > last) {
+            break;
+        }
+        // the amounts of each currency deposited in the day
+        let mut deposits: HashMap<String, Decimal> = HashMap::new();
+        while let Some(record) = records.next_if(|r| r.date <= day) {
+            now = Some(record.time.with_timezone(&Utc));
+            apply(
+                &record,
+                &derivatives,
+                &mut balances,
+                &mut markets,
+                &mut prices,
+                &mut deposits,
+            );
+        }
+        let time = now.unwrap_or(time);
+
+        let mut values: HashMap<String, Option<Decimal>> = HashMap::new();
+        let mut value = async |held: &str| -> Result<Option<Decimal>> {
+            if let Some(value) = values.get(held) {
+                return Ok(*value);
+            }
+            let rate = match rates {
+                Some(rates) => rates.rate(held, time).await?,
+                None => None,
+            };
+            let rate = rate.or_else(|| price_of(&prices, held, &currency));
+            values.insert(held.to_string(), rate);
+            Ok(rate)
+        };
+        for (held, amount) in deposits {
+            match value(&held).await? {
+                Some(rate) => net_deposits += amount * rate,
+                None if !amount.is_zero() => {
+                    unpriced_deposits.insert(held);
+                }
+                None => {}
+            }
+        }
+        let mut amounts = balances.clone();
+        for market in markets.values() {
+            if let (Some(settled), Some(average)) =
+                (&market.currency, market.position.average_price())
+            {
+                *amounts.entry(settled.clone()).or_default() +=
+                    market.position.size() * (market.last_price - average);
+            }
+        }
+        let mut equity = Decimal::ZERO;
+        let mut unpriced = BTreeSet::new();
+        for (held, amount) in amounts {
+            if amount.is_zero() {
+                continue;
+            }
+            match value(&held).await? {
+                Some(rate) => equity += amount * rate,
+                None => {
+                    unpriced.insert(held);
+                }
+            }
+        }
+        if start.is_none_or(|start| start <= day) {
+            days.push(DailyEquity {
+                date: day,
+                currency: currency.clone(),
+                equity: equity.round_dp(8).normalize(),
+                net_deposits: net_deposits.round_dp(8).normalize(),
+                pnl: (equity - net_deposits).round_dp(8).normalize(),
+                unpriced: unpriced.into_iter().collect::<Vec<_>>().join(" "),
+            });
+        }
+        date = day.succ_opt();
+    }
+    if !unpriced_deposits.is_empty() {
+        warn!(
+            "deposits of {} are excluded from net_deposits since they have no price in {}. \
+             specify --rates",
+            unpriced_deposits.into_iter().collect::<Vec<_>>().join(", "),
+            currency
+        );
+    }
+
+    let path = outdir.join(format!("{}.equity.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    for day in &days {
+        writer
+            .serialize(Localized(day, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    if let Some(last) = days.last() {
+        info!(
+            "equity at {}: {} {}, pnl {} {}",
+            last.date, last.equity, currency, last.pnl, currency
+        );
+    }
+    info!("the equity is written into {}", path.display());
+    Ok(path)
+}
+
+/// Adds the record into the balances, the positions and the deposits of the day.
+fn apply(
+    record: &Record,
+    derivatives: &BTreeSet<String>,
+    balances: &mut HashMap<String, Decimal>,
+    markets: &mut HashMap<String, Derivative>,
+    prices: &mut HashMap<(String, String), Decimal>,
+    deposits: &mut HashMap<String, Decimal>,
+) {
+    let mut add = |currency: &Option<String>, amount: Decimal| {
+        if let Some(currency) = currency {
+            *balances.entry(currency.clone()).or_default() += amount;
+        }
+    };
+    match (&record.typ, record.side, record.price) {
+        (Some(FillType::Deposit), _, _) => {
+            add(&record.base_currency, record.size.abs());
+            if let Some(currency) = &record.base_currency {
+                *deposits.entry(currency.clone()).or_default() += record.size.abs();
+            }
+        }
+        (Some(FillType::Withdrawal), _, _) => {
+            // the fee of a withdrawal is in the currency withdrawn
+            add(&record.base_currency, -(record.size.abs() + record.fee));
+            if let Some(currency) = &record.base_currency {
+                *deposits.entry(currency.clone()).or_default() -= record.size.abs();
+            }
+        }
+        // signed, in or out of the account
+        (Some(FillType::Transfer), _, _) => {
+            add(&record.base_currency, record.size);
+            if let Some(currency) = &record.base_currency {
+                *deposits.entry(currency.clone()).or_default() += record.size;
+            }
+        }
+        // the amount is in the base currency of the record, e.g. USDC on dYdX
+        (Some(FillType::Funding), _, _) => {
+            let currency = record.base_currency.clone().or(record.fee_currency.clone());
+            add(&currency, record.size);
+        }
+        (None | Some(FillType::Order | FillType::Liquidation), Some(side), Some(price)) => {
+            let Some(market) = &record.market else {
+                return;
+            };
+            let size = match side {
+                Side::Buy => record.size.abs(),
+                Side::Sell => -record.size.abs(),
+            };
+            add(&record.fee_currency, -record.fee);
+            match (&record.base_currency, &record.quote_currency) {
+                (Some(base), Some(quote)) if !derivatives.contains(market) => {
+                    add(&record.base_currency, size);
+                    add(&record.quote_currency, -size * price);
+                    prices.insert((base.clone(), quote.clone()), price);
+                }
+                _ => {
+                    let derivative = markets.entry(market.clone()).or_default();
+                    let realized = derivative.position.fill(CostMethod::Average, size, price);
+                    derivative.last_price = price;
+                    if derivative.currency.is_none() {
+                        derivative.currency = record.settlement_currency().cloned();
+                    }
+                    add(&derivative.currency.clone(), realized);
+                }
+            }
+        }
+        // the others don't change the balances
+        _ => {}
+    }
+}
+
+/// Returns the last price of a unit of `held` in `currency` of their spot market.
+fn price_of(
+    prices: &HashMap<(String, String), Decimal>,
+    held: &str,
+    currency: &str,
+) -> Option<Decimal> {
+    if held == currency {
+        return Some(Decimal::ONE);
+    }
+    if let Some(price) = prices.get(&(held.to_string(), currency.to_string())) {
+        return Some(*price);
+    }
+    prices
+        .get(&(currency.to_string(), held.to_string()))
+        .filter(|p| !p.is_zero())
+        .map(|p| Decimal::ONE / p)
+}
@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use crate::exchange::{InvalidCredential, InvalidResponse, RateLimited, Transient, Unauthorized};
+
+/// Exit codes of the process, which tell what kind of failure stopped the download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    // an unexpected failure, e.g. an error returned by the exchange
+    Other = 1,
+    // the credential is rejected
+    Auth = 2,
+    // the exchange can't be reached even after retries
+    Network = 3,
+    // a response or a file has an unexpected format
+    Parse = 4,
+    // a file can't be read or written
+    Io = 5,
+    // some days or subaccounts failed while the others are downloaded
+    Partial = 10,
+    // invalid arguments, EX_USAGE of sysexits.h
+    Usage = 64,
+    // stopped by SIGINT or SIGTERM
+    Interrupted = 130,
+}
+
+impl ExitCode {
+    pub fn of(e: &anyhow::Error) -> Self {
+        // the error returned by the library is classified by what it wraps
+        if let Some(e) = e.chain().find_map(|c| c.downcast_ref::<CollectorError>()) {
+            return Self::of(e.error());
+        }
+        let any = |f: fn(&(dyn Error + 'static)) -> bool| e.chain().any(f);
+        if any(|c| c.is::<Interrupted>()) {
+            ExitCode::Interrupted
+        } else if any(|c| c.is::<Usage>()) {
+            ExitCode::Usage
+        } else if any(|c| c.is::<Partial>()) {
+            ExitCode::Partial
+        } else if any(|c| c.is::<Unauthorized>() || c.is::<InvalidCredential>()) {
+            ExitCode::Auth
+        } else if any(|c| c.is::<Transient>() || c.is::<RateLimited>()) {
+            ExitCode::Network
+        } else if any(|c| {
+            c.is::<serde_json::Error>()
+                || c.is::<toml::de::Error>()
+                || c.is::<rust_decimal::Error>()
+        }) {
+            ExitCode::Parse
+        } else if any(|c| c.is::<std::io::Error>() || c.is::<csv_async::Error>()) {
+            ExitCode::Io
+        } else {
+            ExitCode::Other
+        }
+    }
+}
+
+/// A failure of the library classified by its kind, so that the caller can tell what to do
+/// about it. Each variant keeps the error with its context.
+#[derive(Debug, thiserror::Error)]
+pub enum CollectorError {
+    /// The credential is rejected by the exchange or can't sign requests.
+    #[error("{0:#}")]
+    Auth(anyhow::Error),
+    /// The exchange keeps limiting requests even after retries.
+    #[error("{0:#}")]
+    RateLimited(anyhow::Error),
+    /// The exchange can't be reached even after retries.
+    #[error("{0:#}")]
+    Network(anyhow::Error),
+    /// A response or a file has an unexpected format. `body` is the response body if a response
+    /// can't be parsed.
+    #[error("{error:#}")]
+    Parse {
+        body: Option<String>,
+        error: anyhow::Error,
+    },
+    /// A file can't be read or written.
+    #[error("{0:#}")]
+    Io(anyhow::Error),
+    /// Invalid options, e.g. a state file of another exchange is resumed.
+    #[error("{0:#}")]
+    Usage(anyhow::Error),
+    /// The download is stopped by a signal.
+    #[error("{0:#}")]
+    Interrupted(anyhow::Error),
+    /// Some days failed while the others are downloaded.
+    #[error("{0:#}")]
+    Partial(anyhow::Error),
+    /// Any other failure, e.g. an error returned by the exchange.
+    #[error("{0:#}")]
+    Other(anyhow::Error),
+}
+
+impl CollectorError {
+    /// Returns the underlying error.
+    pub fn error(&self) -> &anyhow::Error {
+        match self {
+            CollectorError::Auth(e)
+            | CollectorError::RateLimited(e)
+            | CollectorError::Network(e)
+            | CollectorError::Parse { error: e, .. }
+            | CollectorError::Io(e)
+            | CollectorError::Usage(e)
+            | CollectorError::Interrupted(e)
+            | CollectorError::Partial(e)
+            | CollectorError::Other(e) => e,
+        }
+    }
+
+    pub fn into_error(self) -> anyhow::Error {
+        match self {
+            CollectorError::Auth(e)
+            | CollectorError::RateLimited(e)
+            | CollectorError::Network(e)
+            | CollectorError::Parse { error: e, .. }
+            | CollectorError::Io(e)
+            | CollectorError::Usage(e)
+            | CollectorError::Interrupted(e)
+            | CollectorError::Partial(e)
+            | CollectorError::Other(e) => e,
+        }
+    }
+}
+
+impl From<anyhow::Error> for CollectorError {
+    fn from(e: anyhow::Error) -> Self {
+        match ExitCode::of(&e) {
+            ExitCode::Interrupted => CollectorError::Interrupted(e),
+            ExitCode::Usage => CollectorError::Usage(e),
+            ExitCode::Partial => CollectorError::Partial(e),
+            ExitCode::Auth => CollectorError::Auth(e),
+            ExitCode::Network if e.chain().any(|c| c.is::<RateLimited>()) => {
+                CollectorError::RateLimited(e)
+            }
+            ExitCode::Network => CollectorError::Network(e),
+            ExitCode::Parse => CollectorError::Parse {
+                body: e
+                    .chain()
+                    .find_map(|c| c.downcast_ref::<InvalidResponse>())
+                    .map(|r| r.body.clone()),
+                error: e,
+            },
+            ExitCode::Io => CollectorError::Io(e),
+            ExitCode::Other => CollectorError::Other(e),
+        }
+    }
+}
+
+/// An error caused by invalid arguments.
+#[derive(Debug)]
+pub struct Usage(pub String);
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for Usage {}
+
+/// The download is stopped by a signal.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "interrupted. specify --resume to continue the download")
+    }
+}
+
+impl Error for Interrupted {}
+
+/// Some parts of the download failed and the rest is downloaded.
+#[derive(Debug)]
+pub struct Partial(pub String);
+
+impl Display for Partial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for Partial {}
@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use web_time::SystemTime;
+
+use crate::exchange::{
+    de_from_str, send, Exchange, Fill, FillType, Liquidity, Page, RequestExt, Side, TimeRange,
+};
+
+const BASE_URL: &str = "https://api.binance.com";
+// max number of trades returned by a single myTrades request
+const TRADES_LIMIT: usize = 1000;
+// the longest time range of myTrades
+const TRADES_WINDOW_DAYS: i64 = 1;
+// the longest time range of the deposit and the withdrawal history
+const TRANSFERS_WINDOW_DAYS: i64 = 90;
+// max number of deposits or withdrawals returned by a single request
+const TRANSFERS_LIMIT: usize = 1000;
+
+pub struct Binance {
+    credential: BinanceCredential,
+    // symbol -> (base asset, quote asset)
+    symbols: HashMap<String, (String, String)>,
+}
+
+impl Binance {
+    /// `symbols` are the markets whose trades are collected, e.g. `BTCUSDT`.
+    /// Binance has no endpoint listing the traded symbols of an account, so they must be given.
+    pub async fn new(credential: BinanceCredential, symbols: &[String]) -> Result<Self> {
+        let mut assets = HashMap::new();
+        if !symbols.is_empty() {
+            let info: BinanceExchangeInfo = send(Request::new(
+                Method::GET,
+                Url::parse_with_params(
+                    &format!("{BASE_URL}/api/v3/exchangeInfo"),
+                    &[("symbols", serde_json::to_string(symbols)?)],
+                )?,
+            ))
+            .await
+            .with_context(|| "failed to get symbol information")?;
+            for s in info.symbols {
+                assets.insert(s.symbol, (s.base_asset, s.quote_asset));
+            }
+        }
+        Ok(Self {
+            credential,
+            symbols: assets,
+        })
+    }
+
+    async fn get_trades(
+        &self,
+        symbol: &str,
+        // inclusive
+        start_time: i64,
+        // exclusive
+        end_time: i64,
+    ) -> Result<Vec<BinanceTrade>> {
+        let mut trades = vec![];
+        let mut from_id: Option<u64> = None;
+        loop {
+            let mut params = vec![
+                ("symbol", symbol.to_string()),
+                ("limit", TRADES_LIMIT.to_string()),
+            ];
+            match from_id {
+                // fromId cannot be combined with a time range
+                Some(id) => params.push(("fromId", id.to_string())),
+                None => {
+                    params.push(("startTime", start_time.to_string()));
+                    params.push(("endTime", (end_time - 1).to_string()));
+                }
+            }
+            let page: Vec<BinanceTrade> = self.get("/api/v3/myTrades", &params).await?;
+            let has_more = page.len() == TRADES_LIMIT;
+            let last = page.last().map(|t| (t.id, t.time));
+            trades.extend(page.into_iter().filter(|t| t.time < end_time));
+            match last {
+                Some((id, time)) if has_more && time < end_time => from_id = Some(id + 1),
+                _ => break,
+            }
+        }
+        Ok(trades)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<T> {
+        send(self.authenticate(Request::new(
+            Method::GET,
+            Url::parse_with_params(&format!("{BASE_URL}{path}"), params)?,
+        )))
+        .await
+    }
+
+    /// Returns the deposits or the withdrawals in the range, paging through them by offset.
+    async fn get_transfers<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        // inclusive
+        start_time: i64,
+        // exclusive
+        end_time: i64,
+    ) -> Result<Vec<T>> {
+        get_pages(|offset| async move {
+            let params = [
+                ("startTime", start_time.to_string()),
+                ("endTime", (end_time - 1).to_string()),
+                ("offset", offset.to_string()),
+                ("limit", TRANSFERS_LIMIT.to_string()),
+            ];
+            self.get(path, &params).await
+        })
+        .await
+    }
+
+    async fn get_window(
+        &self,
+        start_time: NaiveDateTime,
+        end_time: NaiveDateTime,
+    ) -> Result<Vec<BinanceRecord>> {
+        let mut records = vec![];
+        // trades are queried day by day within the window of the transfers
+        let mut trades_end = end_time;
+        while start_time < trades_end {
+            let trades_start = start_time.max(trades_end - Duration::days(TRADES_WINDOW_DAYS));
+            for symbol in self.symbols.keys() {
+                records.extend(
+                    self.get_trades(
+                        symbol,
+                        trades_start.timestamp_millis(),
+                        trades_end.timestamp_millis(),
+                    )
+                    .await?
+                    .into_iter()
+                    .map(BinanceRecord::Trade),
+                );
+            }
+            trades_end = trades_start;
+        }
+        let (start_time, end_time) = (start_time.timestamp_millis(), end_time.timestamp_millis());
+        records.extend(
+            self.get_transfers::<BinanceDeposit>(
+                "/sapi/v1/capital/deposit/hisrec",
+                start_time,
+                end_time,
+            )
+            .await?
+            .into_iter()
+            // only succeeded ones
+            .filter(|d| d.status == 1 || d.status == 6)
+            .map(BinanceRecord::Deposit),
+        );
+        records.extend(
+            self.get_transfers::<BinanceWithdrawal>(
+                "/sapi/v1/capital/withdraw/history",
+                start_time,
+                end_time,
+            )
+            .await?
+            .into_iter()
+            // only completed ones
+            .filter(|w| w.status == 6)
+            .map(BinanceRecord::Withdrawal),
+        );
+        records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+        Ok(records)
+    }
+}
+
+/// Concatenates the pages of `TRANSFERS_LIMIT` items from the offset 0 until a page isn't full.
+async fn get_pages<T, F, Fut>(mut get: F) -> Result<Vec<T>>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Vec<T>>>,
+{
+    let mut items = vec![];
+    loop {
+        let page = get(items.len()).await?;
+        let has_more = page.len() == TRANSFERS_LIMIT;
+        items.extend(page);
+        if !has_more {
+            return Ok(items);
+        }
+    }
+}
+
+impl Exchange for Binance {
+    type Cursor = NaiveDateTime;
+    type Record = BinanceRecord;
+
+    fn name(&self) -> &'static str {
+        "binance"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let query = match rb.url().query() {
+            Some(q) => format!("{q}&timestamp={ts}"),
+            None => format!("timestamp={ts}"),
+        };
+        let signature = hex::encode(hmac_sha256::HMAC::mac(&query, &self.credential.api_secret));
+        rb.url_mut()
+            .set_query(Some(&format!("{query}&signature={signature}")));
+        rb.set_header("X-MBX-APIKEY", &self.credential.api_key);
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<BinanceRecord, NaiveDateTime>>> {
+        async move {
+            // a page is a window of the transfer history, in which trades are queried by day
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(TRANSFERS_WINDOW_DAYS),
+                NaiveDate::from_ymd(2017, 7, 14).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let records = self.get_window(start_time, end_time).await?;
+            info!(
+                "{} records between {} and {}",
+                records.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((records, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, record: BinanceRecord) -> Fill {
+        match record {
+            BinanceRecord::Trade(t) => {
+                let assets = self.symbols.get(&t.symbol);
+                Fill {
+                    fee: t.commission,
+                    fee_currency: Some(t.commission_asset),
+                    fee_rate: None,
+                    future: None,
+                    // trade ids are only unique in a symbol
+                    id: format!("{}:{}", t.symbol, t.id).into(),
+                    liquidity: Some(if t.is_maker {
+                        Liquidity::Maker
+                    } else {
+                        Liquidity::Taker
+                    }),
+                    base_currency: assets.map(|(base, _)| base.clone()),
+                    quote_currency: assets.map(|(_, quote)| quote.clone()),
+                    market: Some(t.symbol),
+                    order_id: Some(t.order_id.into()),
+                    trade_id: Some(t.id.to_string()),
+                    price: Some(t.price),
+                    side: Some(if t.is_buyer { Side::Buy } else { Side::Sell }),
+                    size: t.qty,
+                    time: Local.timestamp_millis(t.time),
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                    typ: Some(FillType::Order),
+                }
+            }
+            BinanceRecord::Deposit(d) => Fill {
+                fee: Decimal::ZERO,
+                fee_currency: Some(d.coin.clone()),
+                fee_rate: None,
+                future: None,
+                id: format!("deposit:{}", d.id).into(),
+                liquidity: None,
+                market: None,
+                base_currency: Some(d.coin),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: d.amount,
+                time: Local.timestamp_millis(d.insert_time),
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+                typ: Some(FillType::Deposit),
+            },
+            BinanceRecord::Withdrawal(w) => Fill {
+                fee: w.transaction_fee,
+                fee_currency: Some(w.coin.clone()),
+                fee_rate: None,
+                future: None,
+                id: format!("withdrawal:{}", w.id).into(),
+                liquidity: None,
+                market: None,
+                base_currency: Some(w.coin),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: w.amount,
+                time: Local.from_utc_datetime(&w.apply_time),
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+                typ: Some(FillType::Withdrawal),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BinanceCredential {
+    api_key: String,
+    api_secret: String,
+}
+
+pub enum BinanceRecord {
+    Trade(BinanceTrade),
+    Deposit(BinanceDeposit),
+    Withdrawal(BinanceWithdrawal),
+}
+
+impl BinanceRecord {
+    fn time(&self) -> DateTime<Local> {
+        match self {
+            BinanceRecord::Trade(t) => Local.timestamp_millis(t.time),
+            BinanceRecord::Deposit(d) => Local.timestamp_millis(d.insert_time),
+            BinanceRecord::Withdrawal(w) => Local.from_utc_datetime(&w.apply_time),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceExchangeInfo {
+    symbols: Vec<BinanceSymbol>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceSymbol {
+    symbol: String,
+    base_asset: String,
+    quote_asset: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BinanceTrade {
+    symbol: String,
+    id: u64,
+    order_id: u64,
+    #[serde(deserialize_with = "de_from_str")]
+    price: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    qty: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    commission: Decimal,
+    commission_asset: String,
+    time: i64,
+    is_buyer: bool,
+    is_maker: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BinanceDeposit {
+    id: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amount: Decimal,
+    coin: String,
+    status: u8,
+    insert_time: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BinanceWithdrawal {
+    id: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amount: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    transaction_fee: Decimal,
+    coin: String,
+    status: u8,
+    #[serde(with = "apply_time_format")]
+    apply_time: NaiveDateTime,
+}
+
+mod apply_time_format {
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveDateTime, D::Error> {
+        NaiveDateTime::parse_from_str(&String::deserialize(d)?, "%Y-%m-%d %H:%M:%S")
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+
+    #[test]
+    fn pages_through_the_transfers_by_offset() {
+        let all = (0..TRANSFERS_LIMIT * 2 + 1).collect::<Vec<_>>();
+        let mut offsets = vec![];
+        let items = futures::executor::block_on(get_pages(|offset| {
+            offsets.push(offset);
+            let page = all.iter().skip(offset).take(TRANSFERS_LIMIT).copied();
+            futures::future::ready(Ok(page.collect()))
+        }))
+        .unwrap();
+        assert_eq!(items, all);
+        assert_eq!(offsets, [0, TRANSFERS_LIMIT, TRANSFERS_LIMIT * 2]);
+    }
+
+    #[test]
+    fn requests_another_page_only_when_one_is_full() {
+        let mut offsets = vec![];
+        let items: Vec<usize> = futures::executor::block_on(get_pages(|offset| {
+            offsets.push(offset);
+            futures::future::ready(Ok(if offset == 0 {
+                vec![0; TRANSFERS_LIMIT]
+            } else {
+                vec![]
+            }))
+        }))
+        .unwrap();
+        assert_eq!(items.len(), TRANSFERS_LIMIT);
+        assert_eq!(offsets, [0, TRANSFERS_LIMIT]);
+    }
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let binance = Binance {
+            credential: BinanceCredential {
+                api_key: "key".into(),
+                api_secret: "secret".into(),
+            },
+            symbols: HashMap::from([("BTCUSDT".into(), ("BTC".into(), "USDT".into()))]),
+        };
+        let parse = |json| serde_json::from_str::<Vec<serde_json::Value>>(json).unwrap();
+        let mut records = vec![];
+        for t in parse(include_str!("../../fixtures/binance/my_trades.json")) {
+            records.push(BinanceRecord::Trade(serde_json::from_value(t).unwrap()));
+        }
+        for d in parse(include_str!("../../fixtures/binance/deposits.json")) {
+            records.push(BinanceRecord::Deposit(serde_json::from_value(d).unwrap()));
+        }
+        for w in parse(include_str!("../../fixtures/binance/withdrawals.json")) {
+            records.push(BinanceRecord::Withdrawal(
+                serde_json::from_value(w).unwrap(),
+            ));
+        }
+        let fills = records
+            .into_iter()
+            .map(|r| binance.to_fill(r))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("binance", &fills).await,
+            [
+                "binance,main,BTCUSDT,BTC,USDT,buy,46200.50000000,0.00120000,0.00000120,BTC,2022-01-01T01:20:05.123Z,BTCUSDT:1234567,order",
+                "binance,main,BTCUSDT,BTC,USDT,sell,47000.00000000,0.00100000,0.00008811,BNB,2022-01-01T21:00:00Z,BTCUSDT:1234601,order",
+                "binance,main,,ETH,,,,0.50000000,0,ETH,2022-01-01T01:00:00Z,deposit:769800519366885376,deposit",
+                "binance,main,,USDT,,,,100.00000000,1.00000000,USDT,2022-01-02T03:04:05Z,withdrawal:b6ae22b3aa844210a7041aee7589627c,withdrawal",
+            ]
+        );
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Taker));
+        assert_eq!(fills[1].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[1].order_id.as_ref().unwrap().as_str(), "9876612");
+        assert_eq!(fills[1].trade_id.as_deref(), Some("1234601"));
+    }
+}
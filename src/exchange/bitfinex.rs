@@ -0,0 +1,437 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::exchange::{
+    charged_fee, send, Exchange, Fill, FillType, Liquidity, Nonce, Page, RequestExt, Side,
+    TimeRange,
+};
+
+const BASE_URL: &str = "https://api.bitfinex.com";
+const LIMIT: usize = 2500;
+
+pub struct Bitfinex {
+    credential: BitfinexCredential,
+    nonce: Nonce,
+}
+
+impl Bitfinex {
+    pub fn new(credential: BitfinexCredential) -> Self {
+        Self {
+            credential,
+            nonce: Nonce::default(),
+        }
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, body: serde_json::Value) -> Result<T> {
+        let mut rb = Request::new(Method::POST, Url::parse(&format!("{BASE_URL}/{path}"))?);
+        rb.set_header("Content-Type", "application/json");
+        *rb.body_mut() = Some(body.to_string().into());
+        send(self.authenticate(rb)).await
+    }
+
+    /// Fetches all entries between `start_time` and `end_time` in milliseconds.
+    ///
+    /// Bitfinex returns at most `LIMIT` entries ordered by time desc and both ends are inclusive,
+    /// so the next request ends at the oldest timestamp of the previous page and the entries on
+    /// the boundary are deduplicated by id.
+    async fn get_all<T: BitfinexEntry + DeserializeOwned>(
+        &self,
+        path: &str,
+        // inclusive
+        start_time: i64,
+        // exclusive
+        end_time: i64,
+    ) -> Result<Vec<T>> {
+        let mut entries = vec![];
+        let mut seen = HashSet::new();
+        let mut end = end_time - 1;
+        loop {
+            let page: Vec<T> = self
+                .post(
+                    path,
+                    json!({"start": start_time, "end": end, "limit": LIMIT, "sort": -1}),
+                )
+                .await?;
+            let has_more = page.len() == LIMIT;
+            let oldest = page.last().map(|e| e.mts());
+            let before = entries.len();
+            entries.extend(page.into_iter().filter(|e| seen.insert(e.id())));
+            match oldest {
+                Some(oldest) if has_more => {
+                    if entries.len() == before {
+                        bail!(
+                            "more than {} entries have the same timestamp {}",
+                            LIMIT,
+                            oldest
+                        );
+                    }
+                    end = oldest;
+                }
+                _ => break,
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl Exchange for Bitfinex {
+    type Cursor = NaiveDateTime;
+    type Record = BitfinexRecord;
+
+    fn name(&self) -> &'static str {
+        "bitfinex"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let nonce = self.nonce.next();
+        // the json body is a part of the signature
+        let body = rb
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+            .unwrap_or_default();
+        let text = format!("/api{}{}{}", rb.url().path(), nonce, body);
+        rb.set_header("bfx-nonce", nonce.to_string());
+        rb.set_header("bfx-apikey", &self.credential.api_key);
+        rb.set_header(
+            "bfx-signature",
+            hex::encode(hmac_sha512::sha384::HMAC::mac(
+                &text,
+                &self.credential.api_secret,
+            )),
+        );
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<BitfinexRecord, NaiveDateTime>>> {
+        async move {
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(30),
+                NaiveDate::from_ymd(2013, 1, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let (start_ms, end_ms) = (start_time.timestamp_millis(), end_time.timestamp_millis());
+            let mut records = self
+                .get_all::<BitfinexTrade>("v2/auth/r/trades/hist", start_ms, end_ms)
+                .await?
+                .into_iter()
+                .map(BitfinexRecord::Trade)
+                .collect::<Vec<_>>();
+            records.extend(
+                self.get_all::<BitfinexLedgerEntry>("v2/auth/r/ledgers/hist", start_ms, end_ms)
+                    .await?
+                    .into_iter()
+                    // trades and their fees are already collected as trades
+                    .filter(|e| {
+                        !e.description().starts_with("Exchange ")
+                            && !e.description().starts_with("Trading fees")
+                    })
+                    .map(BitfinexRecord::Ledger),
+            );
+            records.sort_by_key(|r| std::cmp::Reverse(r.mts()));
+            info!(
+                "{} records between {} and {}",
+                records.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((records, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, record: BitfinexRecord) -> Fill {
+        let time = Local.timestamp_millis(record.mts());
+        match record {
+            BitfinexRecord::Trade(t) => {
+                let symbol = t.symbol.trim_start_matches('t');
+                let (base, quote) = match symbol.split_once(':') {
+                    Some((b, q)) => (b, q),
+                    None if symbol.len() == 6 => symbol.split_at(3),
+                    None => (symbol, ""),
+                };
+                Fill {
+                    // bitfinex represents charged fees as negative values
+                    fee: charged_fee(t.fee),
+                    fee_currency: Some(t.fee_currency),
+                    fee_rate: None,
+                    // perpetual contracts are named like tBTCF0:USTF0
+                    future: base.ends_with("F0").then(|| symbol.to_string()),
+                    // trades and ledger entries have separate id sequences
+                    id: format!("trade:{}", t.id).into(),
+                    liquidity: Some(if t.maker {
+                        Liquidity::Maker
+                    } else {
+                        Liquidity::Taker
+                    }),
+                    market: Some(symbol.to_string()),
+                    base_currency: Some(base.to_string()),
+                    quote_currency: (!quote.is_empty()).then(|| quote.to_string()),
+                    order_id: Some(t.order_id.into()),
+                    trade_id: Some(t.id.to_string()),
+                    price: Some(t.exec_price),
+                    // executed amount is negative when selling
+                    side: Some(if t.exec_amount < Decimal::ZERO {
+                        Side::Sell
+                    } else {
+                        Side::Buy
+                    }),
+                    size: t.exec_amount.abs(),
+                    time,
+                    typ: Some(FillType::Order),
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                }
+            }
+            BitfinexRecord::Ledger(e) => {
+                let description = e.description();
+                let typ = if description.starts_with("Deposit") {
+                    FillType::Deposit
+                } else if description.starts_with("Withdrawal") {
+                    FillType::Withdrawal
+                } else if description.contains("Funding") {
+                    FillType::Funding
+                } else if description.starts_with("Transfer") {
+                    FillType::Transfer
+                } else {
+                    FillType::Other("ledger".to_string())
+                };
+                Fill {
+                    fee: Decimal::ZERO,
+                    fee_currency: Some(e.currency.clone()),
+                    fee_rate: None,
+                    future: None,
+                    id: format!("ledger:{}", e.id).into(),
+                    liquidity: None,
+                    market: None,
+                    base_currency: Some(e.currency),
+                    quote_currency: None,
+                    order_id: None,
+                    trade_id: None,
+                    price: None,
+                    side: None,
+                    size: if matches!(typ, FillType::Deposit | FillType::Withdrawal) {
+                        e.amount.abs()
+                    } else {
+                        e.amount
+                    },
+                    time,
+                    typ: Some(typ),
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BitfinexCredential {
+    api_key: String,
+    api_secret: String,
+}
+
+trait BitfinexEntry {
+    fn id(&self) -> u64;
+    fn mts(&self) -> i64;
+}
+
+pub enum BitfinexRecord {
+    Trade(BitfinexTrade),
+    Ledger(BitfinexLedgerEntry),
+}
+
+impl BitfinexRecord {
+    fn mts(&self) -> i64 {
+        match self {
+            BitfinexRecord::Trade(t) => t.mts(),
+            BitfinexRecord::Ledger(e) => e.mts(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(from = "BitfinexTradeRow")]
+pub struct BitfinexTrade {
+    id: u64,
+    symbol: String,
+    mts: i64,
+    order_id: u64,
+    // negative when selling
+    exec_amount: Decimal,
+    exec_price: Decimal,
+    maker: bool,
+    // negative when charged
+    fee: Decimal,
+    fee_currency: String,
+}
+
+/// `[ID, SYMBOL, MTS, ORDER_ID, EXEC_AMOUNT, EXEC_PRICE, ORDER_TYPE, ORDER_PRICE, MAKER, FEE,
+/// FEE_CURRENCY, CID]`
+type BitfinexTradeRow = (
+    u64,
+    String,
+    i64,
+    u64,
+    Decimal,
+    Decimal,
+    IgnoredAny,
+    IgnoredAny,
+    i64,
+    Decimal,
+    String,
+    IgnoredAny,
+);
+
+impl From<BitfinexTradeRow> for BitfinexTrade {
+    fn from(row: BitfinexTradeRow) -> Self {
+        Self {
+            id: row.0,
+            symbol: row.1,
+            mts: row.2,
+            order_id: row.3,
+            exec_amount: row.4,
+            exec_price: row.5,
+            maker: row.8 == 1,
+            fee: row.9,
+            fee_currency: row.10,
+        }
+    }
+}
+
+impl BitfinexEntry for BitfinexTrade {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn mts(&self) -> i64 {
+        self.mts
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(from = "BitfinexLedgerRow")]
+pub struct BitfinexLedgerEntry {
+    id: u64,
+    currency: String,
+    mts: i64,
+    amount: Decimal,
+    description: Option<String>,
+}
+
+/// `[ID, CURRENCY, WALLET, MTS, _, AMOUNT, BALANCE, _, DESCRIPTION]`
+type BitfinexLedgerRow = (
+    u64,
+    String,
+    IgnoredAny,
+    i64,
+    IgnoredAny,
+    Decimal,
+    IgnoredAny,
+    IgnoredAny,
+    Option<String>,
+);
+
+impl From<BitfinexLedgerRow> for BitfinexLedgerEntry {
+    fn from(row: BitfinexLedgerRow) -> Self {
+        Self {
+            id: row.0,
+            currency: row.1,
+            mts: row.3,
+            amount: row.5,
+            description: row.8,
+        }
+    }
+}
+
+impl BitfinexLedgerEntry {
+    fn description(&self) -> &str {
+        self.description.as_deref().unwrap_or_default()
+    }
+}
+
+impl BitfinexEntry for BitfinexLedgerEntry {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn mts(&self) -> i64 {
+        self.mts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let bitfinex = Bitfinex::new(BitfinexCredential {
+            api_key: "key".into(),
+            api_secret: "secret".into(),
+        });
+        let trades: Vec<BitfinexTrade> =
+            serde_json::from_str(include_str!("../../fixtures/bitfinex/trades_hist.json")).unwrap();
+        let entries: Vec<BitfinexLedgerEntry> =
+            serde_json::from_str(include_str!("../../fixtures/bitfinex/ledgers_hist.json"))
+                .unwrap();
+        let mut records = trades
+            .into_iter()
+            .map(BitfinexRecord::Trade)
+            .chain(entries.into_iter().map(BitfinexRecord::Ledger))
+            .collect::<Vec<_>>();
+        records.sort_by_key(|r| std::cmp::Reverse(r.mts()));
+        let fills = records
+            .into_iter()
+            .map(|r| bitfinex.to_fill(r))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("bitfinex", &fills).await,
+            [
+                "bitfinex,main,BTCUSD,BTC,USD,buy,46800,0.005,0.1404,USD,2022-01-02T00:00:00.512Z,trade:1092856841,order",
+                "bitfinex,main,BTCF0:USTF0,BTCF0,USTF0,sell,47010,0.01,0.94,USTF0,2022-01-01T16:00:00Z,trade:1092856842,order",
+                "bitfinex,main,,BTC,,,,0.01,0,BTC,2022-01-01T09:40:00Z,ledger:8148329783,withdrawal",
+                "bitfinex,main,TESTBTC:TESTUSD,TESTBTC,TESTUSD,buy,46941,0.1,0,TESTUSD,2022-01-01T06:53:20.100Z,trade:1092856843,order",
+                "bitfinex,main,,USTF0,,,,-0.512,0,USTF0,2022-01-01T06:00:00Z,ledger:8148329784,funding",
+                "bitfinex,main,,USD,,,,1000,0,USD,2022-01-01T00:00:00Z,ledger:8148329785,deposit",
+            ]
+        );
+        assert_eq!(fills[0].future, None);
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[1].future.as_deref(), Some("BTCF0:USTF0"));
+        assert_eq!(fills[1].liquidity, Some(Liquidity::Taker));
+        assert_eq!(
+            fills[1].order_id.as_ref().map(|id| id.as_str()),
+            Some("84393165264")
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use anyhow::{bail, Result};
+use chrono::{Duration, Local, NaiveDateTime, TimeZone, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use web_time::SystemTime;
+
+use crate::exchange::{
+    de_from_str, send, Exchange, Fill, FillType, Liquidity, Page, RequestExt, TimeRange,
+};
+
+const RECV_WINDOW: &str = "5000";
+const CATEGORIES: [&str; 4] = ["spot", "linear", "inverse", "option"];
+
+pub struct Bybit {
+    credential: BybitCredential,
+}
+
+impl Bybit {
+    pub fn new(credential: BybitCredential) -> Self {
+        Self { credential }
+    }
+
+    fn base_url(&self) -> &'static str {
+        if self.credential.testnet {
+            "https://api-testnet.bybit.com"
+        } else {
+            "https://api.bybit.com"
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, params: &[(&str, String)]) -> Result<T> {
+        let response: BybitResponse<T> = send(self.authenticate(Request::new(
+            Method::GET,
+            Url::parse_with_params(&format!("{}{}", self.base_url(), path), params)?,
+        )))
+        .await?;
+        match response.result {
+            Some(result) if response.ret_code == 0 => Ok(result),
+            _ => bail!(
+                "bybit returned an error: {} (code {})",
+                response.ret_msg,
+                response.ret_code
+            ),
+        }
+    }
+
+    async fn get_executions(
+        &self,
+        category: &str,
+        // inclusive
+        start_time: i64,
+        // exclusive
+        end_time: i64,
+    ) -> Result<Vec<BybitExecution>> {
+        let mut executions = vec![];
+        let mut cursor = None;
+        loop {
+            let mut params = vec![
+                ("category", category.to_string()),
+                ("startTime", start_time.to_string()),
+                ("endTime", (end_time - 1).to_string()),
+                ("limit", "100".to_string()),
+            ];
+            if let Some(cursor) = cursor {
+                params.push(("cursor", cursor));
+            }
+            let page: BybitExecutionList = self.get("/v5/execution/list", &params).await?;
+            executions.extend(page.list.into_iter().map(|e| BybitExecution {
+                category: category.to_string(),
+                ..e
+            }));
+            match page.next_page_cursor {
+                Some(next) if !next.is_empty() => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(executions)
+    }
+}
+
+impl Exchange for Bybit {
+    type Cursor = NaiveDateTime;
+    type Record = BybitExecution;
+
+    fn name(&self) -> &'static str {
+        "bybit"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let text = format!(
+            "{}{}{}{}",
+            ts,
+            self.credential.api_key,
+            RECV_WINDOW,
+            rb.url().query().unwrap_or_default()
+        );
+        rb.set_header("X-BAPI-API-KEY", &self.credential.api_key);
+        rb.set_header("X-BAPI-TIMESTAMP", ts.to_string());
+        rb.set_header("X-BAPI-RECV-WINDOW", RECV_WINDOW);
+        rb.set_header(
+            "X-BAPI-SIGN",
+            hex::encode(hmac_sha256::HMAC::mac(&text, &self.credential.api_secret)),
+        );
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<BybitExecution, NaiveDateTime>>> {
+        async move {
+            // executions can be queried at most 7 days at once,
+            // and bybit keeps them only for 2 years
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(7),
+                Utc::now().naive_utc() - Duration::days(730),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let mut executions = vec![];
+            for category in CATEGORIES {
+                executions.extend(
+                    self.get_executions(
+                        category,
+                        start_time.timestamp_millis(),
+                        end_time.timestamp_millis(),
+                    )
+                    .await?,
+                );
+            }
+            executions.sort_by_key(|e| std::cmp::Reverse(e.exec_time));
+            info!(
+                "{} executions between {} and {}",
+                executions.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((executions, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, e: BybitExecution) -> Fill {
+        Fill {
+            fee: e.exec_fee,
+            fee_currency: e.fee_currency.filter(|c| !c.is_empty()),
+            fee_rate: e.fee_rate.parse().ok(),
+            future: (e.category != "spot").then(|| e.symbol.clone()),
+            id: e.exec_id.into(),
+            liquidity: Some(if e.is_maker {
+                Liquidity::Maker
+            } else {
+                Liquidity::Taker
+            }),
+            market: Some(e.symbol),
+            base_currency: None,
+            quote_currency: None,
+            order_id: Some(e.order_id.into()),
+            trade_id: None,
+            price: Some(e.exec_price),
+            side: e.side.parse().ok(),
+            size: e.exec_qty,
+            time: Local.timestamp_millis(e.exec_time),
+            instrument_kind: None,
+            strike: None,
+            option_type: None,
+            index_price: None,
+            conversion: None,
+            typ: Some(match e.exec_type.as_str() {
+                "Trade" => FillType::Order,
+                "BustTrade" => FillType::Liquidation,
+                other => FillType::from(other.to_lowercase().as_str()),
+            }),
+        }
+    }
+}
+
+/// `testnet` is optional and defaults to false.
+#[derive(Deserialize)]
+pub struct BybitCredential {
+    api_key: String,
+    api_secret: String,
+    #[serde(default)]
+    testnet: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitResponse<T> {
+    ret_code: i64,
+    ret_msg: String,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitExecutionList {
+    list: Vec<BybitExecution>,
+    next_page_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BybitExecution {
+    // not a part of the response but filled after fetching
+    #[serde(default)]
+    category: String,
+    symbol: String,
+    order_id: String,
+    side: String,
+    #[serde(deserialize_with = "de_from_str")]
+    exec_price: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    exec_qty: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    exec_fee: Decimal,
+    #[serde(default)]
+    fee_currency: Option<String>,
+    // empty for some execution types
+    fee_rate: String,
+    #[serde(deserialize_with = "de_from_str")]
+    exec_time: i64,
+    exec_id: String,
+    exec_type: String,
+    is_maker: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let bybit = Bybit::new(BybitCredential {
+            api_key: "key".into(),
+            api_secret: "secret".into(),
+            testnet: false,
+        });
+        let response: BybitResponse<BybitExecutionList> =
+            serde_json::from_str(include_str!("../../fixtures/bybit/execution_list.json")).unwrap();
+        let page = response.result.unwrap();
+        assert_eq!(page.next_page_cursor.as_deref(), Some(""));
+        let fills = page
+            .list
+            .into_iter()
+            .map(|e| {
+                bybit.to_fill(BybitExecution {
+                    category: "linear".into(),
+                    ..e
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("bybit", &fills).await,
+            [
+                "bybit,main,BTCUSDT,,,sell,46800,0.005,-0.0234,,2022-01-02T00:00:00.500Z,0fb2ef89-b4ce-5079-a217-cbdce3fd5e6a,order",
+                "bybit,main,BTCUSDT,,,buy,47010.1,0.005,0.0213,,2022-01-01T16:00:00Z,9fd3b4bb-2be1-4b0f-9a2b-36e0b0c7b5a5,funding",
+                "bybit,main,BTCUSDT,,,buy,46941,0.005,0.1290825,,2022-01-01T06:53:20.123Z,5ae5c2b6-9c3e-4e47-8e8b-2c1d2f0d3c4b,order",
+            ]
+        );
+        assert_eq!(fills[0].future.as_deref(), Some("BTCUSDT"));
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[0].fee_rate, Some(Decimal::new(-1, 4)));
+        assert_eq!(fills[2].liquidity, Some(Liquidity::Taker));
+        assert_eq!(
+            fills[2].order_id.as_ref().map(|id| id.as_str()),
+            Some("c0bd2d7e-6e4e-4b6b-a1de-9e0a5b1a6c3d")
+        );
+    }
+}
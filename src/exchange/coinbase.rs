@@ -0,0 +1,425 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use futures::future::BoxFuture;
+use futures::lock::Mutex;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use web_time::SystemTime;
+
+use crate::exchange::{
+    de_from_str, send, Exchange, Fill, FillType, Liquidity, Page, RequestExt, TimeRange,
+};
+
+const BASE_URL: &str = "https://api.coinbase.com";
+// transaction types which move funds into or out of the account
+const TRANSFER_TYPES: [&str; 7] = [
+    "send",
+    "fiat_deposit",
+    "fiat_withdrawal",
+    "pro_deposit",
+    "pro_withdrawal",
+    "exchange_deposit",
+    "exchange_withdrawal",
+];
+
+pub struct Coinbase {
+    credential: CoinbaseCredential,
+    // transfers can't be queried by time, so all of them are loaded on the first page
+    transfers: Mutex<Option<Vec<CoinbaseTransaction>>>,
+}
+
+impl Coinbase {
+    pub fn new(credential: CoinbaseCredential) -> Self {
+        Self {
+            credential,
+            transfers: Mutex::new(None),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path_and_query: &str) -> Result<T> {
+        send(self.authenticate(Request::new(
+            Method::GET,
+            Url::parse(&format!("{BASE_URL}{path_and_query}"))?,
+        )))
+        .await
+    }
+
+    async fn get_fills(
+        &self,
+        // inclusive
+        start_time: NaiveDateTime,
+        // exclusive
+        end_time: NaiveDateTime,
+    ) -> Result<Vec<CoinbaseFill>> {
+        let mut fills = vec![];
+        let mut cursor = None;
+        loop {
+            let mut params = vec![
+                ("start_sequence_timestamp", rfc3339(start_time)),
+                ("end_sequence_timestamp", rfc3339(end_time)),
+                ("limit", "1000".to_string()),
+            ];
+            if let Some(cursor) = cursor {
+                params.push(("cursor", cursor));
+            }
+            let url = Url::parse_with_params(
+                &format!("{BASE_URL}/api/v3/brokerage/orders/historical/fills"),
+                &params,
+            )?;
+            let page: CoinbaseFills = self
+                .get(&format!(
+                    "{}?{}",
+                    url.path(),
+                    url.query().unwrap_or_default()
+                ))
+                .await?;
+            fills.extend(page.fills);
+            match page.cursor {
+                Some(next) if !next.is_empty() => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(fills)
+    }
+
+    /// Follows `next_uri` of a paginated v2 endpoint.
+    async fn get_all<T: DeserializeOwned>(&self, path_and_query: &str) -> Result<Vec<T>> {
+        let mut items = vec![];
+        let mut next = Some(path_and_query.to_string());
+        while let Some(uri) = next {
+            let page: CoinbasePaginated<T> = self.get(&uri).await?;
+            items.extend(page.data);
+            next = page.pagination.next_uri;
+        }
+        Ok(items)
+    }
+
+    async fn get_transfers(&self) -> Result<Vec<CoinbaseTransaction>> {
+        let mut transfers = vec![];
+        for account in self
+            .get_all::<CoinbaseAccount>("/v2/accounts?limit=100")
+            .await
+            .with_context(|| "failed to get accounts")?
+        {
+            transfers.extend(
+                self.get_all::<CoinbaseTransaction>(&format!(
+                    "/v2/accounts/{}/transactions?limit=100",
+                    account.id
+                ))
+                .await?
+                .into_iter()
+                .filter(CoinbaseTransaction::is_transfer),
+            );
+        }
+        Ok(transfers)
+    }
+}
+
+impl Exchange for Coinbase {
+    type Cursor = NaiveDateTime;
+    type Record = CoinbaseRecord;
+
+    fn name(&self) -> &'static str {
+        "coinbase"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = rb.url().path();
+        // advanced trade api signs the path without the query while v2 api signs both
+        let request_path = match rb.url().query() {
+            Some(q) if !path.starts_with("/api/v3/") => format!("{path}?{q}"),
+            _ => path.to_string(),
+        };
+        let text = format!("{}{}{}", ts, rb.method(), request_path);
+        rb.set_header("CB-ACCESS-KEY", &self.credential.api_key);
+        rb.set_header("CB-ACCESS-TIMESTAMP", ts.to_string());
+        rb.set_header(
+            "CB-ACCESS-SIGN",
+            hex::encode(hmac_sha256::HMAC::mac(&text, &self.credential.api_secret)),
+        );
+        rb.set_header("CB-VERSION", "2022-11-01");
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<CoinbaseRecord, NaiveDateTime>>> {
+        async move {
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(30),
+                NaiveDate::from_ymd(2015, 1, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let mut transfers = self.transfers.lock().await;
+            if transfers.is_none() {
+                *transfers = Some(self.get_transfers().await?);
+            }
+
+            let mut records = self
+                .get_fills(start_time, end_time)
+                .await?
+                .into_iter()
+                .map(CoinbaseRecord::Fill)
+                .collect::<Vec<_>>();
+            records.extend(
+                transfers
+                    .iter()
+                    .flatten()
+                    .filter(|t| {
+                        let time = t.created_at.naive_utc();
+                        start_time <= time && time < end_time
+                    })
+                    .cloned()
+                    .map(CoinbaseRecord::Transfer),
+            );
+            records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+            info!(
+                "{} records between {} and {}",
+                records.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((records, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, record: CoinbaseRecord) -> Fill {
+        match record {
+            CoinbaseRecord::Fill(f) => {
+                let (base, quote) = f
+                    .product_id
+                    .split_once('-')
+                    .map(|(b, q)| (Some(b.to_string()), Some(q.to_string())))
+                    .unwrap_or_default();
+                Fill {
+                    fee: f.commission,
+                    // commission is charged in the quote currency
+                    fee_currency: quote.clone(),
+                    fee_rate: None,
+                    future: None,
+                    id: f.entry_id.into(),
+                    liquidity: match f.liquidity_indicator.as_str() {
+                        "MAKER" => Some(Liquidity::Maker),
+                        "TAKER" => Some(Liquidity::Taker),
+                        _ => None,
+                    },
+                    market: Some(f.product_id),
+                    base_currency: base,
+                    quote_currency: quote,
+                    order_id: Some(f.order_id.into()),
+                    trade_id: Some(f.trade_id),
+                    price: Some(f.price),
+                    side: f.side.parse().ok(),
+                    size: if f.size_in_quote {
+                        f.size / f.price
+                    } else {
+                        f.size
+                    },
+                    time: f.trade_time.with_timezone(&Local),
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                    typ: Some(FillType::Order),
+                }
+            }
+            CoinbaseRecord::Transfer(t) => Fill {
+                fee: t
+                    .network
+                    .and_then(|n| n.transaction_fee)
+                    .map(|f| f.amount)
+                    .unwrap_or_default(),
+                fee_currency: Some(t.amount.currency.clone()),
+                fee_rate: None,
+                future: None,
+                id: t.id.into(),
+                liquidity: None,
+                market: None,
+                base_currency: Some(t.amount.currency),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: t.amount.amount.abs(),
+                time: t.created_at.with_timezone(&Local),
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+                typ: Some(if t.amount.amount < Decimal::ZERO {
+                    FillType::Withdrawal
+                } else {
+                    FillType::Deposit
+                }),
+            },
+        }
+    }
+}
+
+fn rfc3339(time: NaiveDateTime) -> String {
+    Utc.from_utc_datetime(&time)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[derive(Deserialize)]
+pub struct CoinbaseCredential {
+    api_key: String,
+    api_secret: String,
+}
+
+pub enum CoinbaseRecord {
+    Fill(CoinbaseFill),
+    Transfer(CoinbaseTransaction),
+}
+
+impl CoinbaseRecord {
+    fn time(&self) -> DateTime<Utc> {
+        match self {
+            CoinbaseRecord::Fill(f) => f.trade_time,
+            CoinbaseRecord::Transfer(t) => t.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinbaseFills {
+    fills: Vec<CoinbaseFill>,
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CoinbaseFill {
+    entry_id: String,
+    trade_id: String,
+    order_id: String,
+    trade_time: DateTime<Utc>,
+    #[serde(deserialize_with = "de_from_str")]
+    price: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    size: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    commission: Decimal,
+    product_id: String,
+    liquidity_indicator: String,
+    size_in_quote: bool,
+    side: String,
+}
+
+#[derive(Deserialize)]
+struct CoinbasePaginated<T> {
+    pagination: CoinbasePagination,
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct CoinbasePagination {
+    next_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseAccount {
+    id: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CoinbaseTransaction {
+    id: String,
+    #[serde(rename = "type")]
+    typ: String,
+    status: String,
+    amount: CoinbaseAmount,
+    created_at: DateTime<Utc>,
+    network: Option<CoinbaseNetwork>,
+}
+
+impl CoinbaseTransaction {
+    // a completed transaction which moves funds into or out of the account
+    fn is_transfer(&self) -> bool {
+        self.status == "completed" && TRANSFER_TYPES.contains(&self.typ.as_str())
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct CoinbaseAmount {
+    #[serde(deserialize_with = "de_from_str")]
+    amount: Decimal,
+    currency: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct CoinbaseNetwork {
+    transaction_fee: Option<CoinbaseAmount>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let coinbase = Coinbase::new(CoinbaseCredential {
+            api_key: "key".into(),
+            api_secret: "secret".into(),
+        });
+        let fills: CoinbaseFills =
+            serde_json::from_str(include_str!("../../fixtures/coinbase/fills.json")).unwrap();
+        let transactions: CoinbasePaginated<CoinbaseTransaction> =
+            serde_json::from_str(include_str!("../../fixtures/coinbase/transactions.json"))
+                .unwrap();
+        assert_eq!(fills.cursor.as_deref(), Some(""));
+        assert_eq!(transactions.pagination.next_uri, None);
+        let mut records = fills
+            .fills
+            .into_iter()
+            .map(CoinbaseRecord::Fill)
+            .chain(
+                transactions
+                    .data
+                    .into_iter()
+                    .filter(CoinbaseTransaction::is_transfer)
+                    .map(CoinbaseRecord::Transfer),
+            )
+            .collect::<Vec<_>>();
+        records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+        let fills = records
+            .into_iter()
+            .map(|r| coinbase.to_fill(r))
+            .collect::<Vec<_>>();
+        // the size in the quote currency is converted into the base currency
+        assert_eq!(
+            unified_rows("coinbase", &fills).await,
+            [
+                "coinbase,main,BTC-USD,BTC,USD,buy,46800.00,0.005,1.17,USD,2022-01-02T00:00:00.512Z,22222-2222222-22222222,order",
+                "coinbase,main,,BTC,,,,0.01000000,0.00015000,BTC,2022-01-01T09:40:00Z,57ffb4ae-0c59-5430-bcd3-3f98f797a66c,withdrawal",
+                "coinbase,main,ETH-EUR,ETH,EUR,sell,3290.00,0.1,1.9740,EUR,2022-01-01T06:53:20.100Z,22222-2222222-22222223,order",
+                "coinbase,main,,USD,,,,1000.00,0,USD,2022-01-01T00:00:00Z,67e0eaec-07d7-54c4-a72c-2e92826897df,deposit",
+            ]
+        );
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[2].liquidity, Some(Liquidity::Taker));
+        assert_eq!(fills[2].trade_id.as_deref(), Some("1111-11111-111112"));
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use web_time::SystemTime;
+
+use crate::exchange::{send, Exchange, Fill, FillType, Liquidity, Page, RequestExt, TimeRange};
+
+const BASE_URL: &str = "https://www.deribit.com";
+const TRADES_LIMIT: usize = 1000;
+
+pub struct Deribit {
+    credential: DeribitCredential,
+    currencies: Vec<String>,
+}
+
+impl Deribit {
+    pub async fn new(credential: DeribitCredential) -> Result<Self> {
+        let currencies: Vec<DeribitCurrency> = Self::unwrap_response(
+            send(Request::new(
+                Method::GET,
+                Url::parse(&format!("{BASE_URL}/api/v2/public/get_currencies"))?,
+            ))
+            .await?,
+        )
+        .with_context(|| "failed to get currencies")?;
+        Ok(Self {
+            credential,
+            currencies: currencies.into_iter().map(|c| c.currency).collect(),
+        })
+    }
+
+    fn unwrap_response<T>(response: DeribitResponse<T>) -> Result<T> {
+        match (response.result, response.error) {
+            (Some(result), None) => Ok(result),
+            (_, Some(e)) => Err(anyhow!(
+                "deribit returned an error: {} (code {})",
+                e.message,
+                e.code
+            )),
+            (None, None) => Err(anyhow!("deribit returned an empty response")),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, params: &[(&str, String)]) -> Result<T> {
+        Self::unwrap_response(
+            send(self.authenticate(Request::new(
+                Method::GET,
+                Url::parse_with_params(&format!("{BASE_URL}{path}"), params)?,
+            )))
+            .await?,
+        )
+    }
+
+    async fn get_trades(
+        &self,
+        currency: &str,
+        // inclusive
+        start_time: i64,
+        // exclusive
+        end_time: i64,
+    ) -> Result<Vec<DeribitTrade>> {
+        let mut trades: Vec<DeribitTrade> = vec![];
+        let mut seen = HashSet::new();
+        let mut end = end_time - 1;
+        loop {
+            let page: DeribitTrades = self
+                .get(
+                    "/api/v2/private/get_user_trades_by_currency_and_time",
+                    &[
+                        ("currency", currency.to_string()),
+                        ("kind", "any".to_string()),
+                        ("start_timestamp", start_time.to_string()),
+                        // inclusive
+                        ("end_timestamp", end.to_string()),
+                        ("count", TRADES_LIMIT.to_string()),
+                        ("sorting", "desc".to_string()),
+                    ],
+                )
+                .await?;
+            let oldest = page.trades.last().map(|t| t.timestamp);
+            let before = trades.len();
+            trades.extend(
+                page.trades
+                    .into_iter()
+                    // trades on the boundary timestamp are returned twice
+                    .filter(|t| seen.insert(t.trade_id.clone())),
+            );
+            match oldest {
+                Some(oldest) if page.has_more => {
+                    if trades.len() == before {
+                        bail!(
+                            "more than {} trades have the same timestamp {}",
+                            TRADES_LIMIT,
+                            oldest
+                        );
+                    }
+                    end = oldest
+                }
+                _ => break,
+            }
+        }
+        Ok(trades)
+    }
+}
+
+impl Exchange for Deribit {
+    type Cursor = NaiveDateTime;
+    type Record = DeribitTrade;
+
+    fn name(&self) -> &'static str {
+        "deribit"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let nonce = format!("{:x}", ts);
+        let uri = match rb.url().query() {
+            Some(q) => format!("{}?{}", rb.url().path(), q),
+            None => rb.url().path().to_string(),
+        };
+        // body is always empty since only GET requests are used
+        let text = format!("{}\n{}\n{}\n{}\n\n", ts, nonce, rb.method(), uri);
+        let signature = hex::encode(hmac_sha256::HMAC::mac(
+            &text,
+            &self.credential.client_secret,
+        ));
+        rb.set_header(
+            "Authorization",
+            format!(
+                "deri-hmac-sha256 id={},ts={},sig={},nonce={}",
+                self.credential.client_id, ts, signature, nonce
+            ),
+        );
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<DeribitTrade, NaiveDateTime>>> {
+        async move {
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(30),
+                NaiveDate::from_ymd(2016, 6, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let mut trades = vec![];
+            for currency in &self.currencies {
+                trades.extend(
+                    self.get_trades(
+                        currency,
+                        start_time.timestamp_millis(),
+                        end_time.timestamp_millis(),
+                    )
+                    .await?,
+                );
+            }
+            trades.sort_by_key(|t| std::cmp::Reverse(t.timestamp));
+            info!(
+                "{} trades between {} and {}",
+                trades.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((trades, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, t: DeribitTrade) -> Fill {
+        let instrument = Instrument::parse(&t.instrument_name);
+        Fill {
+            fee: t.fee,
+            fee_currency: Some(t.fee_currency),
+            fee_rate: None,
+            future: matches!(instrument, Instrument::Future { .. })
+                .then(|| t.instrument_name.clone()),
+            id: t.trade_id.as_str().into(),
+            liquidity: match t.liquidity.as_str() {
+                "M" => Some(Liquidity::Maker),
+                "T" => Some(Liquidity::Taker),
+                _ => None,
+            },
+            base_currency: instrument.base().map(|s| s.to_string()),
+            quote_currency: match &instrument {
+                Instrument::Spot { quote, .. } => Some(quote.to_string()),
+                _ => None,
+            },
+            market: Some(t.instrument_name.clone()),
+            order_id: Some(t.order_id.into()),
+            trade_id: Some(t.trade_id),
+            price: Some(t.price),
+            side: t.direction.parse().ok(),
+            size: t.amount,
+            time: Local.timestamp_millis(t.timestamp),
+            typ: Some(if t.liquidation.is_some() {
+                FillType::Liquidation
+            } else {
+                FillType::Order
+            }),
+            instrument_kind: Some(instrument.kind().to_string()),
+            strike: match instrument {
+                Instrument::Option { strike, .. } => Some(strike),
+                _ => None,
+            },
+            option_type: match instrument {
+                Instrument::Option { call, .. } => {
+                    Some(if call { "call" } else { "put" }.to_string())
+                }
+                _ => None,
+            },
+            index_price: Some(t.index_price),
+            conversion: None,
+        }
+    }
+}
+
+/// Instrument name of deribit, e.g. `BTC-PERPETUAL`, `BTC-25MAR22`, `BTC-25MAR22-50000-C` and
+/// `BTC_USDC`.
+enum Instrument<'a> {
+    Spot {
+        base: &'a str,
+        quote: &'a str,
+    },
+    Future {
+        base: &'a str,
+    },
+    Option {
+        base: &'a str,
+        strike: Decimal,
+        call: bool,
+    },
+    Unknown,
+}
+
+impl<'a> Instrument<'a> {
+    fn parse(name: &'a str) -> Self {
+        if let Some((base, quote)) = name.split_once('_') {
+            if !name.contains('-') {
+                return Instrument::Spot { base, quote };
+            }
+        }
+        let parts = name.split('-').collect::<Vec<_>>();
+        match parts.as_slice() {
+            [base, _] => Instrument::Future { base },
+            [base, _, strike, kind] => match strike.parse() {
+                Ok(strike) if *kind == "C" || *kind == "P" => Instrument::Option {
+                    base,
+                    strike,
+                    call: *kind == "C",
+                },
+                _ => Instrument::Unknown,
+            },
+            _ => Instrument::Unknown,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Instrument::Spot { .. } => "spot",
+            Instrument::Future { .. } => "future",
+            Instrument::Option { .. } => "option",
+            Instrument::Unknown => "unknown",
+        }
+    }
+
+    fn base(&self) -> Option<&'a str> {
+        match self {
+            Instrument::Spot { base, .. }
+            | Instrument::Future { base }
+            | Instrument::Option { base, .. } => {
+                // linear instruments are named like BTC_USDC-PERPETUAL
+                Some(base.split('_').next().unwrap_or(base))
+            }
+            Instrument::Unknown => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeribitCredential {
+    client_id: String,
+    client_secret: String,
+}
+
+#[derive(Deserialize)]
+struct DeribitResponse<T> {
+    result: Option<T>,
+    error: Option<DeribitError>,
+}
+
+#[derive(Deserialize)]
+struct DeribitError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct DeribitCurrency {
+    currency: String,
+}
+
+#[derive(Deserialize)]
+struct DeribitTrades {
+    trades: Vec<DeribitTrade>,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeribitTrade {
+    trade_id: String,
+    order_id: String,
+    instrument_name: String,
+    timestamp: i64,
+    direction: String,
+    price: Decimal,
+    amount: Decimal,
+    fee: Decimal,
+    fee_currency: String,
+    liquidity: String,
+    index_price: Decimal,
+    // present only when the trade is a liquidation
+    liquidation: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let deribit = Deribit {
+            credential: DeribitCredential {
+                client_id: "id".into(),
+                client_secret: "secret".into(),
+            },
+            currencies: vec!["BTC".into(), "ETH".into()],
+        };
+        let page: DeribitTrades = Deribit::unwrap_response(
+            serde_json::from_str(include_str!(
+                "../../fixtures/deribit/user_trades_by_currency_and_time.json"
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(!page.has_more);
+        let fills = page
+            .trades
+            .into_iter()
+            .map(|t| deribit.to_fill(t))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("deribit", &fills).await,
+            [
+                "deribit,main,BTC-PERPETUAL,BTC,,buy,46800.5,100.0,-0.00000053,BTC,2022-01-02T00:00:00.512Z,48079254,order",
+                "deribit,main,BTC-28JAN22-50000-C,BTC,,sell,0.0215,1.0,0.0003,BTC,2022-01-01T16:00:00Z,48079255,order",
+                "deribit,main,ETH_USDC,ETH,USDC,buy,3290.5,0.1,0.0,ETH,2022-01-01T09:40:00Z,USDC-48079256,order",
+                "deribit,main,BTC-25MAR22,BTC,,sell,46941.0,50.0,0.00000805,BTC,2022-01-01T06:53:20.100Z,48079257,liquidation",
+            ]
+        );
+        assert_eq!(
+            fills
+                .iter()
+                .map(|f| (f.future.as_deref(), f.instrument_kind.as_deref()))
+                .collect::<Vec<_>>(),
+            [
+                (Some("BTC-PERPETUAL"), Some("future")),
+                (None, Some("option")),
+                (None, Some("spot")),
+                (Some("BTC-25MAR22"), Some("future")),
+            ]
+        );
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[0].index_price, Some(Decimal::new(4679034, 2)));
+        assert_eq!(fills[1].strike, Some(Decimal::from(50000)));
+        assert_eq!(fills[1].option_type.as_deref(), Some("call"));
+        assert_eq!(fills[1].liquidity, Some(Liquidity::Taker));
+    }
+}
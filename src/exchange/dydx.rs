@@ -0,0 +1,359 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::exchange::{de_from_str, send, Exchange, Fill, FillType, Page, RequestExt, TimeRange};
+
+const BASE_URL: &str = "https://api.dydx.exchange";
+const LIMIT: usize = 100;
+
+pub struct Dydx {
+    credential: DydxCredential,
+}
+
+impl Dydx {
+    pub fn new(credential: DydxCredential) -> Self {
+        Self { credential }
+    }
+
+    /// Fetches all items between `start_time` and `end_time` of an endpoint which returns items
+    /// created before or at the given time, newest first.
+    async fn get_all<T: DeserializeOwned, R: DeserializeOwned>(
+        &self,
+        path: &str,
+        // a query parameter to specify the inclusive upper bound of the time
+        before_param: &str,
+        // inclusive
+        start_time: DateTime<Utc>,
+        // exclusive
+        end_time: DateTime<Utc>,
+        items: impl Fn(R) -> Vec<T>,
+        key: impl Fn(&T) -> (String, DateTime<Utc>),
+    ) -> Result<Vec<T>> {
+        let mut all = vec![];
+        let mut seen = HashSet::new();
+        let mut before = end_time - Duration::milliseconds(1);
+        loop {
+            let url = Url::parse_with_params(
+                &format!("{BASE_URL}{path}"),
+                &[
+                    ("limit", LIMIT.to_string()),
+                    (
+                        before_param,
+                        before.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    ),
+                ],
+            )?;
+            let page = items(send::<R>(self.authenticate(Request::new(Method::GET, url))).await?);
+            let has_more = page.len() == LIMIT;
+            let oldest = page.last().map(|i| key(i).1);
+            let count = all.len();
+            all.extend(page.into_iter().filter(|i| {
+                let (id, time) = key(i);
+                start_time <= time && seen.insert(id)
+            }));
+            match oldest {
+                // items on the boundary are returned again, so they are deduplicated
+                Some(oldest) if has_more && start_time <= oldest => {
+                    if all.len() == count {
+                        bail!(
+                            "more than {} items have the same timestamp {}",
+                            LIMIT,
+                            oldest
+                        );
+                    }
+                    before = oldest
+                }
+                _ => break,
+            }
+        }
+        Ok(all)
+    }
+}
+
+impl Exchange for Dydx {
+    type Cursor = NaiveDateTime;
+    type Record = DydxRecord;
+
+    fn name(&self) -> &'static str {
+        "dydx"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let request_path = match rb.url().query() {
+            Some(q) => format!("{}?{}", rb.url().path(), q),
+            None => rb.url().path().to_string(),
+        };
+        // body is always empty since only GET requests are used
+        let text = format!("{}{}{}", ts, rb.method(), request_path);
+        let secret =
+            base64::decode_config(&self.credential.secret, base64::URL_SAFE).unwrap_or_default();
+        rb.set_header("DYDX-API-KEY", &self.credential.key);
+        rb.set_header("DYDX-TIMESTAMP", ts);
+        rb.set_header("DYDX-PASSPHRASE", &self.credential.passphrase);
+        rb.set_header(
+            "DYDX-SIGNATURE",
+            base64::encode_config(hmac_sha256::HMAC::mac(&text, &secret), base64::URL_SAFE),
+        );
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<DydxRecord, NaiveDateTime>>> {
+        async move {
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(7),
+                // dYdX v3 was launched in 2021
+                NaiveDate::from_ymd(2021, 2, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let (start, end) = (
+                Utc.from_utc_datetime(&start_time),
+                Utc.from_utc_datetime(&end_time),
+            );
+            let mut records = self
+                .get_all::<DydxFill, DydxFills>(
+                    "/v3/fills",
+                    "createdBeforeOrAt",
+                    start,
+                    end,
+                    |r| r.fills,
+                    |f| (f.id.clone(), f.created_at),
+                )
+                .await?
+                .into_iter()
+                .map(DydxRecord::Fill)
+                .collect::<Vec<_>>();
+            records.extend(
+                self.get_all::<DydxFundingPayment, DydxFundingPayments>(
+                    "/v3/funding",
+                    "effectiveBeforeOrAt",
+                    start,
+                    end,
+                    |r| r.funding_payments,
+                    |p| (p.id(), p.effective_at),
+                )
+                .await?
+                .into_iter()
+                .map(DydxRecord::Funding),
+            );
+            records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+            info!(
+                "{} records between {} and {}",
+                records.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((records, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, record: DydxRecord) -> Fill {
+        let time = record.time().with_timezone(&Local);
+        match record {
+            DydxRecord::Fill(f) => Fill {
+                fee: f.fee,
+                // fees are paid in USDC
+                fee_currency: Some("USDC".to_string()),
+                fee_rate: None,
+                future: Some(f.market.clone()),
+                id: f.id.into(),
+                liquidity: f.liquidity.parse().ok(),
+                base_currency: f.market.split('-').next().map(|s| s.to_string()),
+                quote_currency: Some("USD".to_string()),
+                market: Some(f.market),
+                order_id: f.order_id.map(Into::into),
+                trade_id: None,
+                price: Some(f.price),
+                side: f.side.parse().ok(),
+                size: f.size,
+                time,
+                typ: Some(match f.typ.as_str() {
+                    "LIQUIDATED" | "LIQUIDATION" => FillType::Liquidation,
+                    // positions closed by the deleveraging of others, without orders
+                    other @ ("DELEVERAGED" | "DELEVERAGING") => {
+                        FillType::from(other.to_lowercase().as_str())
+                    }
+                    // the others are the types of the orders, e.g. LIMIT
+                    _ => FillType::Order,
+                }),
+                instrument_kind: Some("future".to_string()),
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+            },
+            DydxRecord::Funding(p) => Fill {
+                fee: Decimal::ZERO,
+                fee_currency: None,
+                fee_rate: Some(p.rate),
+                future: Some(p.market.clone()),
+                id: p.id().into(),
+                liquidity: None,
+                market: Some(p.market),
+                base_currency: Some("USDC".to_string()),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                // the oracle price used to calculate the payment
+                price: Some(p.price),
+                side: None,
+                // positive when received and negative when paid
+                size: p.payment,
+                time,
+                typ: Some(FillType::Funding),
+                instrument_kind: Some("future".to_string()),
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+            },
+        }
+    }
+}
+
+/// An API key of dYdX v3, which can be found in the local storage of the dYdX web app.
+#[derive(Deserialize)]
+pub struct DydxCredential {
+    key: String,
+    secret: String,
+    passphrase: String,
+}
+
+pub enum DydxRecord {
+    Fill(DydxFill),
+    Funding(DydxFundingPayment),
+}
+
+impl DydxRecord {
+    fn time(&self) -> DateTime<Utc> {
+        match self {
+            DydxRecord::Fill(f) => f.created_at,
+            DydxRecord::Funding(p) => p.effective_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DydxFills {
+    fills: Vec<DydxFill>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DydxFill {
+    id: String,
+    side: String,
+    liquidity: String,
+    #[serde(rename = "type")]
+    typ: String,
+    market: String,
+    order_id: Option<String>,
+    #[serde(deserialize_with = "de_from_str")]
+    price: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    size: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DydxFundingPayments {
+    funding_payments: Vec<DydxFundingPayment>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DydxFundingPayment {
+    market: String,
+    #[serde(deserialize_with = "de_from_str")]
+    payment: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    rate: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    price: Decimal,
+    effective_at: DateTime<Utc>,
+}
+
+impl DydxFundingPayment {
+    // funding payments have no id but are paid once per market and hour
+    fn id(&self) -> String {
+        format!("{}-{}", self.market, self.effective_at.timestamp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+    use crate::exchange::Liquidity;
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let dydx = Dydx::new(DydxCredential {
+            key: "key".into(),
+            secret: "c2VjcmV0".into(),
+            passphrase: "passphrase".into(),
+        });
+        let fills: DydxFills =
+            serde_json::from_str(include_str!("../../fixtures/dydx/fills.json")).unwrap();
+        let payments: DydxFundingPayments =
+            serde_json::from_str(include_str!("../../fixtures/dydx/funding.json")).unwrap();
+        let mut records = fills
+            .fills
+            .into_iter()
+            .map(DydxRecord::Fill)
+            .chain(
+                payments
+                    .funding_payments
+                    .into_iter()
+                    .map(DydxRecord::Funding),
+            )
+            .collect::<Vec<_>>();
+        records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+        let fills = records
+            .into_iter()
+            .map(|r| dydx.to_fill(r))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("dydx", &fills).await,
+            [
+                "dydx,main,BTC-USD,BTC,USD,buy,46800,0.005,0.0117,USDC,2022-01-02T00:00:00.512Z,a3c3c0f2-0d3d-5b0e-9d1d-5a0d6b1d4e33,order",
+                "dydx,main,BTC-USD,USDC,,,47010.1,-0.012345,0,,2022-01-01T16:00:00Z,BTC-USD-1641052800,funding",
+                "dydx,main,ETH-USD,ETH,USD,sell,3290.5,0.1,0,USDC,2022-01-01T06:53:20.100Z,f1c5e9d4-8f8e-5b0a-8b7b-2a1e9f0d7c64,liquidation",
+            ]
+        );
+        assert_eq!(fills[0].future.as_deref(), Some("BTC-USD"));
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(
+            fills[0].order_id.as_ref().map(|id| id.as_str()),
+            Some("0b9b5e2a-4e3b-4d53-9b0d-7b4a1c9f0e21")
+        );
+        assert_eq!(fills[1].fee_rate, Some(Decimal::new(125, 7)));
+        assert_eq!(fills[2].liquidity, Some(Liquidity::Taker));
+        assert!(fills[2].order_id.is_none());
+    }
+}
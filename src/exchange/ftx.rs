@@ -0,0 +1,783 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Error, Result};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use web_time::SystemTime;
+
+use crate::exchange::http::client_builder;
+use crate::exchange::{
+    retry_with, send, send_with, Cursor, Exchange, Fill, FillType, Liquidity, Order, Page,
+    RequestExt, Side, TimeRange, Transient, Transport, Unauthorized,
+};
+
+pub struct Ftx {
+    credential: FtxCredential,
+    sub_account: Option<String>,
+    // e.g. ftx.com, ftx.us
+    domain: String,
+    // e.g. https://ftx.com
+    base_url: String,
+    // milliseconds added to the local clock to sign requests with the server time
+    ts_offset: i64,
+    // a transport of its own, or None to use the one built by configure
+    transport: Option<Arc<dyn Transport>>,
+    max_retries: Option<u32>,
+}
+
+/// Options of [`Ftx`]. Only the credential is required.
+#[derive(Default)]
+pub struct FtxBuilder {
+    credential: Option<FtxCredential>,
+    sub_account: Option<String>,
+    domain: Option<String>,
+    base_url: Option<String>,
+    ts_offset: i64,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    max_retries: Option<u32>,
+    transport: Option<Arc<dyn Transport>>,
+}
+
+impl FtxBuilder {
+    pub fn credential(mut self, credential: FtxCredential) -> Self {
+        self.credential = Some(credential);
+        self
+    }
+
+    /// Downloads the history of the subaccount instead of the main account.
+    pub fn sub_account(mut self, sub_account: Option<String>) -> Self {
+        self.sub_account = sub_account;
+        self
+    }
+
+    /// The domain of the api, `ftx.com` by default.
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Overrides the url which the api paths are appended to, e.g. to send requests to a mock
+    /// server. `https://` and the domain by default.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// The offset of the signing timestamp from the local clock in milliseconds.
+    pub fn ts_offset(mut self, ts_offset: i64) -> Self {
+        self.ts_offset = ts_offset;
+        self
+    }
+
+    /// A timeout to establish a connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// A timeout of a whole request including the response body.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Sends requests with the transport, e.g. a mock one, instead of the http client.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// How many times a transient failure of a request is retried.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Builds the client. A timeout or a user agent makes the client send requests with an http
+    /// client of its own, which doesn't use the proxy given to [`crate::exchange::configure`].
+    pub fn build(self) -> Result<Ftx> {
+        let transport = self.build_transport()?;
+        let domain = self.domain_or_default();
+        let base_url = self.base_url_or_default();
+        let credential = self
+            .credential
+            .ok_or_else(|| anyhow!("a credential is required to build the ftx client"))?;
+        Ok(Ftx {
+            credential,
+            sub_account: self.sub_account,
+            domain,
+            base_url,
+            ts_offset: self.ts_offset,
+            transport,
+            max_retries: self.max_retries,
+        })
+    }
+
+    /// Builds a client of the public market data, which doesn't need the credential.
+    pub fn build_markets(self) -> Result<FtxMarkets> {
+        Ok(FtxMarkets {
+            base_url: self.base_url_or_default(),
+            transport: self.build_transport()?,
+            max_retries: self.max_retries,
+        })
+    }
+
+    fn domain_or_default(&self) -> String {
+        self.domain.clone().unwrap_or_else(|| "ftx.com".to_string())
+    }
+
+    fn base_url_or_default(&self) -> String {
+        match &self.base_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("https://{}", self.domain_or_default()),
+        }
+    }
+
+    fn build_transport(&self) -> Result<Option<Arc<dyn Transport>>> {
+        if self.transport.is_some() {
+            return Ok(self.transport.clone());
+        }
+        if self.connect_timeout.is_none()
+            && self.request_timeout.is_none()
+            && self.user_agent.is_none()
+        {
+            return Ok(None);
+        }
+        let mut builder = client_builder(self.connect_timeout, self.request_timeout);
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        Ok(Some(Arc::new(builder.build()?)))
+    }
+}
+
+impl Ftx {
+    pub fn builder() -> FtxBuilder {
+        FtxBuilder::default()
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: Request) -> Result<T> {
+        send_via(&self.transport, request).await
+    }
+
+    /// Returns a client of the public market data sharing the domain and the transport.
+    pub fn markets(&self) -> FtxMarkets {
+        FtxMarkets {
+            base_url: self.base_url.clone(),
+            transport: self.transport.clone(),
+            max_retries: self.max_retries,
+        }
+    }
+
+    /// Measures the offset of the local clock from the server time, so that requests are not
+    /// rejected when the local clock is off.
+    pub async fn sync_clock(&mut self) -> Result<()> {
+        let before = Utc::now();
+        let server_time: DateTime<Utc> = self
+            .send::<FtxResponse<_>>(Request::new(
+                Method::GET,
+                Url::parse(&format!("{}/api/time", self.base_url))?,
+            ))
+            .await?
+            .into_result()?;
+        let after = Utc::now();
+        // assume the server responded at the middle of the round trip
+        let local_time = before + (after - before) / 2;
+        self.ts_offset = (server_time - local_time).num_milliseconds();
+        if self.ts_offset.abs() >= 1000 {
+            warn!(
+                "the local clock is off by {}ms from the server time. adjusting timestamps of requests",
+                self.ts_offset
+            );
+        }
+        Ok(())
+    }
+
+    /// FTX US uses its own prefix for the authentication headers.
+    fn header_prefix(&self) -> &'static str {
+        if self.domain == "ftx.us" {
+            "FTXUS"
+        } else {
+            "FTX"
+        }
+    }
+
+    /// Translates common errors of a misconfigured credential into what to fix.
+    fn explain(&self, e: Error) -> Error {
+        let message = format!("{:#}", e).to_lowercase();
+        let hint = if message.contains("subaccount") {
+            format!(
+                "the subaccount {} is not found. note that --sub-account is case sensitive",
+                self.sub_account.as_deref().unwrap_or_default()
+            )
+        } else if message.contains("withdrawal") {
+            "the api key is withdrawal-only. use an api key with the read permission".to_string()
+        } else if message.contains("invalid api key") || message.contains("not logged in") {
+            format!(
+                "the api key is invalid or expired. check that it's issued on {}{}",
+                self.domain,
+                match &self.sub_account {
+                    Some(sub_account) => format!(" for the subaccount {}", sub_account),
+                    None => String::new(),
+                }
+            )
+        } else if message.contains("signature") {
+            "the signature is rejected. check api_secret in the credential file".to_string()
+        } else {
+            return e;
+        };
+        e.context(hint)
+    }
+
+    /// Lists the nicknames of all subaccounts, which requires an api key of the main account.
+    pub async fn get_subaccounts(&self) -> Result<Vec<String>> {
+        let subaccounts: Vec<FtxSubaccount> = self
+            .send::<FtxResponse<_>>(self.authenticate(Request::new(
+                Method::GET,
+                Url::parse(&format!("{}/api/subaccounts", self.base_url))?,
+            )))
+            .await
+            .map_err(with_error_message)?
+            .into_result()?;
+        Ok(subaccounts.into_iter().map(|s| s.nickname).collect())
+    }
+}
+
+impl Exchange for Ftx {
+    type Cursor = FtxCursor;
+    type Record = FtxFill;
+
+    fn name(&self) -> &'static str {
+        "ftx"
+    }
+
+    fn max_retries(&self) -> Option<u32> {
+        self.max_retries
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64
+            + self.ts_offset;
+        let text = format!(
+            "{}{}{}{}",
+            ts,
+            rb.method(),
+            rb.url().path(),
+            rb.url()
+                .query()
+                .map(|q| format!("?{q}"))
+                .unwrap_or_default()
+        );
+        let prefix = self.header_prefix();
+        rb.set_header(format!("{prefix}-KEY").as_str(), &self.credential.api_key);
+        rb.set_header(format!("{prefix}-TS").as_str(), ts.to_string());
+        rb.set_header(
+            format!("{prefix}-SIGN").as_str(),
+            hex::encode(hmac_sha256::HMAC::mac(&text, &self.credential.api_secret)),
+        );
+        if let Some(sub_account) = &self.sub_account {
+            rb.set_header(format!("{prefix}-SUBACCOUNT").as_str(), sub_account);
+        }
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> FtxCursor {
+        FtxCursor {
+            end_time: range.end,
+            oldest_fill_id: u64::MAX,
+        }
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        cursor: FtxCursor,
+    ) -> BoxFuture<'a, Result<Page<FtxFill, FtxCursor>>> {
+        async move { Paginator::new(self).fetch(range, cursor).await }.boxed()
+    }
+
+    fn fetch_orders<'a>(&'a self, range: &'a TimeRange) -> BoxFuture<'a, Result<Vec<Order>>> {
+        async move {
+            let paginator = Paginator::<FtxOrder>::new(self);
+            let mut cursor = self.first_cursor(range);
+            let mut orders = vec![];
+            while let Some((page, next)) =
+                retry_with(self.max_retries, || paginator.fetch(range, cursor.clone())).await?
+            {
+                orders.extend(page.into_iter().map(|o| Order {
+                    id: o.id.into(),
+                    market: o.market,
+                    filled_size: o.filled_size,
+                    created_at: o.created_at,
+                }));
+                cursor = next;
+            }
+            info!("{} orders are found", orders.len());
+            Ok(orders)
+        }
+        .boxed()
+    }
+
+    fn preflight(&self) -> BoxFuture<'_, Result<()>> {
+        let now = Utc::now().timestamp();
+        async move {
+            Paginator::<FtxFill>::new(self)
+                .get(now - 60, now)
+                .await
+                .map(|_| ())
+                .map_err(|e| self.explain(e))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, f: FtxFill) -> Fill {
+        Fill {
+            fee: f.fee,
+            fee_currency: f.fee_currency,
+            fee_rate: f.fee_rate,
+            future: f.future,
+            id: f.id.into(),
+            liquidity: f.liquidity,
+            market: f.market,
+            base_currency: f.base_currency,
+            quote_currency: f.quote_currency,
+            order_id: f.order_id.map(Into::into),
+            trade_id: f.trade_id.map(|id| id.to_string()),
+            price: Some(f.price),
+            side: f.side,
+            size: f.size,
+            time: f.time,
+            instrument_kind: None,
+            strike: None,
+            option_type: None,
+            index_price: None,
+            conversion: None,
+            typ: f.typ,
+        }
+    }
+}
+
+/// An endpoint which returns at most `LIMIT` items created before `end_time`, newest first.
+trait FtxPaginated: DeserializeOwned + Send {
+    // e.g. /api/fills
+    const PATH: &'static str;
+    // how the items are called in logs and errors
+    const NAME: &'static str;
+    const LIMIT: usize;
+
+    // increases with the time
+    fn id(&self) -> u64;
+    fn time(&self) -> DateTime<Utc>;
+}
+
+impl FtxPaginated for FtxFill {
+    const PATH: &'static str = "/api/fills";
+    const NAME: &'static str = "fills";
+    const LIMIT: usize = 5000;
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn time(&self) -> DateTime<Utc> {
+        self.time.with_timezone(&Utc)
+    }
+}
+
+impl FtxPaginated for FtxOrder {
+    const PATH: &'static str = "/api/orders/history";
+    const NAME: &'static str = "orders";
+    const LIMIT: usize = 100;
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn time(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+/// Walks an endpoint back in time page by page.
+///
+/// The API returns up to `LIMIT` items ordered by time desc, so start_time is always zero and
+/// end_time is moved to the oldest item of the previous page. end_time is in seconds, so the items
+/// in that second are returned again and skipped by id.
+struct Paginator<'a, T> {
+    ftx: &'a Ftx,
+    endpoint: PhantomData<T>,
+}
+
+impl<'a, T: FtxPaginated> Paginator<'a, T> {
+    fn new(ftx: &'a Ftx) -> Self {
+        Self {
+            ftx,
+            endpoint: PhantomData,
+        }
+    }
+
+    // has_more_data tells if older items remain in some endpoints
+    async fn get(
+        &self,
+        // inclusive
+        start_time: i64,
+        // exclusive
+        end_time: i64,
+    ) -> Result<(Vec<T>, bool)> {
+        let response = self
+            .ftx
+            .send::<FtxResponse<_>>(self.ftx.authenticate(Request::new(
+                Method::GET,
+                Url::parse(&format!(
+                    "{}{}?start_time={}&end_time={}",
+                    self.ftx.base_url,
+                    T::PATH,
+                    start_time,
+                    end_time
+                ))?,
+            )))
+            .await
+            .map_err(with_error_message)?;
+        let has_more = response.has_more_data;
+        Ok((response.into_result()?, has_more))
+    }
+
+    /// Fetches the items of the range before the cursor.
+    async fn fetch(
+        &self,
+        range: &TimeRange,
+        FtxCursor {
+            end_time,
+            oldest_fill_id,
+        }: FtxCursor,
+    ) -> Result<Page<T, FtxCursor>> {
+        let (items, has_more) = self.get(0, end_time.timestamp()).await?;
+        if items.is_empty() && oldest_fill_id != u64::MAX {
+            // the oldest item of the previous page must be returned again since end_time is
+            // after it, so the response is broken
+            return Err(Transient(format!(
+                "ftx returned no {} before {} although {} exists before it",
+                T::NAME,
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+                oldest_fill_id
+            ))
+            .into());
+        }
+        let is_full = has_more || items.len() >= T::LIMIT;
+        let reached_start = items
+            .last()
+            .map(|i| !range.is_after_start(&i.time().naive_utc()))
+            .unwrap_or(true);
+        let items = items
+            .into_iter()
+            .filter(|i| {
+                // avoid duplication
+                i.id() < oldest_fill_id
+                    // newer than the specified start time
+                    && range.is_after_start(&i.time().naive_utc())
+            })
+            .collect::<Vec<_>>();
+        if items.is_empty() && is_full && !reached_start {
+            // end_time can't be moved anymore, so the rest of the items in the second would be
+            // silently lost
+            bail!(
+                "more than {} {} exist in the second before {}. the export would be incomplete",
+                T::LIMIT,
+                T::NAME,
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+        }
+        let next_cursor = items.last().map(|oldest| {
+            info!(
+                "{} {} between {} and {} ({} - {})",
+                items.len(),
+                T::NAME,
+                oldest.time().timestamp(),
+                end_time.timestamp(),
+                // logged in UTC as well as end_time
+                oldest.time().naive_utc().format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            FtxCursor {
+                // +1 second because some items on the same second maybe still remaining
+                end_time: oldest.time().naive_utc() + chrono::Duration::seconds(1),
+                oldest_fill_id: oldest.id(),
+            }
+        });
+        Ok(next_cursor.map(|c| (items, c)))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FtxCursor {
+    end_time: NaiveDateTime,
+    // the id of the oldest item of the previous page, which may be an order as well
+    oldest_fill_id: u64,
+}
+
+impl Cursor for FtxCursor {
+    fn end_time(&self) -> NaiveDateTime {
+        self.end_time
+    }
+
+    fn with_end_time(&self, end_time: NaiveDateTime) -> Self {
+        FtxCursor {
+            end_time,
+            oldest_fill_id: u64::MAX,
+        }
+    }
+}
+
+/// A client of the public market data of ftx, built by [`FtxBuilder::build_markets`] or
+/// [`Ftx::markets`].
+pub struct FtxMarkets {
+    base_url: String,
+    transport: Option<Arc<dyn Transport>>,
+    max_retries: Option<u32>,
+}
+
+impl FtxMarkets {
+    /// Returns the open price of the hour of the time in the market from the public candles.
+    /// `None` if the market doesn't exist or has no candle of the hour.
+    pub async fn price_at(&self, market: &str, time: DateTime<Utc>) -> Result<Option<Decimal>> {
+        let start = time.timestamp() - time.timestamp().rem_euclid(3600);
+        let url = Url::parse_with_params(
+            &format!("{}/api/markets/{}/candles", self.base_url, market),
+            &[
+                ("resolution", "3600".to_string()),
+                ("start_time", start.to_string()),
+                ("end_time", start.to_string()),
+            ],
+        )?;
+        let response: FtxResponse<Vec<FtxCandle>> = retry_with(self.max_retries, || {
+            send_via(&self.transport, Request::new(Method::GET, url.clone()))
+        })
+        .await?;
+        if response
+            .error
+            .as_deref()
+            .is_some_and(|e| e.to_lowercase().contains("no such market"))
+        {
+            return Ok(None);
+        }
+        Ok(response.into_result()?.first().map(|c| c.open))
+    }
+}
+
+// sends with the transport of the client, or the one built by configure
+async fn send_via<T: DeserializeOwned>(
+    transport: &Option<Arc<dyn Transport>>,
+    request: Request,
+) -> Result<T> {
+    match transport {
+        Some(transport) => send_with(transport.as_ref(), request).await,
+        None => send(request).await,
+    }
+}
+
+#[derive(Deserialize)]
+struct FtxCandle {
+    open: Decimal,
+}
+
+#[derive(Deserialize)]
+struct FtxSubaccount {
+    nickname: String,
+}
+
+#[derive(Deserialize)]
+pub struct FtxCredential {
+    api_key: String,
+    api_secret: String,
+}
+
+impl FtxCredential {
+    pub fn new(api_key: String, api_secret: String) -> Self {
+        Self {
+            api_key,
+            api_secret,
+        }
+    }
+
+    /// Reads `FTX_API_KEY` and `FTX_API_SECRET` environment variables.
+    pub fn from_env() -> Option<Self> {
+        let var = |key| std::env::var(key).ok().filter(|v: &String| !v.is_empty());
+        Some(Self {
+            api_key: var("FTX_API_KEY")?,
+            api_secret: var("FTX_API_SECRET")?,
+        })
+    }
+}
+
+/// The envelope of all responses, e.g. `{"success": false, "error": "Not logged in"}`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FtxResponse<T> {
+    success: bool,
+    result: Option<T>,
+    error: Option<String>,
+    // only in paginated responses
+    #[serde(default)]
+    has_more_data: bool,
+}
+
+impl<T> FtxResponse<T> {
+    fn into_result(self) -> Result<T> {
+        match self {
+            FtxResponse {
+                success: true,
+                result: Some(result),
+                ..
+            } => Ok(result),
+            FtxResponse { error, .. } => Err(anyhow!(
+                "ftx returned an error: {}",
+                error.as_deref().unwrap_or("unknown error")
+            )),
+        }
+    }
+}
+
+/// Adds the error message of ftx to an error of an unauthorized request, which is rejected
+/// before its envelope is parsed.
+fn with_error_message(e: Error) -> Error {
+    let message = e
+        .downcast_ref::<Unauthorized>()
+        .and_then(|u| serde_json::from_str::<FtxResponse<IgnoredAny>>(&u.0).ok())
+        .and_then(|r| r.error);
+    match message {
+        Some(message) => e.context(format!("ftx returned an error: {}", message)),
+        None => e,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FtxOrder {
+    id: u64,
+    market: Option<String>,
+    filled_size: Decimal,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FtxFill {
+    fee: Decimal,
+    fee_currency: Option<String>,
+    fee_rate: Option<Decimal>,
+    future: Option<String>,
+    id: u64,
+    liquidity: Option<Liquidity>,
+    market: Option<String>,
+    base_currency: Option<String>,
+    quote_currency: Option<String>,
+    order_id: Option<u64>,
+    trade_id: Option<u64>,
+    price: Decimal,
+    side: Option<Side>,
+    size: Decimal,
+    time: DateTime<Local>,
+    #[serde(rename = "type")]
+    typ: Option<FillType>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::exchange::mock::MockTransport;
+    use crate::exchange::tests::unified_rows;
+
+    fn client(domain: &str, transport: MockTransport) -> Ftx {
+        Ftx::builder()
+            .credential(FtxCredential::new("key".into(), "secret".into()))
+            .domain(domain)
+            .transport(transport)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let ftx = client("ftx.com", MockTransport::ftx());
+        let range = TimeRange {
+            start: Some(NaiveDate::from_ymd(2022, 1, 1).and_hms(0, 0, 0)),
+            end: NaiveDate::from_ymd(2022, 1, 2).and_hms(0, 0, 0),
+        };
+        let fills = crate::exchange::fills(&ftx, &range)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            unified_rows("ftx", &fills).await,
+            [
+                "ftx,main,BTC/USD,BTC,USD,sell,47000.0,0.01,0.0001,USD,2022-01-01T23:59:59.999999Z,1004,order",
+                "ftx,main,ETH-PERP,,,sell,3720.2,0.25,-0.00002,USD,2022-01-01T09:30:00.500Z,1003,order",
+                "ftx,main,ETH-PERP,,,sell,3720.1,0.5,-0.00002,USD,2022-01-01T09:30:00Z,1002,order",
+                "ftx,main,BTC/USD,BTC,USD,buy,46200.5,0.01,0.0001,USD,2022-01-01T00:00:05.123456Z,1001,order",
+            ]
+        );
+        assert_eq!(fills[1].future.as_deref(), Some("ETH-PERP"));
+        assert_eq!(fills[1].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[1].fee_rate, Some(Decimal::new(-1, 4)));
+        assert_eq!(
+            fills[3].order_id.as_ref().map(|id| id.as_str()),
+            Some("10001")
+        );
+        assert_eq!(fills[3].trade_id.as_deref(), Some("6001"));
+    }
+
+    #[tokio::test]
+    async fn sends_the_requests_to_the_domain() {
+        let transport = MockTransport::ftx();
+        let requests = transport.requests();
+        let ftx = client("ftx.us", transport);
+        assert_eq!(ftx.get_subaccounts().await.unwrap(), ["bot", "hedge"]);
+        let range = TimeRange {
+            start: Some(NaiveDate::from_ymd(2022, 1, 1).and_hms(0, 0, 0)),
+            end: NaiveDate::from_ymd(2022, 1, 2).and_hms(0, 0, 0),
+        };
+        crate::exchange::fills(&ftx, &range)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        assert!(requests.len() > 1);
+        assert!(requests
+            .iter()
+            .all(|url| url.scheme() == "https" && url.host_str() == Some("ftx.us")));
+
+        // ftx.us names the authentication headers after itself
+        let request = Request::new(Method::GET, Url::parse("https://ftx.us/api/fills").unwrap());
+        let request = ftx.authenticate(request);
+        assert!(request.headers().contains_key("FTXUS-KEY"));
+        assert!(!request.headers().contains_key("FTX-KEY"));
+        let request = Request::new(
+            Method::GET,
+            Url::parse("https://ftx.com/api/fills").unwrap(),
+        );
+        let request = client("ftx.com", MockTransport::new()).authenticate(request);
+        assert!(request.headers().contains_key("FTX-KEY"));
+    }
+}
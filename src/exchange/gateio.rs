@@ -0,0 +1,215 @@
+use anyhow::Result;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use web_time::SystemTime;
+
+use crate::exchange::{de_from_str, send, Exchange, Fill, FillType, Page, RequestExt, TimeRange};
+
+const BASE_URL: &str = "https://api.gateio.ws";
+const LIMIT: usize = 1000;
+
+pub struct GateIo {
+    credential: GateIoCredential,
+}
+
+impl GateIo {
+    pub fn new(credential: GateIoCredential) -> Self {
+        Self { credential }
+    }
+
+    async fn get_trades(
+        &self,
+        // inclusive, in seconds
+        start_time: i64,
+        // exclusive, in seconds
+        end_time: i64,
+    ) -> Result<Vec<GateIoTrade>> {
+        let mut trades = vec![];
+        let mut page = 1;
+        loop {
+            let items: Vec<GateIoTrade> = send(self.authenticate(Request::new(
+                Method::GET,
+                Url::parse_with_params(
+                    &format!("{BASE_URL}/api/v4/spot/my_trades"),
+                    &[
+                        ("from", start_time.to_string()),
+                        // inclusive
+                        ("to", (end_time - 1).to_string()),
+                        ("limit", LIMIT.to_string()),
+                        ("page", page.to_string()),
+                    ],
+                )?,
+            )))
+            .await?;
+            let has_more = items.len() == LIMIT;
+            trades.extend(items);
+            if !has_more {
+                break;
+            }
+            page += 1;
+        }
+        Ok(trades)
+    }
+}
+
+impl Exchange for GateIo {
+    type Cursor = NaiveDateTime;
+    type Record = GateIoTrade;
+
+    fn name(&self) -> &'static str {
+        "gateio"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        // body is always empty since only GET requests are used
+        let text = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            rb.method(),
+            rb.url().path(),
+            rb.url().query().unwrap_or_default(),
+            hex::encode(hmac_sha512::Hash::hash(b"")),
+            ts
+        );
+        rb.set_header("KEY", &self.credential.api_key);
+        rb.set_header("Timestamp", ts.to_string());
+        rb.set_header(
+            "SIGN",
+            hex::encode(hmac_sha512::HMAC::mac(&text, &self.credential.api_secret)),
+        );
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<GateIoTrade, NaiveDateTime>>> {
+        async move {
+            // trades can be queried at most 30 days at once
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(30),
+                NaiveDate::from_ymd(2013, 1, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let mut trades = self
+                .get_trades(start_time.timestamp(), end_time.timestamp())
+                .await?;
+            trades.sort_by(|a, b| b.create_time_ms.total_cmp(&a.create_time_ms));
+            info!(
+                "{} trades between {} and {}",
+                trades.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((trades, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, t: GateIoTrade) -> Fill {
+        let (base, quote) = t
+            .currency_pair
+            .split_once('_')
+            .map(|(b, q)| (Some(b.to_string()), Some(q.to_string())))
+            .unwrap_or_default();
+        Fill {
+            fee: t.fee,
+            fee_currency: Some(t.fee_currency),
+            fee_rate: None,
+            future: None,
+            id: t.id.as_str().into(),
+            liquidity: t.role.parse().ok(),
+            market: Some(t.currency_pair),
+            base_currency: base,
+            quote_currency: quote,
+            order_id: Some(t.order_id.into()),
+            trade_id: Some(t.id),
+            price: Some(t.price),
+            side: t.side.parse().ok(),
+            size: t.amount,
+            // create_time_ms is in milliseconds with a fraction of microseconds
+            time: Local.timestamp_nanos((t.create_time_ms * 1000.0).round() as i64 * 1000),
+            typ: Some(FillType::Order),
+            instrument_kind: None,
+            strike: None,
+            option_type: None,
+            index_price: None,
+            conversion: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GateIoCredential {
+    api_key: String,
+    api_secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct GateIoTrade {
+    id: String,
+    #[serde(deserialize_with = "de_from_str")]
+    create_time_ms: f64,
+    currency_pair: String,
+    side: String,
+    role: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amount: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    price: Decimal,
+    order_id: String,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    fee_currency: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+    use crate::exchange::Liquidity;
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let gateio = GateIo::new(GateIoCredential {
+            api_key: "key".into(),
+            api_secret: "secret".into(),
+        });
+        let trades: Vec<GateIoTrade> =
+            serde_json::from_str(include_str!("../../fixtures/gateio/my_trades.json")).unwrap();
+        let fills = trades
+            .into_iter()
+            .map(|t| gateio.to_fill(t))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("gateio", &fills).await,
+            [
+                "gateio,main,BTC_USDT,BTC,USDT,buy,46800.1,0.005,0.00001,BTC,2022-01-02T00:00:00.512123Z,2876130500,order",
+                "gateio,main,ETH_BTC,ETH,BTC,sell,0.07,0.1,0.000014,BTC,2022-01-01T06:53:20.100Z,2876130501,order",
+            ]
+        );
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[1].liquidity, Some(Liquidity::Taker));
+        assert_eq!(
+            fills[1].order_id.as_ref().map(|id| id.as_str()),
+            Some("125924049994")
+        );
+    }
+}
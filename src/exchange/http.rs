@@ -0,0 +1,592 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
+use log::*;
+use reqwest::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use reqwest::{Method, Request, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use web_time::Instant;
+
+use crate::exchange::replay::{Recorder, Replayer};
+
+// rate limits are expected in a long download, so they are retried more patiently
+const MAX_RATE_LIMIT_RETRIES: u32 = 20;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_INTERVAL: Duration = Duration::from_secs(10);
+
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+static TRANSPORT: OnceLock<Box<dyn Transport>> = OnceLock::new();
+static RAW_DIR: OnceLock<PathBuf> = OnceLock::new();
+static RAW_SEQ: AtomicU64 = AtomicU64::new(0);
+static MAX_RETRIES: AtomicU32 = AtomicU32::new(5);
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+static PACER: Mutex<Pacer> = Mutex::new(Pacer {
+    interval: Duration::ZERO,
+    next: None,
+});
+
+/// Options of the http client shared by all requests.
+pub struct ClientConfig {
+    // a timeout to establish a connection
+    pub connect_timeout: Option<Duration>,
+    // a timeout of a whole request including the response body
+    pub request_timeout: Option<Duration>,
+    // an http or socks5 proxy url, e.g. socks5://localhost:1080
+    pub proxy: Option<String>,
+    // a directory to save all response bodies into
+    pub save_raw: Option<PathBuf>,
+    // a directory to save all requests and responses into to be replayed
+    pub record_http: Option<PathBuf>,
+    // a directory of recorded requests to answer requests with instead of the network
+    pub replay_http: Option<PathBuf>,
+    // how many times a transient failure of a request is retried
+    pub max_retries: u32,
+}
+
+/// Builds the http client used by [`send`]. Requests sent before this is called use the default
+/// client.
+pub fn configure(config: ClientConfig) -> Result<()> {
+    #[allow(unused_mut)]
+    let mut builder = client_builder(config.connect_timeout, config.request_timeout);
+    if let Some(proxy) = config.proxy {
+        #[cfg(target_arch = "wasm32")]
+        anyhow::bail!(
+            "{} can't be used since the browser decides the proxy",
+            proxy
+        );
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            builder = builder.proxy(
+                reqwest::Proxy::all(&proxy)
+                    .with_context(|| format!("invalid proxy url: {}", proxy))?,
+            );
+        }
+    }
+    MAX_RETRIES.store(config.max_retries, Ordering::SeqCst);
+    if let Some(dir) = config.save_raw {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let _ = RAW_DIR.set(dir);
+    }
+    CLIENT
+        .set(builder.build()?)
+        .map_err(|_| anyhow!("the http client is already configured"))?;
+    match (config.record_http, config.replay_http) {
+        (Some(_), Some(_)) => Err(anyhow!("requests can't be recorded and replayed at once")),
+        (Some(dir), None) => set_transport(Recorder::new(dir, client())?),
+        (None, Some(dir)) => set_transport(Replayer::new(dir)?),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Returns a builder of an http client with the timeouts.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn client_builder(
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+) -> reqwest::ClientBuilder {
+    // proxy environment variables are resolved by the caller
+    let mut builder = reqwest::Client::builder().no_proxy();
+    if let Some(timeout) = connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = request_timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder
+}
+
+/// Returns a builder of an http client, which sends requests by the fetch of the browser.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn client_builder(
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+) -> reqwest::ClientBuilder {
+    if connect_timeout.is_some() || request_timeout.is_some() {
+        warn!("timeouts are ignored since the browser decides them");
+    }
+    reqwest::Client::builder()
+}
+
+fn client() -> &'static reqwest::Client {
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// Makes all exchanges send requests with the transport instead of the http client, e.g. to test
+/// them with [`MockTransport`](crate::exchange::mock::MockTransport).
+pub fn set_transport(transport: impl Transport + 'static) -> Result<()> {
+    TRANSPORT
+        .set(Box::new(transport))
+        .map_err(|_| anyhow!("the transport is already set"))
+}
+
+/// A response of a request, which is parsed by [`send`].
+pub struct Response {
+    pub status: StatusCode,
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+/// Sends requests to the exchanges. Connection failures should be returned as [`Transient`]
+/// errors so that they are retried.
+pub trait Transport: Send + Sync {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response>>;
+}
+
+impl Transport for reqwest::Client {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response>> {
+        let response = async move {
+            let response = reqwest::Client::execute(self, request).await.map_err(|e| {
+                // the url may contain a signature of the request
+                let e = e.without_url();
+                if e.is_builder() {
+                    anyhow!(e)
+                } else {
+                    // the whole chain is shown since the top level error of reqwest tells
+                    // nothing
+                    Transient(format!("{:#}", anyhow!(e))).into()
+                }
+            })?;
+            let status = response.status();
+            // only the delay-seconds form is supported since exchanges don't use the http-date one
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok()?.trim().parse().ok())
+                .map(Duration::from_secs);
+            let body = response
+                .text()
+                .await
+                .map_err(|e| Transient(format!("{:#}", anyhow!(e.without_url()))))?;
+            Ok(Response {
+                status,
+                retry_after,
+                body,
+            })
+        };
+        // a fetch isn't Send but never leaves the only thread of the browser
+        #[cfg(target_arch = "wasm32")]
+        let response = send_wrapper::SendWrapper::new(response);
+        response.boxed()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response>> {
+        (**self).execute(request)
+    }
+}
+
+/// Sets headers of a request being signed.
+pub trait RequestExt {
+    /// Sets a header. A value which can't be a header, e.g. one with a line break, is skipped and
+    /// the request will be rejected as unauthorized.
+    fn set_header(&mut self, name: &str, value: impl AsRef<str>);
+}
+
+impl RequestExt for Request {
+    fn set_header(&mut self, name: &str, value: impl AsRef<str>) {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value.as_ref()),
+        ) {
+            (Ok(name), Ok(value)) => {
+                self.headers_mut().insert(name, value);
+            }
+            _ => warn!("{} header is skipped since it has an invalid value", name),
+        }
+    }
+}
+
+/// Returns how many requests are sent so far including retried ones.
+pub fn request_count() -> u64 {
+    REQUESTS.load(Ordering::Relaxed)
+}
+
+/// Sends a request and parses the response body as json.
+///
+/// Connection failures and 5xx responses are returned as [`Transient`] errors, 401 and 403
+/// responses as [`Unauthorized`] errors and 429 responses as [`RateLimited`] errors.
+pub async fn send<T: DeserializeOwned>(request: Request) -> Result<T> {
+    match TRANSPORT.get() {
+        Some(transport) => send_with(transport.as_ref(), request).await,
+        None => send_with(client(), request).await,
+    }
+}
+
+/// Sends a request with the given transport instead of the one built by [`configure`].
+pub async fn send_with<T: DeserializeOwned>(
+    transport: &dyn Transport,
+    request: Request,
+) -> Result<T> {
+    let wait = PACER.lock().unwrap().reserve();
+    futures_timer::Delay::new(wait).await;
+
+    let (method, url) = (request.method().clone(), request.url().clone());
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+    let Response {
+        status,
+        retry_after,
+        body: response_body,
+    } = transport.execute(request).await?;
+    if let Some(dir) = RAW_DIR.get() {
+        save_raw(dir, &method, &url, &response_body)
+            .await
+            .with_context(|| "failed to save the response body")?;
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        PACER.lock().unwrap().slow_down();
+        return Err(RateLimited {
+            retry_after,
+            body: response_body,
+        }
+        .into());
+    }
+    if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+        return Err(Unauthorized(response_body).into());
+    }
+    if status.is_server_error() {
+        return Err(Transient(format!(
+            "server error {}. \n\nresponse body:\n{}",
+            status, response_body
+        ))
+        .into());
+    }
+    PACER.lock().unwrap().speed_up();
+
+    serde_json::from_str(&response_body).map_err(|e| {
+        InvalidResponse {
+            body: response_body,
+            source: e,
+        }
+        .into()
+    })
+}
+
+/// Saves a response body gzipped into `dir`. The file is named after the time, the method, the
+/// path and the query of the request.
+async fn save_raw(dir: &Path, method: &Method, url: &Url, body: &str) -> Result<()> {
+    let name = format!("{}_{}_{}", method, url.path(), public_query(url))
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '=' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(150)
+        .collect::<String>();
+    let path = dir.join(format!(
+        "{}_{:06}_{}.json.gz",
+        Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+        RAW_SEQ.fetch_add(1, Ordering::SeqCst),
+        name
+    ));
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body.as_bytes())?;
+    // small enough to be written without blocking the runtime for long
+    std::fs::write(path, encoder.finish()?)?;
+    Ok(())
+}
+
+/// Returns the query of a url without the signature, which is a part of the credential.
+pub(crate) fn public_query(url: &Url) -> String {
+    url.query_pairs()
+        .filter(|(k, _)| !k.eq_ignore_ascii_case("signature"))
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Runs `f` until it succeeds, retrying [`Transient`] errors with exponential backoff and full
+/// jitter, and [`RateLimited`] errors after the time the exchange asked for. Other errors are
+/// returned immediately.
+pub async fn retry<T, F, Fut>(f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    retry_with(None, f).await
+}
+
+/// Same as [`retry`] but [`Transient`] errors are retried `max_retries` times if specified
+/// instead of the number given to [`configure`].
+pub async fn retry_with<T, F, Fut>(max_retries: Option<u32>, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let max_retries = max_retries.unwrap_or_else(|| MAX_RETRIES.load(Ordering::SeqCst));
+    let mut attempt = 0;
+    let mut rate_limited = 0;
+    loop {
+        let e = match f().await {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+        let wait = if let Some(limited) = e.chain().find_map(|c| c.downcast_ref::<RateLimited>()) {
+            if rate_limited >= MAX_RATE_LIMIT_RETRIES {
+                return Err(e);
+            }
+            rate_limited += 1;
+            limited
+                .retry_after
+                .unwrap_or_else(|| backoff(rate_limited - 1))
+        } else if e.chain().any(|c| c.is::<Transient>()) {
+            if attempt >= max_retries {
+                return Err(e);
+            }
+            attempt += 1;
+            backoff(attempt - 1)
+        } else {
+            return Err(e);
+        };
+        warn!(
+            "request failed, retrying in {:.1}s: {}",
+            wait.as_secs_f64(),
+            e
+        );
+        futures_timer::Delay::new(wait).await;
+    }
+}
+
+// exponential backoff with full jitter
+fn backoff(attempt: u32) -> Duration {
+    MAX_BACKOFF
+        .min(INITIAL_BACKOFF * 2u32.saturating_pow(attempt))
+        .mul_f64(rand::random())
+}
+
+/// An error which may succeed if the same request is sent again, e.g. a timeout, a connection
+/// reset or a 5xx response.
+#[derive(Debug)]
+pub struct Transient(pub String);
+
+impl Display for Transient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// A 401 or 403 response, which means the credential is invalid or lacks a permission.
+#[derive(Debug)]
+pub struct Unauthorized(pub String);
+
+impl Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unauthorized. \n\nresponse body:\n{}", self.0)
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// A credential which can't sign requests, e.g. a secret which isn't base64.
+#[derive(Debug)]
+pub struct InvalidCredential(pub String);
+
+impl Display for InvalidCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid credential: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCredential {}
+
+/// A response body which can't be parsed.
+#[derive(Debug)]
+pub struct InvalidResponse {
+    pub body: String,
+    source: serde_json::Error,
+}
+
+impl Display for InvalidResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unexpected response json format. \n\nresponse body:\n{}",
+            self.body
+        )
+    }
+}
+
+impl std::error::Error for InvalidResponse {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// A 429 response.
+#[derive(Debug)]
+pub struct RateLimited {
+    retry_after: Option<Duration>,
+    body: String,
+}
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rate limit exceeded. \n\nresponse body:\n{}", self.body)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Spaces requests out by `interval`, which is widened whenever a rate limit is hit and narrowed
+/// gradually while requests succeed.
+struct Pacer {
+    interval: Duration,
+    // when the next request is allowed to be sent
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    /// Returns how long to wait before sending a request.
+    fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let at = self.next.map_or(now, |next| next.max(now));
+        self.next = Some(at + self.interval);
+        at - now
+    }
+
+    fn slow_down(&mut self) {
+        let interval = (self.interval * 2).max(Duration::from_millis(100));
+        if interval <= MAX_INTERVAL {
+            info!(
+                "rate limit exceeded, slowing down to 1 request per {}ms",
+                interval.as_millis()
+            );
+        }
+        self.interval = interval.min(MAX_INTERVAL);
+    }
+
+    fn speed_up(&mut self) {
+        self.interval = self.interval.mul_f64(0.95);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::exchange::mock::MockTransport;
+
+    // runs retry_with over the errors in order, and returns the result and the number of calls
+    fn run(max_retries: u32, errors: Vec<anyhow::Error>) -> (Result<()>, usize) {
+        let mut errors = errors.into_iter();
+        let mut calls = 0;
+        let result = block_on(retry_with(Some(max_retries), || {
+            calls += 1;
+            futures::future::ready(errors.next().map_or(Ok(()), Err))
+        }));
+        (result, calls)
+    }
+
+    fn rate_limited() -> anyhow::Error {
+        RateLimited {
+            retry_after: Some(Duration::ZERO),
+            body: String::new(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn retries_rate_limits_after_the_time_asked_for() {
+        let (result, calls) = run(0, vec![rate_limited(), rate_limited()]);
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+
+        let errors = (0..=MAX_RATE_LIMIT_RETRIES)
+            .map(|_| rate_limited())
+            .collect();
+        let (result, calls) = run(0, errors);
+        assert!(result.unwrap_err().is::<RateLimited>());
+        assert_eq!(calls, MAX_RATE_LIMIT_RETRIES as usize + 1);
+    }
+
+    #[test]
+    fn gives_up_transient_errors_after_the_retries() {
+        let transient = || anyhow::Error::from(Transient("timeout".into())).context("fills");
+        let (result, calls) = run(0, vec![transient()]);
+        assert!(result.unwrap_err().chain().any(|c| c.is::<Transient>()));
+        assert_eq!(calls, 1);
+
+        let (result, calls) = run(1, vec![transient()]);
+        assert!(result.is_ok());
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn returns_other_errors_immediately() {
+        let (result, calls) = run(5, vec![Unauthorized(String::new()).into()]);
+        assert!(result.unwrap_err().is::<Unauthorized>());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn caps_the_backoff() {
+        for attempt in [0, 1, 5, 6, 40] {
+            let max = MAX_BACKOFF.min(INITIAL_BACKOFF * 2u32.saturating_pow(attempt));
+            assert!(backoff(attempt) <= max);
+        }
+    }
+
+    #[test]
+    fn slows_down_on_rate_limits_and_speeds_up_gradually() {
+        let mut pacer = Pacer {
+            interval: Duration::ZERO,
+            next: None,
+        };
+        assert_eq!(pacer.reserve(), Duration::ZERO);
+        pacer.slow_down();
+        assert_eq!(pacer.interval, Duration::from_millis(100));
+        pacer.slow_down();
+        assert_eq!(pacer.interval, Duration::from_millis(200));
+        for _ in 0..10 {
+            pacer.slow_down();
+        }
+        assert_eq!(pacer.interval, MAX_INTERVAL);
+        pacer.speed_up();
+        assert_eq!(pacer.interval, MAX_INTERVAL.mul_f64(0.95));
+
+        // requests are spaced out by the interval
+        pacer.interval = Duration::from_secs(1);
+        pacer.next = None;
+        assert_eq!(pacer.reserve(), Duration::ZERO);
+        let wait = pacer.reserve();
+        assert!(Duration::from_millis(900) < wait && wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn returns_429_as_rate_limited() {
+        let transport =
+            MockTransport::new().status("/limited", StatusCode::TOO_MANY_REQUESTS, "slow down");
+        let request = Request::new(
+            Method::GET,
+            Url::parse("https://example.com/limited").unwrap(),
+        );
+        let e = block_on(send_with::<serde_json::Value>(&transport, request)).unwrap_err();
+        // the other tests sharing the pacer aren't slowed down
+        *PACER.lock().unwrap() = Pacer {
+            interval: Duration::ZERO,
+            next: None,
+        };
+        let limited = e.downcast_ref::<RateLimited>().unwrap();
+        assert_eq!(limited.retry_after, None);
+        assert_eq!(limited.body, "slow down");
+    }
+}
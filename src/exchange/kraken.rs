@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::exchange::{
+    de_from_str, send, Exchange, Fill, FillType, InvalidCredential, Nonce, Page, RequestExt,
+    TimeRange,
+};
+
+const BASE_URL: &str = "https://api.kraken.com";
+
+pub struct Kraken {
+    credential: KrakenCredential,
+    // the decoded api_secret which signs requests
+    secret: Vec<u8>,
+    // pair -> (base asset, quote asset)
+    pairs: HashMap<String, (String, String)>,
+    nonce: Nonce,
+}
+
+impl Kraken {
+    pub async fn new(credential: KrakenCredential) -> Result<Self> {
+        let secret = match base64::decode(&credential.api_secret) {
+            Ok(secret) if !secret.is_empty() => secret,
+            Ok(_) => bail!(InvalidCredential(
+                "the api_secret of kraken is empty".into()
+            )),
+            Err(e) => bail!(InvalidCredential(format!(
+                "the api_secret of kraken is not base64: {}",
+                e
+            ))),
+        };
+        let pairs: HashMap<String, KrakenAssetPair> = Self::unwrap_response(
+            send(Request::new(
+                Method::GET,
+                Url::parse(&format!("{BASE_URL}/0/public/AssetPairs"))?,
+            ))
+            .await?,
+        )
+        .with_context(|| "failed to get asset pairs")?;
+        Ok(Self {
+            credential,
+            secret,
+            pairs: pairs
+                .into_iter()
+                .filter_map(|(name, pair)| {
+                    let (base, quote) = pair
+                        .wsname?
+                        .split_once('/')
+                        .map(|(b, q)| (b.to_string(), q.to_string()))?;
+                    Some((name, (base, quote)))
+                })
+                .collect(),
+            nonce: Nonce::default(),
+        })
+    }
+
+    fn unwrap_response<T>(response: KrakenResponse<T>) -> Result<T> {
+        match response.result {
+            Some(result) if response.error.is_empty() => Ok(result),
+            _ => bail!("kraken returned an error: {}", response.error.join(", ")),
+        }
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        form: Vec<(String, String)>,
+    ) -> Result<T> {
+        let mut rb = Request::new(Method::POST, Url::parse(&format!("{BASE_URL}{path}"))?);
+        rb.set_header("Content-Type", "application/x-www-form-urlencoded");
+        *rb.body_mut() = Some(
+            form.iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join("&")
+                .into(),
+        );
+        Self::unwrap_response(send(self.authenticate(rb)).await?)
+    }
+
+    /// Fetches all entries of an offset-paginated endpoint within the given time range.
+    async fn get_all<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        key: &str,
+        start_time: NaiveDateTime,
+        end_time: NaiveDateTime,
+    ) -> Result<Vec<(String, T)>> {
+        let mut entries = vec![];
+        loop {
+            let page: HashMap<String, serde_json::Value> = self
+                .post(
+                    path,
+                    vec![
+                        // start is exclusive and end is inclusive
+                        (
+                            "start".to_string(),
+                            (start_time.timestamp() - 1).to_string(),
+                        ),
+                        ("end".to_string(), end_time.timestamp().to_string()),
+                        ("ofs".to_string(), entries.len().to_string()),
+                    ],
+                )
+                .await?;
+            let count: usize = page
+                .get("count")
+                .and_then(|c| c.as_u64())
+                .unwrap_or_default() as usize;
+            let items: HashMap<String, T> = page
+                .get(key)
+                .map(|v| serde_json::from_value(v.clone()))
+                .transpose()?
+                .unwrap_or_default();
+            if items.is_empty() {
+                break;
+            }
+            entries.extend(items);
+            if entries.len() >= count {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl Exchange for Kraken {
+    type Cursor = NaiveDateTime;
+    type Record = KrakenRecord;
+
+    fn name(&self) -> &'static str {
+        "kraken"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let nonce = self.nonce.next();
+        // the nonce is prepended to the form of the body
+        let form = rb
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+            .unwrap_or_default();
+        let post_data = if form.is_empty() {
+            format!("nonce={nonce}")
+        } else {
+            format!("nonce={nonce}&{form}")
+        };
+        // API-Sign = HMAC-SHA512 of (URI path + SHA256(nonce + POST data)) with base64-decoded secret
+        let mut message = rb.url().path().as_bytes().to_vec();
+        message.extend(hmac_sha256::Hash::hash(
+            format!("{nonce}{post_data}").as_bytes(),
+        ));
+        rb.set_header("API-Key", &self.credential.api_key);
+        rb.set_header(
+            "API-Sign",
+            base64::encode(hmac_sha512::HMAC::mac(&message, &self.secret)),
+        );
+        *rb.body_mut() = Some(post_data.into());
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<KrakenRecord, NaiveDateTime>>> {
+        async move {
+            // kraken rate limit is strict, so use a wide window to reduce requests
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(30),
+                NaiveDate::from_ymd(2013, 9, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let mut records = vec![];
+            records.extend(
+                self.get_all::<KrakenTrade>(
+                    "/0/private/TradesHistory",
+                    "trades",
+                    start_time,
+                    end_time,
+                )
+                .await?
+                .into_iter()
+                .map(|(id, trade)| KrakenRecord::Trade(id, trade)),
+            );
+            records.extend(
+                self.get_all::<KrakenLedgerEntry>(
+                    "/0/private/Ledgers",
+                    "ledger",
+                    start_time,
+                    end_time,
+                )
+                .await?
+                .into_iter()
+                // trades are already collected via TradesHistory
+                .filter(|(_, entry)| entry.typ != "trade")
+                .map(|(id, entry)| KrakenRecord::Ledger(id, entry)),
+            );
+            records.retain(|r| {
+                let time = r.time().naive_utc();
+                start_time <= time && time < end_time
+            });
+            records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+            info!(
+                "{} records between {} and {}",
+                records.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((records, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, record: KrakenRecord) -> Fill {
+        let time = record.time();
+        match record {
+            KrakenRecord::Trade(id, t) => {
+                let assets = self.pairs.get(&t.pair);
+                Fill {
+                    fee: t.fee,
+                    // kraken charges fees in the quote currency by default
+                    fee_currency: assets.map(|(_, quote)| quote.clone()),
+                    fee_rate: None,
+                    future: None,
+                    id: id.as_str().into(),
+                    liquidity: None,
+                    base_currency: assets.map(|(base, _)| base.clone()),
+                    quote_currency: assets.map(|(_, quote)| quote.clone()),
+                    market: Some(t.pair),
+                    order_id: Some(t.ordertxid.into()),
+                    trade_id: Some(id),
+                    price: Some(t.price),
+                    side: t.typ.parse().ok(),
+                    size: t.vol,
+                    time,
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                    typ: Some(FillType::Order),
+                }
+            }
+            KrakenRecord::Ledger(id, e) => {
+                let typ = FillType::from(e.typ.as_str());
+                Fill {
+                    fee: e.fee,
+                    fee_currency: Some(e.asset.clone()),
+                    fee_rate: None,
+                    future: None,
+                    id: id.into(),
+                    liquidity: None,
+                    market: None,
+                    base_currency: Some(e.asset),
+                    quote_currency: None,
+                    order_id: None,
+                    trade_id: Some(e.refid),
+                    price: None,
+                    side: None,
+                    // the type tells the direction of deposits and withdrawals, and the others keep
+                    // the sign of the amount
+                    size: if matches!(typ, FillType::Deposit | FillType::Withdrawal) {
+                        e.amount.abs()
+                    } else {
+                        e.amount
+                    },
+                    time,
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                    typ: Some(typ),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct KrakenCredential {
+    api_key: String,
+    // base64 encoded private key
+    api_secret: String,
+}
+
+pub enum KrakenRecord {
+    Trade(String, KrakenTrade),
+    Ledger(String, KrakenLedgerEntry),
+}
+
+impl KrakenRecord {
+    fn time(&self) -> DateTime<Local> {
+        let seconds = match self {
+            KrakenRecord::Trade(_, t) => t.time,
+            KrakenRecord::Ledger(_, e) => e.time,
+        };
+        Local.timestamp_millis((seconds * 1000.0).round() as i64)
+    }
+}
+
+#[derive(Deserialize)]
+struct KrakenResponse<T> {
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct KrakenAssetPair {
+    wsname: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct KrakenTrade {
+    ordertxid: String,
+    pair: String,
+    time: f64,
+    #[serde(rename = "type")]
+    typ: String,
+    #[serde(deserialize_with = "de_from_str")]
+    price: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    vol: Decimal,
+}
+
+#[derive(Deserialize)]
+pub struct KrakenLedgerEntry {
+    refid: String,
+    time: f64,
+    #[serde(rename = "type")]
+    typ: String,
+    asset: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amount: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ExitCode;
+    use crate::exchange::tests::unified_rows;
+
+    // the entries of the result of a recorded response
+    fn entries<T: DeserializeOwned>(json: &str, key: &str) -> Vec<(String, T)> {
+        let page: HashMap<String, serde_json::Value> =
+            Kraken::unwrap_response(serde_json::from_str(json).unwrap()).unwrap();
+        serde_json::from_value::<HashMap<String, T>>(page[key].clone())
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn rejects_a_secret_which_is_not_base64() {
+        for secret in ["not base64!", ""] {
+            let credential = serde_json::from_value(serde_json::json!({
+                "api_key": "key",
+                "api_secret": secret,
+            }))
+            .unwrap();
+            let e = futures::executor::block_on(Kraken::new(credential))
+                .err()
+                .unwrap();
+            assert_eq!(ExitCode::of(&e), ExitCode::Auth);
+            assert!(format!("{:#}", e).contains("the api_secret of kraken"));
+        }
+    }
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let kraken = Kraken {
+            credential: KrakenCredential {
+                api_key: "key".into(),
+                api_secret: "c2VjcmV0".into(),
+            },
+            secret: b"secret".to_vec(),
+            pairs: HashMap::from([("XXBTZUSD".into(), ("XBT".into(), "USD".into()))]),
+            nonce: Nonce::default(),
+        };
+        let mut records = entries(
+            include_str!("../../fixtures/kraken/trades_history.json"),
+            "trades",
+        )
+        .into_iter()
+        .map(|(id, trade)| KrakenRecord::Trade(id, trade))
+        .collect::<Vec<_>>();
+        records.extend(
+            entries::<KrakenLedgerEntry>(
+                include_str!("../../fixtures/kraken/ledgers.json"),
+                "ledger",
+            )
+            .into_iter()
+            .filter(|(_, entry)| entry.typ != "trade")
+            .map(|(id, entry)| KrakenRecord::Ledger(id, entry)),
+        );
+        records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+        let fills = records
+            .into_iter()
+            .map(|r| kraken.to_fill(r))
+            .collect::<Vec<_>>();
+        // the currencies of a pair missing from the asset pairs are unknown
+        assert_eq!(
+            unified_rows("kraken", &fills).await,
+            [
+                "kraken,main,XXBTZUSD,XBT,USD,buy,46800.00000,0.00500000,0.37440,USD,2022-01-02T00:00:00.512Z,THVRQM-33VKH-UCI7BS,order",
+                "kraken,main,,DOT.S,,,,0.0125000000,0.0000000000,DOT.S,2022-01-01T14:30:00Z,LKRUTP-WC3DM-6EHR4S,staking",
+                "kraken,main,,XXBT,,,,0.0100000000,0.0001500000,XXBT,2022-01-01T09:40:00Z,LQ2M3J-KE3GX-BN6FQ5,withdrawal",
+                "kraken,main,XETHZEUR,,,sell,3290.51000,0.10000000,0.85553,,2022-01-01T06:53:20.100Z,TCWJEG-FL4SZ-3FKGH6,order",
+                "kraken,main,,ZUSD,,,,1000.0000,0.0000,ZUSD,2022-01-01T00:00:00Z,L7V4QK-B57NE-Y5YGNS,deposit",
+            ]
+        );
+        assert_eq!(
+            fills[0].order_id.as_ref().map(|id| id.as_str()),
+            Some("OQCLML-BW3P3-BUCMWZ")
+        );
+        assert_eq!(fills[2].trade_id.as_deref(), Some("QCC5MQB-WQ4JK-2VA6Y3"));
+    }
+}
@@ -0,0 +1,394 @@
+use anyhow::{bail, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use web_time::SystemTime;
+
+use crate::exchange::{de_from_str, send, Exchange, Fill, FillType, Page, RequestExt, TimeRange};
+
+const BASE_URL: &str = "https://api.kucoin.com";
+const TRADE_TYPES: [&str; 2] = ["TRADE", "MARGIN_TRADE"];
+
+pub struct Kucoin {
+    credential: KucoinCredential,
+}
+
+impl Kucoin {
+    pub fn new(credential: KucoinCredential) -> Self {
+        Self { credential }
+    }
+
+    /// Fetches all pages of a `currentPage` paginated endpoint.
+    async fn get_all<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<Vec<T>> {
+        let mut items = vec![];
+        let mut current_page = 1;
+        loop {
+            let mut params = params.to_vec();
+            params.push(("pageSize", "500".to_string()));
+            params.push(("currentPage", current_page.to_string()));
+            let response: KucoinResponse<KucoinPage<T>> = send(self.authenticate(Request::new(
+                Method::GET,
+                Url::parse_with_params(&format!("{BASE_URL}{path}"), &params)?,
+            )))
+            .await?;
+            let page = match response.data {
+                Some(data) if response.code == "200000" => data,
+                _ => bail!(
+                    "kucoin returned an error: {} (code {})",
+                    response.msg.unwrap_or_default(),
+                    response.code
+                ),
+            };
+            items.extend(page.items);
+            if current_page >= page.total_page {
+                break;
+            }
+            current_page += 1;
+        }
+        Ok(items)
+    }
+
+    async fn get_window(&self, start_time: i64, end_time: i64) -> Result<Vec<KucoinRecord>> {
+        let range = [
+            ("startAt", start_time.to_string()),
+            ("endAt", (end_time - 1).to_string()),
+        ];
+        let mut records = vec![];
+        for trade_type in TRADE_TYPES {
+            let mut params = range.to_vec();
+            params.push(("tradeType", trade_type.to_string()));
+            records.extend(
+                self.get_all::<KucoinFill>("/api/v1/fills", &params)
+                    .await?
+                    .into_iter()
+                    .map(KucoinRecord::Fill),
+            );
+        }
+        records.extend(
+            self.get_all::<KucoinDeposit>("/api/v1/deposits", &range)
+                .await?
+                .into_iter()
+                .filter(|d| d.status == "SUCCESS")
+                .map(KucoinRecord::Deposit),
+        );
+        records.extend(
+            self.get_all::<KucoinWithdrawal>("/api/v1/withdrawals", &range)
+                .await?
+                .into_iter()
+                .filter(|w| w.status == "SUCCESS")
+                .map(KucoinRecord::Withdrawal),
+        );
+        records.sort_by_key(|r| std::cmp::Reverse(r.created_at()));
+        Ok(records)
+    }
+}
+
+impl Exchange for Kucoin {
+    type Cursor = NaiveDateTime;
+    type Record = KucoinRecord;
+
+    fn name(&self) -> &'static str {
+        "kucoin"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let text = format!(
+            "{}{}{}{}",
+            ts,
+            rb.method(),
+            rb.url().path(),
+            rb.url()
+                .query()
+                .map(|q| format!("?{q}"))
+                .unwrap_or_default()
+        );
+        let secret = &self.credential.api_secret;
+        rb.set_header("KC-API-KEY", &self.credential.api_key);
+        rb.set_header("KC-API-TIMESTAMP", ts.to_string());
+        rb.set_header(
+            "KC-API-SIGN",
+            base64::encode(hmac_sha256::HMAC::mac(&text, secret)),
+        );
+        // api key version 2 requires the passphrase to be signed as well
+        rb.set_header(
+            "KC-API-PASSPHRASE",
+            base64::encode(hmac_sha256::HMAC::mac(&self.credential.passphrase, secret)),
+        );
+        rb.set_header("KC-API-KEY-VERSION", "2");
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<KucoinRecord, NaiveDateTime>>> {
+        async move {
+            // fills can be queried at most 7 days at once
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(7),
+                NaiveDate::from_ymd(2017, 9, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let records = self
+                .get_window(start_time.timestamp_millis(), end_time.timestamp_millis())
+                .await?;
+            info!(
+                "{} records between {} and {}",
+                records.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((records, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, record: KucoinRecord) -> Fill {
+        let time = Local.timestamp_millis(record.created_at());
+        match record {
+            KucoinRecord::Fill(f) => {
+                let (base, quote) = f
+                    .symbol
+                    .split_once('-')
+                    .map(|(b, q)| (Some(b.to_string()), Some(q.to_string())))
+                    .unwrap_or_default();
+                Fill {
+                    fee: f.fee,
+                    fee_currency: Some(f.fee_currency),
+                    fee_rate: Some(f.fee_rate),
+                    future: None,
+                    id: f.trade_id.as_str().into(),
+                    liquidity: f.liquidity.parse().ok(),
+                    market: Some(f.symbol),
+                    base_currency: base,
+                    quote_currency: quote,
+                    order_id: Some(f.order_id.into()),
+                    trade_id: Some(f.trade_id),
+                    price: Some(f.price),
+                    side: f.side.parse().ok(),
+                    size: f.size,
+                    time,
+                    typ: Some(FillType::Order),
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                }
+            }
+            KucoinRecord::Deposit(d) => Fill {
+                fee: d.fee,
+                fee_currency: Some(d.currency.clone()),
+                fee_rate: None,
+                future: None,
+                // deposits have no id, so the transaction id in the wallet is used
+                id: d.wallet_tx_id.into(),
+                liquidity: None,
+                market: None,
+                base_currency: Some(d.currency),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: d.amount,
+                time,
+                typ: Some(FillType::Deposit),
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+            },
+            KucoinRecord::Withdrawal(w) => Fill {
+                fee: w.fee,
+                fee_currency: Some(w.currency.clone()),
+                fee_rate: None,
+                future: None,
+                id: w.id.into(),
+                liquidity: None,
+                market: None,
+                base_currency: Some(w.currency),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: w.amount,
+                time,
+                typ: Some(FillType::Withdrawal),
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct KucoinCredential {
+    api_key: String,
+    api_secret: String,
+    passphrase: String,
+}
+
+pub enum KucoinRecord {
+    Fill(KucoinFill),
+    Deposit(KucoinDeposit),
+    Withdrawal(KucoinWithdrawal),
+}
+
+impl KucoinRecord {
+    // milliseconds
+    fn created_at(&self) -> i64 {
+        match self {
+            KucoinRecord::Fill(f) => f.created_at,
+            KucoinRecord::Deposit(d) => d.created_at,
+            KucoinRecord::Withdrawal(w) => w.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct KucoinResponse<T> {
+    code: String,
+    msg: Option<String>,
+    data: Option<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KucoinPage<T> {
+    total_page: u64,
+    items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KucoinFill {
+    symbol: String,
+    trade_id: String,
+    order_id: String,
+    side: String,
+    liquidity: String,
+    #[serde(deserialize_with = "de_from_str")]
+    price: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    size: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee_rate: Decimal,
+    fee_currency: String,
+    created_at: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KucoinDeposit {
+    currency: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amount: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    wallet_tx_id: String,
+    status: String,
+    created_at: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KucoinWithdrawal {
+    id: String,
+    currency: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amount: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    status: String,
+    created_at: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+    use crate::exchange::Liquidity;
+
+    // the items of a recorded page
+    fn items<T: DeserializeOwned>(json: &str) -> Vec<T> {
+        let response: KucoinResponse<KucoinPage<T>> = serde_json::from_str(json).unwrap();
+        assert_eq!(response.code, "200000");
+        let page = response.data.unwrap();
+        assert_eq!(page.total_page, 1);
+        page.items
+    }
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let kucoin = Kucoin::new(KucoinCredential {
+            api_key: "key".into(),
+            api_secret: "secret".into(),
+            passphrase: "passphrase".into(),
+        });
+        let mut records = items(include_str!("../../fixtures/kucoin/fills.json"))
+            .into_iter()
+            .map(KucoinRecord::Fill)
+            .collect::<Vec<_>>();
+        records.extend(
+            items(include_str!("../../fixtures/kucoin/deposits.json"))
+                .into_iter()
+                .map(KucoinRecord::Deposit),
+        );
+        records.extend(
+            items(include_str!("../../fixtures/kucoin/withdrawals.json"))
+                .into_iter()
+                .map(KucoinRecord::Withdrawal),
+        );
+        records.sort_by_key(|r| std::cmp::Reverse(r.created_at()));
+        let fills = records
+            .into_iter()
+            .map(|r| kucoin.to_fill(r))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("kucoin", &fills).await,
+            [
+                "kucoin,main,BTC-USDT,BTC,USDT,buy,46800.1,0.005,0.2340005,USDT,2022-01-02T00:00:00.512Z,5c35c02709e4f67d5266954e,order",
+                "kucoin,main,,BTC,,,,0.01000000,0.00050000,BTC,2022-01-01T09:40:00Z,5c2dc64e03aa675aa263f1ac,withdrawal",
+                "kucoin,main,ETH-BTC,ETH,BTC,sell,0.07,0.1,0.000007,BTC,2022-01-01T06:53:20.100Z,5c35c02709e4f67d5266954f,order",
+                "kucoin,main,,USDT,,,,1000.00000000,0.00000000,USDT,2022-01-01T00:00:00Z,2b5376d2fc7a5d2a8d2f6b8a9c42ab0f@0,deposit",
+            ]
+        );
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[0].fee_rate, Some(Decimal::new(1, 3)));
+        assert_eq!(fills[2].liquidity, Some(Liquidity::Taker));
+        assert_eq!(
+            fills[2].order_id.as_ref().map(|id| id.as_str()),
+            Some("5c35c02703aa673ceec2a169")
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use serde::ser::{
+    Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant, Serializer,
+};
+
+use crate::exchange::DecimalSeparator;
+
+// the newtype which `ser_decimal` wraps the decimals in, so that they can be told apart from the
+// other strings of a row. other serializers see through it
+pub(super) const DECIMAL: &str = "$ftx_history::Decimal";
+
+/// A row serialized with the decimal separator of its writer.
+pub struct Localized<'a, T: ?Sized>(pub &'a T, pub DecimalSeparator);
+
+impl<T: Serialize + ?Sized> Serialize for Localized<'_, T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        Wrapped {
+            value: self.0,
+            separator: self.1,
+            decimal: false,
+        }
+        .serialize(s)
+    }
+}
+
+// a value inside a row, which is serialized with the separator as well
+struct Wrapped<'a, T: ?Sized> {
+    value: &'a T,
+    separator: DecimalSeparator,
+    // inside the newtype of a decimal
+    decimal: bool,
+}
+
+impl<T: Serialize + ?Sized> Serialize for Wrapped<'_, T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(Localizer {
+            inner: s,
+            separator: self.separator,
+            decimal: self.decimal,
+        })
+    }
+}
+
+/// Passes everything to the serializer of the writer, replacing the separator of the decimals.
+struct Localizer<S> {
+    inner: S,
+    separator: DecimalSeparator,
+    decimal: bool,
+}
+
+impl<S> Localizer<S> {
+    fn wrap<'a, T: ?Sized>(&self, value: &'a T) -> Wrapped<'a, T> {
+        Wrapped {
+            value,
+            separator: self.separator,
+            decimal: self.decimal,
+        }
+    }
+}
+
+impl<S: Serializer> Serializer for Localizer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Compound<S::SerializeSeq>;
+    type SerializeTuple = Compound<S::SerializeTuple>;
+    type SerializeTupleStruct = Compound<S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Compound<S::SerializeTupleVariant>;
+    type SerializeMap = Compound<S::SerializeMap>;
+    type SerializeStruct = Compound<S::SerializeStruct>;
+    type SerializeStructVariant = Compound<S::SerializeStructVariant>;
+
+    fn serialize_str(self, v: &str) -> Result<S::Ok, S::Error> {
+        match (self.decimal, self.separator) {
+            (true, DecimalSeparator::Comma) => self.inner.serialize_str(&v.replace('.', ",")),
+            _ => self.inner.serialize_str(v),
+        }
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let value = Wrapped {
+            value,
+            separator: self.separator,
+            decimal: self.decimal || name == DECIMAL,
+        };
+        self.inner.serialize_newtype_struct(name, &value)
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i64(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i128(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u64(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u128(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f32(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_char(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_some(&value)
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_unit_variant(name, variant_index, variant)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let value = self.wrap(value);
+        self.inner
+            .serialize_newtype_variant(name, variant_index, variant, &value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        let (separator, decimal) = (self.separator, self.decimal);
+        self.inner.serialize_seq(len).map(|inner| Compound {
+            inner,
+            separator,
+            decimal,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        let (separator, decimal) = (self.separator, self.decimal);
+        self.inner.serialize_tuple(len).map(|inner| Compound {
+            inner,
+            separator,
+            decimal,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        let (separator, decimal) = (self.separator, self.decimal);
+        self.inner
+            .serialize_tuple_struct(name, len)
+            .map(|inner| Compound {
+                inner,
+                separator,
+                decimal,
+            })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        let (separator, decimal) = (self.separator, self.decimal);
+        self.inner
+            .serialize_tuple_variant(name, variant_index, variant, len)
+            .map(|inner| Compound {
+                inner,
+                separator,
+                decimal,
+            })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let (separator, decimal) = (self.separator, self.decimal);
+        self.inner.serialize_map(len).map(|inner| Compound {
+            inner,
+            separator,
+            decimal,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let (separator, decimal) = (self.separator, self.decimal);
+        self.inner
+            .serialize_struct(name, len)
+            .map(|inner| Compound {
+                inner,
+                separator,
+                decimal,
+            })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        let (separator, decimal) = (self.separator, self.decimal);
+        self.inner
+            .serialize_struct_variant(name, variant_index, variant, len)
+            .map(|inner| Compound {
+                inner,
+                separator,
+                decimal,
+            })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+/// The fields or the elements of a compound value, each serialized by [`Localizer`].
+struct Compound<C> {
+    inner: C,
+    separator: DecimalSeparator,
+    decimal: bool,
+}
+
+impl<C> Compound<C> {
+    fn wrap<'a, T: ?Sized>(&self, value: &'a T) -> Wrapped<'a, T> {
+        Wrapped {
+            value,
+            separator: self.separator,
+            decimal: self.decimal,
+        }
+    }
+}
+
+impl<C: SerializeSeq> SerializeSeq for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_element(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTuple> SerializeTuple for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_element(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleStruct> SerializeTupleStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_field(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleVariant> SerializeTupleVariant for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_field(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeMap> SerializeMap for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), C::Error> {
+        let key = self.wrap(key);
+        self.inner.serialize_key(&key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_value(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStruct> SerializeStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_field(key, &value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStructVariant> SerializeStructVariant for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_field(key, &value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use serde::Serialize;
+
+    use super::*;
+    use crate::exchange::{ser_decimal, ser_decimal_opt};
+
+    #[derive(Serialize)]
+    struct Row {
+        market: &'static str,
+        #[serde(serialize_with = "ser_decimal")]
+        price: Decimal,
+        #[serde(serialize_with = "ser_decimal_opt")]
+        fee: Option<Decimal>,
+        // not a decimal
+        version: &'static str,
+    }
+
+    async fn write(separator: DecimalSeparator) -> String {
+        let row = Row {
+            market: "BTC/USD",
+            price: "46200.5".parse().unwrap(),
+            fee: Some("-0.00002".parse().unwrap()),
+            version: "1.2",
+        };
+        let mut writer = csv_async::AsyncSerializer::from_writer(vec![]);
+        writer.serialize(Localized(&row, separator)).await.unwrap();
+        String::from_utf8(writer.into_inner().await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replaces_the_separator_of_the_decimals_only() {
+        assert_eq!(
+            write(DecimalSeparator::Dot).await,
+            "market,price,fee,version\nBTC/USD,46200.5,-0.00002,1.2\n"
+        );
+        assert_eq!(
+            write(DecimalSeparator::Comma).await,
+            "market,price,fee,version\nBTC/USD,\"46200,5\",\"-0,00002\",1.2\n"
+        );
+    }
+}
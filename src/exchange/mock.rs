@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use reqwest::{Request, StatusCode, Url};
+use serde_json::{json, Value};
+
+use crate::exchange::{Response, Transport};
+
+const FTX_FILLS: &str = include_str!("../../fixtures/ftx/fills.json");
+const FTX_ORDERS: &str = include_str!("../../fixtures/ftx/orders.json");
+const FTX_SUBACCOUNTS: &str = include_str!("../../fixtures/ftx/subaccounts.json");
+const FTX_TIME: &str = include_str!("../../fixtures/ftx/time.json");
+
+// more than the items in any second of the fixtures, otherwise the pagination can't go past it
+const FTX_PAGE_LIMIT: usize = 4;
+
+type Handler = Box<dyn Fn(&Url) -> Response + Send + Sync>;
+
+/// A transport which answers requests by their paths without sending them, to test the
+/// pagination and the writer without the exchanges.
+#[derive(Default)]
+pub struct MockTransport {
+    routes: Vec<(String, Handler)>,
+    // shared with the handles returned by `requests`
+    requests: Arc<Mutex<Vec<Url>>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers requests to the path with the handler.
+    pub fn route(
+        mut self,
+        path: impl Into<String>,
+        handler: impl Fn(&Url) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.push((path.into(), Box::new(handler)));
+        self
+    }
+
+    /// Answers requests to the path with the json body.
+    pub fn json(self, path: impl Into<String>, body: impl Into<String>) -> Self {
+        let body = body.into();
+        self.route(path, move |_| ok(body.clone()))
+    }
+
+    /// Answers requests to the path with the status and the body, e.g. a 429.
+    pub fn status(
+        self,
+        path: impl Into<String>,
+        status: StatusCode,
+        body: impl Into<String>,
+    ) -> Self {
+        let body = body.into();
+        self.route(path, move |_| Response {
+            status,
+            retry_after: None,
+            body: body.clone(),
+        })
+    }
+
+    /// Emulates the FTX API serving the fixtures in `fixtures/ftx`.
+    ///
+    /// Fills and orders are returned at most `FTX_PAGE_LIMIT` at once, newest first, as FTX
+    /// does with start_time and end_time, so the fixtures span several pages.
+    pub fn ftx() -> Self {
+        Self::new()
+            .json("/api/time", FTX_TIME)
+            .json("/api/subaccounts", FTX_SUBACCOUNTS)
+            .route("/api/fills", ftx_page(FTX_FILLS, "time", false))
+            .route(
+                "/api/orders/history",
+                ftx_page(FTX_ORDERS, "createdAt", true),
+            )
+    }
+
+    /// Returns a handle to the urls of the requests received so far, which stays valid after
+    /// the transport is moved into a client.
+    pub fn requests(&self) -> Arc<Mutex<Vec<Url>>> {
+        self.requests.clone()
+    }
+}
+
+impl Transport for MockTransport {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response>> {
+        async move {
+            let url = request.url().clone();
+            self.requests.lock().unwrap().push(url.clone());
+            let (_, handler) = self
+                .routes
+                .iter()
+                .find(|(path, _)| *path == url.path())
+                .ok_or_else(|| anyhow!("no mock response for {}", url.path()))?;
+            Ok(handler(&url))
+        }
+        .boxed()
+    }
+}
+
+fn ok(body: String) -> Response {
+    Response {
+        status: StatusCode::OK,
+        retry_after: None,
+        body,
+    }
+}
+
+/// Returns the items created in `[start_time, end_time)` of the query, newest first.
+fn ftx_page(
+    fixture: &'static str,
+    time_key: &'static str,
+    has_more_data: bool,
+) -> impl Fn(&Url) -> Response {
+    let mut items: Vec<(i64, Value)> = serde_json::from_str::<Vec<Value>>(fixture)
+        .expect("valid fixture")
+        .into_iter()
+        .map(|item| {
+            let time = item[time_key]
+                .as_str()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .expect("valid time in fixture");
+            (time.timestamp_millis(), item)
+        })
+        .collect();
+    items.sort_by_key(|(time, item)| (std::cmp::Reverse(*time), item["id"].as_u64()));
+    move |url| {
+        let param = |key| {
+            url.query_pairs()
+                .find(|(k, _)| k == key)
+                .and_then(|(_, v)| v.parse::<i64>().ok())
+        };
+        let start = param("start_time").unwrap_or(0) * 1000;
+        let end = param("end_time").map_or(i64::MAX, |t| t * 1000);
+        let matched = items
+            .iter()
+            .filter(|(time, _)| start <= *time && *time < end)
+            .collect::<Vec<_>>();
+        let page = matched
+            .iter()
+            .take(FTX_PAGE_LIMIT)
+            .map(|(_, item)| item.clone())
+            .collect::<Vec<_>>();
+        let mut body = json!({"success": true, "result": page});
+        if has_more_data {
+            body["hasMoreData"] = json!(matched.len() > FTX_PAGE_LIMIT);
+        }
+        ok(body.to_string())
+    }
+}
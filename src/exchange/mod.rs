@@ -0,0 +1,643 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Local, NaiveDateTime, Utc};
+use clap::ArgEnum;
+use futures::future::BoxFuture;
+use futures::{FutureExt, Stream, TryStreamExt};
+use reqwest::Request;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use web_time::SystemTime;
+
+use crate::error::{CollectorError, Usage};
+
+#[cfg(feature = "binance")]
+pub mod binance;
+#[cfg(feature = "bitfinex")]
+pub mod bitfinex;
+#[cfg(feature = "bybit")]
+pub mod bybit;
+#[cfg(feature = "coinbase")]
+pub mod coinbase;
+#[cfg(feature = "deribit")]
+pub mod deribit;
+#[cfg(feature = "dydx")]
+pub mod dydx;
+pub mod ftx;
+#[cfg(feature = "gateio")]
+pub mod gateio;
+mod http;
+#[cfg(feature = "kraken")]
+pub mod kraken;
+#[cfg(feature = "kucoin")]
+pub mod kucoin;
+mod localized;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+#[cfg(feature = "okx")]
+pub mod okx;
+pub mod replay;
+
+pub use http::{
+    configure, request_count, retry, retry_with, send, send_with, set_transport, ClientConfig,
+    InvalidCredential, InvalidResponse, RateLimited, RequestExt, Response, Transient, Transport,
+    Unauthorized,
+};
+pub use localized::Localized;
+
+/// A source of trade history.
+///
+/// An implementation knows how to authenticate its requests, how to walk through the history
+/// page by page and how to map its own records into [`Fill`]. The download/writer pipeline only
+/// talks to this trait.
+pub trait Exchange {
+    /// Pagination state carried from one request to the next.
+    type Cursor: Cursor;
+    /// A record as returned by the exchange API.
+    type Record;
+
+    /// An identifier of the exchange written to the `exchange` column, e.g. `ftx`.
+    fn name(&self) -> &'static str;
+
+    /// Signs a request with the account credential.
+    fn authenticate(&self, request: Request) -> Request;
+
+    /// Returns the cursor pointing to the newest page of the given range.
+    fn first_cursor(&self, range: &TimeRange) -> Self::Cursor;
+
+    /// Fetches the page pointed by the cursor.
+    ///
+    /// Records must be returned in time-descending order.
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        cursor: Self::Cursor,
+    ) -> BoxFuture<'a, Result<Page<Self::Record, Self::Cursor>>>;
+
+    /// Maps an exchange specific record into the output schema.
+    fn to_fill(&self, record: Self::Record) -> Fill;
+
+    /// Fetches the orders created in the range, which are used to audit the fills.
+    fn fetch_orders<'a>(&'a self, _range: &'a TimeRange) -> BoxFuture<'a, Result<Vec<Order>>> {
+        let name = self.name();
+        async move {
+            bail!(Usage(format!(
+                "the audit command is not supported for {}",
+                name
+            )))
+        }
+        .boxed()
+    }
+
+    /// Makes a cheap authenticated request to check the credential before a long download.
+    ///
+    /// The newest page of the last hour is fetched by default.
+    fn preflight(&self) -> BoxFuture<'_, Result<()>>
+    where
+        Self: Sync,
+    {
+        let now = Utc::now().naive_utc();
+        let range = TimeRange {
+            start: Some(now - Duration::hours(1)),
+            end: now,
+        };
+        async move {
+            self.fetch_page(&range, self.first_cursor(&range))
+                .await
+                .map(|_| ())
+        }
+        .boxed()
+    }
+
+    /// How many times a transient failure of a request is retried, or `None` to follow
+    /// [`configure`].
+    fn max_retries(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// An order with the size filled so far.
+pub struct Order {
+    // the same as the order_id of its fills
+    pub id: OrderId,
+    pub market: Option<String>,
+    pub filled_size: Decimal,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A cursor of pages in time-descending order.
+pub trait Cursor: Clone + Serialize + DeserializeOwned {
+    /// The exclusive end of the page pointed by this cursor.
+    fn end_time(&self) -> NaiveDateTime;
+
+    /// Returns the cursor of the page ending at `end_time`, which is used to skip pages which
+    /// can't be fetched.
+    fn with_end_time(&self, end_time: NaiveDateTime) -> Self;
+}
+
+// most exchanges are queried by windows which end at the cursor
+impl Cursor for NaiveDateTime {
+    fn end_time(&self) -> NaiveDateTime {
+        *self
+    }
+
+    fn with_end_time(&self, end_time: NaiveDateTime) -> Self {
+        end_time
+    }
+}
+
+// a page may be fetched again with the same cursor once, e.g. to confirm the end of the history
+const MAX_STALLED_PAGES: u32 = 3;
+
+/// Detects a download which doesn't move back to older pages, e.g. because of a clock issue or a
+/// broken response, and would otherwise run forever.
+#[derive(Default)]
+pub struct Progress {
+    stalled: u32,
+}
+
+impl Progress {
+    pub fn check<C: Cursor>(&mut self, cursor: &C, next: &C) -> Result<()> {
+        let (end, next_end) = (cursor.end_time(), next.end_time());
+        if next_end > end {
+            bail!(
+                "the next page ends at {} after the current page ending at {}. the clock or the response may be broken",
+                next_end,
+                end
+            );
+        }
+        if next_end < end {
+            self.stalled = 0;
+            return Ok(());
+        }
+        self.stalled += 1;
+        if self.stalled >= MAX_STALLED_PAGES {
+            bail!(
+                "the same page ending at {} is returned {} times in a row. the clock or the response may be broken",
+                end,
+                self.stalled + 1
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A stream of the fills of a range in time-descending order, which fetches the next page only
+/// when the fills of the previous one are consumed.
+pub trait FillsStream: Stream<Item = Result<Fill, CollectorError>> {}
+
+impl<S: Stream<Item = Result<Fill, CollectorError>>> FillsStream for S {}
+
+/// Fetches the fills of the range lazily, so that they can be written to any sink.
+///
+/// Failed requests are retried as well as in a download, and the stream ends with an error when
+/// they keep failing.
+pub fn fills<'a, E: Exchange>(exchange: &'a E, range: &'a TimeRange) -> impl FillsStream + 'a {
+    futures::stream::try_unfold(
+        (exchange.first_cursor(range), Progress::default()),
+        move |(cursor, mut progress)| async move {
+            match retry_with(exchange.max_retries(), || {
+                exchange.fetch_page(range, cursor.clone())
+            })
+            .await?
+            {
+                Some((records, next)) => {
+                    progress.check(&cursor, &next)?;
+                    let fills = records
+                        .into_iter()
+                        .map(|record| anyhow::Ok(exchange.to_fill(record)));
+                    anyhow::Ok(Some((futures::stream::iter(fills), (next, progress))))
+                }
+                None => Ok(None),
+            }
+        },
+    )
+    .try_flatten()
+    .map_err(CollectorError::from)
+}
+
+/// Fetches all fills of the range in time-descending order without writing them.
+pub async fn for_each_fill<E: Exchange>(
+    exchange: &E,
+    range: &TimeRange,
+    mut f: impl FnMut(Fill),
+) -> Result<()> {
+    let fills = fills(exchange, range);
+    futures::pin_mut!(fills);
+    while let Some(fill) = fills.try_next().await.map_err(CollectorError::into_error)? {
+        f(fill);
+    }
+    Ok(())
+}
+
+/// Records of a page together with the cursor of the next (older) page.
+/// `None` means the range is exhausted.
+pub type Page<R, C> = Option<(Vec<R>, C)>;
+
+pub struct TimeRange {
+    // inclusive
+    pub start: Option<NaiveDateTime>,
+    // exclusive
+    pub end: NaiveDateTime,
+}
+
+impl TimeRange {
+    pub fn is_after_start(&self, time: &NaiveDateTime) -> bool {
+        self.start.map(|st| st <= *time).unwrap_or(true)
+    }
+
+    /// Returns the start of the window of at most `width` which ends at `end`, or `None` if `end`
+    /// already reached the start of the range. `earliest` is used when no start is specified.
+    pub fn window_before(
+        &self,
+        end: NaiveDateTime,
+        width: Duration,
+        earliest: NaiveDateTime,
+    ) -> Option<NaiveDateTime> {
+        let start = self.start.unwrap_or(earliest);
+        (start < end).then(|| start.max(end - width))
+    }
+}
+
+/// A row of the output csv.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Fill {
+    #[serde(serialize_with = "ser_decimal")]
+    pub fee: Decimal,
+    pub fee_currency: Option<String>,
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub fee_rate: Option<Decimal>,
+    pub future: Option<String>,
+    pub id: FillId,
+    pub liquidity: Option<Liquidity>,
+    pub market: Option<String>,
+    pub base_currency: Option<String>,
+    pub quote_currency: Option<String>,
+    pub order_id: Option<OrderId>,
+    pub trade_id: Option<String>,
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub price: Option<Decimal>,
+    pub side: Option<Side>,
+    #[serde(serialize_with = "ser_decimal")]
+    pub size: Decimal,
+    pub time: DateTime<Local>,
+    #[serde(rename = "type")]
+    pub typ: Option<FillType>,
+    // columns for derivatives, which are empty unless the exchange provides them
+    // e.g. future, option
+    pub instrument_kind: Option<String>,
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub strike: Option<Decimal>,
+    // call or put
+    pub option_type: Option<String>,
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub index_price: Option<Decimal>,
+    // written as additional columns when set by `--convert-to`
+    #[serde(skip)]
+    pub conversion: Option<Conversion>,
+}
+
+/// The fee and the notional of a record converted into another currency at the time of the
+/// record.
+#[derive(Clone, Debug, Serialize)]
+pub struct Conversion {
+    pub converted_currency: String,
+    // empty when no rate of the fee currency is found at the time
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub converted_fee: Option<Decimal>,
+    // empty for records without a price or when no rate of the quote currency is found
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub converted_notional: Option<Decimal>,
+}
+
+impl Fill {
+    /// Returns the currency the fill is settled in, which is the quote currency or the fee
+    /// currency for the futures of ftx, which have no quote currency.
+    pub fn settlement_currency(&self) -> Option<&String> {
+        self.quote_currency.as_ref().or(self.fee_currency.as_ref())
+    }
+
+    /// Maps the fill into the schema shared by all exchanges.
+    pub fn unify<'a>(&'a self, exchange: &'a str, account: &'a str) -> UnifiedFill<'a> {
+        UnifiedFill {
+            exchange,
+            account,
+            market: self.market.as_deref(),
+            base_currency: self.base_currency.as_deref(),
+            quote_currency: self.quote_currency.as_deref(),
+            side: self.side,
+            price: self.price,
+            size: self.size,
+            fee: self.fee,
+            fee_currency: self.fee_currency.as_deref(),
+            timestamp: self.time.with_timezone(&Utc),
+            native_id: &self.id,
+            typ: self.typ.as_ref(),
+        }
+    }
+}
+
+/// A row of the output csv in the schema shared by all exchanges, so that histories of different
+/// exchanges can be analyzed together. [`Fill`] is written as is in the raw mode instead.
+#[derive(Debug, Serialize)]
+pub struct UnifiedFill<'a> {
+    pub exchange: &'a str,
+    pub account: &'a str,
+    pub market: Option<&'a str>,
+    pub base_currency: Option<&'a str>,
+    pub quote_currency: Option<&'a str>,
+    pub side: Option<Side>,
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub price: Option<Decimal>,
+    #[serde(serialize_with = "ser_decimal")]
+    pub size: Decimal,
+    // positive when charged
+    #[serde(serialize_with = "ser_decimal")]
+    pub fee: Decimal,
+    pub fee_currency: Option<&'a str>,
+    pub timestamp: DateTime<Utc>,
+    // an id of the record given by the exchange
+    pub native_id: &'a FillId,
+    #[serde(rename = "type")]
+    pub typ: Option<&'a FillType>,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl FromStr for Side {
+    type Err = String;
+
+    /// Parses `buy` or `sell` in any case as exchanges write them differently, e.g. `BUY`.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "buy" => Ok(Side::Buy),
+            "sell" => Ok(Side::Sell),
+            _ => Err(format!("unknown side: {}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Side {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        de_from_str(d)
+    }
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        })
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl Liquidity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Liquidity::Maker => "maker",
+            Liquidity::Taker => "taker",
+        }
+    }
+}
+
+impl FromStr for Liquidity {
+    type Err = String;
+
+    /// Parses `maker` or `taker` in any case.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "maker" => Ok(Liquidity::Maker),
+            "taker" => Ok(Liquidity::Taker),
+            _ => Err(format!("unknown liquidity: {}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Liquidity {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        de_from_str(d)
+    }
+}
+
+impl Display for Liquidity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A kind of a record. Kinds which only some exchanges have are kept as they are named there.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FillType {
+    Order,
+    Liquidation,
+    Deposit,
+    Withdrawal,
+    Funding,
+    Transfer,
+    Other(String),
+}
+
+impl FillType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            FillType::Order => "order",
+            FillType::Liquidation => "liquidation",
+            FillType::Deposit => "deposit",
+            FillType::Withdrawal => "withdrawal",
+            FillType::Funding => "funding",
+            FillType::Transfer => "transfer",
+            FillType::Other(typ) => typ,
+        }
+    }
+}
+
+impl From<&str> for FillType {
+    fn from(s: &str) -> Self {
+        match s {
+            "order" => FillType::Order,
+            "liquidation" => FillType::Liquidation,
+            "deposit" => FillType::Deposit,
+            "withdrawal" => FillType::Withdrawal,
+            "funding" => FillType::Funding,
+            "transfer" => FillType::Transfer,
+            other => FillType::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for FillType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for FillType {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for FillType {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        Ok(FillType::from(String::deserialize(d)?.as_str()))
+    }
+}
+
+/// Defines an id given by exchanges, which is a number in some exchanges and a string in others.
+macro_rules! id {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                Self(id.to_string())
+            }
+        }
+
+        impl From<u64> for $name {
+            fn from(id: u64) -> Self {
+                Self(id.to_string())
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+id!(
+    /// An id of a record, which is unique within an exchange.
+    FillId
+);
+id!(
+    /// An id of an order, which is shared by its fills.
+    OrderId
+);
+
+/// A nonce in microseconds for exchanges which require it to be strictly increasing among all
+/// requests of an api key.
+#[derive(Default)]
+pub struct Nonce(AtomicU64);
+
+impl Nonce {
+    pub fn next(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        let prev = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap();
+        now.max(prev + 1)
+    }
+}
+
+/// The decimal separator of the numbers in the output files.
+#[derive(ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecimalSeparator {
+    #[default]
+    Dot,
+    // for spreadsheets in the locales which use it, e.g. `0,001`
+    Comma,
+}
+
+pub(crate) fn ser_decimal<S: Serializer>(
+    d: &Decimal,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    // written with the separator of the row by Localized
+    s.serialize_newtype_struct(localized::DECIMAL, d)
+}
+
+pub(crate) fn ser_decimal_opt<S: Serializer>(
+    d: &Option<Decimal>,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => ser_decimal(d, s),
+        None => s.serialize_none(),
+    }
+}
+
+/// Deserializes a value represented as a json string, e.g. `"0.001"`.
+pub fn de_from_str<'de, D, T>(d: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(d)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Returns the fee of an exchange which represents charged fees as negative values, keeping a
+/// zero fee from becoming `-0`.
+pub(crate) fn charged_fee(fee: Decimal) -> Decimal {
+    if fee.is_zero() {
+        fee.abs()
+    } else {
+        -fee
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Returns the rows of the fills in the unified schema as the csv sink writes them, without
+    /// the header.
+    pub(crate) async fn unified_rows(exchange: &str, fills: &[Fill]) -> Vec<String> {
+        let mut writer = csv_async::AsyncWriterBuilder::new()
+            .has_headers(false)
+            .create_serializer(vec![]);
+        for fill in fills {
+            let row = fill.unify(exchange, "main");
+            writer
+                .serialize(Localized(&row, DecimalSeparator::Dot))
+                .await
+                .unwrap();
+        }
+        let content = String::from_utf8(writer.into_inner().await.unwrap()).unwrap();
+        content.lines().map(String::from).collect()
+    }
+}
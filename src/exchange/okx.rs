@@ -0,0 +1,508 @@
+use anyhow::{bail, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Method, Request, Url};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::exchange::{
+    charged_fee, de_from_str, send, Exchange, Fill, FillType, Liquidity, Page, RequestExt,
+    TimeRange,
+};
+
+const BASE_URL: &str = "https://www.okx.com";
+const INST_TYPES: [&str; 5] = ["SPOT", "MARGIN", "SWAP", "FUTURES", "OPTION"];
+
+pub struct Okx {
+    credential: OkxCredential,
+}
+
+impl Okx {
+    pub fn new(credential: OkxCredential) -> Self {
+        Self { credential }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<Vec<T>> {
+        let response: OkxResponse<T> = send(self.authenticate(Request::new(
+            Method::GET,
+            Url::parse_with_params(&format!("{BASE_URL}{path}"), params)?,
+        )))
+        .await?;
+        if response.code != "0" {
+            bail!(
+                "okx returned an error: {} (code {})",
+                response.msg,
+                response.code
+            );
+        }
+        Ok(response.data)
+    }
+
+    /// Fetches all items of an endpoint which returns older items than `after`.
+    ///
+    /// `key` extracts the value to pass as `after` from the oldest item of a page.
+    async fn get_all<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+        key: impl Fn(&T) -> String,
+    ) -> Result<Vec<T>> {
+        let mut items = vec![];
+        let mut after = None;
+        loop {
+            let mut params = params.to_vec();
+            params.push(("limit", "100".to_string()));
+            if let Some(after) = after {
+                params.push(("after", after));
+            }
+            let page = self.get::<T>(path, &params).await?;
+            let has_more = page.len() == 100;
+            after = page.last().map(&key);
+            items.extend(page);
+            if !has_more {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    async fn get_window(&self, start_time: i64, end_time: i64) -> Result<Vec<OkxRecord>> {
+        let range = [
+            ("begin", start_time.to_string()),
+            ("end", (end_time - 1).to_string()),
+        ];
+        let mut records = vec![];
+        for inst_type in INST_TYPES {
+            let mut params = range.to_vec();
+            params.push(("instType", inst_type.to_string()));
+            records.extend(
+                self.get_all::<OkxFill>("/api/v5/trade/fills-history", &params, |f| {
+                    f.bill_id.clone()
+                })
+                .await?
+                .into_iter()
+                .map(OkxRecord::Fill),
+            );
+        }
+        records.extend(
+            self.get_all::<OkxBill>("/api/v5/account/bills-archive", &range, |b| {
+                b.bill_id.clone()
+            })
+            .await?
+            .into_iter()
+            // trades are already collected as fills
+            .filter(|b| b.typ != "2")
+            .map(OkxRecord::Bill),
+        );
+        // funding account history is paginated by timestamp instead of id
+        let ts_range = [
+            ("before", (start_time - 1).to_string()),
+            ("after", end_time.to_string()),
+        ];
+        records.extend(
+            self.get_ts_paginated::<OkxDeposit>("/api/v5/asset/deposit-history", &ts_range, |d| {
+                d.ts
+            })
+            .await?
+            .into_iter()
+            // deposit success
+            .filter(|d| d.state == "2")
+            .map(OkxRecord::Deposit),
+        );
+        records.extend(
+            self.get_ts_paginated::<OkxWithdrawal>(
+                "/api/v5/asset/withdrawal-history",
+                &ts_range,
+                |w| w.ts,
+            )
+            .await?
+            .into_iter()
+            // withdrawal success
+            .filter(|w| w.state == "2")
+            .map(OkxRecord::Withdrawal),
+        );
+        records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+        Ok(records)
+    }
+
+    async fn get_ts_paginated<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        // (before, after) in milliseconds, both exclusive
+        range: &[(&str, String); 2],
+        ts: impl Fn(&T) -> i64,
+    ) -> Result<Vec<T>> {
+        let mut items = vec![];
+        let mut params = range.to_vec();
+        params.push(("limit", "100".to_string()));
+        loop {
+            let page = self.get::<T>(path, &params).await?;
+            let has_more = page.len() == 100;
+            let oldest = page.last().map(&ts);
+            items.extend(page);
+            match oldest {
+                Some(oldest) if has_more => params[1] = ("after", oldest.to_string()),
+                _ => break,
+            }
+        }
+        Ok(items)
+    }
+}
+
+impl Exchange for Okx {
+    type Cursor = NaiveDateTime;
+    type Record = OkxRecord;
+
+    fn name(&self) -> &'static str {
+        "okx"
+    }
+
+    fn authenticate(&self, mut rb: Request) -> Request {
+        let ts = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let text = format!(
+            "{}{}{}{}",
+            ts,
+            rb.method(),
+            rb.url().path(),
+            rb.url()
+                .query()
+                .map(|q| format!("?{q}"))
+                .unwrap_or_default()
+        );
+        rb.set_header("OK-ACCESS-KEY", &self.credential.api_key);
+        rb.set_header("OK-ACCESS-TIMESTAMP", ts);
+        rb.set_header("OK-ACCESS-PASSPHRASE", &self.credential.passphrase);
+        rb.set_header(
+            "OK-ACCESS-SIGN",
+            base64::encode(hmac_sha256::HMAC::mac(&text, &self.credential.api_secret)),
+        );
+
+        rb
+    }
+
+    fn first_cursor(&self, range: &TimeRange) -> NaiveDateTime {
+        range.end
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        range: &'a TimeRange,
+        end_time: NaiveDateTime,
+    ) -> BoxFuture<'a, Result<Page<OkxRecord, NaiveDateTime>>> {
+        async move {
+            let start_time = match range.window_before(
+                end_time,
+                Duration::days(7),
+                NaiveDate::from_ymd(2017, 1, 1).and_hms(0, 0, 0),
+            ) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            let records = self
+                .get_window(start_time.timestamp_millis(), end_time.timestamp_millis())
+                .await?;
+            info!(
+                "{} records between {} and {}",
+                records.len(),
+                start_time.format("%Y-%m-%dT%H:%M:%S"),
+                end_time.format("%Y-%m-%dT%H:%M:%S"),
+            );
+            Ok(Some((records, start_time)))
+        }
+        .boxed()
+    }
+
+    fn to_fill(&self, record: OkxRecord) -> Fill {
+        let time = Local.timestamp_millis(record.time());
+        match record {
+            OkxRecord::Fill(f) => {
+                let is_spot = f.inst_type == "SPOT" || f.inst_type == "MARGIN";
+                let (base, quote) = f
+                    .inst_id
+                    .split_once('-')
+                    .filter(|_| is_spot)
+                    .map(|(b, q)| (Some(b.to_string()), Some(q.to_string())))
+                    .unwrap_or_default();
+                Fill {
+                    // okx represents charged fees as negative values
+                    fee: charged_fee(f.fee),
+                    fee_currency: Some(f.fee_ccy),
+                    fee_rate: None,
+                    future: (!is_spot).then(|| f.inst_id.clone()),
+                    id: f.bill_id.into(),
+                    liquidity: match f.exec_type.as_str() {
+                        "M" => Some(Liquidity::Maker),
+                        "T" => Some(Liquidity::Taker),
+                        _ => None,
+                    },
+                    market: Some(f.inst_id),
+                    base_currency: base,
+                    quote_currency: quote,
+                    order_id: Some(f.ord_id.into()),
+                    trade_id: Some(f.trade_id),
+                    price: Some(f.fill_px),
+                    side: f.side.parse().ok(),
+                    size: f.fill_sz,
+                    time,
+                    instrument_kind: None,
+                    strike: None,
+                    option_type: None,
+                    index_price: None,
+                    conversion: None,
+                    typ: Some(FillType::Order),
+                }
+            }
+            OkxRecord::Bill(b) => Fill {
+                fee: charged_fee(b.fee),
+                fee_currency: Some(b.ccy.clone()),
+                fee_rate: None,
+                future: None,
+                id: b.bill_id.into(),
+                liquidity: None,
+                market: b.inst_id.filter(|i| !i.is_empty()),
+                base_currency: Some(b.ccy),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: b.bal_chg,
+                time,
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+                typ: Some(FillType::from(bill_type_name(&b.typ).as_str())),
+            },
+            OkxRecord::Deposit(d) => Fill {
+                fee: Decimal::ZERO,
+                fee_currency: Some(d.ccy.clone()),
+                fee_rate: None,
+                future: None,
+                id: d.dep_id.into(),
+                liquidity: None,
+                market: None,
+                base_currency: Some(d.ccy),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: d.amt,
+                time,
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+                typ: Some(FillType::Deposit),
+            },
+            OkxRecord::Withdrawal(w) => Fill {
+                fee: w.fee,
+                fee_currency: Some(w.ccy.clone()),
+                fee_rate: None,
+                future: None,
+                id: w.wd_id.into(),
+                liquidity: None,
+                market: None,
+                base_currency: Some(w.ccy),
+                quote_currency: None,
+                order_id: None,
+                trade_id: None,
+                price: None,
+                side: None,
+                size: w.amt,
+                time,
+                instrument_kind: None,
+                strike: None,
+                option_type: None,
+                index_price: None,
+                conversion: None,
+                typ: Some(FillType::Withdrawal),
+            },
+        }
+    }
+}
+
+fn bill_type_name(typ: &str) -> String {
+    match typ {
+        "1" => "transfer",
+        "2" => "trade",
+        "3" => "delivery",
+        "5" => "liquidation",
+        "6" => "margin_transfer",
+        "7" => "interest",
+        "8" => "funding",
+        "9" => "adl",
+        "10" => "clawback",
+        _ => return format!("bill_{typ}"),
+    }
+    .to_string()
+}
+
+#[derive(Deserialize)]
+pub struct OkxCredential {
+    api_key: String,
+    api_secret: String,
+    passphrase: String,
+}
+
+pub enum OkxRecord {
+    Fill(OkxFill),
+    Bill(OkxBill),
+    Deposit(OkxDeposit),
+    Withdrawal(OkxWithdrawal),
+}
+
+impl OkxRecord {
+    // milliseconds
+    fn time(&self) -> i64 {
+        match self {
+            OkxRecord::Fill(f) => f.ts,
+            OkxRecord::Bill(b) => b.ts,
+            OkxRecord::Deposit(d) => d.ts,
+            OkxRecord::Withdrawal(w) => w.ts,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OkxResponse<T> {
+    code: String,
+    msg: String,
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkxFill {
+    inst_type: String,
+    inst_id: String,
+    trade_id: String,
+    ord_id: String,
+    bill_id: String,
+    #[serde(deserialize_with = "de_from_str")]
+    fill_px: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fill_sz: Decimal,
+    side: String,
+    exec_type: String,
+    fee_ccy: String,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    ts: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkxBill {
+    bill_id: String,
+    ccy: String,
+    inst_id: Option<String>,
+    #[serde(deserialize_with = "de_from_str")]
+    bal_chg: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    #[serde(rename = "type")]
+    typ: String,
+    #[serde(deserialize_with = "de_from_str")]
+    ts: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkxDeposit {
+    dep_id: String,
+    ccy: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amt: Decimal,
+    state: String,
+    #[serde(deserialize_with = "de_from_str")]
+    ts: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkxWithdrawal {
+    wd_id: String,
+    ccy: String,
+    #[serde(deserialize_with = "de_from_str")]
+    amt: Decimal,
+    #[serde(deserialize_with = "de_from_str")]
+    fee: Decimal,
+    state: String,
+    #[serde(deserialize_with = "de_from_str")]
+    ts: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::tests::unified_rows;
+
+    // the data of a recorded response
+    fn data<T: DeserializeOwned>(json: &str) -> Vec<T> {
+        let response: OkxResponse<T> = serde_json::from_str(json).unwrap();
+        assert_eq!(response.code, "0");
+        response.data
+    }
+
+    #[tokio::test]
+    async fn maps_the_recorded_responses_into_fills() {
+        let okx = Okx::new(OkxCredential {
+            api_key: "key".into(),
+            api_secret: "secret".into(),
+            passphrase: "passphrase".into(),
+        });
+        let mut records = data(include_str!("../../fixtures/okx/fills_history.json"))
+            .into_iter()
+            .map(OkxRecord::Fill)
+            .collect::<Vec<_>>();
+        records.extend(
+            data(include_str!("../../fixtures/okx/bills_archive.json"))
+                .into_iter()
+                .map(OkxRecord::Bill),
+        );
+        records.extend(
+            data(include_str!("../../fixtures/okx/deposit_history.json"))
+                .into_iter()
+                .map(OkxRecord::Deposit),
+        );
+        records.extend(
+            data(include_str!("../../fixtures/okx/withdrawal_history.json"))
+                .into_iter()
+                .map(OkxRecord::Withdrawal),
+        );
+        records.sort_by_key(|r| std::cmp::Reverse(r.time()));
+        let fills = records
+            .into_iter()
+            .map(|r| okx.to_fill(r))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unified_rows("okx", &fills).await,
+            [
+                "okx,main,BTC-USDT,BTC,USDT,buy,46800.1,0.005,0.000004,BTC,2022-01-02T00:00:00.512Z,3925653283163856896,order",
+                "okx,main,ETH-USDT-SWAP,USDT,,,,-0.5286267769,0,USDT,2022-01-01T16:00:00Z,3925653283163856898,funding",
+                "okx,main,,BTC,,,,0.01,0.0002,BTC,2022-01-01T09:40:00Z,58041919,withdrawal",
+                "okx,main,,USDT,,,,-0.0021,0,USDT,2022-01-01T08:00:00Z,3925653283163856899,interest",
+                "okx,main,ETH-USDT-SWAP,,,sell,3290.5,10,0.164525,USDT,2022-01-01T06:53:20.100Z,3925653283163856897,order",
+                "okx,main,,USDT,,,,1000,0,USDT,2022-01-01T00:00:00Z,88165462,deposit",
+            ]
+        );
+        assert_eq!(fills[0].future, None);
+        assert_eq!(fills[0].liquidity, Some(Liquidity::Maker));
+        assert_eq!(fills[4].future.as_deref(), Some("ETH-USDT-SWAP"));
+        assert_eq!(fills[4].liquidity, Some(Liquidity::Taker));
+        assert_eq!(fills[4].trade_id.as_deref(), Some("456"));
+    }
+}
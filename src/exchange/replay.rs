@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::*;
+use reqwest::{Request, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::exchange::http::public_query;
+use crate::exchange::{Response, Transport};
+
+/// A request and its response saved by [`Recorder`], one file per request.
+#[derive(Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    path: String,
+    // without the signature of the request
+    query: String,
+    status: u16,
+    // seconds
+    retry_after: Option<u64>,
+    body: String,
+}
+
+/// Sends requests with another transport and saves each request and its response into a
+/// directory, to be replayed by [`Replayer`] later.
+pub struct Recorder<T> {
+    inner: T,
+    dir: PathBuf,
+    seq: AtomicU64,
+}
+
+impl<T: Transport> Recorder<T> {
+    pub fn new(dir: impl Into<PathBuf>, inner: T) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        // appends to an earlier recording in the directory, which is replayed first
+        let seq = recorded_files(&dir)?.len() as u64;
+        Ok(Self {
+            inner,
+            dir,
+            seq: AtomicU64::new(seq),
+        })
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response>> {
+        async move {
+            let (method, url) = (request.method().to_string(), request.url().clone());
+            let response = self.inner.execute(request).await?;
+            let interaction = Interaction {
+                method,
+                path: url.path().to_string(),
+                query: public_query(&url),
+                status: response.status.as_u16(),
+                retry_after: response.retry_after.map(|d| d.as_secs()),
+                body: response.body,
+            };
+            let path = self.dir.join(format!(
+                "{:06}.json",
+                self.seq.fetch_add(1, Ordering::SeqCst)
+            ));
+            // small enough to be written without blocking the runtime for long
+            std::fs::write(&path, serde_json::to_vec_pretty(&interaction)?)
+                .with_context(|| format!("failed to record the request into {}", path.display()))?;
+            Ok(Response {
+                status: response.status,
+                retry_after: response.retry_after,
+                body: interaction.body,
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Answers requests with the responses saved by [`Recorder`] in the recorded order, without the
+/// network.
+///
+/// The requests must be sent in the same order as recorded, which is the case when the same
+/// command is run with the same range. The queries are not compared since they may contain the
+/// current time.
+pub struct Replayer {
+    interactions: Mutex<VecDeque<Interaction>>,
+}
+
+impl Replayer {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let interactions = recorded_files(dir.as_ref())?
+            .into_iter()
+            .map(|path| {
+                std::fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+                    .with_context(|| format!("failed to read {}", path.display()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            interactions: Mutex::new(interactions),
+        })
+    }
+}
+
+impl Transport for Replayer {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response>> {
+        async move {
+            let url = request.url();
+            let interaction = self.interactions.lock().unwrap().pop_front().ok_or_else(|| {
+                anyhow!(
+                    "{} {} is requested after all recorded requests are replayed",
+                    request.method(),
+                    url.path()
+                )
+            })?;
+            if interaction.method != request.method().as_str() || interaction.path != url.path() {
+                return Err(anyhow!(
+                    "{} {} is requested but {} {} was recorded. the recording is of another command",
+                    request.method(),
+                    url.path(),
+                    interaction.method,
+                    interaction.path
+                ));
+            }
+            if interaction.query != public_query(url) {
+                debug!(
+                    "replaying {}?{} for {}?{}",
+                    interaction.path,
+                    interaction.query,
+                    url.path(),
+                    public_query(url)
+                );
+            }
+            Ok(Response {
+                status: StatusCode::from_u16(interaction.status)?,
+                retry_after: interaction.retry_after.map(Duration::from_secs),
+                body: interaction.body,
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Returns the files saved by [`Recorder`] in the recorded order.
+fn recorded_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .filter(|path| !matches!(path, Ok(p) if p.extension().is_none_or(|e| e != "json")))
+        .collect::<Result<Vec<_>>>()?;
+    files.sort();
+    Ok(files)
+}
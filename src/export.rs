@@ -0,0 +1,847 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ArgEnum;
+use csv_async::AsyncWriter;
+use log::*;
+use rust_decimal::Decimal;
+use tokio::fs::File;
+
+use crate::archive::{self, Record};
+use crate::exchange::{FillType, Side};
+use crate::pnl::{CostMethod, Position};
+
+/// A csv template of a crypto tax service.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    // the universal format of koinly
+    Koinly,
+    // the custom exchange import of cointracking
+    Cointracking,
+    // the advanced manual import of crypto tax calculator
+    Cryptotaxcalculator,
+    // the double entry transactions of beancount
+    Beancount,
+    // the double entry transactions of hledger, which ledger reads as well
+    Hledger,
+}
+
+impl ExportFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Koinly => "koinly",
+            ExportFormat::Cointracking => "cointracking",
+            ExportFormat::Cryptotaxcalculator => "cryptotaxcalculator",
+            ExportFormat::Beancount => "beancount",
+            ExportFormat::Hledger => "hledger",
+        }
+    }
+
+    fn file_name(&self, account: &str) -> String {
+        match self {
+            ExportFormat::Beancount => format!("{}.beancount", account),
+            ExportFormat::Hledger => format!("{}.journal", account),
+            _ => format!("{}.{}.csv", account, self.name()),
+        }
+    }
+
+    fn headers(&self) -> &'static [&'static str] {
+        match self {
+            ExportFormat::Koinly => &[
+                "Date",
+                "Sent Amount",
+                "Sent Currency",
+                "Received Amount",
+                "Received Currency",
+                "Fee Amount",
+                "Fee Currency",
+                "Net Worth Amount",
+                "Net Worth Currency",
+                "Label",
+                "Description",
+                "TxHash",
+            ],
+            ExportFormat::Cointracking => &[
+                "Type",
+                "Buy Amount",
+                "Buy Currency",
+                "Sell Amount",
+                "Sell Currency",
+                "Fee",
+                "Fee Currency",
+                "Exchange",
+                "Trade-Group",
+                "Comment",
+                "Date",
+                "Tx-ID",
+            ],
+            ExportFormat::Cryptotaxcalculator => &[
+                "Timestamp (UTC)",
+                "Type",
+                "Base Currency",
+                "Base Amount",
+                "Quote Currency (Optional)",
+                "Quote Amount (Optional)",
+                "Fee Currency (Optional)",
+                "Fee Amount (Optional)",
+                "From (Optional)",
+                "To (Optional)",
+                "Blockchain (Optional)",
+                "ID (Optional)",
+                "Description (Optional)",
+            ],
+            // plain text without a header
+            ExportFormat::Beancount | ExportFormat::Hledger => &[],
+        }
+    }
+}
+
+/// The accounts of the plain text accounting formats.
+#[derive(Clone, Debug)]
+pub struct LedgerAccounts {
+    // holding the balances of the account of the exchange
+    pub assets: String,
+    // charged by the trades and the withdrawals, and reduced by the rebates
+    pub fees: String,
+    // the realized pnl of the derivatives and the funding payments
+    pub pnl: String,
+    // the other side of the deposits, the withdrawals and the transfers
+    pub transfers: String,
+}
+
+impl LedgerAccounts {
+    /// Returns the default accounts, e.g. `Assets:Ftx:Main` for the account `main` of ftx.
+    pub fn new(exchange: &str, account: &str) -> Self {
+        Self {
+            assets: format!("Assets:{}:{}", component(exchange), component(account)),
+            fees: "Expenses:Fees".to_string(),
+            pnl: "Income:Trading".to_string(),
+            transfers: "Equity:Transfers".to_string(),
+        }
+    }
+}
+
+/// Makes a name into a component of an account name, which starts with a capital letter.
+fn component(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase().to_string() + chars.as_str(),
+        // e.g. a subaccount named by a number
+        _ => format!("X{}", name),
+    }
+}
+
+/// An amount of a currency.
+type Amount = (Decimal, String);
+
+/// What a record moves in and out of the account, independent of the formats.
+enum Movement {
+    Trade {
+        side: Side,
+        received: Amount,
+        sent: Amount,
+    },
+    Deposit(Amount),
+    Withdrawal(Amount),
+    // the realized pnl of a derivative or a funding payment, negative when lost
+    Pnl(Amount),
+    // a negative fee, e.g. a maker rebate
+    Rebate(Amount),
+}
+
+/// A movement of a record with its fee.
+struct Entry {
+    time: DateTime<Utc>,
+    movement: Movement,
+    fee: Option<Amount>,
+    description: String,
+    id: Option<String>,
+}
+
+/// Maps the trades, the deposits, the withdrawals and the funding payments of the archive into
+/// the csv template of the tax service and writes it into `<account>.<format>.csv` of `outdir`,
+/// or into the double entry transactions of `accounts` in `<account>.beancount` or
+/// `<account>.journal`.
+///
+/// A spot trade is a trade of its base and quote currencies. A trade of a derivative is the pnl
+/// it realizes at the average entry price in its settlement currency, since the services take
+/// derivatives as gains and losses. All files are read for the entry prices, and the records in
+/// the range are written.
+pub async fn export(
+    outdir: &Path,
+    account: &str,
+    exchange: &str,
+    format: ExportFormat,
+    accounts: &LedgerAccounts,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+) -> Result<PathBuf> {
+    let records = archive::read_range(outdir, account, ..end).await?;
+
+    let derivatives = archive::derivative_markets(&records);
+    let mut positions: HashMap<String, Position> = HashMap::new();
+    let mut exported = vec![];
+    let mut skipped = BTreeSet::new();
+    for record in &records {
+        let mapped = entries(record, &derivatives, &mut positions);
+        if start.is_some_and(|start| record.date < start) {
+            continue;
+        }
+        match mapped {
+            Some(mapped) => exported.extend(mapped),
+            None => {
+                skipped.insert(record.typ.as_ref().map_or("(none)", |t| t.as_str()));
+            }
+        }
+    }
+    if !skipped.is_empty() {
+        warn!(
+            "records of the types {} are not exported since they have no mapping",
+            skipped.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    let path = outdir.join(format.file_name(account));
+    if let ExportFormat::Beancount | ExportFormat::Hledger = format {
+        let journal = journal(&exported, format, accounts);
+        tokio::fs::write(&path, journal)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        info!(
+            "{} transactions are exported for {} into {}",
+            exported.len(),
+            format.name(),
+            path.display()
+        );
+        return Ok(path);
+    }
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncWriter::from_writer(file);
+    writer
+        .write_record(format.headers())
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    for entry in &exported {
+        let row = match format {
+            ExportFormat::Koinly => koinly(entry),
+            ExportFormat::Cointracking => cointracking(entry, exchange),
+            ExportFormat::Cryptotaxcalculator => cryptotaxcalculator(entry),
+            ExportFormat::Beancount | ExportFormat::Hledger => unreachable!("written as text"),
+        };
+        writer
+            .write_record(&row)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!(
+        "{} records are exported for {} into {}",
+        exported.len(),
+        format.name(),
+        path.display()
+    );
+    Ok(path)
+}
+
+/// Maps a record into its entries, with another one for a rebate, or `None` if it has no
+/// mapping.
+fn entries(
+    record: &Record,
+    derivatives: &BTreeSet<String>,
+    positions: &mut HashMap<String, Position>,
+) -> Option<Vec<Entry>> {
+    let amount = |size: Decimal, currency: &Option<String>| Some((size, currency.clone()?));
+    let mut fee = amount(record.fee, &record.fee_currency);
+    let movement = match (&record.typ, record.side, record.price) {
+        (Some(FillType::Deposit), _, _) => {
+            Movement::Deposit(amount(record.size.abs(), &record.base_currency)?)
+        }
+        (Some(FillType::Withdrawal), _, _) => {
+            // the fee of a withdrawal is in the currency withdrawn
+            fee = amount(record.fee, &record.base_currency);
+            Movement::Withdrawal(amount(record.size.abs(), &record.base_currency)?)
+        }
+        // signed, in or out of the account
+        (Some(FillType::Transfer), _, _) if record.size.is_sign_negative() => {
+            Movement::Withdrawal(amount(record.size.abs(), &record.base_currency)?)
+        }
+        (Some(FillType::Transfer), _, _) => {
+            Movement::Deposit(amount(record.size, &record.base_currency)?)
+        }
+        // the amount is in the base currency of the record, e.g. USDC on dYdX
+        (Some(FillType::Funding), _, _) => {
+            let currency = record.base_currency.clone().or(record.fee_currency.clone());
+            Movement::Pnl(amount(record.size, &currency)?)
+        }
+        (None | Some(FillType::Order | FillType::Liquidation), Some(side), Some(price)) => {
+            let market = record.market.as_ref()?;
+            let size = match side {
+                Side::Buy => record.size.abs(),
+                Side::Sell => -record.size.abs(),
+            };
+            match (&record.base_currency, &record.quote_currency) {
+                (Some(base), Some(quote)) if !derivatives.contains(market) => {
+                    let base = (size.abs(), base.clone());
+                    let quote = (size.abs() * price, quote.clone());
+                    match side {
+                        Side::Buy => Movement::Trade {
+                            side,
+                            received: base,
+                            sent: quote,
+                        },
+                        Side::Sell => Movement::Trade {
+                            side,
+                            received: quote,
+                            sent: base,
+                        },
+                    }
+                }
+                _ => {
+                    let mut realized = positions.entry(market.clone()).or_default().fill(
+                        CostMethod::Average,
+                        size,
+                        price,
+                    );
+                    let currency = record.settlement_currency().cloned();
+                    // the fee is a part of the pnl in the settlement currency
+                    if record.fee_currency == currency {
+                        realized -= record.fee;
+                        fee = None;
+                    }
+                    Movement::Pnl(amount(realized, &currency)?)
+                }
+            }
+        }
+        _ => return None,
+    };
+    let entry = |movement, fee, id: Option<String>| Entry {
+        time: record.time.with_timezone(&Utc),
+        movement,
+        fee,
+        description: record.market.clone().unwrap_or_default(),
+        id,
+    };
+    let mut entries = vec![];
+    let paid = fee.clone().filter(|(fee, _)| *fee > Decimal::ZERO);
+    // e.g. an opening fill of a derivative without a fee
+    let empty = matches!(&movement, Movement::Pnl((pnl, _)) if pnl.is_zero()) && paid.is_none();
+    if !empty {
+        entries.push(entry(movement, paid, record.id.clone()));
+    }
+    // negative fees are received
+    if let Some((rebate, currency)) = fee.filter(|(fee, _)| *fee < Decimal::ZERO) {
+        let id = record.id.as_ref().map(|id| format!("{}-rebate", id));
+        entries.push(entry(Movement::Rebate((-rebate, currency)), None, id));
+    }
+    Some(entries)
+}
+
+fn number(amount: Decimal) -> String {
+    amount.normalize().to_string()
+}
+
+fn koinly(entry: &Entry) -> Vec<String> {
+    let (sent, received, label) = match &entry.movement {
+        Movement::Trade { received, sent, .. } => (Some(sent), Some(received), ""),
+        Movement::Deposit(amount) => (None, Some(amount), ""),
+        Movement::Withdrawal(amount) => (Some(amount), None, ""),
+        Movement::Pnl(amount) if amount.0.is_sign_negative() => {
+            (Some(amount), None, "realized gain")
+        }
+        Movement::Pnl(amount) => (None, Some(amount), "realized gain"),
+        Movement::Rebate(amount) => (None, Some(amount), "cashback"),
+    };
+    vec![
+        entry.time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        sent.map(|(a, _)| number(a.abs())).unwrap_or_default(),
+        sent.map(|(_, c)| c.clone()).unwrap_or_default(),
+        received.map(|(a, _)| number(*a)).unwrap_or_default(),
+        received.map(|(_, c)| c.clone()).unwrap_or_default(),
+        entry
+            .fee
+            .as_ref()
+            .map(|(f, _)| number(*f))
+            .unwrap_or_default(),
+        entry
+            .fee
+            .as_ref()
+            .map(|(_, c)| c.clone())
+            .unwrap_or_default(),
+        String::new(),
+        String::new(),
+        label.to_string(),
+        entry.description.clone(),
+        entry.id.clone().unwrap_or_default(),
+    ]
+}
+
+fn cointracking(entry: &Entry, exchange: &str) -> Vec<String> {
+    let (typ, buy, sell) = match &entry.movement {
+        Movement::Trade { received, sent, .. } => ("Trade", Some(received), Some(sent)),
+        Movement::Deposit(amount) => ("Deposit", Some(amount), None),
+        Movement::Withdrawal(amount) => ("Withdrawal", None, Some(amount)),
+        Movement::Pnl(amount) if amount.0.is_sign_negative() => {
+            ("Derivatives / Futures Loss", None, Some(amount))
+        }
+        Movement::Pnl(amount) => ("Derivatives / Futures Profit", Some(amount), None),
+        Movement::Rebate(amount) => ("Reward / Bonus", Some(amount), None),
+    };
+    vec![
+        typ.to_string(),
+        buy.map(|(a, _)| number(*a)).unwrap_or_default(),
+        buy.map(|(_, c)| c.clone()).unwrap_or_default(),
+        sell.map(|(a, _)| number(a.abs())).unwrap_or_default(),
+        sell.map(|(_, c)| c.clone()).unwrap_or_default(),
+        entry
+            .fee
+            .as_ref()
+            .map(|(f, _)| number(*f))
+            .unwrap_or_default(),
+        entry
+            .fee
+            .as_ref()
+            .map(|(_, c)| c.clone())
+            .unwrap_or_default(),
+        exchange.to_string(),
+        String::new(),
+        entry.description.clone(),
+        entry.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        entry.id.clone().unwrap_or_default(),
+    ]
+}
+
+fn cryptotaxcalculator(entry: &Entry) -> Vec<String> {
+    let (typ, base, quote) = match &entry.movement {
+        Movement::Trade {
+            side: Side::Buy,
+            received,
+            sent,
+        } => ("buy", received, Some(sent)),
+        Movement::Trade {
+            side: Side::Sell,
+            received,
+            sent,
+        } => ("sell", sent, Some(received)),
+        Movement::Deposit(amount) => ("receive", amount, None),
+        Movement::Withdrawal(amount) => ("send", amount, None),
+        Movement::Pnl(amount) if amount.0.is_sign_negative() => ("realized-loss", amount, None),
+        Movement::Pnl(amount) => ("realized-profit", amount, None),
+        Movement::Rebate(amount) => ("income", amount, None),
+    };
+    vec![
+        entry.time.format("%d/%m/%Y %H:%M:%S").to_string(),
+        typ.to_string(),
+        base.1.clone(),
+        number(base.0.abs()),
+        quote.map(|(_, c)| c.clone()).unwrap_or_default(),
+        quote.map(|(a, _)| number(*a)).unwrap_or_default(),
+        entry
+            .fee
+            .as_ref()
+            .map(|(_, c)| c.clone())
+            .unwrap_or_default(),
+        entry
+            .fee
+            .as_ref()
+            .map(|(f, _)| number(*f))
+            .unwrap_or_default(),
+        String::new(),
+        String::new(),
+        String::new(),
+        entry.id.clone().unwrap_or_default(),
+        entry.description.clone(),
+    ]
+}
+
+/// Renders the entries as the transactions of beancount or hledger, with the accounts opened at
+/// the first one for beancount.
+fn journal(entries: &[Entry], format: ExportFormat, accounts: &LedgerAccounts) -> String {
+    let beancount = format == ExportFormat::Beancount;
+    let mut journal = String::new();
+    if let (true, Some(first)) = (beancount, entries.first()) {
+        let opened: BTreeSet<_> = [
+            &accounts.assets,
+            &accounts.fees,
+            &accounts.pnl,
+            &accounts.transfers,
+        ]
+        .into_iter()
+        .collect();
+        for account in opened {
+            journal += &format!("{} open {}\n", first.time.format("%Y-%m-%d"), account);
+        }
+        journal += "\n";
+    }
+    for entry in entries {
+        let narration = match &entry.movement {
+            Movement::Trade { side, .. } => side.to_string(),
+            Movement::Deposit(_) => "deposit".to_string(),
+            Movement::Withdrawal(_) => "withdrawal".to_string(),
+            Movement::Pnl(_) => "pnl".to_string(),
+            Movement::Rebate(_) => "rebate".to_string(),
+        };
+        let narration = format!("{} {}", entry.description, narration);
+        let (date, time) = (entry.time.format("%Y-%m-%d"), entry.time.format("%H:%M:%S"));
+        let id = entry.id.clone().unwrap_or_default();
+        if beancount {
+            journal += &format!("{} * \"{}\"\n", date, narration.trim());
+            journal += &format!("  id: \"{}\"\n  time: \"{}\"\n", id, time);
+        } else {
+            journal += &format!(
+                "{} {}  ; id:{}, time:{}\n",
+                date,
+                narration.trim(),
+                id,
+                time
+            );
+        }
+        for (account, (amount, currency), price) in postings(entry, accounts) {
+            if amount.is_zero() {
+                continue;
+            }
+            let mut posting = format!(
+                "{}  {} {}",
+                account,
+                number(amount),
+                commodity(&currency, format)
+            );
+            if let Some((total, currency)) = price {
+                posting += &format!(" @@ {} {}", number(total), commodity(&currency, format));
+            }
+            journal += &format!("{}{}\n", if beancount { "  " } else { "    " }, posting);
+        }
+        journal += "\n";
+    }
+    journal
+}
+
+/// Returns the postings of the entry with the total price of the amount if it's traded.
+fn postings<'a>(
+    entry: &Entry,
+    accounts: &'a LedgerAccounts,
+) -> Vec<(&'a str, Amount, Option<Amount>)> {
+    let negative = |(amount, currency): &Amount| (-*amount, currency.clone());
+    let assets = accounts.assets.as_str();
+    let mut postings = match &entry.movement {
+        // the price is of the base currency
+        Movement::Trade {
+            side: Side::Buy,
+            received,
+            sent,
+        } => vec![
+            (assets, received.clone(), Some(sent.clone())),
+            (assets, negative(sent), None),
+        ],
+        Movement::Trade {
+            side: Side::Sell,
+            received,
+            sent,
+        } => vec![
+            (assets, negative(sent), Some(received.clone())),
+            (assets, received.clone(), None),
+        ],
+        Movement::Deposit(amount) => vec![
+            (assets, amount.clone(), None),
+            (accounts.transfers.as_str(), negative(amount), None),
+        ],
+        Movement::Withdrawal(amount) => vec![
+            (assets, negative(amount), None),
+            (accounts.transfers.as_str(), amount.clone(), None),
+        ],
+        Movement::Pnl(amount) => vec![
+            (assets, amount.clone(), None),
+            (accounts.pnl.as_str(), negative(amount), None),
+        ],
+        Movement::Rebate(amount) => vec![
+            (assets, amount.clone(), None),
+            (accounts.fees.as_str(), negative(amount), None),
+        ],
+    };
+    if let Some(fee) = &entry.fee {
+        postings.push((accounts.fees.as_str(), fee.clone(), None));
+        postings.push((assets, negative(fee), None));
+    }
+    postings
+}
+
+/// Returns the currency as a commodity, quoted for hledger if it has other than letters, e.g.
+/// `1INCH`.
+fn commodity(currency: &str, format: ExportFormat) -> String {
+    let currency = currency.to_uppercase();
+    if format == ExportFormat::Hledger && !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        return format!("\"{}\"", currency);
+    }
+    currency
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    // a deposit, a spot buy, a spot sell with a rebate, a round trip of a perpetual future with
+    // its funding payment and a withdrawal
+    fn write_archive(dir: &Path) {
+        write_file(
+            dir,
+            NaiveDate::from_ymd(2022, 1, 1),
+            &[
+                "ftx,main,,USD,,,,1000,0,USD,2022-01-01T01:00:00Z,d1,deposit",
+                "ftx,main,BTC/USD,BTC,USD,buy,40000,0.01,0.4,USD,2022-01-01T02:00:00Z,1,order",
+                "ftx,main,BTC/USD,BTC,USD,sell,41000,0.005,-0.01,USD,2022-01-01T03:00:00Z,2,order",
+                "ftx,main,ETH-PERP,,,buy,3000,1,0.6,USD,2022-01-01T04:00:00Z,3,order",
+                "ftx,main,ETH-PERP,,,sell,3100,1,0.62,USD,2022-01-01T05:00:00Z,4,order",
+                "ftx,main,ETH-PERP,,,,,-0.5,0,USD,2022-01-01T06:00:00Z,f1,funding",
+            ],
+        );
+        write_file(
+            dir,
+            NaiveDate::from_ymd(2022, 1, 2),
+            &["ftx,main,,BTC,,,,0.005,0.0005,BTC,2022-01-02T00:00:00Z,w1,withdrawal"],
+        );
+    }
+
+    async fn export_into_string(format: ExportFormat) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        write_archive(dir.path());
+        let path = export(
+            dir.path(),
+            "main",
+            "ftx",
+            format,
+            &LedgerAccounts::new("ftx", "main"),
+            None,
+            NaiveDate::from_ymd(2022, 1, 3),
+        )
+        .await
+        .unwrap();
+        tokio::fs::read_to_string(path).await.unwrap()
+    }
+
+    async fn lines(format: ExportFormat) -> Vec<String> {
+        let content = export_into_string(format).await;
+        content.lines().map(String::from).collect()
+    }
+
+    #[tokio::test]
+    async fn exports_koinly() {
+        assert_eq!(
+            lines(ExportFormat::Koinly).await,
+            [
+                "Date,Sent Amount,Sent Currency,Received Amount,Received Currency,Fee Amount,Fee Currency,Net Worth Amount,Net Worth Currency,Label,Description,TxHash",
+                "2022-01-01 01:00:00 UTC,,,1000,USD,,,,,,,d1",
+                "2022-01-01 02:00:00 UTC,400,USD,0.01,BTC,0.4,USD,,,,BTC/USD,1",
+                "2022-01-01 03:00:00 UTC,0.005,BTC,205,USD,,,,,,BTC/USD,2",
+                "2022-01-01 03:00:00 UTC,,,0.01,USD,,,,,cashback,BTC/USD,2-rebate",
+                "2022-01-01 04:00:00 UTC,0.6,USD,,,,,,,realized gain,ETH-PERP,3",
+                "2022-01-01 05:00:00 UTC,,,99.38,USD,,,,,realized gain,ETH-PERP,4",
+                "2022-01-01 06:00:00 UTC,0.5,USD,,,,,,,realized gain,ETH-PERP,f1",
+                "2022-01-02 00:00:00 UTC,0.005,BTC,,,0.0005,BTC,,,,,w1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_cointracking() {
+        assert_eq!(
+            lines(ExportFormat::Cointracking).await,
+            [
+                "Type,Buy Amount,Buy Currency,Sell Amount,Sell Currency,Fee,Fee Currency,Exchange,Trade-Group,Comment,Date,Tx-ID",
+                "Deposit,1000,USD,,,,,ftx,,,2022-01-01 01:00:00,d1",
+                "Trade,0.01,BTC,400,USD,0.4,USD,ftx,,BTC/USD,2022-01-01 02:00:00,1",
+                "Trade,205,USD,0.005,BTC,,,ftx,,BTC/USD,2022-01-01 03:00:00,2",
+                "Reward / Bonus,0.01,USD,,,,,ftx,,BTC/USD,2022-01-01 03:00:00,2-rebate",
+                "Derivatives / Futures Loss,,,0.6,USD,,,ftx,,ETH-PERP,2022-01-01 04:00:00,3",
+                "Derivatives / Futures Profit,99.38,USD,,,,,ftx,,ETH-PERP,2022-01-01 05:00:00,4",
+                "Derivatives / Futures Loss,,,0.5,USD,,,ftx,,ETH-PERP,2022-01-01 06:00:00,f1",
+                "Withdrawal,,,0.005,BTC,0.0005,BTC,ftx,,,2022-01-02 00:00:00,w1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_cryptotaxcalculator() {
+        assert_eq!(
+            lines(ExportFormat::Cryptotaxcalculator).await,
+            [
+                "Timestamp (UTC),Type,Base Currency,Base Amount,Quote Currency (Optional),Quote Amount (Optional),Fee Currency (Optional),Fee Amount (Optional),From (Optional),To (Optional),Blockchain (Optional),ID (Optional),Description (Optional)",
+                "01/01/2022 01:00:00,receive,USD,1000,,,,,,,,d1,",
+                "01/01/2022 02:00:00,buy,BTC,0.01,USD,400,USD,0.4,,,,1,BTC/USD",
+                "01/01/2022 03:00:00,sell,BTC,0.005,USD,205,,,,,,2,BTC/USD",
+                "01/01/2022 03:00:00,income,USD,0.01,,,,,,,,2-rebate,BTC/USD",
+                "01/01/2022 04:00:00,realized-loss,USD,0.6,,,,,,,,3,ETH-PERP",
+                "01/01/2022 05:00:00,realized-profit,USD,99.38,,,,,,,,4,ETH-PERP",
+                "01/01/2022 06:00:00,realized-loss,USD,0.5,,,,,,,,f1,ETH-PERP",
+                "02/01/2022 00:00:00,send,BTC,0.005,,,BTC,0.0005,,,,w1,",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_beancount() {
+        assert_eq!(
+            lines(ExportFormat::Beancount).await,
+            [
+                "2022-01-01 open Assets:Ftx:Main",
+                "2022-01-01 open Equity:Transfers",
+                "2022-01-01 open Expenses:Fees",
+                "2022-01-01 open Income:Trading",
+                "",
+                "2022-01-01 * \"deposit\"",
+                "  id: \"d1\"",
+                "  time: \"01:00:00\"",
+                "  Assets:Ftx:Main  1000 USD",
+                "  Equity:Transfers  -1000 USD",
+                "",
+                "2022-01-01 * \"BTC/USD buy\"",
+                "  id: \"1\"",
+                "  time: \"02:00:00\"",
+                "  Assets:Ftx:Main  0.01 BTC @@ 400 USD",
+                "  Assets:Ftx:Main  -400 USD",
+                "  Expenses:Fees  0.4 USD",
+                "  Assets:Ftx:Main  -0.4 USD",
+                "",
+                "2022-01-01 * \"BTC/USD sell\"",
+                "  id: \"2\"",
+                "  time: \"03:00:00\"",
+                "  Assets:Ftx:Main  -0.005 BTC @@ 205 USD",
+                "  Assets:Ftx:Main  205 USD",
+                "",
+                "2022-01-01 * \"BTC/USD rebate\"",
+                "  id: \"2-rebate\"",
+                "  time: \"03:00:00\"",
+                "  Assets:Ftx:Main  0.01 USD",
+                "  Expenses:Fees  -0.01 USD",
+                "",
+                "2022-01-01 * \"ETH-PERP pnl\"",
+                "  id: \"3\"",
+                "  time: \"04:00:00\"",
+                "  Assets:Ftx:Main  -0.6 USD",
+                "  Income:Trading  0.6 USD",
+                "",
+                "2022-01-01 * \"ETH-PERP pnl\"",
+                "  id: \"4\"",
+                "  time: \"05:00:00\"",
+                "  Assets:Ftx:Main  99.38 USD",
+                "  Income:Trading  -99.38 USD",
+                "",
+                "2022-01-01 * \"ETH-PERP pnl\"",
+                "  id: \"f1\"",
+                "  time: \"06:00:00\"",
+                "  Assets:Ftx:Main  -0.5 USD",
+                "  Income:Trading  0.5 USD",
+                "",
+                "2022-01-02 * \"withdrawal\"",
+                "  id: \"w1\"",
+                "  time: \"00:00:00\"",
+                "  Assets:Ftx:Main  -0.005 BTC",
+                "  Equity:Transfers  0.005 BTC",
+                "  Expenses:Fees  0.0005 BTC",
+                "  Assets:Ftx:Main  -0.0005 BTC",
+                "",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_hledger() {
+        assert_eq!(
+            lines(ExportFormat::Hledger).await,
+            [
+                "2022-01-01 deposit  ; id:d1, time:01:00:00",
+                "    Assets:Ftx:Main  1000 USD",
+                "    Equity:Transfers  -1000 USD",
+                "",
+                "2022-01-01 BTC/USD buy  ; id:1, time:02:00:00",
+                "    Assets:Ftx:Main  0.01 BTC @@ 400 USD",
+                "    Assets:Ftx:Main  -400 USD",
+                "    Expenses:Fees  0.4 USD",
+                "    Assets:Ftx:Main  -0.4 USD",
+                "",
+                "2022-01-01 BTC/USD sell  ; id:2, time:03:00:00",
+                "    Assets:Ftx:Main  -0.005 BTC @@ 205 USD",
+                "    Assets:Ftx:Main  205 USD",
+                "",
+                "2022-01-01 BTC/USD rebate  ; id:2-rebate, time:03:00:00",
+                "    Assets:Ftx:Main  0.01 USD",
+                "    Expenses:Fees  -0.01 USD",
+                "",
+                "2022-01-01 ETH-PERP pnl  ; id:3, time:04:00:00",
+                "    Assets:Ftx:Main  -0.6 USD",
+                "    Income:Trading  0.6 USD",
+                "",
+                "2022-01-01 ETH-PERP pnl  ; id:4, time:05:00:00",
+                "    Assets:Ftx:Main  99.38 USD",
+                "    Income:Trading  -99.38 USD",
+                "",
+                "2022-01-01 ETH-PERP pnl  ; id:f1, time:06:00:00",
+                "    Assets:Ftx:Main  -0.5 USD",
+                "    Income:Trading  0.5 USD",
+                "",
+                "2022-01-02 withdrawal  ; id:w1, time:00:00:00",
+                "    Assets:Ftx:Main  -0.005 BTC",
+                "    Equity:Transfers  0.005 BTC",
+                "    Expenses:Fees  0.0005 BTC",
+                "    Assets:Ftx:Main  -0.0005 BTC",
+                "",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn realizes_the_pnl_at_the_entry_price_before_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            NaiveDate::from_ymd(2022, 1, 1),
+            &["ftx,main,ETH-PERP,,,buy,3000,1,0.6,USD,2022-01-01T04:00:00Z,3,order"],
+        );
+        write_file(
+            dir.path(),
+            NaiveDate::from_ymd(2022, 1, 2),
+            &[
+                "ftx,main,ETH-PERP,,,sell,3100,1,0.62,USD,2022-01-02T05:00:00Z,4,order",
+                "ftx,main,ETH-PERP,,,,,-0.5,0,USD,2022-01-02T06:00:00Z,f1,funding",
+            ],
+        );
+        let path = export(
+            dir.path(),
+            "main",
+            "ftx",
+            ExportFormat::Cointracking,
+            &LedgerAccounts::new("ftx", "main"),
+            Some(NaiveDate::from_ymd(2022, 1, 2)),
+            NaiveDate::from_ymd(2022, 1, 3),
+        )
+        .await
+        .unwrap();
+        let content = tokio::fs::read_to_string(path).await.unwrap();
+        assert_eq!(
+            content.lines().skip(1).collect::<Vec<_>>(),
+            [
+                "Derivatives / Futures Profit,99.38,USD,,,,,ftx,,ETH-PERP,2022-01-02 05:00:00,4",
+                "Derivatives / Futures Loss,,,0.5,USD,,,ftx,,ETH-PERP,2022-01-02 06:00:00,f1",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_the_accounts_and_the_commodities() {
+        let accounts = LedgerAccounts::new("ftx", "1st sub");
+        assert_eq!(accounts.assets, "Assets:Ftx:X1stsub");
+        assert_eq!(commodity("1inch", ExportFormat::Hledger), "\"1INCH\"");
+        assert_eq!(commodity("1inch", ExportFormat::Beancount), "1INCH");
+        assert_eq!(commodity("btc", ExportFormat::Hledger), "BTC");
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, DecimalSeparator, FillType, Localized};
+
+/// The fees of a market in a currency in a month.
+#[derive(Serialize)]
+pub struct MonthlyFees {
+    // YYYY-MM
+    pub month: String,
+    pub market: String,
+    pub fee_currency: Option<String>,
+    pub fills: u64,
+    // the sum of the positive fees
+    #[serde(serialize_with = "ser_decimal")]
+    pub charged: Decimal,
+    // the sum of the negative fees, e.g. maker rebates
+    #[serde(serialize_with = "ser_decimal")]
+    pub rebates: Decimal,
+    // charged + rebates
+    #[serde(serialize_with = "ser_decimal")]
+    pub total: Decimal,
+}
+
+/// Sums the fees of the trades in the archive by month, market and fee currency and writes them
+/// into `<account>.fees.csv` of `outdir`. The months are of the daily files in the range.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let mut months: BTreeMap<(String, String, Option<String>), MonthlyFees> = BTreeMap::new();
+    let files = archive::files(outdir, account);
+    let files = match start {
+        Some(start) => files.range(start..end),
+        None => files.range(..end),
+    };
+    for (date, path) in files {
+        for record in archive::read_records(path, *date).await? {
+            let Some(market) = record.market else {
+                continue;
+            };
+            if !matches!(
+                record.typ,
+                None | Some(FillType::Order | FillType::Liquidation)
+            ) {
+                continue;
+            }
+            let month = date.format("%Y-%m").to_string();
+            let fees = months
+                .entry((month.clone(), market.clone(), record.fee_currency.clone()))
+                .or_insert_with(|| MonthlyFees {
+                    month,
+                    market,
+                    fee_currency: record.fee_currency,
+                    fills: 0,
+                    charged: Decimal::ZERO,
+                    rebates: Decimal::ZERO,
+                    total: Decimal::ZERO,
+                });
+            fees.fills += 1;
+            if record.fee.is_sign_negative() {
+                fees.rebates += record.fee;
+            } else {
+                fees.charged += record.fee;
+            }
+        }
+    }
+
+    let path = outdir.join(format!("{}.fees.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    let mut totals: BTreeMap<Option<String>, Decimal> = BTreeMap::new();
+    for mut fees in months.into_values() {
+        fees.charged = fees.charged.normalize();
+        fees.rebates = fees.rebates.normalize();
+        fees.total = (fees.charged + fees.rebates).normalize();
+        *totals.entry(fees.fee_currency.clone()).or_default() += fees.total;
+        writer
+            .serialize(Localized(&fees, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    for (currency, total) in totals {
+        info!(
+            "fees: {} {}",
+            total,
+            currency.as_deref().unwrap_or("(unknown currency)")
+        );
+    }
+    info!("the fees are written into {}", path.display());
+    Ok(path)
+}
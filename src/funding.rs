@@ -0,0 +1,165 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, ser_decimal_opt, DecimalSeparator, FillType, Localized, Side};
+use crate::pnl::{CostMethod, Position};
+
+/// The funding payments of a market in a month.
+#[derive(Serialize)]
+pub struct MonthlyFunding {
+    // YYYY-MM
+    pub month: String,
+    pub market: String,
+    pub currency: Option<String>,
+    pub payments: u64,
+    // the sum of the negative payments
+    #[serde(serialize_with = "ser_decimal")]
+    pub paid: Decimal,
+    // the sum of the positive payments
+    #[serde(serialize_with = "ser_decimal")]
+    pub received: Decimal,
+    // paid + received
+    #[serde(serialize_with = "ser_decimal")]
+    pub net: Decimal,
+    // the mean of the notional of the position at the payments, at the last fill price
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub average_notional: Option<Decimal>,
+    // net / average_notional annualized over the days with payments, e.g. -0.1 for paying 10%
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub apr: Option<Decimal>,
+    #[serde(skip)]
+    notionals: Vec<Decimal>,
+    #[serde(skip)]
+    days: BTreeSet<NaiveDate>,
+}
+
+/// Sums the funding payments of the archive by month and market with the positions replayed from
+/// the fills, and writes them into `<account>.funding.csv` of `outdir`.
+///
+/// All files are read since a position may be opened before `start`, and only the payments in
+/// the range are summed. The months are of the daily files.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let records = archive::read_range(outdir, account, ..end).await?;
+
+    let mut positions: HashMap<String, (Position, Decimal)> = HashMap::new();
+    let mut months: BTreeMap<(String, String), MonthlyFunding> = BTreeMap::new();
+    let mut unknown = BTreeSet::new();
+    for record in records {
+        let Some(market) = record.market.clone() else {
+            continue;
+        };
+        match (&record.typ, record.side, record.price) {
+            (None | Some(FillType::Order | FillType::Liquidation), Some(side), Some(price)) => {
+                let size = match side {
+                    Side::Buy => record.size.abs(),
+                    Side::Sell => -record.size.abs(),
+                };
+                let (position, last_price) = positions.entry(market).or_default();
+                position.fill(CostMethod::Average, size, price);
+                *last_price = price;
+            }
+            (Some(FillType::Funding), _, _) => {
+                if start.is_some_and(|start| record.date < start) {
+                    continue;
+                }
+                let month = record.date.format("%Y-%m").to_string();
+                let funding = months
+                    .entry((month.clone(), market.clone()))
+                    .or_insert_with(|| MonthlyFunding {
+                        month,
+                        market: market.clone(),
+                        currency: None,
+                        payments: 0,
+                        paid: Decimal::ZERO,
+                        received: Decimal::ZERO,
+                        net: Decimal::ZERO,
+                        average_notional: None,
+                        apr: None,
+                        notionals: vec![],
+                        days: BTreeSet::new(),
+                    });
+                funding.payments += 1;
+                // the amount is in the base currency of the record, e.g. USDC on dYdX
+                if record.size.is_sign_negative() {
+                    funding.paid += record.size;
+                } else {
+                    funding.received += record.size;
+                }
+                funding.currency = funding.currency.take().or(record.base_currency);
+                funding.days.insert(record.date);
+                match positions.get(&market) {
+                    Some((position, price)) if !position.size().is_zero() => {
+                        funding.notionals.push(position.size().abs() * price)
+                    }
+                    // paid for a position opened by fills not in the archive
+                    _ => {
+                        unknown.insert(market);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if !unknown.is_empty() {
+        warn!(
+            "funding of {} is paid without a position in the archive, which is excluded from \
+             the apr. download the earlier history",
+            unknown.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    let path = outdir.join(format!("{}.funding.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    let mut totals: BTreeMap<Option<String>, Decimal> = BTreeMap::new();
+    for mut funding in months.into_values() {
+        funding.paid = funding.paid.normalize();
+        funding.received = funding.received.normalize();
+        funding.net = (funding.paid + funding.received).normalize();
+        if !funding.notionals.is_empty() {
+            let average =
+                funding.notionals.iter().sum::<Decimal>() / Decimal::from(funding.notionals.len());
+            funding.average_notional = Some(average.round_dp(8).normalize());
+            funding.apr = (!average.is_zero()).then(|| {
+                (funding.net / average * Decimal::from(365) / Decimal::from(funding.days.len()))
+                    .round_dp(8)
+                    .normalize()
+            });
+        }
+        *totals.entry(funding.currency.clone()).or_default() += funding.net;
+        writer
+            .serialize(Localized(&funding, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    for (currency, net) in totals {
+        info!(
+            "net funding: {} {}",
+            net,
+            currency.as_deref().unwrap_or("(unknown currency)")
+        );
+    }
+    info!("the funding is written into {}", path.display());
+    Ok(path)
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{Duration, NaiveDate};
+use log::*;
+
+use crate::archive;
+use crate::exchange::TimeRange;
+use crate::timezone::Timezone;
+
+/// Days between the first and the last file of the archive which have no records.
+pub struct Gap {
+    // inclusive
+    pub start: NaiveDate,
+    // exclusive
+    pub end: NaiveDate,
+}
+
+impl Gap {
+    pub fn range(&self, timezone: Timezone) -> TimeRange {
+        TimeRange {
+            start: Some(timezone.start_of(self.start)),
+            end: timezone.start_of(self.end),
+        }
+    }
+}
+
+/// Finds and logs the days without a file and the files without records.
+///
+/// Files are written only for days with fills, so a missing day may be just a day without
+/// trades. An empty file is suspicious since it's left only by an interrupted download.
+pub async fn find(outdir: &Path, account: &str) -> Result<Vec<Gap>> {
+    let mut gaps: Vec<Gap> = vec![];
+    let mut push = |start: NaiveDate, end: NaiveDate| match gaps.last_mut() {
+        // merge adjacent gaps
+        Some(last) if last.end == start => last.end = end,
+        _ => gaps.push(Gap { start, end }),
+    };
+    let mut next: Option<NaiveDate> = None;
+    for (date, path) in archive::files(outdir, account) {
+        if let Some(next) = next.filter(|next| *next < date) {
+            let days = (date - next).num_days();
+            warn!(
+                "{} - {}: no file ({} days)",
+                next,
+                date - Duration::days(1),
+                days
+            );
+            push(next, date);
+        }
+        if archive::read_ids(&path).await?.is_empty() {
+            warn!("{}: empty file", date);
+            push(date, date + Duration::days(1));
+        }
+        next = Some(date + Duration::days(1));
+    }
+    if gaps.is_empty() {
+        info!("no gap is found");
+    }
+    Ok(gaps)
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use tokio::io::AsyncWriteExt;
+
+use crate::archive;
+
+/// Ids of the fills already written with the date of the file each is in, so that runs with
+/// overlapping ranges (e.g. with different timezones) don't write a fill into two files.
+///
+/// It's saved as `<account>.index.csv` in the output directory, which is appended only. The last
+/// line wins when an id appears more than once.
+pub struct Index {
+    path: PathBuf,
+    outdir: PathBuf,
+    account: String,
+    dates: HashMap<String, NaiveDate>,
+    // not saved yet
+    pending: Vec<(String, NaiveDate)>,
+}
+
+impl Index {
+    pub async fn load(outdir: &Path, account: &str) -> Result<Self> {
+        let path = outdir.join(format!("{}.index.csv", account));
+        let mut dates = HashMap::new();
+        if path.exists() {
+            let content = tokio::fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            for line in content.lines() {
+                if let Some((key, date)) = line.rsplit_once(',') {
+                    if let Ok(date) = date.parse() {
+                        dates.insert(key.to_string(), date);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            path,
+            outdir: outdir.to_path_buf(),
+            account: account.to_string(),
+            dates,
+            pending: vec![],
+        })
+    }
+
+    /// Returns the date of another file which the fill is already written to.
+    pub fn written_elsewhere(&self, key: &str, date: NaiveDate) -> Option<NaiveDate> {
+        self.dates.get(key).copied().filter(|written| {
+            *written != date && archive::path(&self.outdir, &self.account, *written).exists()
+        })
+    }
+
+    pub fn insert(&mut self, key: String, date: NaiveDate) {
+        if self.dates.get(&key) != Some(&date) {
+            self.dates.insert(key.clone(), date);
+            self.pending.push((key, date));
+        }
+    }
+
+    /// Appends the inserted ids to the file.
+    pub async fn save(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for (key, date) in self.pending.drain(..) {
+            lines.push_str(&format!("{},{}\n", key, date));
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_the_fill_written_to_an_existing_file_of_another_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (
+            NaiveDate::from_ymd(2022, 1, 1),
+            NaiveDate::from_ymd(2022, 1, 2),
+        );
+        let mut index = Index::load(dir.path(), "main").await.unwrap();
+        index.insert("ftx:1001".into(), first);
+        index.save().await.unwrap();
+
+        let index = Index::load(dir.path(), "main").await.unwrap();
+        // the file of the date is deleted or not written yet
+        assert_eq!(index.written_elsewhere("ftx:1001", second), None);
+        tokio::fs::write(archive::path(dir.path(), "main", first), "")
+            .await
+            .unwrap();
+        assert_eq!(index.written_elsewhere("ftx:1001", second), Some(first));
+        assert_eq!(index.written_elsewhere("ftx:1001", first), None);
+        assert_eq!(index.written_elsewhere("ftx:1002", second), None);
+    }
+
+    #[tokio::test]
+    async fn the_last_line_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (
+            NaiveDate::from_ymd(2022, 1, 1),
+            NaiveDate::from_ymd(2022, 1, 2),
+        );
+        let mut index = Index::load(dir.path(), "main").await.unwrap();
+        index.insert("ftx:1001".into(), first);
+        index.save().await.unwrap();
+        index.insert("ftx:1001".into(), second);
+        index.save().await.unwrap();
+
+        let index = Index::load(dir.path(), "main").await.unwrap();
+        assert_eq!(index.dates["ftx:1001"], second);
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::IsTerminal;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use dialoguer::{Confirm, Input, Password, Select};
+use log::*;
+use serde_json::json;
+use toml::Value;
+
+use ftx_history::error::Usage;
+use ftx_history::exchange::ftx::{Ftx, FtxCredential};
+use ftx_history::exchange::{retry, Exchange};
+use ftx_history::keychain;
+
+const DOMAINS: [&str; 2] = ["ftx.com", "ftx.us"];
+
+/// Asks the credential and the options of FTX interactively, checks the credential with the API
+/// and writes a config file to be given by `--config`.
+pub async fn init() -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        bail!(Usage(
+            "init asks questions interactively so run it in a terminal".into()
+        ));
+    }
+    let domain = DOMAINS[Select::new()
+        .with_prompt("FTX domain")
+        .items(DOMAINS)
+        .default(0)
+        .interact()?];
+    let api_key: String = Input::new().with_prompt("API key").interact_text()?;
+    let api_secret = Password::new().with_prompt("API secret").interact()?;
+    let sub_accounts: String = Input::new()
+        .with_prompt(
+            "Subaccounts (comma separated, `all` for all of them, empty for the main account)",
+        )
+        .allow_empty(true)
+        .interact_text()?;
+    let sub_accounts = sub_accounts
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let all_subaccounts = matches!(sub_accounts.as_slice(), [all] if all == "all");
+    let outdir: String = Input::new()
+        .with_prompt("Output directory")
+        .default("./output".to_string())
+        .interact_text()?;
+    let raw = Confirm::new()
+        .with_prompt(
+            "Write all columns including exchange specific ones instead of the unified schema?",
+        )
+        .default(false)
+        .interact()?;
+
+    // the main account is checked for --all-subaccounts since they are listed with it
+    let checked = if all_subaccounts || sub_accounts.is_empty() {
+        vec![None]
+    } else {
+        sub_accounts.iter().cloned().map(Some).collect()
+    };
+    for sub_account in checked {
+        let account = sub_account.clone().unwrap_or_else(|| "main".to_string());
+        let mut exchange = Ftx::builder()
+            .credential(FtxCredential::new(api_key.clone(), api_secret.clone()))
+            .sub_account(sub_account)
+            .domain(domain)
+            .build()?;
+        if let Err(e) = exchange.sync_clock().await {
+            warn!(
+                "failed to get the server time. using the local clock: {:#}",
+                e
+            );
+        }
+        match retry(|| exchange.preflight()).await {
+            Ok(()) => info!("the credential is valid for {}", account),
+            Err(e) => {
+                error!("the credential is not valid for {}: {:#}", account, e);
+                if !Confirm::new()
+                    .with_prompt("Save it anyway?")
+                    .default(false)
+                    .interact()?
+                {
+                    bail!(Usage("aborted".into()));
+                }
+            }
+        }
+    }
+
+    let credential_json = json!({"api_key": api_key, "api_secret": api_secret}).to_string();
+    let credential = if Confirm::new()
+        .with_prompt("Save the credential into the OS keyring instead of a file?")
+        .default(false)
+        .interact()?
+    {
+        let name: String = Input::new()
+            .with_prompt("Name of the credential in the keyring")
+            .default("ftx".to_string())
+            .interact_text()?;
+        keychain::set(&name, credential_json)
+            .await
+            .with_context(|| "failed to save the credential into the keyring")?;
+        format!("keyring:{}", name)
+    } else {
+        let path: String = Input::new()
+            .with_prompt("Credential file")
+            .default("./credential.json".to_string())
+            .interact_text()?;
+        write_new(Path::new(&path), &credential_json, true)?;
+        path
+    };
+
+    let mut config = toml::value::Table::new();
+    config.insert("exchange".into(), "ftx".into());
+    if domain != DOMAINS[0] {
+        config.insert("domain".into(), domain.into());
+    }
+    config.insert("credential".into(), credential.into());
+    config.insert("outdir".into(), outdir.into());
+    if all_subaccounts {
+        config.insert("all-subaccounts".into(), true.into());
+    } else if !sub_accounts.is_empty() {
+        config.insert("sub-account".into(), sub_accounts.into());
+    }
+    if raw {
+        config.insert("raw".into(), true.into());
+    }
+    let path: String = Input::new()
+        .with_prompt("Config file")
+        .default("./collector.toml".to_string())
+        .interact_text()?;
+    write_new(
+        Path::new(&path),
+        &toml::to_string(&Value::Table(config))?,
+        false,
+    )?;
+    info!(
+        "the config is written. run `{} --config {}` to download the history",
+        env!("CARGO_BIN_NAME"),
+        path
+    );
+    Ok(())
+}
+
+/// Writes a file after confirming to overwrite the existing one. Other users can't read it if
+/// `private` is true.
+fn write_new(path: &Path, contents: &str, private: bool) -> Result<()> {
+    if path.exists()
+        && !Confirm::new()
+            .with_prompt(format!("{} already exists. Overwrite it?", path.display()))
+            .default(false)
+            .interact()?
+    {
+        bail!(Usage("aborted not to overwrite the file".into()));
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    std::io::Write::write_all(
+        &mut options
+            .open(path)
+            .with_context(|| format!("failed to create {}", path.display()))?,
+        contents.as_bytes(),
+    )
+    .with_context(|| format!("failed to write {}", path.display()))
+}
@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+// the service name of the entries in the OS keyring
+const SERVICE: &str = "ftx-history-downloader";
+const PREFIX: &str = "keyring:";
+
+/// Returns the name of the keyring entry if the credential is specified as `keyring:<name>`.
+pub fn entry_name(credential: &Path) -> Option<&str> {
+    credential.to_str()?.strip_prefix(PREFIX)
+}
+
+/// Reads a secret from the macOS Keychain, the Windows Credential Manager or the Secret Service.
+pub async fn get(name: &str) -> Result<String> {
+    let name = name.to_string();
+    // the platform apis are blocking
+    tokio::task::spawn_blocking(move || {
+        keyring::Entry::new(SERVICE, &name)?
+            .get_password()
+            .with_context(|| format!("no credential named {} is found in the keyring", name))
+    })
+    .await?
+}
+
+/// Saves a secret into the OS keyring, replacing the existing one of the same name.
+pub async fn set(name: &str, secret: String) -> Result<()> {
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || {
+        keyring::Entry::new(SERVICE, &name)?.set_password(&secret)?;
+        Ok(())
+    })
+    .await?
+}
@@ -0,0 +1,82 @@
+//! Downloads the trade history of crypto exchanges into daily csv files.
+//!
+//! The `ftx-history-downloder` binary is a thin CLI over this crate. Build an exchange client from
+//! [`exchange`], then pass it to [`collect::collect`] with an [`collect::Output`] to write the
+//! fills of a [`exchange::TimeRange`].
+//!
+//! The exchange clients and the pagination don't depend on an async runtime, and compile to wasm32
+//! where requests are sent by the fetch of the browser. The modules writing files need the
+//! `tokio` feature, which is enabled by default.
+
+#[cfg(feature = "tokio")]
+pub mod aggregate;
+#[cfg(feature = "tokio")]
+pub mod archive;
+pub mod audit;
+#[cfg(feature = "tokio")]
+pub mod candles;
+#[cfg(feature = "tokio")]
+pub mod collect;
+#[cfg(feature = "tokio")]
+pub mod convert;
+#[cfg(feature = "tokio")]
+pub mod dedupe;
+#[cfg(feature = "tokio")]
+pub mod equity;
+pub mod error;
+pub mod exchange;
+#[cfg(feature = "tokio")]
+pub mod export;
+#[cfg(feature = "tokio")]
+pub mod fees;
+#[cfg(feature = "tokio")]
+pub mod funding;
+#[cfg(feature = "tokio")]
+pub mod gaps;
+#[cfg(feature = "tokio")]
+pub mod index;
+#[cfg(feature = "tokio")]
+pub mod keychain;
+#[cfg(feature = "tokio")]
+pub mod makertaker;
+#[cfg(feature = "tokio")]
+pub mod manifest;
+#[cfg(feature = "tokio")]
+pub mod merge;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod period;
+#[cfg(feature = "tokio")]
+pub mod pnl;
+#[cfg(feature = "tokio")]
+pub mod positions;
+#[cfg(feature = "tokio")]
+pub mod preview;
+pub mod progress;
+#[cfg(feature = "query")]
+pub mod query;
+#[cfg(feature = "tokio")]
+pub mod reencode;
+#[cfg(feature = "tokio")]
+pub mod roundtrips;
+#[cfg(feature = "tokio")]
+pub mod shutdown;
+#[cfg(feature = "tokio")]
+pub mod sink;
+#[cfg(feature = "tokio")]
+pub mod slippage;
+#[cfg(feature = "tokio")]
+pub mod state;
+#[cfg(feature = "tokio")]
+pub mod stats;
+#[cfg(feature = "tokio")]
+pub mod summary;
+#[cfg(feature = "tokio")]
+pub mod tax;
+pub mod timezone;
+#[cfg(feature = "tokio")]
+pub mod verify;
+#[cfg(feature = "tokio")]
+pub mod volume;
+#[cfg(feature = "tokio")]
+pub mod vwap;
@@ -1,282 +1,1273 @@
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::time::SystemTime;
-
-use anyhow::{Context, Error, Result};
-use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
-use clap::Parser;
-use csv_async::AsyncSerializer;
-use futures::FutureExt;
-use futures::StreamExt;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use clap::{ArgEnum, CommandFactory, Parser};
+use glob::Pattern;
 use log::*;
-use serde::{Deserialize, Serialize};
-use surf::Request;
-use tokio::fs::File;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+
+use ftx_history::convert::Converter;
+use ftx_history::error::{ExitCode, Partial, Usage};
+#[cfg(feature = "binance")]
+use ftx_history::exchange::binance::Binance;
+#[cfg(feature = "bitfinex")]
+use ftx_history::exchange::bitfinex::Bitfinex;
+#[cfg(feature = "bybit")]
+use ftx_history::exchange::bybit::Bybit;
+#[cfg(feature = "coinbase")]
+use ftx_history::exchange::coinbase::Coinbase;
+#[cfg(feature = "deribit")]
+use ftx_history::exchange::deribit::Deribit;
+#[cfg(feature = "dydx")]
+use ftx_history::exchange::dydx::Dydx;
+use ftx_history::exchange::ftx::{Ftx, FtxCredential};
+#[cfg(feature = "gateio")]
+use ftx_history::exchange::gateio::GateIo;
+#[cfg(feature = "kraken")]
+use ftx_history::exchange::kraken::Kraken;
+#[cfg(feature = "kucoin")]
+use ftx_history::exchange::kucoin::Kucoin;
+#[cfg(feature = "okx")]
+use ftx_history::exchange::okx::Okx;
+use ftx_history::exchange::{
+    configure, retry, retry_with, ClientConfig, DecimalSeparator, Exchange, TimeRange, Unauthorized,
+};
+use ftx_history::export::{ExportFormat, LedgerAccounts};
+use ftx_history::period::{Month, Period, Year};
+use ftx_history::pnl::CostMethod;
+use ftx_history::progress::LogFormat;
+use ftx_history::sink::SinkOptions;
+use ftx_history::tax::LotMethod;
+use ftx_history::timezone::Timezone;
+use ftx_history::vwap::Interval;
+
+use ftx_history::candles::Resolution;
+use ftx_history::collect::{collect, pending_ranges, Liquidity, Output, Side};
+use ftx_history::{
+    aggregate, archive, audit, candles, dedupe, equity, export, fees, funding, gaps, keychain,
+    makertaker, manifest, merge, pnl, positions, progress, reencode, roundtrips, shutdown, sink,
+    slippage, stats, summary, tax, verify, volume, vwap,
+};
+
+mod config;
+mod init;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    // A json file path of your FTX credential.
+    /// optional. a toml file of the options below, e.g. `outdir = "./output"`. options in the
+    /// command line override it.
     #[clap(long, parse(from_os_str))]
-    credential: PathBuf,
-    // An output directory.
-    #[clap(long, parse(from_os_str))]
-    outdir: PathBuf,
-    // optional. If not specified, the script will download main account's data.
-    #[clap(long)]
-    sub_account: Option<String>,
-    // optional. inclusive yyyy-MM-dd starting date.
-    #[clap(long)]
+    config: Option<PathBuf>,
+    /// An exchange to download the history from.
+    #[clap(help_heading = "EXCHANGE", long, arg_enum, default_value = "ftx")]
+    exchange: ExchangeKind,
+    /// A json file path of your exchange credential. required except for the gaps command.
+    /// keyring:<name> reads it from the OS keyring saved by the store-credential command instead.
+    /// FTX_API_KEY and FTX_API_SECRET environment variables can be used instead for ftx.
+    #[clap(help_heading = "EXCHANGE", long, parse(from_os_str))]
+    credential: Option<PathBuf>,
+    /// optional. refuses a credential file which can be accessed by other users instead of
+    /// warning about it.
+    #[clap(help_heading = "EXCHANGE", long)]
+    strict_permissions: bool,
+    /// An output directory. required except for the store-credential command.
+    #[clap(help_heading = "OUTPUT", long, parse(from_os_str))]
+    outdir: Option<PathBuf>,
+    /// optional. If not specified, the script will download main account's data.
+    /// can be repeated or comma separated to download several subaccounts one by one.
+    /// FTX_SUBACCOUNT environment variable is used for ftx if not specified.
+    #[clap(
+        help_heading = "EXCHANGE",
+        long,
+        alias = "sub-accounts",
+        use_value_delimiter = true
+    )]
+    sub_account: Vec<String>,
+    /// optional. inclusive yyyy-MM-dd starting date.
+    #[clap(help_heading = "RANGE", long)]
     start: Option<NaiveDate>,
-    // optional. exclusive yyyy-MM-dd ending date.
-    #[clap(long)]
+    /// optional. exclusive yyyy-MM-dd ending date.
+    #[clap(help_heading = "RANGE", long)]
     end: Option<NaiveDate>,
+    /// optional. downloads the days of the period before --end (today by default) instead of
+    /// --start, e.g. 30d, 2w, 6m or 1y.
+    #[clap(help_heading = "RANGE", long, conflicts_with_all = &["start", "incremental"])]
+    last: Option<Period>,
+    /// optional. downloads the yyyy-MM month instead of --start and --end, e.g. 2022-05.
+    #[clap(
+        help_heading = "RANGE",
+        long,
+        conflicts_with_all = &["start", "end", "last", "incremental", "year", "yesterday", "today"]
+    )]
+    month: Option<Month>,
+    /// optional. downloads the yyyy year instead of --start and --end, e.g. 2021.
+    #[clap(
+        help_heading = "RANGE",
+        long,
+        conflicts_with_all = &["start", "end", "last", "incremental", "yesterday", "today"]
+    )]
+    year: Option<Year>,
+    /// optional. downloads the day before today in --timezone, e.g. for a daily cron job.
+    #[clap(
+        help_heading = "RANGE",
+        long,
+        conflicts_with_all = &["start", "end", "last", "incremental", "today"]
+    )]
+    yesterday: bool,
+    /// optional. downloads today so far in --timezone.
+    #[clap(
+        help_heading = "RANGE",
+        long,
+        conflicts_with_all = &["start", "end", "last", "incremental"]
+    )]
+    today: bool,
+    /// ftx only. downloads the main account and all subaccounts found with the credential.
+    #[clap(help_heading = "EXCHANGE", long, conflicts_with = "sub-account")]
+    all_subaccounts: bool,
+    /// ftx only. a domain of the FTX API, e.g. ftx.us for FTX US customers.
+    #[clap(help_heading = "EXCHANGE", long, default_value = "ftx.com")]
+    domain: String,
+    /// ftx only. milliseconds to add to the timestamp of requests to fix the local clock.
+    /// measured with the server time if not specified.
+    #[clap(help_heading = "EXCHANGE", long, allow_hyphen_values = true)]
+    ts_offset: Option<i64>,
+    /// binance only. comma separated symbols to download trades of, e.g. BTCUSDT,ETHBTC.
+    #[clap(help_heading = "EXCHANGE", long, use_value_delimiter = true)]
+    symbols: Vec<String>,
+    /// optional. writes all columns including exchange specific ones instead of the unified schema.
+    #[clap(help_heading = "OUTPUT", long)]
+    raw: bool,
+    /// optional. where the fills are written. csv, parquet in a build with the parquet feature, or
+    /// a sink registered by a build embedding the library.
+    #[clap(help_heading = "OUTPUT", long, default_value = "csv")]
+    sink: String,
+    /// optional. also writes the trades, the volume, the notional, the fees and the net position
+    /// change of each market into <account>_<date>.daily.csv next to each daily file.
+    #[clap(help_heading = "OUTPUT", long)]
+    daily_aggregate: bool,
+    /// optional. also writes the fee and the notional of each record converted into the currency,
+    /// e.g. USD, EUR or JPY, at the time of the record with the prices of ftx.com or --rates.
+    #[clap(help_heading = "OUTPUT", long)]
+    convert_to: Option<String>,
+    /// optional. a csv file of time,currency,rate to convert with instead of the prices of ftx.com,
+    /// where rate is the value of a unit of the currency from the time until the next row.
+    #[clap(help_heading = "OUTPUT", long, requires = "convert-to")]
+    rates: Option<PathBuf>,
+    /// optional. writes only the fills of the markets matching any of the comma separated glob
+    /// patterns, e.g. BTC-PERP,*-PERP. case insensitive.
+    #[clap(help_heading = "OUTPUT", long, use_value_delimiter = true)]
+    filter_market: Vec<Pattern>,
+    /// optional. writes only the fills of the side.
+    #[clap(help_heading = "OUTPUT", long, arg_enum)]
+    side: Option<Side>,
+    /// optional. writes only the fills of the liquidity, e.g. to audit maker rebates.
+    #[clap(help_heading = "OUTPUT", long, arg_enum)]
+    liquidity: Option<Liquidity>,
+    /// optional. writes only the records of the comma separated types, e.g. order, otc,
+    /// liquidation, deposit or withdrawal.
+    #[clap(
+        help_heading = "OUTPUT",
+        long = "type",
+        value_name = "TYPE",
+        use_value_delimiter = true
+    )]
+    types: Vec<String>,
+    /// optional. drops dust fills whose price * size is less than this, e.g. 1. it's in the quote
+    /// currency, which is USD for most markets of FTX.
+    #[clap(help_heading = "OUTPUT", long)]
+    min_notional: Option<Decimal>,
+    /// optional. writes the summary of the run logged at the end into the file as json.
+    #[clap(help_heading = "OUTPUT", long, parse(from_os_str))]
+    summary: Option<PathBuf>,
+    /// optional. stops after the page in which this many records are written, e.g. to check the
+    /// credential and the output format. --resume continues the rest.
+    #[clap(help_heading = "OUTPUT", long)]
+    limit: Option<u64>,
+    /// optional. stops after this many requests are sent including retried ones.
+    #[clap(help_heading = "OUTPUT", long)]
+    limit_requests: Option<u64>,
+    /// optional. prints the newest n records as a table without writing any files, e.g. to check
+    /// the schema before a long download.
+    #[clap(
+        help_heading = "OUTPUT",
+        long,
+        value_name = "N",
+        conflicts_with = "dry-run"
+    )]
+    preview: Option<usize>,
+    /// optional. only counts the fills of each day without writing any files. only reports the
+    /// duplicates with dedupe.
+    #[clap(help_heading = "OUTPUT", long)]
+    dry_run: bool,
+    /// optional. continues the interrupted download from where it stopped.
+    #[clap(help_heading = "OUTPUT", long)]
+    resume: bool,
+    /// optional. a timezone to split fills into daily files and to interpret --start, --end and --last in.
+    /// local, an IANA name (e.g. Asia/Tokyo) or an offset (e.g. +09:00).
+    #[clap(
+        help_heading = "RANGE",
+        long,
+        default_value = "local",
+        allow_hyphen_values = true
+    )]
+    timezone: Timezone,
+    /// optional. downloads only fills newer than the ones already in the output directory.
+    #[clap(help_heading = "RANGE", long, conflicts_with = "start")]
+    incremental: bool,
+    /// optional. the decimal separator of the numbers in the output files. comma is for
+    /// spreadsheets in the locales which use it, and the values are quoted then.
+    #[clap(help_heading = "OUTPUT", long, arg_enum, default_value = "dot")]
+    decimal_separator: DecimalSeparator,
+    /// optional. doesn't download the days whose file is already complete in the manifest again.
+    #[clap(help_heading = "OUTPUT", long)]
+    skip_existing: bool,
+    /// optional. downloads all days even if --skip-existing is specified, and overwrites existing
+    /// files without confirmation.
+    #[clap(help_heading = "OUTPUT", long)]
+    force: bool,
+    /// optional. seconds to wait for a connection to be established. 0 means no timeout.
+    #[clap(help_heading = "NETWORK", long, default_value = "30")]
+    connect_timeout: u64,
+    /// optional. seconds to wait for a response of a request. 0 means no timeout.
+    #[clap(help_heading = "NETWORK", long, default_value = "120")]
+    request_timeout: u64,
+    /// optional. an http or socks5 proxy url, e.g. http://localhost:8080, socks5://localhost:1080.
+    /// HTTPS_PROXY or ALL_PROXY environment variable is used if not specified.
+    #[clap(help_heading = "NETWORK", long)]
+    proxy: Option<String>,
+    /// optional. a directory to save all raw responses of the API into, gzipped.
+    #[clap(help_heading = "NETWORK", long, parse(from_os_str))]
+    save_raw: Option<PathBuf>,
+    /// optional. a directory to record all requests and responses into, to replay them later with
+    /// --replay-http, e.g. to debug a parse failure offline. the credential is not recorded.
+    #[clap(
+        help_heading = "NETWORK",
+        long,
+        parse(from_os_str),
+        conflicts_with = "replay-http"
+    )]
+    record_http: Option<PathBuf>,
+    /// optional. a directory recorded with --record-http to answer the requests with instead of
+    /// the API. the command must be run with the same options as recorded.
+    #[clap(help_heading = "NETWORK", long, parse(from_os_str))]
+    replay_http: Option<PathBuf>,
+    /// optional. how many times a request is retried when it fails temporarily.
+    #[clap(help_heading = "NETWORK", long, default_value = "5")]
+    max_retries: u32,
+    /// optional. aborts the download when a request fails after the retries. this is the default.
+    #[clap(help_heading = "NETWORK", long, conflicts_with = "continue-on-error")]
+    fail_fast: bool,
+    /// optional. skips the day of a request which fails after the retries and records it in the
+    /// manifest to backfill it later with the gaps command.
+    #[clap(help_heading = "NETWORK", long)]
+    continue_on_error: bool,
+    /// optional. the format of the log lines written to stderr.
+    #[clap(help_heading = "LOGGING", long, arg_enum, default_value = "text")]
+    log_format: LogFormat,
+    /// optional. logs only warnings and errors and hides the progress bar, e.g. for cron.
+    #[clap(help_heading = "LOGGING", short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// optional. logs debug messages, or trace messages of all libraries when repeated (-vv).
+    /// RUST_LOG is used if neither --quiet nor --verbose is specified.
+    #[clap(help_heading = "LOGGING", short, long, parse(from_occurrences))]
+    verbose: u8,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Clone, Debug, PartialEq, Eq)]
+enum Command {
+    /// downloads a kind of history. `collect fills` is run if no command is specified.
+    Collect {
+        #[clap(subcommand)]
+        data: Collect,
+    },
+    /// compares the archive in the output directory with the history on the exchange
+    Verify,
+    /// compares the filled size of each order with the total size of its fills
+    Audit,
+    /// reports missing days and empty files in the output directory
+    Gaps {
+        /// downloads the history of the gaps again
+        #[clap(long)]
+        backfill: bool,
+    },
+    /// computes reports from the archive in the output directory without the exchange
+    Report {
+        #[clap(subcommand)]
+        report: Report,
+    },
+    /// merges the daily files of the output directories into --outdir, sorting the records by
+    /// time and dropping the duplicated ones by their ids
+    Merge {
+        /// the output directories of other downloads, e.g. on other machines
+        #[clap(required = true)]
+        sources: Vec<PathBuf>,
+    },
+    /// removes the records whose ids appear more than once in the output directory and rewrites
+    /// the files. the record in the file of its date in --timezone is kept. only reports the
+    /// duplicates with --dry-run.
+    Dedupe,
+    /// writes the daily files of the directory into --outdir with another sink or schema, e.g.
+    /// without --raw, since the history of ftx can't be downloaded again
+    Convert {
+        /// the format of the files to read
+        #[clap(long, default_value = "csv", possible_values = &["csv"])]
+        from: String,
+        /// the sink to write into, csv, parquet in a build with the parquet feature, or a sink
+        /// registered by a build embedding the library
+        #[clap(long)]
+        to: String,
+        /// the output directory of a download
+        dir: PathBuf,
+    },
+    /// writes the trades, the deposits, the withdrawals and the funding payments of --outdir in
+    /// the csv template of a crypto tax service into <account>.<format>.csv, or as double entry
+    /// transactions into <account>.beancount or <account>.journal. the trades of derivatives
+    /// are written as their realized pnl.
+    Export {
+        /// the csv template or the ledger format to write
+        #[clap(long, arg_enum)]
+        format: ExportFormat,
+        /// optional. the account of the balances for beancount and hledger, Assets:<Exchange>:
+        /// <Account> by default
+        #[clap(long)]
+        assets_account: Option<String>,
+        /// the account of the fees for beancount and hledger
+        #[clap(long, default_value = "Expenses:Fees")]
+        fees_account: String,
+        /// the account of the realized pnl and the funding payments for beancount and hledger
+        #[clap(long, default_value = "Income:Trading")]
+        pnl_account: String,
+        /// the other side of the deposits and the withdrawals for beancount and hledger
+        #[clap(long, default_value = "Equity:Transfers")]
+        transfers_account: String,
+    },
+    /// runs sql over the daily files of --outdir as the table `fills` and prints the result.
+    /// needs a build with `--features query`
+    Query {
+        /// e.g. "SELECT market, sum(fee) FROM fills GROUP BY market"
+        sql: String,
+    },
+    /// asks the credential and the options of ftx interactively and writes a config file
+    Init,
+    /// saves a credential json read from stdin into the OS keyring as --credential keyring:<name>
+    StoreCredential,
+    /// prints a completion script of the shell to stdout
+    Completions {
+        #[clap(arg_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// prints a man page to stdout for packagers
+    #[clap(hide = true)]
+    Mangen,
+}
+
+#[derive(clap::Subcommand, Clone, Copy, Debug, PartialEq, Eq)]
+enum Collect {
+    /// downloads fills, and deposits, withdrawals and fundings on some exchanges
+    Fills,
+}
+
+#[derive(clap::Subcommand, Clone, Debug, PartialEq, Eq)]
+enum Report {
+    /// writes the realized pnl of each market per day, including fees and funding payments, into
+    /// <account>.pnl.csv. the days of --start and --end are written.
+    Pnl {
+        /// how the cost of a position is tracked
+        #[clap(long, arg_enum, default_value = "average")]
+        method: CostMethod,
+    },
+    /// writes the fees of the trades summed by month, market and fee currency into
+    /// <account>.fees.csv. maker rebates are summed as negative fees. the days of --start and
+    /// --end are included.
+    Fees,
+    /// writes the funding payments summed by month and market into <account>.funding.csv, with
+    /// the average notional of the position at the payments and the effective apr. the days of
+    /// --start and --end are included.
+    Funding,
+    /// writes the volume weighted average price of the fills per period, market and side into
+    /// <account>.vwap.csv, e.g. to evaluate the execution. the periods are in --timezone.
+    Vwap {
+        /// the length of the periods
+        #[clap(long, arg_enum, default_value = "day")]
+        interval: Interval,
+    },
+    /// writes candles of the prices and the sizes of the fills per market into
+    /// <account>.candles.csv, e.g. to plot the activity. the candles are in --timezone.
+    Candles {
+        /// the length of a candle, e.g. 15m, 4h or 1d
+        #[clap(long, default_value = "1h")]
+        resolution: Resolution,
+    },
+    /// writes how much worse the fill prices are than the opens and the closes of their candles
+    /// per market into <account>.slippage.csv, e.g. to estimate the execution cost
+    Slippage {
+        /// a csv file of time,market,open,close of downloaded candles, where time is the start of
+        /// a candle
+        #[clap(long)]
+        candles: PathBuf,
+        /// the length of the candles in the file
+        #[clap(long, default_value = "1m")]
+        resolution: Resolution,
+    },
+    /// writes the position and the average entry price of the market after each fill into
+    /// <account>.positions.csv. fills which make the position inconsistent, which is likely by
+    /// missing fills, are flagged. the fills of --start and --end are written.
+    Positions,
+    /// writes the round trips of each market, from the fill opening a position to the one closing
+    /// it, with the holding time, the pnl and the fees into <account>.round-trips.csv, e.g. for a
+    /// trade journal. the round trips whose last fill is in --start and --end are written.
+    RoundTrips,
+    /// writes the notional and the fees of the maker and the taker fills per month into
+    /// <account>.maker-taker.csv, with what the maker fills saved against the taker fee rate. the
+    /// liquidity is only in the files downloaded with --raw.
+    MakerTaker,
+    /// writes the notional of the fills per day and quote currency with the one of the rolling
+    /// window ending with the day into <account>.volume.csv, e.g. to compare against the volume
+    /// thresholds of the fee tiers
+    Volume {
+        /// the length of the window in days
+        #[clap(long, default_value = "30")]
+        days: u32,
+        /// optional. the comma separated volume thresholds of the fee tiers, e.g.
+        /// 2000000,5000000,10000000. the tier column is how many of them the volume reaches.
+        #[clap(long, use_value_delimiter = true)]
+        tiers: Vec<Decimal>,
+    },
+    /// writes the win rate, the average win and loss, the profit factor and the largest drawdown
+    /// of the closed round trips of each market into <account>.stats.csv
+    Stats,
+    /// writes the equity of the account at the end of each day, with the net deposits and the pnl,
+    /// into <account>.equity.csv, e.g. to plot the performance. the balances are replayed from all
+    /// fills, funding payments, deposits, withdrawals and transfers.
+    Equity {
+        /// the currency to value the balances in
+        #[clap(long, default_value = "USD")]
+        currency: String,
+        /// optional. a csv file of time,currency,rate to value the currencies with instead of the
+        /// last prices of their spot markets in the archive
+        #[clap(long)]
+        rates: Option<PathBuf>,
+    },
+    /// writes the disposals of the year paired with the lots they take, with their cost basis and
+    /// proceeds, into <account>.tax-lots-<year>.csv
+    TaxLots {
+        /// which lots a disposal takes first
+        #[clap(long, arg_enum)]
+        method: LotMethod,
+        /// the year of the disposals, e.g. 2022
+        #[clap(long)]
+        tax_year: i32,
+    },
+}
+
+impl Args {
+    fn subcommand(&self) -> Command {
+        self.command.clone().unwrap_or(Command::Collect {
+            data: Collect::Fills,
+        })
+    }
+
+    fn credential(&self) -> Result<&Path> {
+        match &self.credential {
+            Some(path) => Ok(path),
+            None => bail!(Usage("--credential is required".into())),
+        }
+    }
+
+    fn outdir(&self) -> Result<&Path> {
+        match &self.outdir {
+            Some(path) => Ok(path),
+            None => bail!(Usage("--outdir is required".into())),
+        }
+    }
+
+    /// Returns the inclusive starting date and the exclusive ending date of the range options.
+    fn dates(&self, today: NaiveDate) -> (Option<NaiveDate>, NaiveDate) {
+        match self
+            .month
+            .map(|m| m.range())
+            .or(self.year.map(|y| y.range()))
+            .or(self.yesterday.then(|| (today.pred(), today)))
+            .or(self.today.then(|| (today, today.succ())))
+        {
+            Some((start, end)) => (Some(start), end),
+            None => {
+                let end = self.end.unwrap_or(today);
+                (
+                    self.start
+                        .or_else(|| self.last.map(|last| last.before(end))),
+                    end,
+                )
+            }
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum ExchangeKind {
+    Ftx,
+    Binance,
+    Bybit,
+    Kraken,
+    Coinbase,
+    Okx,
+    Deribit,
+    Bitfinex,
+    Kucoin,
+    Gateio,
+    Dydx,
+}
+
+impl ExchangeKind {
+    /// Returns false if the exchange is left out of this build by the cargo features.
+    fn is_enabled(&self) -> bool {
+        match self {
+            ExchangeKind::Ftx => true,
+            ExchangeKind::Binance => cfg!(feature = "binance"),
+            ExchangeKind::Bybit => cfg!(feature = "bybit"),
+            ExchangeKind::Kraken => cfg!(feature = "kraken"),
+            ExchangeKind::Coinbase => cfg!(feature = "coinbase"),
+            ExchangeKind::Okx => cfg!(feature = "okx"),
+            ExchangeKind::Deribit => cfg!(feature = "deribit"),
+            ExchangeKind::Bitfinex => cfg!(feature = "bitfinex"),
+            ExchangeKind::Kucoin => cfg!(feature = "kucoin"),
+            ExchangeKind::Gateio => cfg!(feature = "gateio"),
+            ExchangeKind::Dydx => cfg!(feature = "dydx"),
+        }
+    }
+
+    /// Returns the name given to --exchange, which is also the name of the exchange client.
+    fn name(&self) -> &'static str {
+        self.to_possible_value()
+            .map(|v| v.get_name())
+            .unwrap_or_default()
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    std::env::set_var(
-        "RUST_LOG",
-        std::env::var("RUST_LOG").unwrap_or("info,surf=warn".to_string()),
-    );
-    env_logger::init();
+    let args: Args = match config::args(&Args::command()) {
+        Ok(args) => match Args::try_parse_from(args) {
+            Ok(args) => args,
+            // clap exits with 2 for invalid arguments, which is the code of a rejected credential
+            Err(e) if e.use_stderr() => {
+                let _ = e.print();
+                exit(ExitCode::Usage as i32);
+            }
+            Err(e) => e.exit(),
+        },
+        Err(e) => {
+            progress::init_logger(LogFormat::Text, false);
+            error!("{:#}", e);
+            exit(ExitCode::of(&e) as i32);
+        }
+    };
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => "warn".to_string(),
+        (_, 0) => std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+        // the library logs most of the download
+        (_, 1) => format!("info,{}=debug,ftx_history=debug", module_path!()),
+        _ => "trace".to_string(),
+    };
+    std::env::set_var("RUST_LOG", level);
+    progress::init_logger(args.log_format, args.quiet);
+    // ctrl-c should abort the prompts of init immediately
+    if args.subcommand() != Command::Init {
+        shutdown::listen();
+    }
+    let started = Instant::now();
+    let result = run(&args).await;
+    if !args.dry_run
+        && args.preview.is_none()
+        && matches!(
+            args.subcommand(),
+            Command::Collect { .. } | Command::Gaps { backfill: true }
+        )
+    {
+        // reported even if the run failed to tell how far it went
+        if let Err(e) = summary::report(started, args.summary.as_deref()).await {
+            error!("{:#}", e);
+        }
+    }
+    if let Err(e) = result {
+        error!("{:#}", e);
+        exit(ExitCode::of(&e) as i32);
+    }
+}
 
-    let args: Args = Args::parse();
+async fn run(args: &Args) -> Result<()> {
+    if let Command::Completions { shell } = args.subcommand() {
+        clap_complete::generate(
+            shell,
+            &mut Args::command(),
+            env!("CARGO_BIN_NAME"),
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+    if args.subcommand() == Command::Mangen {
+        clap_mangen::Man::new(Args::command())
+            .render(&mut std::io::stdout())
+            .with_context(|| "failed to write the man page")?;
+        return Ok(());
+    }
     if args
         .start
         .zip(args.end)
         .map(|(start, end)| start >= end)
         .unwrap_or(false)
     {
-        error!("end date must be greater than start date");
-        exit(1);
+        bail!(Usage("end date must be greater than start date".into()));
+    }
+    if !args.exchange.is_enabled() {
+        let name = args.exchange.name();
+        bail!(Usage(format!(
+            "{} is not supported by this build. build it with `--features {}`",
+            name, name
+        )));
+    }
+    if matches!(args.subcommand(), Command::Query { .. }) && !cfg!(feature = "query") {
+        bail!(Usage(
+            "query is not supported by this build. build it with `--features query`".into()
+        ));
+    }
+    sink::check(&args.sink)?;
+    if args.daily_aggregate && args.sink != "csv" {
+        bail!(Usage(
+            "--daily-aggregate is supported only for the csv sink".into()
+        ));
     }
 
-    let cred: FtxCredential = serde_json::from_str(
-        &tokio::fs::read_to_string(args.credential)
-            .await
-            .expect("failed to read credential file"),
-    )
-    .expect("failed to parse credential file");
-
-    let outdir = &args.outdir;
-    let sub_account = &args.sub_account;
-    let start_time = args.start.map(|d| d.and_hms(0, 0, 0));
-    let end_time = args
-        .end
-        .map(|d| d.and_hms(0, 0, 0))
-        .unwrap_or(Utc::now().naive_utc().date().and_hms(0, 0, 0));
-
-    futures::stream::unfold(
-        RequestCursor {
-            end_time: end_time.clone(),
-            oldest_fill_id: u64::MAX,
-        },
-        |RequestCursor {
-             end_time,
-             oldest_fill_id,
-         }| {
-            // FTX API returns up to 5000 fills order by time desc
-            // So always specifying start_time=zero and moves end_time to obtain all fills
-            get_fills(0, end_time.timestamp(), &cred, sub_account).map(move |result| {
-                let fills = result
-                    .expect("failed to request")
-                    .into_iter()
-                    .filter(|f: &FtxFill| {
-                        // avoid duplication
-                        f.id < oldest_fill_id
-                            // newer than the specified start time
-                            && start_time
-                                .map(|st| st <= f.time.naive_utc())
-                                .unwrap_or(true)
-                    })
-                    .collect::<Vec<_>>();
-                let next_cursor = fills.last().map(|oldest: &FtxFill| {
-                    info!(
-                        "{} fills between {} and {} ({} - {})",
-                        fills.len(),
-                        oldest.time.timestamp(),
-                        end_time.timestamp(),
-                        // TODO use the specified timezone
-                        oldest.time.naive_utc().format("%Y-%m-%dT%H:%M:%S"),
-                        end_time.format("%Y-%m-%dT%H:%M:%S"),
-                    );
-                    RequestCursor {
-                        // +1 second because some fills on the same second maybe still remaining
-                        end_time: oldest.time.naive_utc() + chrono::Duration::seconds(1),
-                        oldest_fill_id: oldest.id,
-                    }
-                });
-
-                next_cursor.map(|c| (fills, c))
-            })
-        },
-    )
-    .flat_map(|fills| futures::stream::iter(fills))
-    .fold(None, |cursor, fill: FtxFill| async move {
-        let fill_date = fill.time.date().naive_utc();
-        let mut writer = cursor
-            .and_then(
-                |WriterCursor {
-                     target_date: date,
-                     writer,
-                 }| {
-                    if date == fill_date {
-                        // continue writing to current file
-                        Some(futures::future::ready(writer).left_future())
-                    } else {
-                        // date is changed so change file to write
-                        None
-                    }
-                },
-            )
-            .unwrap_or_else(|| {
-                // date is changed or cursor is not initialized yet
-                new_writer(outdir, sub_account, &fill_date)
-                    .map(|result| result.expect("failed to open a new file"))
-                    .right_future()
-            })
-            .await;
-        writer
-            .serialize(&fill)
-            .await
-            .expect("failed to write data to file");
-        Some(WriterCursor {
-            target_date: fill_date,
-            writer,
-        })
+    let timeout = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+    configure(ClientConfig {
+        connect_timeout: timeout(args.connect_timeout),
+        request_timeout: timeout(args.request_timeout),
+        proxy: args.proxy.clone().or_else(|| {
+            ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+                .iter()
+                .find_map(|key| std::env::var(key).ok().filter(|v| !v.is_empty()))
+        }),
+        save_raw: args.save_raw.clone(),
+        record_http: args.record_http.clone(),
+        replay_http: args.replay_http.clone(),
+        max_retries: args.max_retries,
     })
-    .await;
+    .with_context(|| "failed to build the http client")?;
+
+    if args.subcommand() == Command::StoreCredential {
+        return store_credential(args.credential()?).await;
+    }
+    if args.subcommand() == Command::Init {
+        return init::init().await;
+    }
+
+    let mut sub_accounts = args
+        .sub_account
+        .iter()
+        .cloned()
+        .map(Some)
+        .collect::<Vec<_>>();
+    if sub_accounts.is_empty() {
+        sub_accounts.push(match args.exchange {
+            ExchangeKind::Ftx => std::env::var("FTX_SUBACCOUNT")
+                .ok()
+                .filter(|s| !s.is_empty()),
+            _ => None,
+        });
+    }
+    if args.all_subaccounts {
+        if !matches!(args.exchange, ExchangeKind::Ftx) {
+            bail!(Usage("--all-subaccounts is supported only for ftx".into()));
+        }
+        let exchange = ftx(args, None).await?;
+        let nicknames = retry(|| exchange.get_subaccounts()).await.map_err(|e| {
+            // subaccounts can't be listed with an api key of a subaccount
+            if e.chain().any(|c| c.is::<Unauthorized>())
+                || format!("{:#}", e).to_lowercase().contains("subaccount")
+            {
+                e.context("failed to list the subaccounts. use an api key of the main account")
+            } else {
+                e.context("failed to list the subaccounts")
+            }
+        })?;
+        info!("{} subaccounts are found", nicknames.len());
+        sub_accounts = std::iter::once(None)
+            .chain(nicknames.into_iter().map(Some))
+            .collect();
+    }
+    if let [sub_account] = sub_accounts.as_slice() {
+        return run_account(args, sub_account.clone()).await;
+    }
+
+    let mut failed = vec![];
+    for sub_account in sub_accounts {
+        let account = sub_account.clone().unwrap_or_else(|| "main".to_string());
+        info!("downloading the subaccount {}", account);
+        if let Err(e) = run_account(args, sub_account).await {
+            // the other subaccounts would fail in the same way
+            if matches!(ExitCode::of(&e), ExitCode::Interrupted | ExitCode::Usage) {
+                return Err(e);
+            }
+            error!("failed to download the subaccount {}: {:#}", account, e);
+            failed.push(account);
+        }
+    }
+    if !failed.is_empty() {
+        bail!(Partial(format!(
+            "{} subaccounts failed: {}",
+            failed.len(),
+            failed.join(", ")
+        )));
+    }
+    Ok(())
 }
 
-async fn get_fills(
-    // inclusive
-    start_time: i64,
-    // exclusive
-    end_time: i64,
-    credential: &FtxCredential,
-    sub_account: &Option<String>,
-) -> Result<Vec<FtxFill>> {
-    let response_body = surf::client()
-        .send(signed_request(
-            surf::get(format!(
-                "https://ftx.com/api/fills?start_time={}&end_time={}",
-                start_time, end_time
-            ))
-            .build(),
-            credential,
-            sub_account,
-        ))
-        .await
-        .map_err(Error::msg)?
-        .body_string()
-        .await
-        .map_err(Error::msg)?;
+/// Downloads the history of a subaccount, or the main account if `sub_account` is `None`.
+async fn run_account(args: &Args, sub_account: Option<String>) -> Result<()> {
+    let tz = args.timezone;
+    let outdir = args.outdir()?;
+    let account = sub_account.as_deref().unwrap_or("main");
+    let (start, end) = args.dates(tz.today());
+    let start = if args.incremental {
+        // the newest file may be incomplete so it is downloaded again
+        let latest = archive::files(outdir, account).into_keys().next_back();
+        match latest {
+            Some(date) => info!("downloading fills since {}", date),
+            None => info!("no downloaded file is found so downloading all fills"),
+        }
+        latest
+    } else if args.subcommand() == Command::Verify {
+        // verify all days in the archive by default
+        start.or_else(|| archive::files(outdir, account).into_keys().next())
+    } else {
+        start
+    };
+    let range = TimeRange {
+        start: start.map(|d| tz.start_of(d)),
+        end: tz.start_of(end),
+    };
+    let converter = match (&args.convert_to, &args.rates) {
+        (Some(currency), Some(rates)) => Some(Converter::from_csv(currency, rates).await?),
+        (Some(currency), None) => Some(Converter::ftx(
+            currency,
+            Ftx::builder().domain(&args.domain).build_markets()?,
+        )),
+        (None, _) => None,
+    };
+    let output = Output {
+        dir: outdir,
+        account,
+        raw: args.raw,
+        resume: args.resume,
+        dry_run: args.dry_run,
+        preview: args.preview,
+        skip_existing: args.skip_existing && !args.force,
+        continue_on_error: args.continue_on_error,
+        timezone: tz,
+        markets: &args.filter_market,
+        side: args.side,
+        liquidity: args.liquidity,
+        types: &args.types,
+        min_notional: args.min_notional,
+        limit: args.limit,
+        limit_requests: args.limit_requests,
+        cancel: shutdown::token(),
+        sink: &args.sink,
+        convert: converter.as_ref(),
+        decimal_separator: args.decimal_separator,
+    };
+
+    if let Command::Gaps { backfill: false } = args.subcommand() {
+        gaps::find(outdir, account).await?;
+        return Ok(());
+    }
+    if let Command::Merge { sources } = args.subcommand() {
+        merge::merge(outdir, account, args.exchange.name(), &sources).await?;
+        return Ok(());
+    }
+    if args.subcommand() == Command::Dedupe {
+        dedupe::dedupe(outdir, account, args.exchange.name(), tz, args.dry_run).await?;
+        return Ok(());
+    }
+    if let Command::Convert { to, dir, .. } = args.subcommand() {
+        sink::check(&to)?;
+        let options = SinkOptions {
+            dir: outdir,
+            account,
+            exchange: args.exchange.name(),
+            raw: args.raw,
+            decimal_separator: args.decimal_separator,
+        };
+        reencode::reencode(&dir, &to, options, start, end).await?;
+        return Ok(());
+    }
+    if let Command::Export {
+        format,
+        assets_account,
+        fees_account,
+        pnl_account,
+        transfers_account,
+    } = args.subcommand()
+    {
+        let exchange = args.exchange.name();
+        let mut accounts = LedgerAccounts::new(exchange, account);
+        accounts.assets = assets_account.unwrap_or(accounts.assets);
+        accounts.fees = fees_account;
+        accounts.pnl = pnl_account;
+        accounts.transfers = transfers_account;
+        export::export(outdir, account, exchange, format, &accounts, start, end).await?;
+        return Ok(());
+    }
+    #[cfg(feature = "query")]
+    if let Command::Query { sql } = args.subcommand() {
+        ftx_history::query::query(outdir, account, &sql).await?;
+        return Ok(());
+    }
+    if let Command::Report { report } = args.subcommand() {
+        let separator = args.decimal_separator;
+        match report {
+            Report::Pnl { method } => {
+                pnl::report(outdir, account, method, start, end, separator).await?
+            }
+            Report::Fees => fees::report(outdir, account, start, end, separator).await?,
+            Report::Funding => funding::report(outdir, account, start, end, separator).await?,
+            Report::Vwap { interval } => {
+                vwap::report(outdir, account, interval, tz, start, end, separator).await?
+            }
+            Report::Candles { resolution } => {
+                candles::report(outdir, account, resolution, tz, start, end, separator).await?
+            }
+            Report::Slippage {
+                candles,
+                resolution,
+            } => {
+                slippage::report(outdir, account, &candles, resolution, start, end, separator)
+                    .await?
+            }
+            Report::Positions => positions::report(outdir, account, start, end, separator).await?,
+            Report::RoundTrips => {
+                roundtrips::report(outdir, account, start, end, separator).await?
+            }
+            Report::MakerTaker => {
+                makertaker::report(outdir, account, start, end, separator).await?
+            }
+            Report::Volume { days, tiers } => {
+                volume::report(outdir, account, days, &tiers, start, end, separator).await?
+            }
+            Report::Stats => stats::report(outdir, account, start, end, separator).await?,
+            Report::Equity { currency, rates } => {
+                let rates = match rates {
+                    Some(rates) => Some(Converter::from_csv(&currency, &rates).await?),
+                    None => None,
+                };
+                equity::report(
+                    outdir,
+                    account,
+                    &currency,
+                    rates.as_ref(),
+                    start,
+                    end,
+                    separator,
+                )
+                .await?
+            }
+            Report::TaxLots { method, tax_year } => {
+                tax::report(outdir, account, method, tax_year, separator).await?
+            }
+        };
+        return Ok(());
+    }
+    // the newest file is downloaded again by design of --incremental, and the files of
+    // --resume and --skip-existing are known to be incomplete
+    let overwrites = matches!(args.subcommand(), Command::Collect { .. })
+        && !(args.force
+            || args.dry_run
+            || args.preview.is_some()
+            || args.resume
+            || args.incremental
+            || args.skip_existing);
+    if overwrites {
+        let existing = archive::files(outdir, account)
+            .into_keys()
+            .filter(|date| start.is_none_or(|start| start <= *date) && *date < end)
+            .collect::<Vec<_>>();
+        confirm_overwrite(account, &existing).await?;
+    }
 
-    serde_json::from_str::<FtxResponse<_>>(&response_body)
-        .with_context(|| {
-            format!(
-                "unexpected response json format. \n\nresponse body:\n{}",
-                response_body
+    match args.exchange {
+        ExchangeKind::Ftx => {
+            let exchange = ftx(args, sub_account.clone()).await?;
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "binance")]
+        ExchangeKind::Binance => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for binance".into()));
+            }
+            if args.symbols.is_empty() {
+                warn!("--symbols is not specified so only deposits and withdrawals are downloaded");
+            }
+            let exchange = Binance::new(
+                read_credential(args.credential()?, args.strict_permissions).await?,
+                &args.symbols,
             )
-        })
-        .map(|body_json| body_json.result)
+            .await
+            .with_context(|| "failed to initialize binance client")?;
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "bybit")]
+        ExchangeKind::Bybit => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for bybit".into()));
+            }
+            let exchange =
+                Bybit::new(read_credential(args.credential()?, args.strict_permissions).await?);
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "kraken")]
+        ExchangeKind::Kraken => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for kraken".into()));
+            }
+            let exchange =
+                Kraken::new(read_credential(args.credential()?, args.strict_permissions).await?)
+                    .await
+                    .with_context(|| "failed to initialize kraken client")?;
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "coinbase")]
+        ExchangeKind::Coinbase => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for coinbase".into()));
+            }
+            let exchange =
+                Coinbase::new(read_credential(args.credential()?, args.strict_permissions).await?);
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "okx")]
+        ExchangeKind::Okx => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for okx".into()));
+            }
+            let exchange =
+                Okx::new(read_credential(args.credential()?, args.strict_permissions).await?);
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "deribit")]
+        ExchangeKind::Deribit => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for deribit. use an api key of the subaccount instead".into()));
+            }
+            let exchange =
+                Deribit::new(read_credential(args.credential()?, args.strict_permissions).await?)
+                    .await
+                    .with_context(|| "failed to initialize deribit client")?;
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "bitfinex")]
+        ExchangeKind::Bitfinex => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for bitfinex. use an api key of the subaccount instead".into()));
+            }
+            let exchange =
+                Bitfinex::new(read_credential(args.credential()?, args.strict_permissions).await?);
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "kucoin")]
+        ExchangeKind::Kucoin => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for kucoin. use an api key of the subaccount instead".into()));
+            }
+            let exchange =
+                Kucoin::new(read_credential(args.credential()?, args.strict_permissions).await?);
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "gateio")]
+        ExchangeKind::Gateio => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for gateio. use an api key of the subaccount instead".into()));
+            }
+            let exchange =
+                GateIo::new(read_credential(args.credential()?, args.strict_permissions).await?);
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[cfg(feature = "dydx")]
+        ExchangeKind::Dydx => {
+            if sub_account.is_some() {
+                bail!(Usage("--sub-account is not supported for dydx".into()));
+            }
+            let exchange =
+                Dydx::new(read_credential(args.credential()?, args.strict_permissions).await?);
+            execute(&exchange, &range, &output, args.subcommand()).await?;
+        }
+        #[allow(unreachable_patterns)]
+        kind => unreachable!("{:?} is checked to be enabled", kind),
+    }
+    let downloaded = matches!(
+        args.subcommand(),
+        Command::Collect { .. } | Command::Gaps { backfill: true }
+    ) && !args.dry_run
+        && args.preview.is_none();
+    if args.daily_aggregate && downloaded {
+        aggregate::write(outdir, account, start, end, args.decimal_separator).await?;
+    }
+    Ok(())
 }
 
-fn signed_request(
-    mut rb: Request,
-    credential: &FtxCredential,
-    sub_account: &Option<String>,
-) -> Request {
-    let ts = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
-    let text = format!(
-        "{}{}{}{}",
-        ts,
-        rb.method().to_string(),
-        rb.url().path(),
-        rb.url()
-            .query()
-            .map(|q| format!("?{q}"))
-            .unwrap_or_else(|| "".to_string())
+/// Creates an FTX client whose clock is adjusted to the server time.
+async fn ftx(args: &Args, sub_account: Option<String>) -> Result<Ftx> {
+    let credential = match &args.credential {
+        Some(path) => read_credential(path, args.strict_permissions).await?,
+        None => FtxCredential::from_env().ok_or_else(|| {
+            Usage("--credential or FTX_API_KEY and FTX_API_SECRET are required".into())
+        })?,
+    };
+    let builder = Ftx::builder()
+        .credential(credential)
+        .sub_account(sub_account)
+        .domain(&args.domain);
+    match args.ts_offset {
+        Some(ts_offset) => builder.ts_offset(ts_offset).build(),
+        None => {
+            let mut exchange = builder.build()?;
+            if let Err(e) = exchange.sync_clock().await {
+                warn!(
+                    "failed to get the server time. using the local clock: {:#}",
+                    e
+                );
+            }
+            Ok(exchange)
+        }
+    }
+}
+
+async fn read_credential<T: DeserializeOwned>(path: &Path, strict_permissions: bool) -> Result<T> {
+    let json = match keychain::entry_name(path) {
+        Some(name) => keychain::get(name)
+            .await
+            .with_context(|| "failed to read credential from the keyring")?,
+        None => {
+            check_permissions(path, strict_permissions).await?;
+            tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| "failed to read credential file")?
+        }
+    };
+    serde_json::from_str(&json).with_context(|| "failed to parse credential file")
+}
+
+/// Asks whether to overwrite the files of the dates, which is refused when stdin is not a
+/// terminal.
+async fn confirm_overwrite(account: &str, dates: &[NaiveDate]) -> Result<()> {
+    let (first, last) = match (dates.first(), dates.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(()),
+    };
+    let message = format!(
+        "{} files of {} between {} and {} already exist and will be overwritten",
+        dates.len(),
+        account,
+        first,
+        last
     );
-    rb.set_header("FTX-KEY", &credential.api_key);
-    rb.set_header("FTX-TS", ts.to_string());
-    rb.set_header(
-        "FTX-SIGN",
-        hex::encode(hmac_sha256::HMAC::mac(&text, &credential.api_secret)),
+    if !std::io::stdin().is_terminal() {
+        bail!(Usage(format!(
+            "{}. specify --force to overwrite them",
+            message
+        )));
+    }
+    let answer = tokio::task::spawn_blocking(move || {
+        eprint!("{}. continue? [y/N] ", message);
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).map(|_| answer)
+    })
+    .await?
+    .with_context(|| "failed to read the answer")?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        bail!(Usage("aborted not to overwrite the files".into()));
+    }
+    Ok(())
+}
+
+/// Warns, or refuses if `strict` is true, when the credential file can be accessed by other
+/// users, as ssh does for private keys.
+#[cfg(unix)]
+async fn check_permissions(path: &Path, strict: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.permissions().mode() & 0o777,
+        // reported when it's read
+        Err(_) => return Ok(()),
+    };
+    if mode & 0o077 == 0 {
+        return Ok(());
+    }
+    let message = format!(
+        "the credential file {} is accessible by other users (mode {:03o}). run `chmod 600 {}`",
+        path.display(),
+        mode,
+        path.display()
     );
-    if let Some(sub_account) = &sub_account {
-        rb.set_header("FTX-SUBACCOUNT", sub_account);
+    if strict {
+        bail!(Usage(message));
     }
+    warn!("{}", message);
+    Ok(())
+}
 
-    rb
+#[cfg(not(unix))]
+async fn check_permissions(_path: &Path, _strict: bool) -> Result<()> {
+    Ok(())
 }
 
-async fn new_writer(
-    outdir: &PathBuf,
-    sub_account: &Option<String>,
-    date: &NaiveDate,
-) -> Result<AsyncSerializer<File>> {
-    let filepath = outdir.join(format!(
-        "{}_{}.csv",
-        sub_account
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or_else(|| "main"),
-        date,
-    ));
-    tokio::fs::create_dir_all(outdir)
+async fn store_credential(path: &Path) -> Result<()> {
+    let name = keychain::entry_name(path).ok_or_else(|| {
+        Usage("specify --credential keyring:<name> to name the credential to store".into())
+    })?;
+    let mut json = String::new();
+    tokio::io::AsyncReadExt::read_to_string(&mut tokio::io::stdin(), &mut json)
         .await
-        .with_context(|| "failed to create directory to put a file")?;
-    let file = File::create(filepath)
+        .with_context(|| "failed to read the credential from stdin")?;
+    serde_json::from_str::<serde_json::Value>(&json)
+        .with_context(|| "the credential read from stdin is not a json")?;
+    keychain::set(name, json.trim().to_string())
         .await
-        .with_context(|| "failed to create a file to write")?;
-    Ok(csv_async::AsyncSerializer::from_writer(file))
+        .with_context(|| "failed to save the credential into the keyring")?;
+    info!("the credential is saved as keyring:{}", name);
+    Ok(())
 }
 
-struct RequestCursor {
-    end_time: NaiveDateTime,
-    oldest_fill_id: u64,
+async fn execute<E: Exchange + Sync>(
+    exchange: &E,
+    range: &TimeRange,
+    output: &Output<'_>,
+    command: Command,
+) -> Result<()> {
+    // fail early rather than in the middle of a long download
+    retry_with(exchange.max_retries(), || exchange.preflight()).await.map_err(|e| {
+        if e.chain().any(|c| c.is::<Unauthorized>()) {
+            e.context(format!(
+                "the credential is rejected by {}. check that the api key is correct, not expired and allowed to read the trade history",
+                exchange.name()
+            ))
+        } else {
+            e.context("failed to check the credential")
+        }
+    })?;
+    match command {
+        Command::Collect {
+            data: Collect::Fills,
+        } if output.skip_existing => {
+            // newest first as well as a single download, so that --resume continues the
+            // interrupted range which is the first one not complete yet
+            for range in pending_ranges(range, output).await?.iter().rev() {
+                collect(exchange, range, output).await?;
+            }
+            Ok(())
+        }
+        Command::Collect {
+            data: Collect::Fills,
+        } => Ok(collect(exchange, range, output).await?),
+        Command::Verify => {
+            verify::verify(exchange, range, output.dir, output.account, output.timezone).await
+        }
+        Command::Audit => audit::audit(exchange, range).await,
+        Command::Init
+        | Command::StoreCredential
+        | Command::Report { .. }
+        | Command::Merge { .. }
+        | Command::Convert { .. }
+        | Command::Export { .. }
+        | Command::Query { .. }
+        | Command::Dedupe
+        | Command::Completions { .. }
+        | Command::Mangen => {
+            unreachable!("handled before creating the exchange")
+        }
+        Command::Gaps { .. } => {
+            for gap in gaps::find(output.dir, output.account).await? {
+                info!("backfilling {} - {}", gap.start, gap.end);
+                collect(exchange, &gap.range(output.timezone), output).await?;
+            }
+            for window in manifest::failed(output.dir, output.account).await? {
+                info!("backfilling failed {} - {}", window.start, window.end);
+                collect(exchange, &window.range(output.timezone), output).await?;
+            }
+            Ok(())
+        }
+    }
 }
 
-struct WriterCursor {
-    target_date: NaiveDate,
-    writer: AsyncSerializer<File>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[derive(Deserialize)]
-struct FtxCredential {
-    api_key: String,
-    api_secret: String,
-}
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct FtxResponse<T> {
-    result: T,
-}
+    // the range of the options on 2022-03-15
+    fn dates(options: &[&str]) -> clap::Result<(Option<NaiveDate>, NaiveDate)> {
+        let args = Args::try_parse_from(
+            std::iter::once("ftx-history-downloder").chain(options.iter().copied()),
+        )?;
+        Ok(args.dates(date("2022-03-15")))
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct FtxFill {
-    fee: f64,
-    fee_currency: Option<String>,
-    fee_rate: Option<f64>,
-    future: Option<String>,
-    id: u64,
-    liquidity: Option<String>,
-    market: Option<String>,
-    base_currency: Option<String>,
-    quote_currency: Option<String>,
-    order_id: Option<u64>,
-    trade_id: Option<u64>,
-    price: f64,
-    side: Option<String>,
-    size: f64,
-    time: DateTime<Local>,
-    #[serde(rename = "type")]
-    typ: Option<String>,
+    #[test]
+    fn resolves_the_range_options() {
+        let today = date("2022-03-15");
+        assert_eq!(dates(&[]).unwrap(), (None, today));
+        assert_eq!(
+            dates(&["--start", "2022-01-01", "--end", "2022-02-01"]).unwrap(),
+            (Some(date("2022-01-01")), date("2022-02-01"))
+        );
+        assert_eq!(
+            dates(&["--last", "2w"]).unwrap(),
+            (Some(date("2022-03-01")), today)
+        );
+        assert_eq!(
+            dates(&["--last", "1m", "--end", "2022-03-31"]).unwrap(),
+            (Some(date("2022-02-28")), date("2022-03-31"))
+        );
+        assert_eq!(
+            dates(&["--month", "2022-02"]).unwrap(),
+            (Some(date("2022-02-01")), date("2022-03-01"))
+        );
+        assert_eq!(
+            dates(&["--year", "2021"]).unwrap(),
+            (Some(date("2021-01-01")), date("2022-01-01"))
+        );
+        assert_eq!(
+            dates(&["--yesterday"]).unwrap(),
+            (Some(date("2022-03-14")), today)
+        );
+        assert_eq!(
+            dates(&["--today"]).unwrap(),
+            (Some(today), date("2022-03-16"))
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_range_options() {
+        for options in [
+            &["--last", "30d", "--start", "2022-01-01"][..],
+            &["--month", "2022-02", "--end", "2022-03-01"],
+            &["--month", "2022-02", "--year", "2022"],
+            &["--year", "2022", "--yesterday"],
+            &["--yesterday", "--today"],
+            &["--today", "--last", "1d"],
+        ] {
+            assert!(dates(options).is_err(), "{:?}", options);
+        }
+    }
 }
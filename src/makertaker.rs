@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, ser_decimal_opt, DecimalSeparator, FillType, Localized};
+
+/// The trades of a liquidity in a quote currency in a month.
+#[derive(Serialize)]
+pub struct MonthlyLiquidity {
+    // YYYY-MM
+    pub month: String,
+    pub currency: Option<String>,
+    // maker, taker, or unknown for the files of the unified schema
+    pub liquidity: &'static str,
+    pub fills: u64,
+    #[serde(serialize_with = "ser_decimal")]
+    pub notional: Decimal,
+    // positive when charged, negative for rebates
+    #[serde(serialize_with = "ser_decimal")]
+    pub fees: Decimal,
+    // fees / notional
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub fee_rate: Option<Decimal>,
+    // of the maker fills, what they would have been charged at the taker fee rate of the month
+    // minus the fees
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub saved: Option<Decimal>,
+}
+
+/// Sums the notional and the fees of the maker and the taker fills by month and quote currency,
+/// and writes them into `<account>.maker-taker.csv` of `outdir`, e.g. to check a fee tier.
+///
+/// The liquidity is only in the files of the raw schema. Fees in other currencies than the base
+/// and the quote are excluded. The months are of the daily files in the range.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let mut months: BTreeMap<(String, Option<String>, &'static str), MonthlyLiquidity> =
+        BTreeMap::new();
+    let mut excluded_fees = BTreeSet::new();
+    let files = archive::files(outdir, account);
+    let files = match start {
+        Some(start) => files.range(start..end),
+        None => files.range(..end),
+    };
+    for (date, path) in files {
+        for record in archive::read_records(path, *date).await? {
+            let (None | Some(FillType::Order | FillType::Liquidation), Some(price)) =
+                (&record.typ, record.price)
+            else {
+                continue;
+            };
+            let currency = record.settlement_currency().cloned();
+            let fee = match &record.fee_currency {
+                Some(c) if Some(c) == currency.as_ref() => Some(record.fee),
+                Some(c) if Some(c) == record.base_currency.as_ref() => Some(record.fee * price),
+                Some(_) => None,
+                None => Some(record.fee),
+            };
+            let liquidity = match record.liquidity {
+                Some(l) => l.as_str(),
+                None => "unknown",
+            };
+            let month = date.format("%Y-%m").to_string();
+            let row = months
+                .entry((month.clone(), currency.clone(), liquidity))
+                .or_insert_with(|| MonthlyLiquidity {
+                    month,
+                    currency,
+                    liquidity,
+                    fills: 0,
+                    notional: Decimal::ZERO,
+                    fees: Decimal::ZERO,
+                    fee_rate: None,
+                    saved: None,
+                });
+            row.fills += 1;
+            row.notional += price * record.size.abs();
+            match fee {
+                Some(fee) => row.fees += fee,
+                None => excluded_fees.extend(record.fee_currency),
+            }
+        }
+    }
+    if !excluded_fees.is_empty() {
+        warn!(
+            "fees in {} are not included since they are not in the currencies of the markets",
+            excluded_fees.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    if months
+        .keys()
+        .any(|(_, _, liquidity)| *liquidity == "unknown")
+    {
+        warn!(
+            "the liquidity of some fills is unknown since it's only in the files downloaded with \
+             --raw"
+        );
+    }
+    for row in months.values_mut() {
+        row.fee_rate = (!row.notional.is_zero()).then(|| row.fees / row.notional);
+    }
+    let taker_rates: BTreeMap<_, _> = months
+        .iter()
+        .filter(|((_, _, liquidity), _)| *liquidity == "taker")
+        .filter_map(|((month, currency, _), row)| {
+            Some(((month.clone(), currency.clone()), row.fee_rate?))
+        })
+        .collect();
+    let path = outdir.join(format!("{}.maker-taker.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    let mut saved: BTreeMap<Option<String>, Decimal> = BTreeMap::new();
+    for ((month, currency, liquidity), mut row) in months {
+        if liquidity == "maker" {
+            row.saved = taker_rates
+                .get(&(month, currency.clone()))
+                .map(|rate| (row.notional * rate - row.fees).round_dp(8).normalize());
+            *saved.entry(currency).or_default() += row.saved.unwrap_or_default();
+        }
+        row.notional = row.notional.normalize();
+        row.fees = row.fees.normalize();
+        row.fee_rate = row.fee_rate.map(|r| r.round_dp(8).normalize());
+        writer
+            .serialize(Localized(&row, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    for (currency, saved) in saved {
+        info!(
+            "saved by the maker fills: {} {}",
+            saved.normalize(),
+            currency.as_deref().unwrap_or("(unknown currency)")
+        );
+    }
+    info!(
+        "the maker and taker fills are written into {}",
+        path.display()
+    );
+    Ok(path)
+}
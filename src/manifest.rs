@@ -0,0 +1,284 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::archive;
+use crate::exchange::{FillId, TimeRange};
+use crate::timezone::Timezone;
+
+/// A summary of the archive of an account, which is used to validate and sync the archive.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    generated_at: DateTime<Utc>,
+    files: Vec<FileEntry>,
+    // kept until they are downloaded again
+    #[serde(default)]
+    failed: Vec<FailedWindow>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileEntry {
+    file: String,
+    rows: usize,
+    min_id: Option<FillId>,
+    max_id: Option<FillId>,
+    sha256: String,
+}
+
+/// A time range which couldn't be downloaded with `--continue-on-error`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FailedWindow {
+    // inclusive
+    pub start: DateTime<Utc>,
+    // exclusive
+    pub end: DateTime<Utc>,
+    // the error of the last attempt
+    pub error: String,
+}
+
+impl FailedWindow {
+    /// Returns the whole days in the timezone which the window overlaps, since downloading a part
+    /// of a day replaces the file of the day with the records of the part.
+    pub fn range(&self, timezone: Timezone) -> TimeRange {
+        let first = timezone.date(&self.start);
+        // the end is exclusive
+        let last = timezone.date(&(self.end - chrono::Duration::nanoseconds(1)));
+        TimeRange {
+            start: Some(timezone.start_of(first)),
+            end: timezone.start_of(last.succ()),
+        }
+    }
+}
+
+/// Writes `<account>.manifest.json` and `<account>.sha256` into `outdir` for all daily files of
+/// the account. The latter can be checked with `sha256sum -c`.
+///
+/// `failed` windows of the download of `range` are recorded in the manifest together with the
+/// ones of previous downloads outside of the range.
+pub async fn write(
+    outdir: &Path,
+    account: &str,
+    range: &TimeRange,
+    failed: Vec<FailedWindow>,
+) -> Result<()> {
+    let mut previous = read(outdir, account)
+        .await?
+        .map(|m| m.failed)
+        .unwrap_or_default();
+    previous
+        .retain(|w| !range.is_after_start(&w.start.naive_utc()) || range.end < w.end.naive_utc());
+    previous.extend(failed);
+    let mut files = vec![];
+    for (_, path) in archive::files(outdir, account) {
+        let ids = archive::read_ids(&path).await?;
+        files.push(FileEntry {
+            file: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            rows: ids.len(),
+            min_id: ids
+                .iter()
+                .min_by(|a, b| compare_ids(a.as_str(), b.as_str()))
+                .cloned(),
+            max_id: ids
+                .iter()
+                .max_by(|a, b| compare_ids(a.as_str(), b.as_str()))
+                .cloned(),
+            sha256: hex::encode(hmac_sha256::Hash::hash(&tokio::fs::read(&path).await?)),
+        });
+    }
+
+    let mut checksums = String::new();
+    for f in &files {
+        writeln!(checksums, "{}  {}", f.sha256, f.file)?;
+    }
+    tokio::fs::write(outdir.join(format!("{}.sha256", account)), checksums).await?;
+    let manifest = Manifest {
+        generated_at: Utc::now(),
+        files,
+        failed: previous,
+    };
+    tokio::fs::write(
+        path(outdir, account),
+        serde_json::to_string_pretty(&manifest)?,
+    )
+    .await?;
+    info!("manifest of {} files is written", manifest.files.len());
+    Ok(())
+}
+
+/// Returns the dates of the files which are listed in the manifest and not modified since then.
+///
+/// The manifest is written only after a download is completed, so those files have all fills of
+/// the day.
+pub async fn complete_dates(outdir: &Path, account: &str) -> Result<BTreeSet<NaiveDate>> {
+    let manifest = match read(outdir, account).await? {
+        Some(manifest) => manifest,
+        None => return Ok(BTreeSet::new()),
+    };
+    let mut dates = BTreeSet::new();
+    for (date, path) in archive::files(outdir, account) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let entry = match manifest.files.iter().find(|f| f.file == name) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if entry.sha256 == hex::encode(hmac_sha256::Hash::hash(&tokio::fs::read(&path).await?)) {
+            dates.insert(date);
+        }
+    }
+    Ok(dates)
+}
+
+/// Returns the windows which failed to be downloaded and are not downloaded again yet.
+pub async fn failed(outdir: &Path, account: &str) -> Result<Vec<FailedWindow>> {
+    Ok(read(outdir, account)
+        .await?
+        .map(|m| m.failed)
+        .unwrap_or_default())
+}
+
+async fn read(outdir: &Path, account: &str) -> Result<Option<Manifest>> {
+    let path = path(outdir, account);
+    if !path.exists() {
+        return Ok(None);
+    }
+    serde_json::from_str(&tokio::fs::read_to_string(&path).await?)
+        .map(Some)
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+fn path(outdir: &Path, account: &str) -> PathBuf {
+    outdir.join(format!("{}.manifest.json", account))
+}
+
+// ids are compared as numbers if possible, since most exchanges use sequential numbers
+fn compare_ids(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u128>(), b.parse::<u128>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 1, day)
+    }
+
+    fn row(id: &str) -> String {
+        format!(
+            "ftx,main,BTC/USD,BTC,USD,buy,40000,0.01,0.0004,USD,2022-01-01T00:00:00Z,{id},order"
+        )
+    }
+
+    fn window(start: u32, end: u32) -> FailedWindow {
+        FailedWindow {
+            start: Utc.from_utc_datetime(&date(start).and_hms(0, 0, 0)),
+            end: Utc.from_utc_datetime(&date(end).and_hms(0, 0, 0)),
+            error: "timed out".into(),
+        }
+    }
+
+    fn range(start: u32, end: u32) -> TimeRange {
+        TimeRange {
+            start: Some(date(start).and_hms(0, 0, 0)),
+            end: date(end).and_hms(0, 0, 0),
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_the_files_with_the_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), date(1), &[&row("9"), &row("10"), &row("8")]);
+        write_file(dir.path(), date(2), &[]);
+        write(dir.path(), "main", &range(1, 3), vec![])
+            .await
+            .unwrap();
+
+        let manifest = read(dir.path(), "main").await.unwrap().unwrap();
+        let entries = manifest
+            .files
+            .iter()
+            .map(|f| (f.file.as_str(), f.rows, f.min_id.clone(), f.max_id.clone()))
+            .collect::<Vec<_>>();
+        // the ids are compared as numbers
+        assert_eq!(
+            entries,
+            [
+                (
+                    "main_2022-01-01.csv",
+                    3,
+                    Some("8".into()),
+                    Some("10".into())
+                ),
+                ("main_2022-01-02.csv", 0, None, None),
+            ]
+        );
+        let checksums = tokio::fs::read_to_string(dir.path().join("main.sha256"))
+            .await
+            .unwrap();
+        let content = std::fs::read(archive::path(dir.path(), "main", date(1))).unwrap();
+        assert_eq!(
+            checksums.lines().next().unwrap(),
+            format!(
+                "{}  main_2022-01-01.csv",
+                hex::encode(hmac_sha256::Hash::hash(&content))
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn excludes_the_files_modified_after_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(complete_dates(dir.path(), "main").await.unwrap().is_empty());
+        write_file(dir.path(), date(1), &[&row("1")]);
+        write_file(dir.path(), date(2), &[&row("2")]);
+        write(dir.path(), "main", &range(1, 3), vec![])
+            .await
+            .unwrap();
+        write_file(dir.path(), date(2), &[&row("2"), &row("3")]);
+        write_file(dir.path(), date(3), &[&row("4")]);
+        assert_eq!(
+            complete_dates(dir.path(), "main").await.unwrap(),
+            BTreeSet::from([date(1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_the_failed_windows_until_they_are_downloaded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "main",
+            &range(1, 10),
+            vec![window(2, 3), window(5, 6)],
+        )
+        .await
+        .unwrap();
+        // the download of 2022-01-04 - 2022-01-08 replaces the failed windows in it
+        write(dir.path(), "main", &range(4, 8), vec![window(7, 8)])
+            .await
+            .unwrap();
+        let failed = failed(dir.path(), "main")
+            .await
+            .unwrap()
+            .iter()
+            .map(|w| (w.start.naive_utc().date(), w.end.naive_utc().date()))
+            .collect::<Vec<_>>();
+        assert_eq!(failed, [(date(2), date(3)), (date(7), date(8))]);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use csv_async::{AsyncReader, AsyncWriter, StringRecord};
+use futures::TryStreamExt;
+use log::*;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::error::Usage;
+use crate::index::Index;
+
+/// Merges the daily files of the account in `sources` into the ones in `outdir`, e.g. of
+/// archives downloaded on different machines.
+///
+/// The records of each date are sorted by time after the ones already written to another file or
+/// earlier in the file are dropped by their ids. `exchange` makes the keys of the index for the
+/// files of the raw schema, which has no exchange column. All files must have the same columns.
+pub async fn merge(
+    outdir: &Path,
+    account: &str,
+    exchange: &str,
+    sources: &[PathBuf],
+) -> Result<()> {
+    let mut dates: BTreeMap<NaiveDate, Vec<PathBuf>> = BTreeMap::new();
+    for dir in std::iter::once(outdir).chain(sources.iter().map(PathBuf::as_path)) {
+        if dir != outdir && !dir.is_dir() {
+            bail!(Usage(format!("{} is not a directory", dir.display())));
+        }
+        for (date, path) in archive::files(dir, account) {
+            dates.entry(date).or_default().push(path);
+        }
+    }
+    if dates.is_empty() {
+        info!("no daily file of {} is found", account);
+        return Ok(());
+    }
+    tokio::fs::create_dir_all(outdir)
+        .await
+        .with_context(|| "failed to create directory to put a file")?;
+
+    // checked before any file is written
+    let mut headers: Option<StringRecord> = None;
+    for path in dates.values().flatten() {
+        let file = File::open(path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let columns = AsyncReader::from_reader(file).headers().await?.clone();
+        match &headers {
+            Some(headers) if *headers != columns => bail!(Usage(format!(
+                "{} has different columns from the other files. merge archives downloaded with \
+                 the same --raw and --convert-to",
+                path.display()
+            ))),
+            Some(_) => {}
+            None => headers = Some(columns),
+        }
+    }
+    let headers = headers.unwrap_or_default();
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h));
+    let id = column(&["native_id", "id"]).ok_or_else(|| anyhow!("no id column"))?;
+    let time = column(&["timestamp", "time"]).ok_or_else(|| anyhow!("no time column"))?;
+    let exchange_column = column(&["exchange"]);
+
+    let mut index = Index::load(outdir, account)
+        .await
+        .with_context(|| "failed to load the index of written fills")?;
+    let mut seen = HashSet::new();
+    let (mut merged, mut duplicates) = (0, 0);
+    for (date, paths) in &dates {
+        let mut rows = vec![];
+        for path in paths {
+            let file = File::open(path)
+                .await
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            let records: Vec<StringRecord> = AsyncReader::from_reader(file)
+                .records()
+                .try_collect()
+                .await
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            rows.extend(records);
+        }
+        let mut records = vec![];
+        for row in rows {
+            // ids are unique only within an exchange
+            let key = format!(
+                "{}:{}",
+                exchange_column.and_then(|i| row.get(i)).unwrap_or(exchange),
+                row.get(id).unwrap_or_default()
+            );
+            if !seen.insert(key.clone()) {
+                duplicates += 1;
+                continue;
+            }
+            let time: DateTime<FixedOffset> = row
+                .get(time)
+                .unwrap_or_default()
+                .parse()
+                .with_context(|| format!("invalid time of {}", key))?;
+            records.push((time, key, row));
+        }
+        // stable so that the records of the same time keep the order of the files
+        records.sort_by_key(|(time, _, _)| *time);
+
+        let path = archive::path(outdir, account, *date);
+        let file = File::create(&path)
+            .await
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = AsyncWriter::from_writer(file);
+        writer
+            .write_record(&headers)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        for (_, key, row) in records {
+            writer
+                .write_record(&row)
+                .await
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            index.insert(key, *date);
+            merged += 1;
+        }
+        writer
+            .flush()
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    index
+        .save()
+        .await
+        .with_context(|| "failed to save the index of written fills")?;
+    if duplicates > 0 {
+        info!("{} duplicated records are dropped", duplicates);
+    }
+    info!(
+        "{} records are merged into {} files in {}",
+        merged,
+        dates.len(),
+        outdir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::write_file;
+    use crate::error::ExitCode;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 1, day)
+    }
+
+    fn row(exchange: &str, time: &str, id: &str) -> String {
+        format!("{exchange},main,BTC/USD,BTC,USD,buy,40000,0.01,0.0004,USD,{time},{id},order")
+    }
+
+    // the exchanges and the ids of the rows of the daily file
+    fn ids(dir: &Path, date: NaiveDate) -> Vec<String> {
+        std::fs::read_to_string(archive::path(dir, "main", date))
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let columns = line.split(',').collect::<Vec<_>>();
+                format!("{}:{}", columns[0], columns[11])
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn merges_the_files_of_the_dates_by_time_without_duplicates() {
+        let (outdir, source) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write_file(
+            outdir.path(),
+            date(1),
+            &[
+                &row("ftx", "2022-01-01T03:00:00Z", "3"),
+                &row("ftx", "2022-01-01T01:00:00Z", "1"),
+            ],
+        );
+        write_file(
+            source.path(),
+            date(1),
+            &[
+                &row("ftx", "2022-01-01T02:00:00Z", "2"),
+                &row("ftx", "2022-01-01T03:00:00Z", "3"),
+                // the same id of another exchange
+                &row("binance", "2022-01-01T03:00:00Z", "1"),
+            ],
+        );
+        write_file(
+            source.path(),
+            date(2),
+            &[
+                &row("ftx", "2022-01-02T00:00:00Z", "4"),
+                // already in the file of 2022-01-01
+                &row("ftx", "2022-01-01T02:00:00Z", "2"),
+            ],
+        );
+        merge(outdir.path(), "main", "ftx", &[source.path().to_path_buf()])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ids(outdir.path(), date(1)),
+            ["ftx:1", "ftx:2", "ftx:3", "binance:1"]
+        );
+        assert_eq!(ids(outdir.path(), date(2)), ["ftx:4"]);
+        let index = Index::load(outdir.path(), "main").await.unwrap();
+        assert_eq!(index.written_elsewhere("ftx:2", date(2)), Some(date(1)));
+        assert_eq!(index.written_elsewhere("ftx:4", date(1)), Some(date(2)));
+        // the sources are left as they are
+        assert_eq!(ids(source.path(), date(2)), ["ftx:4", "ftx:2"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_the_files_of_different_columns() {
+        let (outdir, source) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let row = row("ftx", "2022-01-01T01:00:00Z", "1");
+        write_file(outdir.path(), date(1), &[&row]);
+        std::fs::write(
+            archive::path(source.path(), "main", date(2)),
+            "fee,feeCurrency,id,time\n0.0004,USD,2,2022-01-02T00:00:00Z\n",
+        )
+        .unwrap();
+        let e = merge(outdir.path(), "main", "ftx", &[source.path().to_path_buf()])
+            .await
+            .unwrap_err();
+        assert_eq!(ExitCode::of(&e), ExitCode::Usage);
+        // nothing is written
+        assert_eq!(ids(outdir.path(), date(1)), ["ftx:1"]);
+        assert!(!archive::path(outdir.path(), "main", date(2)).exists());
+    }
+}
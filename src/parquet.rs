@@ -0,0 +1,260 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow::csv::ReaderBuilder;
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use chrono::NaiveDate;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use parquet::arrow::ArrowWriter;
+
+use crate::archive;
+use crate::exchange::{DecimalSeparator, Fill};
+use crate::sink::{CsvSink, Sink, SinkOptions};
+use crate::state::FileState;
+
+// of the decimal columns. digits beyond the scale are cut
+const PRECISION: usize = 38;
+const SCALE: usize = 18;
+
+/// Returns the path of the daily parquet file of the account.
+pub fn path(outdir: &Path, account: &str, date: NaiveDate) -> PathBuf {
+    archive::path(outdir, account, date).with_extension("parquet")
+}
+
+// the csv file of the rows of the date being written, which is encoded into the parquet file when
+// the date is finished
+fn staged_path(outdir: &Path, account: &str, date: NaiveDate) -> PathBuf {
+    archive::path(outdir, account, date).with_extension("parquet.part")
+}
+
+/// Writes fills into the daily parquet files in the output directory, e.g.
+/// `main_2022-01-01.parquet`, with the columns of the csv files.
+///
+/// The times are timestamps in UTC and the numbers are decimals of 18 fractional digits. Since a
+/// parquet file can't be appended to, the rows of a date are staged in a csv file until the next
+/// date or the end of the download, so that an interrupted download is resumed from it.
+pub struct ParquetSink<'a> {
+    outdir: &'a Path,
+    account: &'a str,
+    staged: CsvSink<'a>,
+    // of the staged file
+    date: Option<NaiveDate>,
+}
+
+impl<'a> ParquetSink<'a> {
+    pub fn new(options: SinkOptions<'a>) -> Self {
+        Self {
+            outdir: options.dir,
+            account: options.account,
+            staged: CsvSink::with_path(
+                SinkOptions {
+                    // the numbers are parsed back from the staged file
+                    decimal_separator: DecimalSeparator::Dot,
+                    ..options
+                },
+                staged_path,
+            ),
+            date: None,
+        }
+    }
+
+    // encodes the staged file into the parquet file of its date
+    async fn encode(&mut self) -> Result<()> {
+        self.staged.finalize().await?;
+        let Some(date) = self.date.take() else {
+            return Ok(());
+        };
+        let staged = staged_path(self.outdir, self.account, date);
+        let path = path(self.outdir, self.account, date);
+        tokio::task::spawn_blocking(move || {
+            encode(&staged, &path)
+                .with_context(|| format!("failed to write {} into parquet", staged.display()))
+        })
+        .await?
+    }
+}
+
+impl Sink for ParquetSink<'_> {
+    fn write_record<'a>(&'a mut self, fill: &'a Fill) -> BoxFuture<'a, Result<()>> {
+        self.staged.write_record(fill)
+    }
+
+    fn rotate(&mut self, date: NaiveDate) -> BoxFuture<'_, Result<()>> {
+        async move {
+            self.encode().await?;
+            self.staged.rotate(date).await?;
+            self.date = Some(date);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn reopen<'a>(&'a mut self, state: &'a FileState) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.staged.reopen(state).await?;
+            self.date = Some(state.date);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, Result<Option<FileState>>> {
+        self.staged.flush()
+    }
+
+    fn finalize(&mut self) -> BoxFuture<'_, Result<()>> {
+        self.encode().boxed()
+    }
+}
+
+/// Writes the rows of the csv file into the parquet file and removes the csv file. A date without
+/// rows has no parquet file, since the columns aren't known.
+fn encode(staged: &Path, path: &Path) -> Result<()> {
+    let mut header = String::new();
+    BufReader::new(File::open(staged)?).read_line(&mut header)?;
+    if !header.trim_end().is_empty() {
+        let schema = Arc::new(schema(header.trim_end()));
+        let reader = ReaderBuilder::new()
+            .has_header(true)
+            .with_schema(schema.clone())
+            .build(File::open(staged)?)?;
+        let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+        for batch in reader {
+            writer.write(&batch?)?;
+        }
+        writer.close()?;
+    }
+    std::fs::remove_file(staged)?;
+    Ok(())
+}
+
+// the types of the columns of the header
+fn schema(header: &str) -> Schema {
+    let fields = header
+        .split(',')
+        .map(|name| {
+            let typ = match name {
+                "timestamp" | "time" => DataType::Timestamp(TimeUnit::Nanosecond, None),
+                _ if archive::NUMBERS.contains(&name) => DataType::Decimal(PRECISION, SCALE),
+                _ => DataType::Utf8,
+            };
+            Field::new(name, typ, true)
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{
+        Array, BasicDecimalArray, DecimalArray, StringArray, TimestampNanosecondArray,
+    };
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+
+    use super::*;
+    use crate::sink::tests::fills;
+
+    fn read(path: &Path) -> RecordBatch {
+        let mut reader = ParquetFileArrowReader::try_new(File::open(path).unwrap()).unwrap();
+        let mut batches = reader.get_record_reader(1024).unwrap();
+        let batch = batches.next().unwrap().unwrap();
+        assert!(batches.next().is_none());
+        batch
+    }
+
+    #[tokio::test]
+    async fn writes_the_daily_files_into_parquet() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(SinkOptions {
+            dir: dir.path(),
+            account: "main",
+            exchange: "ftx",
+            raw: false,
+            decimal_separator: DecimalSeparator::Comma,
+        });
+        let dates = [
+            NaiveDate::from_ymd(2022, 1, 1),
+            NaiveDate::from_ymd(2022, 1, 2),
+        ];
+        for date in dates {
+            sink.rotate(date).await.unwrap();
+            for fill in fills(date).await {
+                sink.write_record(&fill).await.unwrap();
+            }
+            sink.flush().await.unwrap();
+        }
+        sink.finalize().await.unwrap();
+
+        for date in dates {
+            assert!(!staged_path(dir.path(), "main", date).exists());
+        }
+        let batch = read(&path(dir.path(), "main", dates[0]));
+        assert_eq!(batch.num_rows(), 4);
+        let column = |name| batch.column(batch.schema().index_of(name).unwrap()).clone();
+        let ids = column("native_id");
+        let ids = ids.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(
+            (0..4).map(|i| ids.value(i)).collect::<Vec<_>>(),
+            ["1001", "1002", "1003", "1004"]
+        );
+        let fees = column("fee");
+        let fees = fees.as_any().downcast_ref::<DecimalArray>().unwrap();
+        assert_eq!(fees.value_as_string(1), "-0.000020000000000000");
+        let prices = column("price");
+        let prices = prices.as_any().downcast_ref::<DecimalArray>().unwrap();
+        assert_eq!(prices.value_as_string(0), "46200.500000000000000000");
+        let times = column("timestamp");
+        let times = times
+            .as_any()
+            .downcast_ref::<TimestampNanosecondArray>()
+            .unwrap();
+        assert_eq!(
+            times.value(0),
+            NaiveDate::from_ymd(2022, 1, 1)
+                .and_hms_micro(0, 0, 5, 123456)
+                .timestamp_nanos()
+        );
+        assert_eq!(read(&path(dir.path(), "main", dates[1])).num_rows(), 4);
+    }
+
+    #[tokio::test]
+    async fn resumes_the_staged_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SinkOptions {
+            dir: dir.path(),
+            account: "main",
+            exchange: "ftx",
+            raw: true,
+            decimal_separator: DecimalSeparator::Dot,
+        };
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        let fills = fills(date).await;
+        let mut sink = ParquetSink::new(options);
+        sink.rotate(date).await.unwrap();
+        sink.write_record(&fills[0]).await.unwrap();
+        let state = sink.flush().await.unwrap().unwrap();
+        // interrupted before the next flush
+        sink.write_record(&fills[1]).await.unwrap();
+        drop(sink);
+
+        let mut sink = ParquetSink::new(options);
+        sink.reopen(&state).await.unwrap();
+        for fill in &fills[1..] {
+            sink.write_record(fill).await.unwrap();
+        }
+        sink.finalize().await.unwrap();
+        let batch = read(&path(dir.path(), "main", date));
+        assert_eq!(batch.num_rows(), 4);
+        assert_eq!(
+            batch
+                .column(batch.schema().index_of("time").unwrap())
+                .null_count(),
+            0
+        );
+    }
+}
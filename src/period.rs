@@ -0,0 +1,175 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate};
+
+/// A length of a range relative to its end, e.g. `30d`, `2w`, `6m` and `1y`.
+#[derive(Clone, Copy, Debug)]
+pub enum Period {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+}
+
+impl Period {
+    /// Returns the date this period before `date`. The day is clamped to the end of the month
+    /// for months and years, e.g. 1 month before 2022-03-31 is 2022-02-28.
+    pub fn before(&self, date: NaiveDate) -> NaiveDate {
+        match *self {
+            Period::Days(n) => date - Duration::days(n.into()),
+            Period::Weeks(n) => date - Duration::weeks(n.into()),
+            Period::Months(n) => months_before(date, n),
+            Period::Years(n) => months_before(date, n * 12),
+        }
+    }
+}
+
+fn months_before(date: NaiveDate, months: u32) -> NaiveDate {
+    let index = date.year() * 12 + date.month0() as i32 - months as i32;
+    let (year, month) = (index.div_euclid(12), index.rem_euclid(12) as u32 + 1);
+    // the last day of the month is the day before the first day of the next month
+    let last_day = NaiveDate::from_ymd_opt(year + month as i32 / 12, month % 12 + 1, 1)
+        .map(|d| d.pred().day())
+        .unwrap_or(28);
+    NaiveDate::from_ymd(year, month, date.day().min(last_day))
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid period: {}. specify a positive number followed by d, w, m or y (e.g. 30d)",
+                s
+            )
+        };
+        let (n, unit) = s.split_at(s.len().saturating_sub(1));
+        let n: u32 = n.parse().map_err(|_| invalid())?;
+        if n == 0 {
+            return Err(invalid());
+        }
+        match unit {
+            "d" => Ok(Period::Days(n)),
+            "w" => Ok(Period::Weeks(n)),
+            "m" => Ok(Period::Months(n)),
+            "y" => Ok(Period::Years(n)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A calendar month given as `yyyy-MM`.
+#[derive(Clone, Copy, Debug)]
+pub struct Month {
+    year: i32,
+    month: u32,
+}
+
+impl Month {
+    /// Returns the first day of the month and the first day of the next month.
+    pub fn range(&self) -> (NaiveDate, NaiveDate) {
+        let next = match self.month {
+            12 => NaiveDate::from_ymd(self.year + 1, 1, 1),
+            month => NaiveDate::from_ymd(self.year, month + 1, 1),
+        };
+        (NaiveDate::from_ymd(self.year, self.month, 1), next)
+    }
+}
+
+impl FromStr for Month {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // parsed as the first day so that an invalid month is rejected
+        NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d")
+            .map(|d| Month {
+                year: d.year(),
+                month: d.month(),
+            })
+            .map_err(|_| format!("invalid month: {}. specify yyyy-MM, e.g. 2022-05", s))
+    }
+}
+
+/// A calendar year given as `yyyy`.
+#[derive(Clone, Copy, Debug)]
+pub struct Year(i32);
+
+impl Year {
+    /// Returns the first day of the year and the first day of the next year.
+    pub fn range(&self) -> (NaiveDate, NaiveDate) {
+        (
+            NaiveDate::from_ymd(self.0, 1, 1),
+            NaiveDate::from_ymd(self.0 + 1, 1, 1),
+        )
+    }
+}
+
+impl FromStr for Year {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            // the range chrono can represent with a margin
+            Ok(year) if (1970..=9999).contains(&year) => Ok(Year(year)),
+            _ => Err(format!("invalid year: {}. specify yyyy, e.g. 2021", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_the_periods() {
+        let before = |s: &str| s.parse::<Period>().unwrap().before(date("2022-03-31"));
+        assert_eq!(before("30d"), date("2022-03-01"));
+        assert_eq!(before("2w"), date("2022-03-17"));
+        assert_eq!(before("1m"), date("2022-02-28"));
+        assert_eq!(before("13m"), date("2021-02-28"));
+        assert_eq!(before("1y"), date("2021-03-31"));
+        for invalid in ["", "d", "0d", "-1d", "30", "30h", "1.5m"] {
+            assert!(invalid.parse::<Period>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn clamps_the_day_to_the_end_of_the_month() {
+        let months = |n, d| Period::Months(n).before(date(d));
+        assert_eq!(months(1, "2024-03-30"), date("2024-02-29"));
+        assert_eq!(months(12, "2024-02-29"), date("2023-02-28"));
+        assert_eq!(months(3, "2022-01-31"), date("2021-10-31"));
+        assert_eq!(months(1, "2022-01-15"), date("2021-12-15"));
+        assert_eq!(
+            Period::Years(4).before(date("2024-02-29")),
+            date("2020-02-29")
+        );
+    }
+
+    #[test]
+    fn parses_the_months_and_the_years() {
+        let month = |s: &str| s.parse::<Month>().map(|m| m.range());
+        assert_eq!(
+            month("2022-05"),
+            Ok((date("2022-05-01"), date("2022-06-01")))
+        );
+        assert_eq!(
+            month("2021-12"),
+            Ok((date("2021-12-01"), date("2022-01-01")))
+        );
+        for invalid in ["2022-13", "2022-00", "2022", "2022-05-01", "May"] {
+            assert!(invalid.parse::<Month>().is_err(), "{}", invalid);
+        }
+
+        let year = |s: &str| s.parse::<Year>().map(|y| y.range());
+        assert_eq!(year("2021"), Ok((date("2021-01-01"), date("2022-01-01"))));
+        for invalid in ["1969", "10000", "21", "2021-01"] {
+            assert!(invalid.parse::<Year>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use clap::ArgEnum;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, DecimalSeparator, FillType, Localized, Side};
+
+/// How the cost of a position is tracked to compute the pnl of reducing it.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CostMethod {
+    // reduces the position at its average price
+    Average,
+    // reduces the oldest fills of the position first
+    Fifo,
+}
+
+/// Realized pnl of a market in a day, in the settlement currency of the market.
+#[derive(Serialize)]
+pub struct DailyPnl {
+    pub date: NaiveDate,
+    pub market: String,
+    pub currency: Option<String>,
+    #[serde(rename = "realized_pnl", serialize_with = "ser_decimal")]
+    pub realized: Decimal,
+    // positive when charged. fees in other currencies than the base and the quote are excluded
+    #[serde(serialize_with = "ser_decimal")]
+    pub fees: Decimal,
+    // positive when received
+    #[serde(serialize_with = "ser_decimal")]
+    pub funding: Decimal,
+    // realized - fees + funding
+    #[serde(rename = "net_pnl", serialize_with = "ser_decimal")]
+    pub net: Decimal,
+}
+
+/// Computes the realized pnl of each market per day from the archive and writes it into
+/// `<account>.pnl.csv` of `outdir`.
+///
+/// All files are read since the cost of a position may come from fills before `start`, and
+/// only the days in the range are written. The days are the ones of the files, so they are in
+/// the timezone of the download.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    method: CostMethod,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let records = archive::read_range(outdir, account, ..end).await?;
+
+    let mut positions: HashMap<String, Position> = HashMap::new();
+    let mut days: BTreeMap<(NaiveDate, String), DailyPnl> = BTreeMap::new();
+    let mut excluded_fees = BTreeSet::new();
+    for record in records {
+        let Some(market) = record.market.clone() else {
+            continue;
+        };
+        let day = days
+            .entry((record.date, market.clone()))
+            .or_insert_with(|| DailyPnl {
+                date: record.date,
+                market: market.clone(),
+                currency: None,
+                realized: Decimal::ZERO,
+                fees: Decimal::ZERO,
+                funding: Decimal::ZERO,
+                net: Decimal::ZERO,
+            });
+        match (&record.typ, record.side, record.price) {
+            // the amount is in the base currency of the record, e.g. USDC on dYdX
+            (Some(FillType::Funding), _, _) => {
+                day.funding += record.size;
+                day.currency = day.currency.take().or(record.base_currency);
+            }
+            (None | Some(FillType::Order | FillType::Liquidation), Some(side), Some(price)) => {
+                let size = match side {
+                    Side::Buy => record.size.abs(),
+                    Side::Sell => -record.size.abs(),
+                };
+                day.realized += positions
+                    .entry(market)
+                    .or_default()
+                    .fill(method, size, price);
+                match record.fee_in_quote(price) {
+                    Some(fee) => day.fees += fee,
+                    None => excluded_fees.extend(record.fee_currency.clone()),
+                }
+                day.currency = day
+                    .currency
+                    .take()
+                    .or(record.settlement_currency().cloned());
+            }
+            // deposits, withdrawals and the others don't change the pnl
+            _ => {}
+        }
+    }
+    if !excluded_fees.is_empty() {
+        warn!(
+            "fees in {} are not included since they are not in the currencies of the markets",
+            excluded_fees.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    let path = outdir.join(format!("{}.pnl.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    let mut totals: BTreeMap<Option<String>, Decimal> = BTreeMap::new();
+    for mut day in days.into_values() {
+        if start.is_some_and(|start| day.date < start) {
+            continue;
+        }
+        day.net = (day.realized - day.fees + day.funding).normalize();
+        day.realized = day.realized.normalize();
+        day.fees = day.fees.normalize();
+        day.funding = day.funding.normalize();
+        *totals.entry(day.currency.clone()).or_default() += day.net;
+        writer
+            .serialize(Localized(&day, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    for (currency, net) in totals {
+        info!(
+            "net pnl: {} {}",
+            net,
+            currency.as_deref().unwrap_or("(unknown currency)")
+        );
+    }
+    info!("the pnl is written into {}", path.display());
+    Ok(path)
+}
+
+/// The open lots of a market, all of the same direction. Their sizes are negative when short.
+#[derive(Default)]
+pub(crate) struct Position {
+    lots: VecDeque<(Decimal, Decimal)>,
+}
+
+impl Position {
+    /// Returns the signed size of the position.
+    pub(crate) fn size(&self) -> Decimal {
+        self.lots.iter().map(|(size, _)| *size).sum()
+    }
+
+    /// Returns the average price of the open lots, or `None` if the position is closed.
+    pub(crate) fn average_price(&self) -> Option<Decimal> {
+        let size = self.size();
+        (!size.is_zero()).then(|| {
+            self.lots
+                .iter()
+                .map(|(size, price)| size * price)
+                .sum::<Decimal>()
+                / size
+        })
+    }
+
+    /// Adds a fill of the signed size and returns the pnl realized by reducing the position.
+    pub(crate) fn fill(
+        &mut self,
+        method: CostMethod,
+        mut size: Decimal,
+        price: Decimal,
+    ) -> Decimal {
+        let mut realized = Decimal::ZERO;
+        while !size.is_zero() {
+            let Some((lot_size, lot_price)) = self.lots.front_mut() else {
+                break;
+            };
+            if lot_size.is_sign_positive() == size.is_sign_positive() {
+                break;
+            }
+            // of the sign of the lot
+            let mut closed = size.abs().min(lot_size.abs());
+            if lot_size.is_sign_negative() {
+                closed = -closed;
+            }
+            realized += closed * (price - *lot_price);
+            *lot_size -= closed;
+            size += closed;
+            if lot_size.is_zero() {
+                self.lots.pop_front();
+            }
+        }
+        if size.is_zero() {
+            return realized;
+        }
+        match (method, self.lots.front_mut()) {
+            (CostMethod::Average, Some((lot_size, lot_price))) => {
+                let total = *lot_size + size;
+                *lot_price = (*lot_size * *lot_price + size * price) / total;
+                *lot_size = total;
+            }
+            _ => self.lots.push_back((size, price)),
+        }
+        realized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn reduces_the_position_at_the_average_price() {
+        let mut position = Position::default();
+        assert_eq!(
+            position.fill(CostMethod::Average, dec("1"), dec("100")),
+            dec("0")
+        );
+        assert_eq!(
+            position.fill(CostMethod::Average, dec("1"), dec("200")),
+            dec("0")
+        );
+        assert_eq!(position.average_price(), Some(dec("150")));
+        assert_eq!(
+            position.fill(CostMethod::Average, dec("-1"), dec("180")),
+            dec("30")
+        );
+        assert_eq!(position.size(), dec("1"));
+        assert_eq!(position.average_price(), Some(dec("150")));
+    }
+
+    #[test]
+    fn reduces_the_oldest_lot_first_with_fifo() {
+        let mut position = Position::default();
+        position.fill(CostMethod::Fifo, dec("1"), dec("100"));
+        position.fill(CostMethod::Fifo, dec("1"), dec("200"));
+        // 1 of 100 and 0.5 of 200
+        assert_eq!(
+            position.fill(CostMethod::Fifo, dec("-1.5"), dec("180")),
+            dec("70")
+        );
+        assert_eq!(position.size(), dec("0.5"));
+        assert_eq!(position.average_price(), Some(dec("200")));
+    }
+
+    #[test]
+    fn flips_the_position_with_the_rest_of_the_fill() {
+        let mut position = Position::default();
+        position.fill(CostMethod::Average, dec("1"), dec("100"));
+        assert_eq!(
+            position.fill(CostMethod::Average, dec("-3"), dec("90")),
+            dec("-10")
+        );
+        assert_eq!(position.size(), dec("-2"));
+        assert_eq!(position.average_price(), Some(dec("90")));
+        // a short gains when the price falls
+        assert_eq!(
+            position.fill(CostMethod::Average, dec("2"), dec("80")),
+            dec("20")
+        );
+        assert_eq!(position.average_price(), None);
+    }
+
+    #[tokio::test]
+    async fn includes_the_fees_of_the_futures_in_their_settlement_currency() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        write_file(
+            dir.path(),
+            date,
+            &[
+                "ftx,main,ETH-PERP,,,buy,3000,1,1.5,USD,2022-01-01T00:00:00Z,1,order",
+                "ftx,main,ETH-PERP,,,sell,3100,1,-0.3,USD,2022-01-01T01:00:00Z,2,order",
+                "ftx,main,ETH-PERP,,,,,-0.2,0,USD,2022-01-01T02:00:00Z,3,funding",
+            ],
+        );
+        let path = report(
+            dir.path(),
+            "main",
+            CostMethod::Average,
+            None,
+            date.succ(),
+            DecimalSeparator::Dot,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "date,market,currency,realized_pnl,fees,funding,net_pnl\n\
+             2022-01-01,ETH-PERP,USD,100,1.2,-0.2,98.6\n"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, ser_decimal_opt, DecimalSeparator, FillType, Localized, Side};
+use crate::pnl::{CostMethod, Position};
+
+/// The position of a market after a fill.
+#[derive(Serialize)]
+pub struct PositionPoint {
+    pub time: DateTime<FixedOffset>,
+    pub market: String,
+    pub side: Side,
+    #[serde(serialize_with = "ser_decimal")]
+    pub size: Decimal,
+    #[serde(serialize_with = "ser_decimal")]
+    pub price: Decimal,
+    // negative when short
+    #[serde(serialize_with = "ser_decimal")]
+    pub position: Decimal,
+    // empty when the position is closed
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub average_entry: Option<Decimal>,
+    // why the position is inconsistent at the fill, which is likely by missing fills
+    pub flag: Option<&'static str>,
+}
+
+/// Replays the fills of the archive and writes the position of the market after each fill into
+/// `<account>.positions.csv` of `outdir`.
+///
+/// All files are read since a position may be opened before `start`, and only the fills in the
+/// range are written. A point is flagged when
+///
+/// - `liquidation_increases`: a liquidation opens or increases the position, while it only
+///   reduces one.
+/// - `negative_balance`: the base currency of a spot trade, which is the balance of the
+///   deposits, the withdrawals and the trades of the currency, goes below zero.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let records = archive::read_range(outdir, account, ..end).await?;
+
+    let derivatives = archive::derivative_markets(&records);
+    let mut positions: HashMap<String, Position> = HashMap::new();
+    let mut balances: HashMap<String, Decimal> = HashMap::new();
+    let mut points = vec![];
+    for record in records {
+        match (&record.typ, record.side, record.price) {
+            (Some(FillType::Deposit), _, _) => {
+                if let Some(currency) = &record.base_currency {
+                    *balances.entry(currency.clone()).or_default() += record.size.abs();
+                }
+            }
+            (Some(FillType::Withdrawal), _, _) => {
+                if let Some(currency) = &record.base_currency {
+                    // the fee of a withdrawal is in the currency withdrawn
+                    *balances.entry(currency.clone()).or_default() -=
+                        record.size.abs() + record.fee;
+                }
+            }
+            (None | Some(FillType::Order | FillType::Liquidation), Some(side), Some(price)) => {
+                let Some(market) = record.market.clone() else {
+                    continue;
+                };
+                let size = match side {
+                    Side::Buy => record.size.abs(),
+                    Side::Sell => -record.size.abs(),
+                };
+                let position = positions.entry(market.clone()).or_default();
+                let before = position.size();
+                position.fill(CostMethod::Average, size, price);
+                let after = position.size();
+
+                let mut flag = None;
+                let increased = after.abs() > before.abs()
+                    || (!before.is_zero() && before.is_sign_positive() != after.is_sign_positive());
+                if record.typ == Some(FillType::Liquidation) && increased {
+                    flag = Some("liquidation_increases");
+                }
+                if let (Some(base), Some(_)) = (&record.base_currency, &record.quote_currency) {
+                    if !derivatives.contains(&market) {
+                        let balance = balances.entry(base.clone()).or_default();
+                        *balance += size;
+                        if record.fee_currency.as_ref() == Some(base) {
+                            *balance -= record.fee;
+                        }
+                        if balance.is_sign_negative() && !balance.is_zero() {
+                            flag = flag.or(Some("negative_balance"));
+                        }
+                    }
+                }
+                if start.is_some_and(|start| record.date < start) {
+                    continue;
+                }
+                points.push(PositionPoint {
+                    time: record.time,
+                    market,
+                    side,
+                    size: record.size.abs().normalize(),
+                    price: price.normalize(),
+                    position: after.normalize(),
+                    average_entry: position.average_price().map(|p| p.normalize()),
+                    flag,
+                });
+            }
+            // funding and the others don't change the positions
+            _ => {}
+        }
+    }
+
+    let path = outdir.join(format!("{}.positions.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    for point in &points {
+        writer
+            .serialize(Localized(point, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    let flagged = points.iter().filter(|p| p.flag.is_some()).count();
+    if flagged > 0 {
+        warn!(
+            "the positions are inconsistent at {} fills, which is likely by missing fills. see \
+             the flag column",
+            flagged
+        );
+    }
+    info!("the positions are written into {}", path.display());
+    Ok(path)
+}
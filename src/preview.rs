@@ -0,0 +1,68 @@
+use anyhow::Result;
+use comfy_table::presets::UTF8_FULL_CONDENSED;
+use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table};
+use futures::{StreamExt, TryStreamExt};
+
+use crate::collect::Output;
+use crate::exchange::{self, Exchange, Fill, FillType, Side, TimeRange};
+
+/// Prints the newest `n` records of the range as a table in the unified schema instead of
+/// writing them, to check the output before a long download.
+pub async fn preview<E: Exchange>(
+    exchange: &E,
+    range: &TimeRange,
+    output: &Output<'_>,
+    n: usize,
+) -> Result<()> {
+    // pages after the one containing the nth record are not fetched
+    let fills: Vec<Fill> = exchange::fills(exchange, range)
+        .try_filter(|fill| futures::future::ready(output.selects(fill)))
+        .take(n)
+        .try_collect()
+        .await?;
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL_CONDENSED)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(
+            [
+                "timestamp",
+                "market",
+                "side",
+                "price",
+                "size",
+                "fee",
+                "fee_currency",
+                "type",
+                "native_id",
+            ]
+            .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+        );
+    for fill in &fills {
+        table.add_row(row(fill, exchange.name(), output.account));
+    }
+    println!("{table}");
+    println!("{} records", fills.len());
+    Ok(())
+}
+
+fn row(fill: &Fill, exchange: &str, account: &str) -> Vec<Cell> {
+    let fill = fill.unify(exchange, account);
+    let text = |s: Option<&str>| Cell::new(s.unwrap_or_default());
+    vec![
+        Cell::new(fill.timestamp.format("%Y-%m-%d %H:%M:%S")),
+        text(fill.market).fg(Color::Cyan),
+        match fill.side {
+            Some(Side::Buy) => Cell::new(Side::Buy).fg(Color::Green),
+            Some(Side::Sell) => Cell::new(Side::Sell).fg(Color::Red),
+            None => Cell::new(""),
+        },
+        Cell::new(fill.price.map(|p| p.to_string()).unwrap_or_default()),
+        Cell::new(fill.size),
+        Cell::new(fill.fee),
+        text(fill.fee_currency),
+        text(fill.typ.map(FillType::as_str)).fg(Color::Yellow),
+        Cell::new(fill.native_id).fg(Color::DarkGrey),
+    ]
+}
@@ -0,0 +1,108 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, SecondsFormat, Utc};
+use clap::ArgEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+use serde_json::json;
+
+use crate::exchange::TimeRange;
+
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(MultiProgress::new)
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    // one json object per line with timestamp, level, target and message
+    Json,
+}
+
+/// Installs the logger configured by `RUST_LOG`, which hides the progress bar while writing a
+/// line so that they are not mixed up.
+///
+/// The progress bar is never drawn if `quiet` is true.
+pub fn init_logger(format: LogFormat, quiet: bool) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let LogFormat::Json = format {
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                json!({
+                    "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                })
+            )
+        });
+    }
+    if quiet {
+        bars().set_draw_target(ProgressDrawTarget::hidden());
+    }
+    // fails only when a logger is already installed
+    let _ = LogWrapper::new(bars().clone(), builder.build()).try_init();
+}
+
+/// A progress bar of a download, which walks through the range from the end to the start.
+///
+/// It's drawn only when stderr is a terminal. A spinner is shown instead when the range has no
+/// start since the total is unknown.
+pub struct DownloadBar {
+    bar: ProgressBar,
+    end: NaiveDateTime,
+    records: AtomicU64,
+}
+
+impl DownloadBar {
+    pub fn new(range: &TimeRange) -> Self {
+        let bar = match range.start {
+            Some(start) => ProgressBar::new(seconds(range.end - start)).with_style(
+                ProgressStyle::with_template(
+                    "{elapsed_precise} [{wide_bar}] {percent}% ETA {eta} {msg}",
+                )
+                .expect("valid template")
+                .progress_chars("=> "),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{spinner} {elapsed_precise} {msg}")
+                    .expect("valid template"),
+            ),
+        };
+        let bar = bars().add(bar);
+        bar.enable_steady_tick(Duration::from_millis(250));
+        Self {
+            bar,
+            end: range.end,
+            records: AtomicU64::new(0),
+        }
+    }
+
+    /// Moves the bar to `end_time` of the next page after `records` of a page are written.
+    pub fn update(&self, end_time: NaiveDateTime, records: u64) {
+        let total = self.records.fetch_add(records, Ordering::SeqCst) + records;
+        let rate = total as f64 / self.bar.elapsed().as_secs_f64().max(0.001);
+        self.bar.set_position(seconds(self.end - end_time));
+        self.bar.set_message(format!(
+            "{} records ({:.1}/s), {}",
+            total,
+            rate,
+            end_time.format("%Y-%m-%d")
+        ));
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+fn seconds(duration: chrono::Duration) -> u64 {
+    duration.num_seconds().max(0) as u64
+}
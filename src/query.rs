@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use datafusion::prelude::{CsvReadOptions, SessionContext};
+use tokio::fs::File;
+
+use crate::archive;
+use crate::error::Usage;
+
+/// Runs the sql over the daily files of the account in `outdir` as the table `fills`, and prints
+/// the result.
+///
+/// The numbers are read as floats, so sums are approximate. The time is a timestamp in UTC.
+pub async fn query(outdir: &Path, account: &str, sql: &str) -> Result<()> {
+    let files = archive::files(outdir, account);
+    let Some(first) = files.values().next() else {
+        bail!(Usage(format!(
+            "no daily file of {} is found in {}",
+            account,
+            outdir.display()
+        )));
+    };
+    // the files of an archive have the same columns
+    let file = File::open(first)
+        .await
+        .with_context(|| format!("failed to open {}", first.display()))?;
+    let headers = csv_async::AsyncReader::from_reader(file)
+        .headers()
+        .await?
+        .clone();
+    let fields = headers
+        .iter()
+        .map(|name| {
+            let typ = match name {
+                "timestamp" | "time" => DataType::Timestamp(TimeUnit::Nanosecond, None),
+                _ if archive::NUMBERS.contains(&name) => DataType::Float64,
+                _ => DataType::Utf8,
+            };
+            Field::new(name, typ, true)
+        })
+        .collect();
+    let schema = Schema::new(fields);
+
+    let ctx = SessionContext::new();
+    // only the daily files, not the reports written next to them
+    let pattern = outdir.join(format!(
+        "{}_[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9].csv",
+        account
+    ));
+    ctx.register_csv(
+        "fills",
+        &pattern.to_string_lossy(),
+        CsvReadOptions::new().schema(&schema),
+    )
+    .await
+    .with_context(|| format!("failed to read the daily files in {}", outdir.display()))?;
+    let result = ctx.sql(sql).await.map_err(|e| Usage(e.to_string()))?;
+    result
+        .show()
+        .await
+        .with_context(|| "failed to run the query")?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use futures::StreamExt;
+use log::*;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::error::Usage;
+use crate::sink::{self, SinkOptions};
+
+/// Writes the daily files of the account in `source` in the range into the sink of the name,
+/// e.g. to change the schema or the destination of an archive which can't be downloaded again.
+pub async fn reencode(
+    source: &Path,
+    sink: &str,
+    options: SinkOptions<'_>,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+) -> Result<()> {
+    if sink == "csv" && source == options.dir {
+        bail!(Usage(
+            "the csv files can't be written into the directory they are read from. specify \
+             another --outdir"
+                .into()
+        ));
+    }
+    let files = archive::files(source, options.account);
+    let files = match start {
+        Some(start) => files.range(start..end),
+        None => files.range(..end),
+    };
+    let mut sink = sink::open(sink, options)?;
+    let (mut days, mut records) = (0, 0);
+    for (date, path) in files {
+        if let Some(exchange) = exchange_of(path).await? {
+            if exchange != options.exchange {
+                bail!(Usage(format!(
+                    "{} is of {}. specify --exchange {}",
+                    path.display(),
+                    exchange,
+                    exchange
+                )));
+            }
+        }
+        let fills = archive::read_fills(path).await?;
+        sink.rotate(*date).await?;
+        for fill in &fills {
+            sink.write_record(fill).await?;
+        }
+        sink.flush().await?;
+        days += 1;
+        records += fills.len();
+    }
+    sink.finalize().await?;
+    info!(
+        "{} records of {} days are written into {}",
+        records,
+        days,
+        options.dir.display()
+    );
+    Ok(())
+}
+
+/// Returns the exchange of the first record of a file of the unified schema.
+async fn exchange_of(path: &Path) -> Result<Option<String>> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = csv_async::AsyncReader::from_reader(file);
+    let Some(column) = reader.headers().await?.iter().position(|h| h == "exchange") else {
+        return Ok(None);
+    };
+    let record = reader.records().next().await.transpose()?;
+    Ok(record.and_then(|r| r.get(column).map(str::to_string)))
+}
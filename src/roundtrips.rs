@@ -0,0 +1,318 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive::{self, Record};
+use crate::exchange::{ser_decimal, ser_decimal_opt, DecimalSeparator, FillType, Localized, Side};
+use crate::pnl::{CostMethod, Position};
+
+/// A position of a market from its opening fill until it's closed.
+#[derive(Serialize)]
+pub struct RoundTrip {
+    pub market: String,
+    // buy when long, sell when short
+    pub side: Side,
+    pub opened_at: DateTime<FixedOffset>,
+    // empty while the position is open
+    pub closed_at: Option<DateTime<FixedOffset>>,
+    pub holding_seconds: Option<i64>,
+    // the sum of the sizes the position is opened and increased by
+    #[serde(serialize_with = "ser_decimal")]
+    pub size: Decimal,
+    #[serde(serialize_with = "ser_decimal")]
+    pub entry_price: Decimal,
+    // the average price of the fills reducing the position, empty if none
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub exit_price: Option<Decimal>,
+    pub currency: Option<String>,
+    // realized at the average entry price
+    #[serde(serialize_with = "ser_decimal")]
+    pub pnl: Decimal,
+    // positive when charged. fees in other currencies than the base and the quote are excluded
+    #[serde(serialize_with = "ser_decimal")]
+    pub fees: Decimal,
+    // pnl - fees
+    #[serde(serialize_with = "ser_decimal")]
+    pub net_pnl: Decimal,
+    pub fills: u64,
+    #[serde(skip)]
+    entry_notional: Decimal,
+    #[serde(skip)]
+    exit_size: Decimal,
+    #[serde(skip)]
+    exit_notional: Decimal,
+    // of the file of the last fill
+    #[serde(skip)]
+    last_date: NaiveDate,
+}
+
+/// Pairs the fills of each market into round trips, from the fill opening a position to the one
+/// closing it, and writes them into `<account>.round-trips.csv` of `outdir`, e.g. to import into
+/// a trade journal.
+///
+/// A fill flipping the position closes the round trip and opens another one with the rest, with
+/// the fee split by the sizes. All files are read since a position may be opened before
+/// `start`, and the round trips whose last fill is in the range are written, including the ones
+/// still open.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let trips = round_trips(outdir, account, start, end).await?;
+    let path = outdir.join(format!("{}.round-trips.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    for trip in &trips {
+        writer
+            .serialize(Localized(trip, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    let closed = trips.iter().filter(|t| t.closed_at.is_some()).count();
+    info!(
+        "{} round trips are closed and {} are open",
+        closed,
+        trips.len() - closed
+    );
+    info!("the round trips are written into {}", path.display());
+    Ok(path)
+}
+
+/// Replays the fills of the archive into the round trips whose last fill is in the range, sorted
+/// by the time they are opened.
+pub(crate) async fn round_trips(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+) -> Result<Vec<RoundTrip>> {
+    let records = archive::read_range(outdir, account, ..end).await?;
+
+    let mut positions: HashMap<String, (Position, Option<RoundTrip>)> = HashMap::new();
+    let mut trips = vec![];
+    let mut excluded_fees = BTreeSet::new();
+    for record in records {
+        let (None | Some(FillType::Order | FillType::Liquidation), Some(side), Some(price)) =
+            (&record.typ, record.side, record.price)
+        else {
+            continue;
+        };
+        let Some(market) = record.market.clone() else {
+            continue;
+        };
+        let mut size = match side {
+            Side::Buy => record.size.abs(),
+            Side::Sell => -record.size.abs(),
+        };
+        let fee = match record.fee_in_quote(price) {
+            Some(fee) => fee,
+            None => {
+                excluded_fees.extend(record.fee_currency.clone());
+                Decimal::ZERO
+            }
+        };
+        let (position, open) = positions.entry(market.clone()).or_default();
+        while !size.is_zero() {
+            let before = position.size();
+            // the part of the fill up to closing the position
+            let part = if !before.is_zero()
+                && before.is_sign_positive() != size.is_sign_positive()
+                && size.abs() > before.abs()
+            {
+                -before
+            } else {
+                size
+            };
+            let trip = open.get_or_insert_with(|| RoundTrip::open(&market, &record, part));
+            let realized = position.fill(CostMethod::Average, part, price);
+            if before.is_zero() || before.is_sign_positive() == part.is_sign_positive() {
+                trip.size += part.abs();
+                trip.entry_notional += part.abs() * price;
+            } else {
+                trip.exit_size += part.abs();
+                trip.exit_notional += part.abs() * price;
+            }
+            trip.pnl += realized;
+            trip.fees += fee * part.abs() / record.size.abs();
+            trip.fills += 1;
+            trip.last_date = record.date;
+            if position.size().is_zero() {
+                trip.closed_at = Some(record.time);
+                trips.extend(open.take());
+            }
+            size -= part;
+        }
+    }
+    trips.extend(positions.into_values().filter_map(|(_, open)| open));
+    trips.retain(|t| start.is_none_or(|start| start <= t.last_date));
+    trips.sort_by(|a, b| (a.opened_at, &a.market).cmp(&(b.opened_at, &b.market)));
+    if !excluded_fees.is_empty() {
+        warn!(
+            "fees in {} are not included since they are not in the currencies of the markets",
+            excluded_fees.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    for trip in &mut trips {
+        trip.holding_seconds = trip.closed_at.map(|c| (c - trip.opened_at).num_seconds());
+        trip.entry_price = (trip.entry_notional / trip.size).round_dp(8).normalize();
+        trip.exit_price = (!trip.exit_size.is_zero()).then(|| {
+            (trip.exit_notional / trip.exit_size)
+                .round_dp(8)
+                .normalize()
+        });
+        trip.size = trip.size.normalize();
+        trip.net_pnl = (trip.pnl - trip.fees).round_dp(8).normalize();
+        trip.pnl = trip.pnl.round_dp(8).normalize();
+        trip.fees = trip.fees.round_dp(8).normalize();
+    }
+    Ok(trips)
+}
+
+impl RoundTrip {
+    fn open(market: &str, record: &Record, size: Decimal) -> Self {
+        RoundTrip {
+            market: market.to_string(),
+            side: if size.is_sign_positive() {
+                Side::Buy
+            } else {
+                Side::Sell
+            },
+            opened_at: record.time,
+            closed_at: None,
+            holding_seconds: None,
+            size: Decimal::ZERO,
+            entry_price: Decimal::ZERO,
+            exit_price: None,
+            currency: record.settlement_currency().cloned(),
+            pnl: Decimal::ZERO,
+            fees: Decimal::ZERO,
+            net_pnl: Decimal::ZERO,
+            fills: 0,
+            entry_notional: Decimal::ZERO,
+            exit_size: Decimal::ZERO,
+            exit_notional: Decimal::ZERO,
+            last_date: record.date,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn pairs_the_entries_and_the_exits() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        write_file(
+            dir.path(),
+            date,
+            &[
+                "ftx,main,BTC/USD,BTC,USD,buy,100,1,0.1,USD,2022-01-01T00:00:00Z,1,order",
+                "ftx,main,BTC/USD,BTC,USD,buy,200,1,0.2,USD,2022-01-01T01:00:00Z,2,order",
+                // closes the long and opens a short of 1
+                "ftx,main,BTC/USD,BTC,USD,sell,180,3,0.6,USD,2022-01-01T02:00:00Z,3,order",
+                "ftx,main,BTC/USD,BTC,USD,buy,170,0.5,0.05,USD,2022-01-01T03:00:00Z,4,order",
+            ],
+        );
+        let trips = round_trips(dir.path(), "main", None, date.succ())
+            .await
+            .unwrap();
+        assert_eq!(trips.len(), 2);
+
+        let long = &trips[0];
+        assert_eq!(long.side, Side::Buy);
+        assert_eq!(long.size, dec("2"));
+        assert_eq!(long.entry_price, dec("150"));
+        assert_eq!(long.exit_price, Some(dec("180")));
+        assert_eq!(long.pnl, dec("60"));
+        // 2 / 3 of the fee of the flipping fill
+        assert_eq!(long.fees, dec("0.7"));
+        assert_eq!(long.net_pnl, dec("59.3"));
+        assert_eq!(long.fills, 3);
+        assert_eq!(long.holding_seconds, Some(7200));
+
+        let short = &trips[1];
+        assert_eq!(short.side, Side::Sell);
+        assert_eq!(short.size, dec("1"));
+        assert_eq!(short.entry_price, dec("180"));
+        assert_eq!(short.exit_price, Some(dec("170")));
+        assert_eq!(short.pnl, dec("5"));
+        assert_eq!(short.fees, dec("0.25"));
+        // still open
+        assert_eq!(short.closed_at, None);
+    }
+
+    #[tokio::test]
+    async fn includes_the_fees_of_perpetual_futures() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        write_file(
+            dir.path(),
+            date,
+            &[
+                "ftx,main,ETH-PERP,,,sell,3000,2,4.2,USD,2022-01-01T00:00:00Z,1,order",
+                "ftx,main,ETH-PERP,,,buy,2900,2,-0.6,USD,2022-01-01T01:00:00Z,2,order",
+            ],
+        );
+        let trips = round_trips(dir.path(), "main", None, date.succ())
+            .await
+            .unwrap();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].currency.as_deref(), Some("USD"));
+        assert_eq!(trips[0].pnl, dec("200"));
+        assert_eq!(trips[0].fees, dec("3.6"));
+        assert_eq!(trips[0].net_pnl, dec("196.4"));
+    }
+
+    #[tokio::test]
+    async fn writes_the_round_trips_whose_last_fill_is_in_the_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (
+            NaiveDate::from_ymd(2022, 1, 1),
+            NaiveDate::from_ymd(2022, 1, 2),
+        );
+        write_file(
+            dir.path(),
+            first,
+            &[
+                "ftx,main,BTC/USD,BTC,USD,buy,100,1,0,USD,2022-01-01T00:00:00Z,1,order",
+                "ftx,main,BTC/USD,BTC,USD,sell,110,1,0,USD,2022-01-01T01:00:00Z,2,order",
+                "ftx,main,BTC/USD,BTC,USD,buy,120,1,0,USD,2022-01-01T02:00:00Z,3,order",
+            ],
+        );
+        write_file(
+            dir.path(),
+            second,
+            &["ftx,main,BTC/USD,BTC,USD,sell,130,1,0,USD,2022-01-02T00:00:00Z,4,order"],
+        );
+        let trips = round_trips(dir.path(), "main", Some(second), second.succ())
+            .await
+            .unwrap();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].entry_price, dec("120"));
+        assert_eq!(trips[0].pnl, dec("10"));
+    }
+}
@@ -0,0 +1,52 @@
+use std::process::exit;
+use std::sync::OnceLock;
+
+use log::*;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::ExitCode;
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Starts listening to SIGINT and SIGTERM, which cancel [`token`]. The process is killed
+/// immediately on the second one.
+pub fn listen() {
+    TOKEN.get_or_init(|| {
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        tokio::spawn(async move {
+            signal().await;
+            warn!("stopping after writing the fetched records. press Ctrl-C again to kill");
+            cancel.cancel();
+            signal().await;
+            exit(ExitCode::Interrupted as i32);
+        });
+        token
+    });
+}
+
+/// Returns the token cancelled by a signal. It is never cancelled if [`listen`] is not called.
+pub fn token() -> CancellationToken {
+    TOKEN.get().cloned().unwrap_or_default()
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut term) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
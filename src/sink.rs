@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::NaiveDate;
+use csv_async::AsyncSerializer;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::fs::{File, OpenOptions};
+
+use crate::archive;
+use crate::error::Usage;
+use crate::exchange::{DecimalSeparator, Fill, Localized};
+use crate::state::FileState;
+
+/// A destination of downloaded fills, which are written into one file (or table, object, ...)
+/// per date.
+///
+/// The download calls `rotate` before the first record of each date, and `flush` after each
+/// page so that an interrupted download can be resumed from the returned state.
+pub trait Sink: Send {
+    /// Writes a record into the file of the date of the last `rotate` or `reopen`.
+    fn write_record<'a>(&'a mut self, fill: &'a Fill) -> BoxFuture<'a, Result<()>>;
+
+    /// Starts a new file of the date, replacing the one written before if any.
+    fn rotate(&mut self, date: NaiveDate) -> BoxFuture<'_, Result<()>>;
+
+    /// Continues the file which was being written when `state` was returned by `flush`.
+    fn reopen<'a>(&'a mut self, state: &'a FileState) -> BoxFuture<'a, Result<()>>;
+
+    /// Persists the written records and returns the state of the current file, if any.
+    fn flush(&mut self) -> BoxFuture<'_, Result<Option<FileState>>>;
+
+    /// Closes the current file after the range is downloaded.
+    fn finalize(&mut self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// What a sink is opened for by [`open`].
+#[derive(Clone, Copy)]
+pub struct SinkOptions<'a> {
+    pub dir: &'a Path,
+    pub account: &'a str,
+    pub exchange: &'static str,
+    // writes all columns instead of the unified schema
+    pub raw: bool,
+    // of the numbers in the files, which a sink may ignore
+    pub decimal_separator: DecimalSeparator,
+}
+
+/// Opens a sink registered by [`register`].
+pub type SinkFactory = fn(SinkOptions<'_>) -> Result<Box<dyn Sink + '_>>;
+
+// the sinks built into this crate, which can't be registered again
+#[cfg(feature = "parquet")]
+const BUILT_IN: [&str; 2] = ["csv", "parquet"];
+#[cfg(not(feature = "parquet"))]
+const BUILT_IN: [&str; 1] = ["csv"];
+
+// the sinks registered in addition to the built-in ones
+static SINKS: Mutex<BTreeMap<&'static str, SinkFactory>> = Mutex::new(BTreeMap::new());
+
+/// Makes a sink available by the name, e.g. as `--sink` of the CLI, so that a crate embedding
+/// this one can write into its own destination.
+///
+/// Fails if the name is already registered.
+pub fn register(name: &'static str, factory: SinkFactory) -> Result<()> {
+    let mut sinks = SINKS.lock().unwrap();
+    if BUILT_IN.contains(&name) || sinks.contains_key(name) {
+        bail!("a sink named {} is already registered", name);
+    }
+    sinks.insert(name, factory);
+    Ok(())
+}
+
+/// Returns the names of the available sinks, the built-in ones first.
+pub fn names() -> Vec<&'static str> {
+    let sinks = SINKS.lock().unwrap();
+    BUILT_IN.into_iter().chain(sinks.keys().copied()).collect()
+}
+
+/// Opens the sink of the name.
+pub fn open<'a>(name: &str, options: SinkOptions<'a>) -> Result<Box<dyn Sink + 'a>> {
+    match name {
+        "csv" => return Ok(Box::new(CsvSink::new(options))),
+        #[cfg(feature = "parquet")]
+        "parquet" => return Ok(Box::new(crate::parquet::ParquetSink::new(options))),
+        _ => {}
+    }
+    check(name)?;
+    let factory = SINKS.lock().unwrap()[name];
+    factory(options)
+}
+
+/// Fails with a usage error if no sink of the name is available.
+pub fn check(name: &str) -> Result<()> {
+    let names = names();
+    if !names.contains(&name) {
+        bail!(Usage(format!(
+            "unknown sink: {}. available sinks are {}",
+            name,
+            names.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn write_record<'a>(&'a mut self, fill: &'a Fill) -> BoxFuture<'a, Result<()>> {
+        (**self).write_record(fill)
+    }
+
+    fn rotate(&mut self, date: NaiveDate) -> BoxFuture<'_, Result<()>> {
+        (**self).rotate(date)
+    }
+
+    fn reopen<'a>(&'a mut self, state: &'a FileState) -> BoxFuture<'a, Result<()>> {
+        (**self).reopen(state)
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, Result<Option<FileState>>> {
+        (**self).flush()
+    }
+
+    fn finalize(&mut self) -> BoxFuture<'_, Result<()>> {
+        (**self).finalize()
+    }
+}
+
+/// Writes fills into the daily csv files in the output directory.
+pub struct CsvSink<'a> {
+    outdir: &'a Path,
+    // of the file of a date, which is the daily file unless staged for another format
+    path: fn(&Path, &str, NaiveDate) -> PathBuf,
+    account: &'a str,
+    exchange: &'static str,
+    // writes all columns instead of the unified schema
+    raw: bool,
+    decimal_separator: DecimalSeparator,
+    file: Option<CsvFile>,
+}
+
+impl<'a> CsvSink<'a> {
+    pub fn new(options: SinkOptions<'a>) -> Self {
+        Self::with_path(options, archive::path)
+    }
+
+    /// Writes the files of the dates into the paths instead of the daily files.
+    pub(crate) fn with_path(
+        options: SinkOptions<'a>,
+        path: fn(&Path, &str, NaiveDate) -> PathBuf,
+    ) -> Self {
+        Self {
+            outdir: options.dir,
+            path,
+            account: options.account,
+            exchange: options.exchange,
+            raw: options.raw,
+            decimal_separator: options.decimal_separator,
+            file: None,
+        }
+    }
+}
+
+impl Sink for CsvSink<'_> {
+    fn write_record<'a>(&'a mut self, fill: &'a Fill) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let file = self
+                .file
+                .as_mut()
+                .ok_or_else(|| anyhow!("no file is opened to write"))?;
+            let unified = || fill.unify(self.exchange, self.account);
+            let separator = self.decimal_separator;
+            match (&fill.conversion, self.raw) {
+                (None, true) => file.writer.serialize(Localized(fill, separator)).await,
+                (None, false) => {
+                    file.writer
+                        .serialize(Localized(&unified(), separator))
+                        .await
+                }
+                (Some(conversion), true) => {
+                    file.writer
+                        .serialize(Localized(&(fill, conversion), separator))
+                        .await
+                }
+                (Some(conversion), false) => {
+                    file.writer
+                        .serialize(Localized(&(unified(), conversion), separator))
+                        .await
+                }
+            }
+            .with_context(|| "failed to write data to file")
+        }
+        .boxed()
+    }
+
+    fn rotate(&mut self, date: NaiveDate) -> BoxFuture<'_, Result<()>> {
+        async move {
+            self.finalize().await?;
+            self.file = Some(
+                CsvFile::create(
+                    self.outdir,
+                    (self.path)(self.outdir, self.account, date),
+                    date,
+                )
+                .await
+                .with_context(|| "failed to open a new file")?,
+            );
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn reopen<'a>(&'a mut self, state: &'a FileState) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.file = Some(
+                CsvFile::reopen((self.path)(self.outdir, self.account, state.date), state)
+                    .await
+                    .with_context(|| "failed to reopen the file being written")?,
+            );
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, Result<Option<FileState>>> {
+        async move {
+            match &mut self.file {
+                Some(file) => Ok(Some(
+                    file.checkpoint()
+                        .await
+                        .with_context(|| "failed to write data to file")?,
+                )),
+                None => Ok(None),
+            }
+        }
+        .boxed()
+    }
+
+    fn finalize(&mut self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            if let Some(mut file) = self.file.take() {
+                file.writer
+                    .flush()
+                    .await
+                    .with_context(|| "failed to write data to file")?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+struct CsvFile {
+    date: NaiveDate,
+    path: PathBuf,
+    writer: AsyncSerializer<File>,
+}
+
+impl CsvFile {
+    async fn create(outdir: &Path, path: PathBuf, date: NaiveDate) -> Result<Self> {
+        tokio::fs::create_dir_all(outdir)
+            .await
+            .with_context(|| "failed to create directory to put a file")?;
+        let file = File::create(&path)
+            .await
+            .with_context(|| "failed to create a file to write")?;
+        Ok(Self {
+            date,
+            path,
+            writer: csv_async::AsyncSerializer::from_writer(file),
+        })
+    }
+
+    /// Opens the file which was being written when the state was saved. Rows written after that
+    /// are discarded since they will be downloaded again.
+    async fn reopen(path: PathBuf, state: &FileState) -> Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        file.set_len(state.len)
+            .await
+            .with_context(|| "failed to truncate the file")?;
+        Ok(Self {
+            date: state.date,
+            path,
+            writer: csv_async::AsyncWriterBuilder::new()
+                // the header is already written
+                .has_headers(state.len == 0)
+                .create_serializer(file),
+        })
+    }
+
+    /// Flushes the written rows and returns the current state of the file.
+    async fn checkpoint(&mut self) -> Result<FileState> {
+        self.writer.flush().await?;
+        Ok(FileState {
+            date: self.date,
+            len: tokio::fs::metadata(&self.path).await?.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::exchange::ftx::{Ftx, FtxCredential};
+    use crate::exchange::mock::MockTransport;
+    use crate::exchange::{self, TimeRange};
+
+    // the fills of the fixtures on the date, oldest first
+    pub(crate) async fn fills(date: NaiveDate) -> Vec<Fill> {
+        let ftx = Ftx::builder()
+            .credential(FtxCredential::new("key".into(), "secret".into()))
+            .transport(MockTransport::ftx())
+            .build()
+            .unwrap();
+        let range = TimeRange {
+            start: Some(date.and_hms(0, 0, 0)),
+            end: date.succ().and_hms(0, 0, 0),
+        };
+        let mut fills = exchange::fills(&ftx, &range)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        fills.reverse();
+        fills
+    }
+
+    // writes the fills with the schema and returns the lines of the file
+    async fn write(raw: bool, decimal_separator: DecimalSeparator) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        let mut sink = CsvSink::new(SinkOptions {
+            dir: dir.path(),
+            account: "main",
+            exchange: "ftx",
+            raw,
+            decimal_separator,
+        });
+        sink.rotate(date).await.unwrap();
+        for fill in fills(date).await {
+            sink.write_record(&fill).await.unwrap();
+        }
+        sink.finalize().await.unwrap();
+        let content = tokio::fs::read_to_string(archive::path(dir.path(), "main", date))
+            .await
+            .unwrap();
+        content.lines().map(String::from).collect()
+    }
+
+    #[tokio::test]
+    async fn writes_the_raw_schema() {
+        let lines = write(true, DecimalSeparator::Dot).await;
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            "fee,feeCurrency,feeRate,future,id,liquidity,market,baseCurrency,quoteCurrency,orderId,tradeId,price,side,size,time,type,instrumentKind,strike,optionType,indexPrice"
+        );
+        // the time is in the local timezone
+        let (head, tail) = lines[2].split_at(lines[2].find("2022-01-01T").unwrap());
+        assert_eq!(
+            head,
+            "-0.00002,USD,-0.0001,ETH-PERP,1002,maker,ETH-PERP,,,10002,6002,3720.1,sell,0.5,"
+        );
+        assert!(tail.ends_with(",order,,,,"));
+    }
+
+    #[tokio::test]
+    async fn writes_the_unified_schema() {
+        let lines = write(false, DecimalSeparator::Dot).await;
+        assert_eq!(
+            lines,
+            [
+                "exchange,account,market,base_currency,quote_currency,side,price,size,fee,fee_currency,timestamp,native_id,type",
+                "ftx,main,BTC/USD,BTC,USD,buy,46200.5,0.01,0.0001,USD,2022-01-01T00:00:05.123456Z,1001,order",
+                "ftx,main,ETH-PERP,,,sell,3720.1,0.5,-0.00002,USD,2022-01-01T09:30:00Z,1002,order",
+                "ftx,main,ETH-PERP,,,sell,3720.2,0.25,-0.00002,USD,2022-01-01T09:30:00.500Z,1003,order",
+                "ftx,main,BTC/USD,BTC,USD,sell,47000.0,0.01,0.0001,USD,2022-01-01T23:59:59.999999Z,1004,order",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn quotes_the_numbers_with_the_decimal_comma() {
+        let lines = write(false, DecimalSeparator::Comma).await;
+        assert_eq!(
+            lines[1],
+            "ftx,main,BTC/USD,BTC,USD,buy,\"46200,5\",\"0,01\",\"0,0001\",USD,2022-01-01T00:00:05.123456Z,1001,order"
+        );
+    }
+}
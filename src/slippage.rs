@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use csv_async::AsyncSerializer;
+use futures::TryStreamExt;
+use log::*;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+
+use crate::archive;
+use crate::candles::Resolution;
+use crate::exchange::{ser_decimal, ser_decimal_opt, DecimalSeparator, FillType, Localized, Side};
+
+/// The slippage of the fills of a market against the candles they are in.
+#[derive(Serialize)]
+pub struct MarketSlippage {
+    pub market: String,
+    pub fills: u64,
+    // fills without a candle, which are excluded
+    pub unmatched: u64,
+    #[serde(serialize_with = "ser_decimal")]
+    pub notional: Decimal,
+    // the notional weighted average of how much worse the fill prices are than the opens of
+    // their candles, in basis points. negative when better
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub slippage_open_bps: Option<Decimal>,
+    // the same against the closes
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub slippage_close_bps: Option<Decimal>,
+    // the amount paid by the slippage against the opens, in the quote currency
+    #[serde(serialize_with = "ser_decimal")]
+    pub cost: Decimal,
+}
+
+#[derive(Deserialize)]
+struct CandleRow {
+    // the start of the candle
+    time: DateTime<Utc>,
+    market: String,
+    open: Decimal,
+    close: Decimal,
+}
+
+/// Compares the price of each fill in the range with the open and the close of the candle of
+/// its market it is in, and writes the slippage per market into `<account>.slippage.csv` of
+/// `outdir`, e.g. to estimate the execution cost.
+///
+/// `candles` is a csv file of `time,market,open,close` of downloaded candles of the length of
+/// `resolution`, where `time` is the start of a candle. Other columns, e.g. of the highs and the
+/// lows, are ignored.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    candles: &Path,
+    resolution: Resolution,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let file = File::open(candles)
+        .await
+        .with_context(|| format!("failed to open {}", candles.display()))?;
+    let rows: Vec<CandleRow> = csv_async::AsyncDeserializer::from_reader(file)
+        .deserialize()
+        .try_collect()
+        .await
+        .with_context(|| format!("failed to read {}", candles.display()))?;
+    let mut prices: HashMap<String, BTreeMap<DateTime<Utc>, (Decimal, Decimal)>> = HashMap::new();
+    for row in rows {
+        prices
+            .entry(row.market.to_uppercase())
+            .or_default()
+            .insert(row.time, (row.open, row.close));
+    }
+
+    // the notional weighted sums of the slippage against the opens and the closes
+    let mut markets: BTreeMap<String, (MarketSlippage, Decimal, Decimal)> = BTreeMap::new();
+    let files = archive::files(outdir, account);
+    let files = match start {
+        Some(start) => files.range(start..end),
+        None => files.range(..end),
+    };
+    for (date, path) in files {
+        for record in archive::read_records(path, *date).await? {
+            let (
+                None | Some(FillType::Order | FillType::Liquidation),
+                Some(market),
+                Some(side),
+                Some(price),
+            ) = (&record.typ, &record.market, record.side, record.price)
+            else {
+                continue;
+            };
+            let (slippage, open_sum, close_sum) =
+                markets.entry(market.clone()).or_insert_with(|| {
+                    (
+                        MarketSlippage {
+                            market: market.clone(),
+                            fills: 0,
+                            unmatched: 0,
+                            notional: Decimal::ZERO,
+                            slippage_open_bps: None,
+                            slippage_close_bps: None,
+                            cost: Decimal::ZERO,
+                        },
+                        Decimal::ZERO,
+                        Decimal::ZERO,
+                    )
+                });
+            slippage.fills += 1;
+            let time = record.time.with_timezone(&Utc);
+            let candle = prices
+                .get(&market.to_uppercase())
+                .and_then(|c| c.range(..=time).next_back())
+                .filter(|(start, _)| time < **start + resolution.duration());
+            let Some((_, (open, close))) = candle else {
+                slippage.unmatched += 1;
+                continue;
+            };
+            if open.is_zero() || close.is_zero() {
+                slippage.unmatched += 1;
+                continue;
+            }
+            // positive when a buy pays more or a sell gets less
+            let worse = |reference: Decimal| match side {
+                Side::Buy => price - reference,
+                Side::Sell => reference - price,
+            };
+            let notional = price * record.size.abs();
+            slippage.notional += notional;
+            slippage.cost += worse(*open) * record.size.abs();
+            *open_sum += notional * worse(*open) / open;
+            *close_sum += notional * worse(*close) / close;
+        }
+    }
+
+    let path = outdir.join(format!("{}.slippage.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    let mut unmatched = 0;
+    for (mut slippage, open_sum, close_sum) in markets.into_values() {
+        let bps = |sum: Decimal| {
+            (!slippage.notional.is_zero()).then(|| {
+                (sum / slippage.notional * Decimal::from(10000))
+                    .round_dp(4)
+                    .normalize()
+            })
+        };
+        slippage.slippage_open_bps = bps(open_sum);
+        slippage.slippage_close_bps = bps(close_sum);
+        slippage.notional = slippage.notional.normalize();
+        slippage.cost = slippage.cost.round_dp(8).normalize();
+        unmatched += slippage.unmatched;
+        writer
+            .serialize(Localized(&slippage, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    if unmatched > 0 {
+        warn!(
+            "{} fills have no candle in {} and are excluded",
+            unmatched,
+            candles.display()
+        );
+    }
+    info!("the slippage is written into {}", path.display());
+    Ok(path)
+}
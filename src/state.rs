@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::FailedWindow;
+
+/// Progress of a download, which is saved in the output directory after each page so that an
+/// interrupted download can be resumed.
+#[derive(Serialize, Deserialize)]
+pub struct State<C> {
+    pub exchange: String,
+    // the cursor of the next page to fetch
+    pub cursor: C,
+    // the file being written, if any
+    pub file: Option<FileState>,
+    // windows skipped with --continue-on-error so far
+    #[serde(default)]
+    pub failed: Vec<FailedWindow>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileState {
+    pub date: NaiveDate,
+    // the length of the file in bytes when the state was saved
+    pub len: u64,
+}
+
+pub fn path(outdir: &Path, account: &str) -> PathBuf {
+    outdir.join(format!("{}.state.json", account))
+}
+
+impl<C: Serialize + DeserializeOwned> State<C> {
+    pub async fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = tokio::fs::read_to_string(path).await?;
+        serde_json::from_str(&json)
+            .map(Some)
+            .with_context(|| format!("broken state file: {}", path.display()))
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        // write to a temporary file first not to leave a broken state file when interrupted
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_string(self)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    pub async fn remove(path: &Path) -> Result<()> {
+        if path.exists() {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::exchange::{ser_decimal, ser_decimal_opt, DecimalSeparator, Localized};
+use crate::roundtrips::{self, RoundTrip};
+
+/// The statistics of the closed round trips of a market.
+#[derive(Serialize)]
+pub struct MarketStats {
+    pub market: String,
+    pub currency: Option<String>,
+    pub trades: u64,
+    pub wins: u64,
+    pub losses: u64,
+    // wins / trades
+    #[serde(serialize_with = "ser_decimal")]
+    pub win_rate: Decimal,
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub average_win: Option<Decimal>,
+    // negative
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub average_loss: Option<Decimal>,
+    // the sum of the wins over the sum of the losses, empty without a loss
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub profit_factor: Option<Decimal>,
+    #[serde(serialize_with = "ser_decimal")]
+    pub net_pnl: Decimal,
+    // the largest fall of the cumulative net pnl from its peak, by the closing times
+    #[serde(serialize_with = "ser_decimal")]
+    pub max_drawdown: Decimal,
+    pub average_holding_seconds: i64,
+}
+
+/// Computes the win rate, the average win and loss, the profit factor and the largest drawdown
+/// of the closed round trips of each market, and writes them into `<account>.stats.csv` of
+/// `outdir`.
+///
+/// The round trips are the ones of `report round-trips`, and a win or a loss is by the net pnl
+/// after the fees. The round trips closed in the range are counted.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let mut markets: BTreeMap<String, Vec<RoundTrip>> = BTreeMap::new();
+    for trip in roundtrips::round_trips(outdir, account, start, end).await? {
+        if trip.closed_at.is_some() {
+            markets.entry(trip.market.clone()).or_default().push(trip);
+        }
+    }
+
+    let path = outdir.join(format!("{}.stats.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    let mut trades = 0;
+    for (market, mut trips) in markets {
+        trips.sort_by_key(|t| t.closed_at);
+        let wins: Vec<_> = trips
+            .iter()
+            .map(|t| t.net_pnl)
+            .filter(|p| *p > Decimal::ZERO)
+            .collect();
+        let losses: Vec<_> = trips
+            .iter()
+            .map(|t| t.net_pnl)
+            .filter(|p| *p < Decimal::ZERO)
+            .collect();
+        let (won, lost) = (wins.iter().sum::<Decimal>(), losses.iter().sum::<Decimal>());
+        let average = |sum: Decimal, n: usize| {
+            (n > 0).then(|| (sum / Decimal::from(n)).round_dp(8).normalize())
+        };
+        let (mut cumulative, mut peak, mut drawdown) =
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+        for trip in &trips {
+            cumulative += trip.net_pnl;
+            peak = peak.max(cumulative);
+            drawdown = drawdown.max(peak - cumulative);
+        }
+        let holding: i64 = trips.iter().filter_map(|t| t.holding_seconds).sum();
+        let stats = MarketStats {
+            market,
+            currency: trips[0].currency.clone(),
+            trades: trips.len() as u64,
+            wins: wins.len() as u64,
+            losses: losses.len() as u64,
+            win_rate: (Decimal::from(wins.len()) / Decimal::from(trips.len()))
+                .round_dp(8)
+                .normalize(),
+            average_win: average(won, wins.len()),
+            average_loss: average(lost, losses.len()),
+            profit_factor: (!lost.is_zero()).then(|| (won / -lost).round_dp(8).normalize()),
+            net_pnl: (won + lost).normalize(),
+            max_drawdown: drawdown.normalize(),
+            average_holding_seconds: holding / trips.len() as i64,
+        };
+        trades += trips.len();
+        writer
+            .serialize(Localized(&stats, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!(
+        "the statistics of {} round trips are written into {}",
+        trades,
+        path.display()
+    );
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    #[tokio::test]
+    async fn counts_the_wins_and_the_losses_after_the_fees_of_perpetual_futures() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        write_file(
+            dir.path(),
+            date,
+            &[
+                "ftx,main,ETH-PERP,,,sell,3000,2,4.2,USD,2022-01-01T00:00:00Z,1,order",
+                "ftx,main,ETH-PERP,,,buy,2900,2,-0.6,USD,2022-01-01T01:00:00Z,2,order",
+                "ftx,main,ETH-PERP,,,buy,3000,1,3,USD,2022-01-01T02:00:00Z,3,order",
+                "ftx,main,ETH-PERP,,,sell,2990,1,3,USD,2022-01-01T03:00:00Z,4,order",
+                // a loss only by the fees
+                "ftx,main,ETH-PERP,,,buy,3000,1,3,USD,2022-01-01T04:00:00Z,5,order",
+                "ftx,main,ETH-PERP,,,sell,3002,1,3,USD,2022-01-01T05:00:00Z,6,order",
+            ],
+        );
+        let path = report(dir.path(), "main", None, date.succ(), DecimalSeparator::Dot)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "market,currency,trades,wins,losses,win_rate,average_win,average_loss,profit_factor,net_pnl,max_drawdown,average_holding_seconds\n\
+             ETH-PERP,USD,3,1,2,0.33333333,196.4,-10,9.82,176.4,20,3600\n"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::exchange::{request_count, Fill, FillType};
+
+static SUMMARY: Mutex<Summary> = Mutex::new(Summary {
+    records: 0,
+    first_date: None,
+    last_date: None,
+    markets: BTreeMap::new(),
+    fees: BTreeMap::new(),
+});
+
+/// Totals of the records downloaded in this run, which are reported at the end of it.
+#[derive(Clone, Serialize)]
+struct Summary {
+    records: u64,
+    first_date: Option<NaiveDate>,
+    last_date: Option<NaiveDate>,
+    // records without a market are counted by their type, e.g. (deposit)
+    markets: BTreeMap<String, u64>,
+    // by currency
+    fees: BTreeMap<String, Decimal>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    #[serde(flatten)]
+    summary: Summary,
+    duration_secs: f64,
+    requests: u64,
+    requests_per_sec: f64,
+}
+
+/// Counts a record of the date into the summary.
+pub fn add(fill: &Fill, date: NaiveDate) {
+    let mut summary = SUMMARY.lock().unwrap();
+    summary.records += 1;
+    summary.first_date = Some(summary.first_date.map_or(date, |d| d.min(date)));
+    summary.last_date = Some(summary.last_date.map_or(date, |d| d.max(date)));
+    let market = match &fill.market {
+        Some(market) => market.clone(),
+        None => format!(
+            "({})",
+            fill.typ.as_ref().map_or("unknown", FillType::as_str)
+        ),
+    };
+    *summary.markets.entry(market).or_default() += 1;
+    if let Some(currency) = fill.fee_currency.as_ref().filter(|_| !fill.fee.is_zero()) {
+        *summary.fees.entry(currency.clone()).or_default() += fill.fee;
+    }
+}
+
+/// Returns how many records are downloaded so far in this run.
+pub fn records() -> u64 {
+    SUMMARY.lock().unwrap().records
+}
+
+/// Logs the summary of the run started at `started`, and writes it as json into `path` if
+/// specified.
+pub async fn report(started: Instant, path: Option<&Path>) -> Result<()> {
+    let summary = SUMMARY.lock().unwrap().clone();
+    let duration_secs = started.elapsed().as_secs_f64();
+    let requests = request_count();
+    let report = Report {
+        summary,
+        duration_secs,
+        requests,
+        requests_per_sec: requests as f64 / duration_secs.max(0.001),
+    };
+
+    let Report { summary, .. } = &report;
+    match summary.first_date.zip(summary.last_date) {
+        Some((first, last)) => info!(
+            "{} records from {} to {} in {} markets",
+            summary.records,
+            first,
+            last,
+            summary.markets.len()
+        ),
+        None => info!("no record is downloaded"),
+    }
+    for (market, count) in &summary.markets {
+        info!("  {}: {} records", market, count);
+    }
+    for (currency, fee) in &summary.fees {
+        info!("  fee: {} {}", fee.normalize(), currency);
+    }
+    info!(
+        "finished in {:.1}s with {} requests ({:.1}/s)",
+        report.duration_secs, report.requests, report.requests_per_sec
+    );
+
+    if let Some(path) = path {
+        tokio::fs::write(path, serde_json::to_vec_pretty(&report)?)
+            .await
+            .with_context(|| format!("failed to write the summary to {}", path.display()))?;
+    }
+    Ok(())
+}
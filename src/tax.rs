@@ -0,0 +1,377 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate};
+use clap::ArgEnum;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive::{self, Record};
+use crate::exchange::{ser_decimal, ser_decimal_opt, DecimalSeparator, FillType, Localized, Side};
+
+/// Which lots a disposal takes first.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LotMethod {
+    // the oldest lots first
+    Fifo,
+    // the newest lots first
+    Lifo,
+    // the lots of the highest unit cost first
+    Hifo,
+}
+
+/// A part of a disposal paired with the lot it takes, in the quote currencies of the trades.
+#[derive(Serialize)]
+pub struct Disposal {
+    pub currency: String,
+    #[serde(serialize_with = "ser_decimal")]
+    pub size: Decimal,
+    // empty when more is disposed than acquired in the archive
+    pub acquired: Option<DateTime<FixedOffset>>,
+    // order or deposit
+    pub acquired_by: Option<&'static str>,
+    pub disposed: DateTime<FixedOffset>,
+    pub held_days: Option<i64>,
+    // empty for deposited lots, whose cost is not in the archive
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub cost_basis: Option<Decimal>,
+    pub cost_currency: Option<String>,
+    // after the fee of the disposal
+    #[serde(serialize_with = "ser_decimal")]
+    pub proceeds: Decimal,
+    pub proceeds_currency: Option<String>,
+    // empty unless the cost basis is known and in the currency of the proceeds
+    #[serde(serialize_with = "ser_decimal_opt")]
+    pub gain: Option<Decimal>,
+}
+
+/// A part of the holdings of a currency acquired at once.
+struct Lot {
+    size: Decimal,
+    // including the fee of the acquisition
+    unit_cost: Option<Decimal>,
+    cost_currency: Option<String>,
+    time: DateTime<FixedOffset>,
+    acquired_by: &'static str,
+}
+
+/// Pairs the disposals of the tax year with the lots they take and writes them into
+/// `<account>.tax-lots-<year>.csv` of `outdir`.
+///
+/// Spot trades acquire and dispose of their base currency, and deposits add lots without a cost
+/// basis. Withdrawals take lots without a disposal since the currency is still held elsewhere.
+/// The quote currency is the currency of the cost basis, so only the base currency of a trade
+/// between two cryptocurrencies is disposed of.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    method: LotMethod,
+    tax_year: i32,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    // lots acquired later can't be taken by the disposals of the year
+    let records =
+        archive::read_range(outdir, account, ..NaiveDate::from_ymd(tax_year + 1, 1, 1)).await?;
+
+    let mut holdings: HashMap<String, Vec<Lot>> = HashMap::new();
+    let mut disposals = vec![];
+    let mut excluded_fees = BTreeSet::new();
+    let mut unmatched = BTreeSet::new();
+    for record in records {
+        let Some(currency) = record.base_currency.clone() else {
+            continue;
+        };
+        let lots = holdings.entry(currency.clone()).or_default();
+        match (&record.typ, record.side, record.price) {
+            (Some(FillType::Deposit), _, _) => lots.push(Lot {
+                size: record.size.abs(),
+                unit_cost: None,
+                cost_currency: None,
+                time: record.time,
+                acquired_by: "deposit",
+            }),
+            (Some(FillType::Withdrawal), _, _) => {
+                // the fee of a withdrawal is in the currency withdrawn
+                let size = record.size.abs() + record.fee;
+                take(lots, method, size);
+            }
+            (None | Some(FillType::Order | FillType::Liquidation), Some(side), Some(price))
+                if record.quote_currency.is_some() =>
+            {
+                let size = record.size.abs();
+                let fee = match record.fee_in_quote(price) {
+                    Some(fee) => fee,
+                    None => {
+                        excluded_fees.extend(record.fee_currency.clone());
+                        Decimal::ZERO
+                    }
+                };
+                match side {
+                    Side::Buy => {
+                        // a fee in the base currency is taken from the bought size, and the rest
+                        // costs all the price paid
+                        let (received, cost) = match &record.fee_currency {
+                            Some(c) if Some(c) == record.base_currency.as_ref() => {
+                                (size - record.fee, size * price)
+                            }
+                            _ => (size, size * price + fee),
+                        };
+                        if received > Decimal::ZERO {
+                            lots.push(Lot {
+                                size: received,
+                                unit_cost: Some(cost / received),
+                                cost_currency: record.quote_currency.clone(),
+                                time: record.time,
+                                acquired_by: "order",
+                            });
+                        }
+                    }
+                    Side::Sell => {
+                        let proceeds = size * price - fee;
+                        let mut taken = take(lots, method, size);
+                        let rest = size - taken.iter().map(|(s, _)| *s).sum::<Decimal>();
+                        if !rest.is_zero() {
+                            unmatched.insert(currency.clone());
+                            taken.push((rest, None));
+                        }
+                        if record.date.year() != tax_year {
+                            continue;
+                        }
+                        for (part, lot) in taken {
+                            disposals.push(disposal(
+                                &record,
+                                &currency,
+                                part,
+                                lot,
+                                proceeds / size,
+                            ));
+                        }
+                    }
+                }
+            }
+            // derivatives and the other records hold no currency
+            _ => {}
+        }
+    }
+    if !excluded_fees.is_empty() {
+        warn!(
+            "fees in {} are not included since they are not in the currencies of the markets",
+            excluded_fees.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    if !unmatched.is_empty() {
+        warn!(
+            "more {} is disposed of than acquired in the archive. download the earlier history \
+             or the deposits",
+            unmatched.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    let path = outdir.join(format!("{}.tax-lots-{}.csv", account, tax_year));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    for disposal in &disposals {
+        writer
+            .serialize(Localized(disposal, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!(
+        "{} disposals in {} are written into {}",
+        disposals.len(),
+        tax_year,
+        path.display()
+    );
+    Ok(path)
+}
+
+/// Pairs `part` of the sale of the record with the lot it takes.
+fn disposal(
+    record: &Record,
+    currency: &str,
+    part: Decimal,
+    lot: Option<Lot>,
+    unit_proceeds: Decimal,
+) -> Disposal {
+    let proceeds = (unit_proceeds * part).normalize();
+    let cost_basis = lot
+        .as_ref()
+        .and_then(|l| l.unit_cost)
+        .map(|cost| (cost * part).normalize());
+    let cost_currency = lot.as_ref().and_then(|l| l.cost_currency.clone());
+    Disposal {
+        currency: currency.to_string(),
+        size: part.normalize(),
+        acquired: lot.as_ref().map(|l| l.time),
+        acquired_by: lot.as_ref().map(|l| l.acquired_by),
+        disposed: record.time,
+        held_days: lot.as_ref().map(|l| (record.time - l.time).num_days()),
+        gain: cost_basis
+            .filter(|_| cost_currency == record.quote_currency)
+            .map(|cost| proceeds - cost),
+        cost_basis,
+        cost_currency,
+        proceeds,
+        proceeds_currency: record.quote_currency.clone(),
+    }
+}
+
+/// Takes up to `size` from the lots in the order of the method, splitting the last lot taken.
+/// Returns the sizes taken with the lots they are taken from.
+fn take(lots: &mut Vec<Lot>, method: LotMethod, mut size: Decimal) -> Vec<(Decimal, Option<Lot>)> {
+    let mut taken = vec![];
+    while size > Decimal::ZERO && !lots.is_empty() {
+        let index = match method {
+            LotMethod::Fifo => 0,
+            LotMethod::Lifo => lots.len() - 1,
+            // lots without a cost basis are taken last
+            LotMethod::Hifo => (0..lots.len())
+                .max_by_key(|i| lots[*i].unit_cost)
+                .unwrap_or(0),
+        };
+        let lot = &mut lots[index];
+        let part = size.min(lot.size);
+        lot.size -= part;
+        size -= part;
+        let lot = if lot.size.is_zero() {
+            lots.remove(index)
+        } else {
+            Lot {
+                size: part,
+                unit_cost: lot.unit_cost,
+                cost_currency: lot.cost_currency.clone(),
+                time: lot.time,
+                acquired_by: lot.acquired_by,
+            }
+        };
+        taken.push((part, Some(lot)));
+    }
+    taken
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+    use crate::archive::tests::write_file;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    // acquired at the hour of 2022-01-01
+    fn lot(size: &str, unit_cost: Option<&str>, hour: u32) -> Lot {
+        Lot {
+            size: dec(size),
+            unit_cost: unit_cost.map(dec),
+            cost_currency: unit_cost.map(|_| "USD".to_string()),
+            time: format!("2022-01-01T{:02}:00:00Z", hour).parse().unwrap(),
+            acquired_by: if unit_cost.is_some() {
+                "order"
+            } else {
+                "deposit"
+            },
+        }
+    }
+
+    fn lots() -> Vec<Lot> {
+        vec![
+            lot("1", Some("100"), 0),
+            lot("1", None, 1),
+            lot("1", Some("300"), 2),
+            lot("1", Some("200"), 3),
+        ]
+    }
+
+    // the sizes taken with the hours of their lots, and the sizes left
+    fn taken(method: LotMethod, size: &str) -> (Vec<(Decimal, u32)>, Vec<Decimal>) {
+        let mut lots = lots();
+        let taken = take(&mut lots, method, dec(size))
+            .into_iter()
+            .map(|(size, lot)| (size, lot.unwrap().time.time().hour()))
+            .collect();
+        (taken, lots.iter().map(|l| l.size).collect())
+    }
+
+    #[test]
+    fn takes_the_oldest_lots_first_with_fifo() {
+        assert_eq!(
+            taken(LotMethod::Fifo, "1.5"),
+            (
+                vec![(dec("1"), 0), (dec("0.5"), 1)],
+                vec![dec("0.5"), dec("1"), dec("1")]
+            )
+        );
+    }
+
+    #[test]
+    fn takes_the_newest_lots_first_with_lifo() {
+        assert_eq!(
+            taken(LotMethod::Lifo, "2.25"),
+            (
+                vec![(dec("1"), 3), (dec("1"), 2), (dec("0.25"), 1)],
+                vec![dec("1"), dec("0.75")]
+            )
+        );
+    }
+
+    #[test]
+    fn takes_the_lots_without_a_cost_basis_last_with_hifo() {
+        assert_eq!(
+            taken(LotMethod::Hifo, "3.5"),
+            (
+                vec![(dec("1"), 2), (dec("1"), 3), (dec("1"), 0), (dec("0.5"), 1)],
+                vec![dec("0.5")]
+            )
+        );
+    }
+
+    #[test]
+    fn takes_what_is_held_when_more_is_disposed_of() {
+        let (taken, left) = taken(LotMethod::Fifo, "5");
+        assert_eq!(taken.iter().map(|(s, _)| *s).sum::<Decimal>(), dec("4"));
+        assert!(left.is_empty());
+    }
+
+    #[tokio::test]
+    async fn takes_a_fee_in_the_base_currency_from_the_lot() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd(2022, 1, 1);
+        write_file(
+            dir.path(),
+            date,
+            &[
+                // 0.99 BTC is received for 10000 USD
+                "ftx,main,BTC/USD,BTC,USD,buy,10000,1,0.01,BTC,2022-01-01T00:00:00Z,1,order",
+                "ftx,main,BTC/USD,BTC,USD,sell,20000,0.99,0,USD,2022-01-01T01:00:00Z,2,order",
+                "ftx,main,BTC/USD,BTC,USD,sell,20000,0.01,0,USD,2022-01-01T02:00:00Z,3,order",
+            ],
+        );
+        let path = report(
+            dir.path(),
+            "main",
+            LotMethod::Fifo,
+            2022,
+            DecimalSeparator::Dot,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "currency,size,acquired,acquired_by,disposed,held_days,cost_basis,cost_currency,proceeds,proceeds_currency,gain\n\
+             BTC,0.99,2022-01-01T00:00:00+00:00,order,2022-01-01T01:00:00+00:00,0,10000,USD,19800,USD,9800\n\
+             BTC,0.01,,,2022-01-01T02:00:00+00:00,,,,200,USD,\n"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone};
+
+/// A timezone in which fills are split into daily files and `--start` / `--end` are interpreted.
+#[derive(Clone, Copy, Debug)]
+pub enum Timezone {
+    // the timezone of the system
+    Local,
+    // an IANA name, e.g. Asia/Tokyo
+    Named(chrono_tz::Tz),
+    // an offset from UTC, e.g. +09:00
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    /// Returns the date of the time in this timezone.
+    pub fn date<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> NaiveDate {
+        self.local(time).date()
+    }
+
+    /// Returns the wall clock time of the time in this timezone.
+    pub fn local<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> NaiveDateTime {
+        match self {
+            Timezone::Local => time.with_timezone(&Local).naive_local(),
+            Timezone::Named(tz) => time.with_timezone(tz).naive_local(),
+            Timezone::Fixed(offset) => time.with_timezone(offset).naive_local(),
+        }
+    }
+
+    /// Returns the UTC time at which the date starts in this timezone.
+    pub fn start_of(&self, date: NaiveDate) -> NaiveDateTime {
+        let midnight = date.and_hms(0, 0, 0);
+        let start = match self {
+            Timezone::Local => start_in(&Local, &midnight),
+            Timezone::Named(tz) => start_in(tz, &midnight),
+            Timezone::Fixed(offset) => start_in(offset, &midnight),
+        };
+        start.unwrap_or(midnight)
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.date(&Local::now())
+    }
+}
+
+fn start_in<Tz: TimeZone>(tz: &Tz, midnight: &NaiveDateTime) -> Option<NaiveDateTime> {
+    if let Some(t) = tz.from_local_datetime(midnight).earliest() {
+        return Some(t.naive_utc());
+    }
+    // midnight doesn't exist when the daylight saving time starts at that time, so the day starts
+    // at the transition, which is midnight in the offset before it
+    let before = tz
+        .offset_from_local_datetime(&(*midnight - Duration::days(1)))
+        .earliest()?;
+    Some(*midnight - Duration::seconds(before.fix().local_minus_utc().into()))
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Timezone::Local);
+        }
+        if let Ok(tz) = s.parse() {
+            return Ok(Timezone::Named(tz));
+        }
+        parse_offset(s).map(Timezone::Fixed).ok_or_else(|| {
+            format!(
+                "invalid timezone: {}. specify local, an IANA name (e.g. Asia/Tokyo) or an offset (e.g. +09:00)",
+                s
+            )
+        })
+    }
+}
+
+/// Parses an offset like `+09:00`, `-0530` or `+9`.
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let (sign, rest) = match s.chars().next()? {
+        '+' => (1, &s[1..]),
+        '-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    fn utc(s: &str) -> NaiveDateTime {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn starts_the_days_at_the_midnight_of_the_timezone() {
+        let tokyo: Timezone = "Asia/Tokyo".parse().unwrap();
+        assert_eq!(
+            tokyo.start_of(date("2022-01-01")),
+            utc("2021-12-31T15:00:00")
+        );
+        let fixed: Timezone = "-0530".parse().unwrap();
+        assert_eq!(
+            fixed.start_of(date("2022-01-01")),
+            utc("2022-01-01T05:30:00")
+        );
+        assert_eq!(
+            "UTC"
+                .parse::<Timezone>()
+                .unwrap()
+                .start_of(date("2022-01-01")),
+            utc("2022-01-01T00:00:00")
+        );
+    }
+
+    #[test]
+    fn keeps_the_days_across_the_daylight_saving_time() {
+        let new_york: Timezone = "America/New_York".parse().unwrap();
+        // the day of 23 hours in march
+        assert_eq!(
+            new_york.start_of(date("2022-03-13")),
+            utc("2022-03-13T05:00:00")
+        );
+        assert_eq!(
+            new_york.start_of(date("2022-03-14")),
+            utc("2022-03-14T04:00:00")
+        );
+        // the day of 25 hours in november
+        assert_eq!(
+            new_york.start_of(date("2022-11-06")),
+            utc("2022-11-06T04:00:00")
+        );
+        assert_eq!(
+            new_york.start_of(date("2022-11-07")),
+            utc("2022-11-07T05:00:00")
+        );
+
+        let last_hour = Utc.from_utc_datetime(&utc("2022-11-07T04:59:59"));
+        assert_eq!(new_york.date(&last_hour), date("2022-11-06"));
+        let first_hour = Utc.from_utc_datetime(&utc("2022-03-14T04:00:00"));
+        assert_eq!(new_york.date(&first_hour), date("2022-03-14"));
+    }
+
+    #[test]
+    fn starts_a_day_without_midnight_at_the_transition() {
+        // the clocks went from 00:00 to 01:00 on 2022-03-13 in Havana
+        let havana: Timezone = "America/Havana".parse().unwrap();
+        let start = havana.start_of(date("2022-03-13"));
+        assert_eq!(start, utc("2022-03-13T05:00:00"));
+        assert_eq!(
+            havana.date(&Utc.from_utc_datetime(&start)),
+            date("2022-03-13")
+        );
+        let before = start - Duration::seconds(1);
+        assert_eq!(
+            havana.date(&Utc.from_utc_datetime(&before)),
+            date("2022-03-12")
+        );
+    }
+
+    #[test]
+    fn parses_the_timezones() {
+        assert!(matches!("local".parse(), Ok(Timezone::Local)));
+        assert!(matches!("Asia/Tokyo".parse(), Ok(Timezone::Named(_))));
+        for (offset, seconds) in [
+            ("+09:00", 9 * 3600),
+            ("-0530", -(5 * 3600 + 1800)),
+            ("+9", 9 * 3600),
+        ] {
+            match offset.parse() {
+                Ok(Timezone::Fixed(o)) => assert_eq!(o.local_minus_utc(), seconds),
+                _ => panic!("{}", offset),
+            }
+        }
+        for invalid in ["Mars/Olympus", "+09:60", "0900", "+"] {
+            assert!(invalid.parse::<Timezone>().is_err(), "{}", invalid);
+        }
+    }
+}
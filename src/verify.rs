@@ -0,0 +1,66 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use log::*;
+
+use crate::archive;
+use crate::exchange::{for_each_fill, Exchange, FillId, TimeRange};
+use crate::timezone::Timezone;
+
+/// Downloads the fills of the range into memory and compares them with the archive day by day.
+pub async fn verify<E: Exchange>(
+    exchange: &E,
+    range: &TimeRange,
+    outdir: &Path,
+    account: &str,
+    timezone: Timezone,
+) -> Result<()> {
+    let mut remote: BTreeMap<NaiveDate, HashSet<FillId>> = BTreeMap::new();
+    for_each_fill(exchange, range, |fill| {
+        remote
+            .entry(timezone.date(&fill.time))
+            .or_default()
+            .insert(fill.id);
+    })
+    .await?;
+
+    let mut local: BTreeMap<NaiveDate, HashSet<FillId>> = BTreeMap::new();
+    for (date, path) in archive::files(outdir, account) {
+        let start = timezone.start_of(date);
+        if range.is_after_start(&start) && start < range.end {
+            local.insert(date, archive::read_ids(&path).await?.into_iter().collect());
+        }
+    }
+
+    let empty = HashSet::new();
+    let dates = remote.keys().chain(local.keys()).collect::<BTreeSet<_>>();
+    let mut mismatches = 0;
+    for date in &dates {
+        let remote = remote.get(date).unwrap_or(&empty);
+        let local = local.get(date).unwrap_or(&empty);
+        let missing = remote.difference(local).count();
+        let unknown = local.difference(remote).count();
+        if missing > 0 || unknown > 0 {
+            mismatches += 1;
+            warn!(
+                "{}: {} records in the archive but {} on the exchange ({} missing, {} not found on the exchange)",
+                date,
+                local.len(),
+                remote.len(),
+                missing,
+                unknown,
+            );
+        }
+    }
+    if mismatches > 0 {
+        bail!(
+            "{} of {} days don't match the exchange",
+            mismatches,
+            dates.len()
+        );
+    }
+    info!("all {} days match the exchange", dates.len());
+    Ok(())
+}
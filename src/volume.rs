@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDate};
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, DecimalSeparator, FillType, Localized};
+
+/// The trading volume of a quote currency up to a day.
+#[derive(Serialize)]
+pub struct RollingVolume {
+    pub date: NaiveDate,
+    pub currency: Option<String>,
+    // the notional of the fills of the day
+    #[serde(serialize_with = "ser_decimal")]
+    pub notional: Decimal,
+    // the notional of the fills of the window ending with the day
+    #[serde(serialize_with = "ser_decimal")]
+    pub rolling_notional: Decimal,
+    // how many of the thresholds the rolling notional reaches
+    pub tier: usize,
+}
+
+/// Sums the notional of the fills by day and quote currency over the rolling window of `days`
+/// ending with each day, and writes it into `<account>.volume.csv` of `outdir`, e.g. to compare
+/// against the volume thresholds of the fee tiers in `tiers`.
+///
+/// The days before `start` are read for the window. The days are the ones of the files, from
+/// the first in the range to the last without a gap.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    days: u32,
+    tiers: &[Decimal],
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let window = Duration::days(days.max(1) as i64 - 1);
+    let files = archive::files(outdir, account);
+    let files = match start {
+        Some(start) => files.range(start - window..end),
+        None => files.range(..end),
+    };
+    let mut daily: BTreeMap<(Option<String>, NaiveDate), Decimal> = BTreeMap::new();
+    for (date, path) in files {
+        for record in archive::read_records(path, *date).await? {
+            let (None | Some(FillType::Order | FillType::Liquidation), Some(price)) =
+                (&record.typ, record.price)
+            else {
+                continue;
+            };
+            let currency = record.settlement_currency().cloned();
+            *daily.entry((currency, *date)).or_default() += price * record.size.abs();
+        }
+    }
+    let currencies: BTreeSet<_> = daily.keys().map(|(currency, _)| currency.clone()).collect();
+    if daily.is_empty() {
+        info!("no downloaded fill is found");
+    }
+    let first = daily.keys().map(|(_, date)| *date).min();
+    let last = daily.keys().map(|(_, date)| *date).max();
+
+    let path = outdir.join(format!("{}.volume.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    let mut latest = vec![];
+    for currency in currencies {
+        let (Some(first), Some(last)) = (first, last) else {
+            break;
+        };
+        let mut date = start.map_or(first, |start| start.max(first));
+        let mut volume = None;
+        while date <= last {
+            let rolling: Decimal = daily
+                .range((currency.clone(), date - window)..=(currency.clone(), date))
+                .map(|(_, notional)| notional)
+                .sum();
+            let row = RollingVolume {
+                date,
+                currency: currency.clone(),
+                notional: daily
+                    .get(&(currency.clone(), date))
+                    .copied()
+                    .unwrap_or_default()
+                    .normalize(),
+                rolling_notional: rolling.normalize(),
+                tier: tiers.iter().filter(|t| rolling >= **t).count(),
+            };
+            writer
+                .serialize(Localized(&row, decimal_separator))
+                .await
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            volume = Some(row);
+            date += Duration::days(1);
+        }
+        latest.extend(volume);
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    for volume in latest {
+        info!(
+            "{}-day volume at {}: {} {} (tier {})",
+            days,
+            volume.date,
+            volume.rolling_notional,
+            volume.currency.as_deref().unwrap_or("(unknown currency)"),
+            volume.tier
+        );
+    }
+    info!("the volume is written into {}", path.display());
+    Ok(path)
+}
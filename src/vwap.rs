@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use clap::ArgEnum;
+use csv_async::AsyncSerializer;
+use log::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::fs::File;
+
+use crate::archive;
+use crate::exchange::{ser_decimal, DecimalSeparator, FillType, Localized, Side};
+use crate::timezone::Timezone;
+
+/// The length of the periods in which fills are averaged.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    Day,
+    Hour,
+}
+
+/// The volume weighted average price of the fills of a side of a market in a period.
+#[derive(Serialize)]
+pub struct Vwap {
+    // YYYY-MM-DD, or YYYY-MM-DDTHH:00 for hours, in the timezone of the report
+    pub period: String,
+    pub market: String,
+    pub side: Side,
+    pub trades: u64,
+    #[serde(serialize_with = "ser_decimal")]
+    pub size: Decimal,
+    // price * size in the quote currency
+    #[serde(serialize_with = "ser_decimal")]
+    pub notional: Decimal,
+    #[serde(serialize_with = "ser_decimal")]
+    pub vwap: Decimal,
+}
+
+/// Computes the vwap of the fills of the archive in the range per period, market and side and
+/// writes it into `<account>.vwap.csv` of `outdir`.
+pub async fn report(
+    outdir: &Path,
+    account: &str,
+    interval: Interval,
+    timezone: Timezone,
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+    decimal_separator: DecimalSeparator,
+) -> Result<PathBuf> {
+    let format = match interval {
+        Interval::Day => "%Y-%m-%d",
+        Interval::Hour => "%Y-%m-%dT%H:00",
+    };
+    let mut periods: BTreeMap<(String, String, Side), Vwap> = BTreeMap::new();
+    let files = archive::files(outdir, account);
+    let files = match start {
+        Some(start) => files.range(start..end),
+        None => files.range(..end),
+    };
+    for (date, path) in files {
+        for record in archive::read_records(path, *date).await? {
+            let (Some(market), Some(side), Some(price)) =
+                (record.market, record.side, record.price)
+            else {
+                continue;
+            };
+            if !matches!(
+                record.typ,
+                None | Some(FillType::Order | FillType::Liquidation)
+            ) {
+                continue;
+            }
+            let period = timezone.local(&record.time).format(format).to_string();
+            let vwap = periods
+                .entry((period.clone(), market.clone(), side))
+                .or_insert_with(|| Vwap {
+                    period,
+                    market,
+                    side,
+                    trades: 0,
+                    size: Decimal::ZERO,
+                    notional: Decimal::ZERO,
+                    vwap: Decimal::ZERO,
+                });
+            let size = record.size.abs();
+            vwap.trades += 1;
+            vwap.size += size;
+            vwap.notional += price * size;
+        }
+    }
+
+    let path = outdir.join(format!("{}.vwap.csv", account));
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = AsyncSerializer::from_writer(file);
+    for mut vwap in periods.into_values() {
+        // fills of zero size can't be averaged
+        if vwap.size.is_zero() {
+            continue;
+        }
+        vwap.vwap = (vwap.notional / vwap.size).normalize();
+        vwap.size = vwap.size.normalize();
+        vwap.notional = vwap.notional.normalize();
+        writer
+            .serialize(Localized(&vwap, decimal_separator))
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!("the vwap is written into {}", path.display());
+    Ok(path)
+}